@@ -2,7 +2,22 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use crate::baseline::Reservoir;
+use crate::command_stats::CommandStats;
+use crate::config_file::{LatencyUnit, OutputFormat};
+use crate::exemplars::ExemplarHistogram;
 use crate::metrics::*;
+use crate::phases::Phases;
+use crate::profile::RateProfile;
+use crate::report::{Report, RunLatency};
+use crate::response_sizes::ResponseSizes;
+use crate::samples::LatencySamples;
+use crate::self_stats::SelfStats;
+use crate::server_stats::ServerStats;
+use crate::sqlite::Sqlite;
+use crate::statsd::Statsd;
+use crate::sweep::{RateSweep, SweepLevel};
+use crate::target_stats::TargetStats;
 use crate::Arc;
 use crate::Config;
 use rustcommon_heatmap::AtomicHeatmap;
@@ -11,22 +26,62 @@ use rustcommon_logger::Drain;
 use rustcommon_ratelimiter::Ratelimiter;
 use rustcommon_waterfall::WaterfallBuilder;
 use std::collections::HashMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Instant;
 
 use std::net::SocketAddr;
 use std::time::Duration;
 use tiny_http::{Method, Response, Server};
 
+/// Consecutive windows with responses slower than latency_max before warning
+/// that the tail percentiles are clipped
+const CLIPPED_WARN_WINDOWS: usize = 3;
+
 pub struct Admin {
     config: Option<Arc<Config>>,
+    /// the config of every target, the first of which is `config`
+    targets: Vec<Arc<Config>>,
     snapshot: Snapshot,
     connect_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    lifetime_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    delivery_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    key_update_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
     reconnect_ratelimit: Option<Arc<Ratelimiter>>,
     request_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
     request_ratelimit: Option<Arc<Ratelimiter>>,
     request_waterfall: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    value_heatmaps: Vec<(usize, Arc<AtomicHeatmap<u64, AtomicU64>>)>,
+    sequence_heatmaps: Vec<(String, Arc<AtomicHeatmap<u64, AtomicU64>>)>,
+    wait_heatmaps: Vec<(String, Arc<AtomicHeatmap<u64, AtomicU64>>)>,
     server: Option<Server>,
     log: Box<dyn Drain>,
+    report: Option<(String, Arc<RunLatency>)>,
+    latency_samples: Option<LatencySamples>,
+    baseline: Option<(Vec<u64>, Reservoir)>,
+    statsd: Option<Statsd>,
+    sqlite: Option<Sqlite>,
+    self_stats: Option<SelfStats>,
+    cdf_plot: Option<String>,
+    latency_histogram: Option<Arc<ExemplarHistogram>>,
+    phases: Option<Arc<Phases>>,
+    response_sizes: Option<Arc<ResponseSizes>>,
+    rate_profile: Option<RateProfile>,
+    sweep: Option<RateSweep>,
+    target_stats: Vec<Arc<TargetStats>>,
+    workload_stats: Vec<Arc<TargetStats>>,
+    command_stats: Vec<Arc<CommandStats>>,
+    error_abort: Option<ErrorAbort>,
+    request_budget: Option<(Arc<AtomicUsize>, usize)>,
+    recycle: Option<Arc<AtomicUsize>>,
+    /// set once the minimum fraction of connections are established, which
+    /// holds back recording response latencies
+    ready: Option<Arc<AtomicBool>>,
+    expected_connections: usize,
+    clipped_windows: usize,
+    /// the count of responses above latency_max in the request heatmap as of
+    /// the last window
+    too_high: u64,
 }
 
 impl Admin {
@@ -38,15 +93,44 @@ impl Admin {
             .map(|admin_addr| Server::http(admin_addr).unwrap());
 
         Self {
+            targets: vec![config.clone()],
             config: Some(config),
             snapshot,
             connect_heatmap: None,
+            lifetime_heatmap: None,
+            delivery_heatmap: None,
+            key_update_heatmap: None,
             reconnect_ratelimit: None,
             request_heatmap: None,
             request_ratelimit: None,
             request_waterfall: None,
+            value_heatmaps: Vec::new(),
+            sequence_heatmaps: Vec::new(),
+            wait_heatmaps: Vec::new(),
             server,
             log,
+            report: None,
+            latency_samples: None,
+            baseline: None,
+            statsd: None,
+            sqlite: None,
+            self_stats: None,
+            cdf_plot: None,
+            latency_histogram: None,
+            phases: None,
+            response_sizes: None,
+            rate_profile: None,
+            sweep: None,
+            target_stats: Vec::new(),
+            workload_stats: Vec::new(),
+            command_stats: Vec::new(),
+            error_abort: None,
+            request_budget: None,
+            recycle: None,
+            ready: None,
+            expected_connections: 0,
+            clipped_windows: 0,
+            too_high: 0,
         }
     }
 
@@ -56,14 +140,43 @@ impl Admin {
 
         Self {
             config: None,
+            targets: Vec::new(),
             snapshot,
             connect_heatmap: None,
+            lifetime_heatmap: None,
+            delivery_heatmap: None,
+            key_update_heatmap: None,
             reconnect_ratelimit: None,
             request_heatmap: None,
             request_ratelimit: None,
             request_waterfall: None,
+            value_heatmaps: Vec::new(),
+            sequence_heatmaps: Vec::new(),
+            wait_heatmaps: Vec::new(),
             server,
             log,
+            report: None,
+            latency_samples: None,
+            baseline: None,
+            statsd: None,
+            sqlite: None,
+            self_stats: None,
+            cdf_plot: None,
+            latency_histogram: None,
+            phases: None,
+            response_sizes: None,
+            rate_profile: None,
+            sweep: None,
+            target_stats: Vec::new(),
+            workload_stats: Vec::new(),
+            command_stats: Vec::new(),
+            error_abort: None,
+            request_budget: None,
+            recycle: None,
+            ready: None,
+            expected_connections: 0,
+            clipped_windows: 0,
+            too_high: 0,
         }
     }
 
@@ -71,6 +184,18 @@ impl Admin {
         self.connect_heatmap = heatmap;
     }
 
+    pub fn set_lifetime_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
+        self.lifetime_heatmap = heatmap;
+    }
+
+    pub fn set_delivery_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
+        self.delivery_heatmap = heatmap;
+    }
+
+    pub fn set_key_update_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
+        self.key_update_heatmap = heatmap;
+    }
+
     pub fn set_reconnect_ratelimit(&mut self, ratelimiter: Option<Arc<Ratelimiter>>) {
         self.reconnect_ratelimit = ratelimiter;
     }
@@ -87,7 +212,303 @@ impl Admin {
         self.request_waterfall = heatmap;
     }
 
-    pub fn run(mut self) {
+    /// Provide heatmaps of request latency by value length, which are reported
+    /// in order of length
+    pub fn set_value_heatmaps(
+        &mut self,
+        heatmaps: HashMap<usize, Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    ) {
+        let mut heatmaps: Vec<_> = heatmaps.into_iter().collect();
+        heatmaps.sort_by_key(|(length, _)| *length);
+        self.value_heatmaps = heatmaps;
+    }
+
+    /// Provide heatmaps of the latency of each sequence, along with its name
+    pub fn set_sequence_heatmaps(
+        &mut self,
+        heatmaps: Vec<(String, Arc<AtomicHeatmap<u64, AtomicU64>>)>,
+    ) {
+        self.sequence_heatmaps = heatmaps;
+    }
+
+    /// Provide heatmaps of the latency of `WAIT` requests, by target
+    pub fn set_wait_heatmaps(
+        &mut self,
+        heatmaps: Vec<(String, Arc<AtomicHeatmap<u64, AtomicU64>>)>,
+    ) {
+        self.wait_heatmaps = heatmaps;
+    }
+
+    /// Write a report to the file at the end of the run, from the latency
+    /// recorded over the whole run
+    pub fn set_report(&mut self, report: Option<(String, Arc<RunLatency>)>) {
+        self.report = report;
+    }
+
+    pub fn set_rate_profile(&mut self, profile: Option<RateProfile>) {
+        self.rate_profile = profile;
+    }
+
+    /// Step the request rate through the levels of the sweep, logging a
+    /// summary of the latency at each level at the end of the run
+    pub fn set_sweep(&mut self, sweep: Option<RateSweep>) {
+        self.sweep = sweep;
+    }
+
+    pub fn set_latency_histogram(&mut self, histogram: Option<Arc<ExemplarHistogram>>) {
+        self.latency_histogram = histogram;
+    }
+
+    /// Provide the window counter which workers watch to recycle a fraction
+    /// of their connections at the start of each window
+    pub fn set_recycle(&mut self, windows: Option<Arc<AtomicUsize>>) {
+        self.recycle = windows;
+    }
+
+    /// Provide the flag which is set once the minimum fraction of connections
+    /// are established
+    pub fn set_ready(&mut self, ready: Option<Arc<AtomicBool>>) {
+        self.ready = ready;
+    }
+
+    /// The number of connections the workers will open, which readiness is
+    /// measured against
+    pub fn set_expected_connections(&mut self, connections: usize) {
+        self.expected_connections = connections;
+        OPEN_TARGET.set(connections as _);
+    }
+
+    pub fn set_response_sizes(&mut self, sizes: Option<Arc<ResponseSizes>>) {
+        self.response_sizes = sizes;
+    }
+
+    pub fn set_phases(&mut self, phases: Option<Arc<Phases>>) {
+        self.phases = phases;
+    }
+
+    pub fn set_cdf_plot(&mut self, file: Option<String>) {
+        self.cdf_plot = file;
+    }
+
+    pub fn set_latency_samples(&mut self, samples: Option<LatencySamples>) {
+        self.latency_samples = samples;
+    }
+
+    /// Compare response latency against the latencies of a baseline run at
+    /// the end of the run
+    pub fn set_baseline(&mut self, baseline: Option<(Vec<u64>, Reservoir)>) {
+        self.baseline = baseline;
+    }
+
+    pub fn set_statsd(&mut self, statsd: Option<Statsd>) {
+        self.statsd = statsd;
+    }
+
+    pub fn set_sqlite(&mut self, sqlite: Option<Sqlite>) {
+        self.sqlite = sqlite;
+    }
+
+    pub fn set_self_stats(&mut self, stats: Option<SelfStats>) {
+        self.self_stats = stats;
+    }
+
+    /// Provide the config of every target, whose keyspaces are counted while
+    /// filling and whose endpoints are polled for server stats
+    pub fn set_targets(&mut self, targets: Vec<Arc<Config>>) {
+        self.targets = targets;
+    }
+
+    /// Provide per-target stats, which are reported each window alongside the
+    /// overall stats
+    pub fn set_target_stats(&mut self, stats: Vec<Arc<TargetStats>>) {
+        self.target_stats = stats;
+    }
+
+    pub fn set_command_stats(&mut self, stats: Vec<Arc<CommandStats>>) {
+        self.command_stats = stats;
+    }
+
+    /// Provide per-workload stats, which are reported each window alongside
+    /// the overall stats
+    pub fn set_workload_stats(&mut self, stats: Vec<Arc<TargetStats>>) {
+        self.workload_stats = stats;
+    }
+
+    /// Exit the process once the error rate, as a percentage, exceeds the
+    /// threshold for the given number of consecutive windows
+    pub fn set_error_abort(&mut self, threshold: Option<f64>, windows: usize) {
+        self.error_abort = threshold.map(|threshold| ErrorAbort::new(threshold, windows));
+    }
+
+    /// End the run once the given number of workers have completed their
+    /// request budgets
+    pub fn set_request_budget(&mut self, complete: Arc<AtomicUsize>, workers: usize) {
+        self.request_budget = Some((complete, workers));
+    }
+
+    /// Returns true if every worker has completed its request budget
+    fn budget_complete(&self) -> bool {
+        match self.request_budget {
+            Some((ref complete, workers)) => complete.load(Ordering::Relaxed) >= workers,
+            None => false,
+        }
+    }
+
+    /// The number of distinct keys written while filling the keyspaces during
+    /// warmup, and the number which must be written
+    fn filled(&self) -> (u64, u64) {
+        self.targets
+            .iter()
+            .flat_map(|config| config.keyspaces())
+            .filter_map(|keyspace| keyspace.filled())
+            .fold((0, 0), |(filled, target), (f, t)| (filled + f, target + t))
+    }
+
+    /// Answer any pending requests to the admin http server. Uptime is
+    /// measured from when the admin was launched.
+    fn serve_requests(&self, launched: Instant) {
+        if let Some(ref server) = self.server {
+            while let Ok(Some(mut request)) = server.try_recv() {
+                let url = request.url();
+                let parts: Vec<&str> = url.split('?').collect();
+                let url = parts[0];
+                match request.method() {
+                    Method::Get => match url {
+                        "/" => {
+                            debug!("Serving GET on index");
+                            let _ = request.respond(Response::from_string(format!(
+                                "Welcome to {}\nVersion: {}\n",
+                                crate::config::NAME,
+                                crate::config::VERSION,
+                            )));
+                        }
+                        "/health" => {
+                            debug!("Serving health check");
+                            let _ = request.respond(Response::from_string(format!(
+                                "{{\"status\": \"ok\", \"uptime_secs\": {}, \"windows\": {}}}",
+                                launched.elapsed().as_secs(),
+                                WINDOW.value(),
+                            )));
+                        }
+                        "/metrics" => {
+                            debug!("Serving Prometheus compatible stats");
+                            let _ =
+                                request.respond(Response::from_string(self.snapshot.prometheus()));
+                        }
+                        "/metrics.json" | "/vars.json" | "/admin/metrics.json" => {
+                            debug!("Serving machine readable stats");
+                            let _ = request.respond(Response::from_string(self.snapshot.json()));
+                        }
+                        "/vars" => {
+                            debug!("Serving human readable stats");
+                            let _ = request.respond(Response::from_string(self.snapshot.human()));
+                        }
+                        url => {
+                            debug!("GET on non-existent url: {}", url);
+                            debug!("Serving machine readable stats");
+                            let _ = request.respond(Response::from_string(self.snapshot.json()));
+                        }
+                    },
+                    Method::Put => match request.url() {
+                        "/ratelimit/reconnect" => {
+                            let mut content = String::new();
+                            request.as_reader().read_to_string(&mut content).unwrap();
+                            if let Ok(rate) = content.parse() {
+                                if let Some(ref ratelimiter) = self.reconnect_ratelimit {
+                                    ratelimiter.set_rate(rate);
+                                    let _ = request.respond(Response::empty(200));
+                                } else {
+                                    let _ = request.respond(Response::empty(400));
+                                }
+                            } else {
+                                let _ = request.respond(Response::empty(400));
+                            }
+                        }
+                        "/ratelimit/request" => {
+                            let mut content = String::new();
+                            request.as_reader().read_to_string(&mut content).unwrap();
+                            if let Ok(rate) = content.parse() {
+                                if let Some(ref ratelimiter) = self.request_ratelimit {
+                                    ratelimiter.set_rate(rate);
+                                    let _ = request.respond(Response::empty(200));
+                                } else {
+                                    let _ = request.respond(Response::empty(400));
+                                }
+                            } else {
+                                let _ = request.respond(Response::empty(400));
+                            }
+                        }
+                        url => {
+                            debug!("PUT on non-existent url: {}", url);
+                            let _ = request.respond(Response::empty(404));
+                        }
+                    },
+                    method => {
+                        debug!("unsupported request method: {}", method);
+                        let _ = request.respond(Response::empty(404));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Wait until the minimum fraction of connections are established, so that
+    /// the first window does not include the connection ramp. The admin http
+    /// server keeps answering while waiting, and response latencies are only
+    /// recorded once ready.
+    fn wait_ready(&mut self, launched: Instant) {
+        let (fraction, timeout) = match self.config.as_ref() {
+            Some(config) => match config.general().min_ready_fraction() {
+                Some(fraction) => (fraction, config.general().ready_timeout()),
+                None => return,
+            },
+            None => return,
+        };
+        let required = (fraction * self.expected_connections as f64).ceil();
+        let start = Instant::now();
+        let deadline = start + timeout;
+        loop {
+            rustcommon_time::refresh_clock();
+            let open = OPEN.value() as f64;
+            if open >= required {
+                info!(
+                    "ready: {} of {} connections established after {} ms",
+                    open,
+                    self.expected_connections,
+                    (Instant::now() - start).as_nanos() / 1_000_000
+                );
+                break;
+            }
+            if crate::signal::stopped() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    "ready timeout: {} of {} connections established, starting anyway",
+                    open, self.expected_connections
+                );
+                break;
+            }
+            let _ = self.log.flush();
+            self.serve_requests(launched);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        if let Some(ref ready) = self.ready {
+            ready.store(true, Ordering::Relaxed);
+        }
+
+        // the first window is measured from here
+        self.snapshot = Snapshot::new(self.connect_heatmap.as_ref(), self.request_heatmap.as_ref());
+    }
+
+    /// Run until the end of the test, returning the exit code for the process,
+    /// which is nonzero if the run was aborted
+    pub fn run(mut self) -> i32 {
+        let launched = Instant::now();
+        self.wait_ready(launched);
+        let server_stats = ServerStats::spawn(&self.targets);
+        let start = Instant::now();
         let mut next = Instant::now()
             + match self.config.as_ref() {
                 Some(config) => config.general().interval(),
@@ -96,90 +517,63 @@ impl Admin {
         let mut snapshot =
             Snapshot::new(self.connect_heatmap.as_ref(), self.request_heatmap.as_ref());
 
+        // while warming up, windows are reported but do not count towards the
+        // test duration
+        let mut warmup = self
+            .config
+            .as_ref()
+            .and_then(|config| config.general().warmup_hitrate());
+        let warmup_stable_windows = self
+            .config
+            .as_ref()
+            .map(|config| config.general().warmup_stable_windows())
+            .unwrap_or(0);
+        let mut stable_windows = 0;
+        let mut filling = self
+            .config
+            .as_ref()
+            .map(|config| config.general().warmup_fill_fraction().is_some())
+            .unwrap_or(false);
+
+        // without a config, as when driven by rpc-replay, latencies are
+        // recorded in microseconds
+        let unit = self
+            .config
+            .as_ref()
+            .map(|config| config.general().latency_unit())
+            .unwrap_or(LatencyUnit::Microseconds);
+        let output_format = self
+            .config
+            .as_ref()
+            .map(|config| config.general().output_format())
+            .unwrap_or_default();
+
+        // with slow-start, the request rate doubles each window until it
+        // reaches the configured ratelimit. A rate profile takes precedence.
+        let slow_start = self.config.as_ref().and_then(|config| {
+            config
+                .request()
+                .slow_start()
+                .zip(config.request().ratelimit())
+                .filter(|_| self.rate_profile.is_none() && self.sweep.is_none())
+        });
+        let mut slow_start_windows = 0;
+
+        let mut sweep_window = 0;
+        let mut sweep_response_rate = 0.0;
+        let mut sweep_levels: Vec<SweepLevel> = Vec::new();
+        let mut aborted = false;
+
         loop {
-            while Instant::now() < next {
+            if crate::signal::stopped() {
+                break;
+            }
+            while Instant::now() < next && !self.budget_complete() && !crate::signal::stopped() {
                 rustcommon_time::refresh_clock();
                 let _ = self.log.flush();
                 snapshot =
                     Snapshot::new(self.connect_heatmap.as_ref(), self.request_heatmap.as_ref());
-                if let Some(ref server) = self.server {
-                    while let Ok(Some(mut request)) = server.try_recv() {
-                        let url = request.url();
-                        let parts: Vec<&str> = url.split('?').collect();
-                        let url = parts[0];
-                        match request.method() {
-                            Method::Get => match url {
-                                "/" => {
-                                    debug!("Serving GET on index");
-                                    let _ = request.respond(Response::from_string(format!(
-                                        "Welcome to {}\nVersion: {}\n",
-                                        crate::config::NAME,
-                                        crate::config::VERSION,
-                                    )));
-                                }
-                                "/metrics" => {
-                                    debug!("Serving Prometheus compatible stats");
-                                    let _ = request
-                                        .respond(Response::from_string(self.snapshot.prometheus()));
-                                }
-                                "/metrics.json" | "/vars.json" | "/admin/metrics.json" => {
-                                    debug!("Serving machine readable stats");
-                                    let _ = request
-                                        .respond(Response::from_string(self.snapshot.json()));
-                                }
-                                "/vars" => {
-                                    debug!("Serving human readable stats");
-                                    let _ = request
-                                        .respond(Response::from_string(self.snapshot.human()));
-                                }
-                                url => {
-                                    debug!("GET on non-existent url: {}", url);
-                                    debug!("Serving machine readable stats");
-                                    let _ = request
-                                        .respond(Response::from_string(self.snapshot.json()));
-                                }
-                            },
-                            Method::Put => match request.url() {
-                                "/ratelimit/reconnect" => {
-                                    let mut content = String::new();
-                                    request.as_reader().read_to_string(&mut content).unwrap();
-                                    if let Ok(rate) = content.parse() {
-                                        if let Some(ref ratelimiter) = self.reconnect_ratelimit {
-                                            ratelimiter.set_rate(rate);
-                                            let _ = request.respond(Response::empty(200));
-                                        } else {
-                                            let _ = request.respond(Response::empty(400));
-                                        }
-                                    } else {
-                                        let _ = request.respond(Response::empty(400));
-                                    }
-                                }
-                                "/ratelimit/request" => {
-                                    let mut content = String::new();
-                                    request.as_reader().read_to_string(&mut content).unwrap();
-                                    if let Ok(rate) = content.parse() {
-                                        if let Some(ref ratelimiter) = self.request_ratelimit {
-                                            ratelimiter.set_rate(rate);
-                                            let _ = request.respond(Response::empty(200));
-                                        } else {
-                                            let _ = request.respond(Response::empty(400));
-                                        }
-                                    } else {
-                                        let _ = request.respond(Response::empty(400));
-                                    }
-                                }
-                                url => {
-                                    debug!("PUT on non-existent url: {}", url);
-                                    let _ = request.respond(Response::empty(404));
-                                }
-                            },
-                            method => {
-                                debug!("unsupported request method: {}", method);
-                                let _ = request.respond(Response::empty(404));
-                            }
-                        }
-                    }
-                }
+                self.serve_requests(launched);
                 std::thread::sleep(std::time::Duration::from_millis(10));
             }
             next += match self.config.as_ref() {
@@ -189,17 +583,40 @@ impl Admin {
 
             let window = WINDOW.value();
 
+            if let Some(ref recycle) = self.recycle {
+                recycle.fetch_add(1, Ordering::Relaxed);
+            }
+
             info!("-----");
-            info!("Window: {}", window);
+            if warmup.is_some() || filling {
+                info!("Window: warmup");
+            } else {
+                info!("Window: {}", window);
+            }
             info!(
-                "Connections: Attempts: {} Opened: {} Errors: {} Timeouts: {} Open: {}",
+                "Connections: Attempts: {} Opened: {} Errors: {} Timeouts: {} Abandoned: {} Open: {}",
                 snapshot.delta_count(&self.snapshot, CONNECT.name()),
                 snapshot.delta_count(&self.snapshot, SESSION.name()),
                 snapshot.delta_count(&self.snapshot, CONNECT_EX.name()),
                 snapshot.delta_count(&self.snapshot, CONNECT_TIMEOUT.name()),
+                snapshot.delta_count(&self.snapshot, CONNECTIONS_ABANDONED.name()),
+                OPEN.value()
+            );
+            info!(
+                "Connections: Target: {} Established: {}",
+                self.expected_connections,
                 OPEN.value()
             );
 
+            let closed_active = snapshot.delta_count(&self.snapshot, SERVER_CLOSED_ACTIVE.name());
+            let closed_idle = snapshot.delta_count(&self.snapshot, SERVER_CLOSED_IDLE.name());
+            if closed_active > 0 || closed_idle > 0 {
+                info!(
+                    "Server Closed: Active: {} Idle: {}",
+                    closed_active, closed_idle
+                );
+            }
+
             let request_rate = snapshot.rate(&self.snapshot, REQUEST.name());
             let response_rate = snapshot.rate(&self.snapshot, RESPONSE.name());
             let connect_rate = snapshot.rate(&self.snapshot, CONNECT.name());
@@ -208,6 +625,84 @@ impl Admin {
                 "Rate: Request: {:.2} rps Response: {:.2} rps Connect: {:.2} cps",
                 request_rate, response_rate, connect_rate
             );
+            // goodput only counts the bytes of successful responses, while
+            // throughput counts every byte received
+            info!(
+                "Bandwidth: Goodput: {:.2} B/s Throughput: {:.2} B/s",
+                snapshot.rate(&self.snapshot, RESPONSE_BYTE.name()),
+                snapshot.rate(&self.snapshot, SESSION_RECV_BYTE.name())
+            );
+
+            let interval = match self.config.as_ref() {
+                Some(config) => config.general().interval().as_secs_f64(),
+                None => 60.0,
+            };
+            for target in &self.target_stats {
+                let (requests, responses) = target.take();
+                info!(
+                    "Rate: Target: {} Request: {:.2} rps Response: {:.2} rps",
+                    target.name(),
+                    requests as f64 / interval,
+                    responses as f64 / interval
+                );
+            }
+            for workload in &self.workload_stats {
+                let (requests, responses) = workload.take();
+                info!(
+                    "Rate: Workload: {} Request: {:.2} rps Response: {:.2} rps",
+                    workload.name(),
+                    requests as f64 / interval,
+                    responses as f64 / interval
+                );
+            }
+            // per command stats are also served over http, as gauges
+            let mut command_stats = Vec::new();
+            for stats in &self.command_stats {
+                for window in stats.take() {
+                    info!(
+                        "Rate: Command: {} Request: {:.2} rps Response: {:.2} rps Error: {:.2} rps",
+                        window.label,
+                        window.requests as f64 / interval,
+                        window.responses as f64 / interval,
+                        window.errors as f64 / interval
+                    );
+                    if window.get {
+                        info!(
+                            "Hit-rate: Command: {}: {:.2} %",
+                            window.label,
+                            window.hitrate()
+                        );
+                        command_stats.push((format!("{}/hitrate", window.key), window.hitrate()));
+                    }
+                    info!(
+                        "Response Latency ({}): Command: {}: {}",
+                        unit.suffix(),
+                        window.label,
+                        window
+                            .percentiles
+                            .iter()
+                            .map(|(label, value)| format!("{}: {}", label, value))
+                            .collect::<Vec<String>>()
+                            .join(" ")
+                    );
+                    for (label, value) in [
+                        ("request", window.requests),
+                        ("response", window.responses),
+                        ("error", window.errors),
+                    ] {
+                        command_stats.push((
+                            format!("{}/{}_rate", window.key, label),
+                            value as f64 / interval,
+                        ));
+                    }
+                    for (label, value) in &window.percentiles {
+                        command_stats.push((
+                            format!("{}/response_latency/{}", window.key, label),
+                            *value as f64,
+                        ));
+                    }
+                }
+            }
 
             let request_success =
                 snapshot.success_rate(&self.snapshot, REQUEST.name(), REQUEST_EX.name());
@@ -221,10 +716,31 @@ impl Admin {
                 request_success, response_success, connect_success
             );
 
+            if let Some(ref mut abort) = self.error_abort {
+                let error_rate = snapshot.error_rate(&self.snapshot);
+                if abort.observe(error_rate) {
+                    error!(
+                        "aborting: error rate: {:.2} % exceeded: {:.2} % for {} consecutive windows",
+                        error_rate, abort.threshold, abort.windows
+                    );
+                    aborted = true;
+                    break;
+                }
+            }
+
             let hit_rate =
                 snapshot.hitrate(&self.snapshot, REQUEST_GET.name(), RESPONSE_HIT.name());
 
             info!("Hit-rate: {:.2} %", hit_rate);
+            // batched gets where only some of the keys were found
+            let partial_hit_rate = snapshot.hitrate(
+                &self.snapshot,
+                REQUEST_GET.name(),
+                RESPONSE_PARTIAL_HIT.name(),
+            );
+            if partial_hit_rate > 0.0 {
+                info!("Partial Hit-rate: {:.2} %", partial_hit_rate);
+            }
 
             if let Some(ref heatmap) = self.connect_heatmap {
                 let p25 = heatmap.percentile(25.0).unwrap_or(0);
@@ -234,8 +750,57 @@ impl Admin {
                 let p99 = heatmap.percentile(99.0).unwrap_or(0);
                 let p999 = heatmap.percentile(99.9).unwrap_or(0);
                 let p9999 = heatmap.percentile(99.99).unwrap_or(0);
-                info!("Connect Latency (us): p25: {} p50: {} p75: {} p90: {} p99: {} p999: {} p9999: {}",
-                    p25, p50, p75, p90, p99, p999, p9999
+                info!("Connect Latency ({}): p25: {} p50: {} p75: {} p90: {} p99: {} p999: {} p9999: {}",
+                    unit.suffix(), p25, p50, p75, p90, p99, p999, p9999
+                );
+            }
+
+            if let Some(ref heatmap) = self.delivery_heatmap {
+                info!(
+                    "Subscribe: Messages: {:.2} mps",
+                    snapshot.rate(&self.snapshot, SUBSCRIBE_MESSAGE.name())
+                );
+                let p50 = heatmap.percentile(50.0).unwrap_or(0);
+                let p90 = heatmap.percentile(90.0).unwrap_or(0);
+                let p99 = heatmap.percentile(99.0).unwrap_or(0);
+                let p999 = heatmap.percentile(99.9).unwrap_or(0);
+                info!(
+                    "Delivery Latency ({}): p50: {} p90: {} p99: {} p999: {}",
+                    unit.suffix(),
+                    p50,
+                    p90,
+                    p99,
+                    p999
+                );
+            }
+
+            if let Some(ref heatmap) = self.key_update_heatmap {
+                info!(
+                    "TLS: Key Updates: {:.2} /s",
+                    snapshot.rate(&self.snapshot, TLS_KEY_UPDATE.name())
+                );
+                let p50 = heatmap.percentile(50.0).unwrap_or(0);
+                let p90 = heatmap.percentile(90.0).unwrap_or(0);
+                let p99 = heatmap.percentile(99.0).unwrap_or(0);
+                let p999 = heatmap.percentile(99.9).unwrap_or(0);
+                info!(
+                    "Key Update Latency ({}): p50: {} p90: {} p99: {} p999: {}",
+                    unit.suffix(),
+                    p50,
+                    p90,
+                    p99,
+                    p999
+                );
+            }
+
+            if let Some(ref heatmap) = self.lifetime_heatmap {
+                let p50 = heatmap.percentile(50.0).unwrap_or(0);
+                let p90 = heatmap.percentile(90.0).unwrap_or(0);
+                let p99 = heatmap.percentile(99.0).unwrap_or(0);
+                let p999 = heatmap.percentile(99.9).unwrap_or(0);
+                info!(
+                    "Connection Lifetime (ms): p50: {} p90: {} p99: {} p999: {}",
+                    p50, p90, p99, p999
                 );
             }
 
@@ -247,11 +812,267 @@ impl Admin {
                 let p99 = heatmap.percentile(99.0).unwrap_or(0);
                 let p999 = heatmap.percentile(99.9).unwrap_or(0);
                 let p9999 = heatmap.percentile(99.99).unwrap_or(0);
-                info!("Response Latency (us): p25: {} p50: {} p75: {} p90: {} p99: {} p999: {} p9999: {}",
-                    p25, p50, p75, p90, p99, p999, p9999
+                info!("Response Latency ({}): p25: {} p50: {} p75: {} p90: {} p99: {} p999: {} p9999: {}",
+                    unit.suffix(), p25, p50, p75, p90, p99, p999, p9999
+                );
+                info!(
+                    "Tail Amplification: p99/p50: {} p999/p50: {}",
+                    tail_ratio(p99, p50),
+                    tail_ratio(p999, p50)
                 );
             }
 
+            // a few slow responses are expected, but when latency_max is
+            // exceeded window after window the tail percentiles are clipped.
+            // The heatmap is not widened during the run, as every worker holds
+            // it and the clipped samples can not be recovered, so the warning
+            // asks for a larger latency_max instead.
+            let too_high = self
+                .request_heatmap
+                .as_ref()
+                .map(|heatmap| heatmap.too_high())
+                .unwrap_or(0);
+            let clipped = too_high.saturating_sub(self.too_high);
+            self.too_high = too_high;
+            RESPONSE_LATENCY_CLIPPED.add(clipped as _);
+            if clipped > 0 {
+                self.clipped_windows += 1;
+                if self.clipped_windows == CLIPPED_WARN_WINDOWS {
+                    if let Some(ref config) = self.config {
+                        warn!(
+                            "response latency has exceeded latency_max: {} {} for {} consecutive windows, tail percentiles are clipped. Increase latency_max",
+                            config.general().latency_max(),
+                            unit.suffix(),
+                            CLIPPED_WARN_WINDOWS
+                        );
+                    }
+                }
+            } else {
+                self.clipped_windows = 0;
+            }
+
+            if let Some(ref phases) = self.phases {
+                for (phase, heatmap) in phases.heatmaps() {
+                    // the handshake phase is empty without tls
+                    if heatmap.percentile(50.0).is_err() {
+                        continue;
+                    }
+                    let p50 = heatmap.percentile(50.0).unwrap_or(0);
+                    let p90 = heatmap.percentile(90.0).unwrap_or(0);
+                    let p99 = heatmap.percentile(99.0).unwrap_or(0);
+                    let p999 = heatmap.percentile(99.9).unwrap_or(0);
+                    info!(
+                        "Phase Latency ({}): {}: p50: {} p90: {} p99: {} p999: {}",
+                        unit.suffix(),
+                        phase,
+                        p50,
+                        p90,
+                        p99,
+                        p999
+                    );
+                }
+            }
+
+            let mut value_percentiles = Vec::new();
+            for (length, heatmap) in &self.value_heatmaps {
+                value_percentiles.push((*length, heatmap_percentiles(heatmap)));
+                let p50 = heatmap.percentile(50.0).unwrap_or(0);
+                let p90 = heatmap.percentile(90.0).unwrap_or(0);
+                let p99 = heatmap.percentile(99.0).unwrap_or(0);
+                let p999 = heatmap.percentile(99.9).unwrap_or(0);
+                info!(
+                    "Response Latency ({}): Value: {} bytes: p50: {} p90: {} p99: {} p999: {}",
+                    unit.suffix(),
+                    length,
+                    p50,
+                    p90,
+                    p99,
+                    p999
+                );
+            }
+
+            for (sequence, heatmap) in &self.sequence_heatmaps {
+                let p50 = heatmap.percentile(50.0).unwrap_or(0);
+                let p90 = heatmap.percentile(90.0).unwrap_or(0);
+                let p99 = heatmap.percentile(99.0).unwrap_or(0);
+                let p999 = heatmap.percentile(99.9).unwrap_or(0);
+                info!(
+                    "Sequence Latency ({}): {}: p50: {} p90: {} p99: {} p999: {}",
+                    unit.suffix(),
+                    sequence,
+                    p50,
+                    p90,
+                    p99,
+                    p999
+                );
+            }
+
+            for (target, heatmap) in &self.wait_heatmaps {
+                let p50 = heatmap.percentile(50.0).unwrap_or(0);
+                let p90 = heatmap.percentile(90.0).unwrap_or(0);
+                let p99 = heatmap.percentile(99.0).unwrap_or(0);
+                let p999 = heatmap.percentile(99.9).unwrap_or(0);
+                info!(
+                    "Wait Latency ({}): {}: p50: {} p90: {} p99: {} p999: {}",
+                    unit.suffix(),
+                    target,
+                    p50,
+                    p90,
+                    p99,
+                    p999
+                );
+            }
+
+            snapshot.server_stats = server_stats
+                .as_ref()
+                .map(|stats| stats.latest())
+                .unwrap_or_default();
+            snapshot.command_stats = command_stats;
+            snapshot.value_percentiles = value_percentiles;
+            snapshot.histogram = self
+                .latency_histogram
+                .as_ref()
+                // the exemplar histogram is always in microseconds
+                .map(|h| h.openmetrics("response_latency_us"));
+            for (key, value) in &snapshot.server_stats {
+                info!("Server: {}: {}", key, value);
+            }
+
+            if let Some(ref mut stats) = self.self_stats {
+                stats.report();
+            }
+
+            if let Some(ref mut samples) = self.latency_samples {
+                samples.flush();
+            }
+
+            if let (Some((initial, target)), Some(ratelimiter)) =
+                (slow_start, self.request_ratelimit.as_ref())
+            {
+                slow_start_windows += 1;
+                let rate = slow_start_rate(initial, target, slow_start_windows);
+                if rate != slow_start_rate(initial, target, slow_start_windows - 1) {
+                    info!("Slow-start: request rate: {}", rate);
+                    ratelimiter.set_rate(rate as u64);
+                }
+            }
+
+            if let (Some(profile), Some(ratelimiter)) =
+                (self.rate_profile.as_ref(), self.request_ratelimit.as_ref())
+            {
+                let rate = profile.rate(start.elapsed().as_secs_f64());
+                info!("Rate profile: request rate: {}", rate);
+                ratelimiter.set_rate(rate);
+            }
+
+            // warmup runs at the rate of the first level, which only starts
+            // once warmup is complete, so that every level is measured for
+            // its full number of windows
+            if let (Some(sweep), Some(ratelimiter), None, false) = (
+                self.sweep.as_ref(),
+                self.request_ratelimit.as_ref(),
+                warmup,
+                filling,
+            ) {
+                if let Some(rate) = sweep.rate(sweep_window) {
+                    sweep_window += 1;
+                    sweep_response_rate += response_rate;
+                    if sweep_window % sweep.windows() == 0 {
+                        // the latency window spans one level, so the
+                        // percentiles only cover the level which just ended
+                        let percentile = |p| {
+                            self.request_heatmap
+                                .as_ref()
+                                .map(|h| h.percentile(p).unwrap_or(0))
+                                .unwrap_or(0)
+                        };
+                        sweep_levels.push(SweepLevel {
+                            rate,
+                            response_rate: sweep_response_rate / sweep.windows() as f64,
+                            p50: percentile(50.0),
+                            p90: percentile(90.0),
+                            p99: percentile(99.0),
+                            p999: percentile(99.9),
+                        });
+                        sweep_response_rate = 0.0;
+                        if let Some(rate) = sweep.rate(sweep_window) {
+                            info!("Sweep: request rate: {}", rate);
+                            ratelimiter.set_rate(rate);
+                        }
+                    }
+                }
+            }
+
+            if output_format == OutputFormat::Json {
+                println!(
+                    "{}",
+                    snapshot.window_json(&self.snapshot, window, warmup.is_some() || filling, unit)
+                );
+            }
+
+            if let Some(ref statsd) = self.statsd {
+                statsd.send(&snapshot.statsd(&self.snapshot, statsd.prefix(), unit));
+            }
+
+            if let Some(ref sqlite) = self.sqlite {
+                sqlite.insert(
+                    window,
+                    warmup.is_some() || filling,
+                    start.elapsed().as_secs_f64(),
+                    &snapshot.deltas(&self.snapshot),
+                    &snapshot.request_percentiles,
+                );
+            }
+
+            // write out the window now, rather than when the next window
+            // starts, so that the output can be followed as it is written
+            let _ = self.log.flush();
+
+            if self.budget_complete() {
+                info!("Request budget complete");
+                break;
+            }
+
+            if let Some(target) = warmup {
+                if hit_rate >= target * 100.0 {
+                    stable_windows += 1;
+                } else {
+                    stable_windows = 0;
+                }
+                if stable_windows >= warmup_stable_windows {
+                    info!("Warmup complete");
+                    warmup = None;
+                } else {
+                    info!(
+                        "Warmup: target hit-rate: {:.2} % stable windows: {}/{}",
+                        target * 100.0,
+                        stable_windows,
+                        warmup_stable_windows
+                    );
+                }
+                self.snapshot = snapshot.clone();
+                continue;
+            }
+
+            if filling {
+                let (filled, target) = self.filled();
+                let percent = 100.0 * filled as f64 / target.max(1) as f64;
+                if filled >= target {
+                    info!(
+                        "Warmup complete: filled {} of {} keys ({:.2} %)",
+                        filled, target, percent
+                    );
+                    filling = false;
+                } else {
+                    info!(
+                        "Warmup: filled {} of {} keys ({:.2} %)",
+                        filled, target, percent
+                    );
+                }
+                self.snapshot = snapshot.clone();
+                continue;
+            }
+
             WINDOW.increment();
             self.snapshot = snapshot.clone();
 
@@ -261,30 +1082,187 @@ impl Admin {
                 .and_then(|config| config.general().windows())
             {
                 if window >= max_window as u64 {
-                    if let Some(ref heatmap) = self.request_waterfall {
-                        if let Some(file) = self.config.as_ref().and_then(|c| c.waterfall().file())
-                        {
-                            let config = self.config.as_ref().unwrap();
-                            let scale = config.waterfall().scale();
-                            let palette = config.waterfall().palette();
-
-                            WaterfallBuilder::new(&file)
-                                .label(100, "100ns")
-                                .label(1000, "1us")
-                                .label(10000, "10us")
-                                .label(100000, "100us")
-                                .label(1000000, "1ms")
-                                .label(10000000, "10ms")
-                                .label(100000000, "100ms")
-                                .scale(scale)
-                                .palette(palette)
-                                .build(&heatmap.load());
-                        }
-                    }
+                    self.write_waterfall();
                     break;
                 }
             }
         }
+
+        if crate::signal::stopped() {
+            info!("-----");
+            info!("Stopped by signal after {} windows", WINDOW.value());
+            self.write_waterfall();
+        }
+
+        if aborted {
+            info!("-----");
+            info!("Aborted after {} windows", WINDOW.value());
+            self.write_waterfall();
+        }
+
+        if let Some(ref mut samples) = self.latency_samples {
+            samples.flush();
+        }
+
+        if RESPONSE_LATENCY_CLIPPED.value() > 0 {
+            info!("-----");
+            info!(
+                "Response Latency Clipped: {} responses exceeded latency_max",
+                RESPONSE_LATENCY_CLIPPED.value()
+            );
+        }
+
+        if CONNECT_EX.value() > 0 {
+            info!("-----");
+            info!(
+                "Connect Errors: Refused: {} Timed Out: {} Address Unavailable: {} Unreachable: {} Reset: {} No Files: {} TLS: {} Other: {}",
+                CONNECT_EX_REFUSED.value(),
+                CONNECT_EX_TIMEDOUT.value(),
+                CONNECT_EX_ADDRNOTAVAIL.value(),
+                CONNECT_EX_UNREACHABLE.value(),
+                CONNECT_EX_RESET.value(),
+                CONNECT_EX_NOFILE.value(),
+                CONNECT_EX_TLS.value(),
+                CONNECT_EX_OTHER.value()
+            );
+        }
+
+        if !sweep_levels.is_empty() {
+            info!("-----");
+            info!("Sweep summary:");
+            for line in crate::sweep::summary(&sweep_levels, unit.suffix()) {
+                info!("{}", line);
+            }
+        }
+
+        if let Some((ref baseline, ref samples)) = self.baseline {
+            info!("-----");
+            match crate::baseline::compare(baseline, &samples.samples()) {
+                Some(comparison) => {
+                    info!(
+                        "Baseline: {}: p-value: {:.4} effect size: {:.3} median (ns): baseline: {} current: {}",
+                        comparison.verdict(),
+                        comparison.p_value,
+                        comparison.effect_size(),
+                        comparison.baseline_median,
+                        comparison.current_median
+                    );
+                }
+                None => {
+                    info!("Baseline: no responses to compare");
+                }
+            }
+        }
+
+        if let Some(ref sizes) = self.response_sizes {
+            info!("-----");
+            info!("Response Latency by size:");
+            for line in sizes.summary() {
+                info!("{}", line);
+            }
+        }
+
+        if let (Some((file, latency)), Some(config)) = (self.report.as_ref(), self.config.as_ref())
+        {
+            let report = Report::new(config, start.elapsed(), latency);
+            if let Err(e) = report.write(file) {
+                error!("failed to write report: {}: {}", file, e);
+            } else {
+                info!("wrote report to: {}", file);
+            }
+        }
+
+        if let (Some(file), Some(heatmap)) = (self.cdf_plot.as_ref(), self.request_heatmap.as_ref())
+        {
+            if let Err(e) = crate::cdf::write(heatmap, unit, file) {
+                error!("failed to write cdf plot: {}: {}", file, e);
+            } else {
+                info!("wrote cdf plot to: {}", file);
+            }
+        }
+
+        let _ = self.log.flush();
+
+        if aborted {
+            2
+        } else {
+            0
+        }
+    }
+}
+
+impl Admin {
+    /// Render the request waterfall to the configured file, if any
+    fn write_waterfall(&self) {
+        if let Some(ref heatmap) = self.request_waterfall {
+            if let Some(file) = self.config.as_ref().and_then(|c| c.waterfall().file()) {
+                let config = self.config.as_ref().unwrap();
+                let scale = config.waterfall().scale();
+                let palette = config.waterfall().palette();
+
+                WaterfallBuilder::new(&file)
+                    .label(100, "100ns")
+                    .label(1000, "1us")
+                    .label(10000, "10us")
+                    .label(100000, "100us")
+                    .label(1000000, "1ms")
+                    .label(10000000, "10ms")
+                    .label(100000000, "100ms")
+                    .scale(scale)
+                    .palette(palette)
+                    .build(&heatmap.load());
+            }
+        }
+    }
+}
+
+/// Tracks consecutive windows where the error rate exceeds a threshold, used to
+/// fail fast when a server is clearly broken
+struct ErrorAbort {
+    threshold: f64,
+    windows: usize,
+    consecutive: usize,
+}
+
+impl ErrorAbort {
+    fn new(threshold: f64, windows: usize) -> Self {
+        Self {
+            threshold,
+            windows,
+            consecutive: 0,
+        }
+    }
+
+    /// Record the error rate for a window, returning true if the run should be
+    /// aborted
+    fn observe(&mut self, error_rate: f64) -> bool {
+        if error_rate > self.threshold {
+            self.consecutive += 1;
+        } else {
+            self.consecutive = 0;
+        }
+        self.consecutive >= self.windows
+    }
+}
+
+/// The slow-start request rate after the given number of windows, doubling
+/// from the initial rate and capped at the target.
+fn slow_start_rate(initial: usize, target: usize, windows: u32) -> usize {
+    let rate = 2_usize
+        .checked_pow(windows)
+        .and_then(|multiplier| initial.checked_mul(multiplier))
+        .unwrap_or(usize::MAX);
+    std::cmp::min(rate, target)
+}
+
+/// The ratio of a tail percentile to the median, which shows how much the
+/// tail is amplified independent of the absolute latency. There is no ratio
+/// while the median is zero.
+fn tail_ratio(tail: u64, p50: u64) -> String {
+    if p50 == 0 {
+        "n/a".to_string()
+    } else {
+        format!("{:.2}", tail as f64 / p50 as f64)
     }
 }
 
@@ -295,6 +1273,11 @@ pub struct Snapshot {
     timestamp: Instant,
     connect_percentiles: Vec<(String, u64)>,
     request_percentiles: Vec<(String, u64)>,
+    /// the response latency percentiles for each value length
+    value_percentiles: Vec<(usize, Vec<(String, u64)>)>,
+    server_stats: Vec<(String, f64)>,
+    command_stats: Vec<(String, f64)>,
+    histogram: Option<String>,
 }
 
 #[derive(Clone)]
@@ -331,38 +1314,20 @@ impl Snapshot {
             }
         }
 
-        let percentiles = vec![
-            ("p25", 25.0),
-            ("p50", 50.0),
-            ("p75", 75.0),
-            ("p90", 90.0),
-            ("p99", 99.0),
-            ("p999", 99.9),
-            ("p9999", 99.99),
-        ];
-
-        let mut connect_percentiles = Vec::new();
-        if let Some(heatmap) = connect_heatmap {
-            for (label, value) in &percentiles {
-                connect_percentiles
-                    .push((label.to_string(), heatmap.percentile(*value).unwrap_or(0)));
-            }
-        }
-
-        let mut request_percentiles = Vec::new();
-        if let Some(heatmap) = request_heatmap {
-            for (label, value) in &percentiles {
-                request_percentiles
-                    .push((label.to_string(), heatmap.percentile(*value).unwrap_or(0)));
-            }
-        }
-
         Self {
             counters,
             gauges,
             timestamp: Instant::now(),
-            connect_percentiles,
-            request_percentiles,
+            connect_percentiles: connect_heatmap
+                .map(|heatmap| heatmap_percentiles(heatmap))
+                .unwrap_or_default(),
+            request_percentiles: request_heatmap
+                .map(|heatmap| heatmap_percentiles(heatmap))
+                .unwrap_or_default(),
+            value_percentiles: Vec::new(),
+            server_stats: Vec::new(),
+            command_stats: Vec::new(),
+            histogram: None,
         }
     }
 
@@ -372,10 +1337,22 @@ impl Snapshot {
         this - other
     }
 
+    /// The count of each counter within the window between the two snapshots
+    fn deltas(&self, other: &Self) -> Vec<(&'static str, u64)> {
+        self.counters
+            .keys()
+            .map(|counter| (*counter, self.delta_count(other, *counter)))
+            .collect()
+    }
+
     fn rate(&self, other: &Self, counter: &'static str) -> f64 {
         let delta = self.delta_count(other, counter) as f64;
         let time = (self.timestamp - other.timestamp).as_secs_f64();
-        delta / time
+        if time > 0.0 {
+            delta / time
+        } else {
+            0.0
+        }
     }
 
     fn success_rate(&self, other: &Self, total: &'static str, error: &'static str) -> f64 {
@@ -388,6 +1365,20 @@ impl Snapshot {
         }
     }
 
+    /// The percentage of requests and connects in the window which errored
+    fn error_rate(&self, other: &Self) -> f64 {
+        let errors = self.delta_count(other, REQUEST_EX.name())
+            + self.delta_count(other, RESPONSE_EX.name())
+            + self.delta_count(other, CONNECT_EX.name());
+        let total =
+            self.delta_count(other, REQUEST.name()) + self.delta_count(other, CONNECT.name());
+        if total > 0 {
+            100.0 * errors as f64 / total as f64
+        } else {
+            0.0
+        }
+    }
+
     fn hitrate(&self, other: &Self, total: &'static str, hit: &'static str) -> f64 {
         let total = self.rate(other, total);
         let hit = self.rate(other, hit);
@@ -398,6 +1389,75 @@ impl Snapshot {
         }
     }
 
+    /// Produce StatsD lines for the window between the two snapshots. Counters
+    /// are reported as deltas and latencies are converted from the recording
+    /// unit to milliseconds.
+    pub fn statsd(&self, other: &Self, prefix: &str, unit: LatencyUnit) -> Vec<String> {
+        let mut data = Vec::new();
+        for counter in self.counters.keys() {
+            data.push(format!(
+                "{}.{}:{}|c",
+                prefix,
+                counter,
+                self.delta_count(other, *counter)
+            ));
+        }
+        for (gauge, entry) in &self.gauges {
+            data.push(format!("{}.{}:{}|g", prefix, gauge, entry.value));
+        }
+        for (label, entry) in &self.connect_percentiles {
+            data.push(format!(
+                "{}.connect_latency.{}:{:.3}|ms",
+                prefix,
+                label,
+                (*entry * unit.nanos()) as f64 / 1_000_000.0
+            ));
+        }
+        for (label, entry) in &self.request_percentiles {
+            data.push(format!(
+                "{}.response_latency.{}:{:.3}|ms",
+                prefix,
+                label,
+                (*entry * unit.nanos()) as f64 / 1_000_000.0
+            ));
+        }
+        data.sort();
+        data
+    }
+
+    /// One line of JSON for the window between the two snapshots, with the
+    /// change in each counter and the response latency in nanoseconds
+    pub fn window_json(
+        &self,
+        other: &Self,
+        window: u64,
+        warmup: bool,
+        unit: LatencyUnit,
+    ) -> String {
+        let mut counters = serde_json::Map::new();
+        let mut deltas = self.deltas(other);
+        deltas.sort();
+        for (counter, delta) in deltas {
+            counters.insert(counter.to_string(), delta.into());
+        }
+        let mut latency = serde_json::Map::new();
+        for (label, value) in &self.request_percentiles {
+            if matches!(label.as_str(), "p50" | "p99" | "p999") {
+                latency.insert(label.clone(), (value * unit.nanos()).into());
+            }
+        }
+        let mut content = serde_json::Map::new();
+        content.insert("window".to_string(), window.into());
+        content.insert("warmup".to_string(), warmup.into());
+        content.insert(
+            "request_rate".to_string(),
+            self.rate(other, REQUEST.name()).into(),
+        );
+        content.insert("counters".to_string(), counters.into());
+        content.insert("response_latency_ns".to_string(), latency.into());
+        serde_json::Value::Object(content).to_string()
+    }
+
     pub fn human(&self) -> String {
         let mut data = Vec::new();
         for (counter, entry) in &self.counters {
@@ -412,6 +1472,17 @@ impl Snapshot {
         for (label, entry) in &self.request_percentiles {
             data.push(format!("response_latency/{}: {}", label, entry));
         }
+        for (length, percentiles) in &self.value_percentiles {
+            for (label, entry) in percentiles {
+                data.push(format!(
+                    "response_latency/value_{}/{}: {}",
+                    length, label, entry
+                ));
+            }
+        }
+        for (label, entry) in self.server_stats.iter().chain(&self.command_stats) {
+            data.push(format!("{}: {}", label, entry));
+        }
         data.sort();
         let mut content = data.join("\n");
         content += "\n";
@@ -434,6 +1505,17 @@ impl Snapshot {
         for (label, entry) in &self.request_percentiles {
             data.push(format!("\"response_latency/{}\": {}", label, entry));
         }
+        for (length, percentiles) in &self.value_percentiles {
+            for (label, entry) in percentiles {
+                data.push(format!(
+                    "\"response_latency/value_{}/{}\": {}",
+                    length, label, entry
+                ));
+            }
+        }
+        for (label, entry) in self.server_stats.iter().chain(&self.command_stats) {
+            data.push(format!("\"{}\": {}", label, entry));
+        }
         data.sort();
         let body = data.join(",");
         let mut content = head;
@@ -467,20 +1549,30 @@ impl Snapshot {
                 data.push(format!("# TYPE {} gauge\n{} {}", gauge, gauge, entry.value));
             }
         }
-        for (percentile, entry) in &self.connect_percentiles {
-            let label = "connect_latency";
-            data.push(format!(
-                "# TYPE {} gauge\n{}{{percentile=\"{}\"}} {}",
-                label, label, percentile, entry
+        if !self.connect_percentiles.is_empty() {
+            data.push(prometheus_percentiles(
+                "connect_latency",
+                &self.connect_percentiles,
             ));
         }
-        for (percentile, entry) in &self.request_percentiles {
-            let label = "response_latency";
-            data.push(format!(
-                "# TYPE {} gauge\n{}{{percentile=\"{}\"}} {}",
-                label, label, percentile, entry
+        if !self.request_percentiles.is_empty() {
+            data.push(prometheus_percentiles(
+                "response_latency",
+                &self.request_percentiles,
+            ));
+        }
+        for (length, percentiles) in &self.value_percentiles {
+            data.push(prometheus_percentiles(
+                &format!("response_latency_value_{}", length),
+                percentiles,
             ));
         }
+        for (label, entry) in self.server_stats.iter().chain(&self.command_stats) {
+            data.push(format!("# TYPE {} gauge\n{} {}", label, label, entry));
+        }
+        if let Some(ref histogram) = self.histogram {
+            data.push(histogram.clone());
+        }
         data.sort();
         let mut content = data.join("\n");
         content += "\n";
@@ -488,3 +1580,158 @@ impl Snapshot {
         parts.join("_")
     }
 }
+
+/// The percentiles reported for each latency heatmap
+const PERCENTILES: &[(&str, f64)] = &[
+    ("p25", 25.0),
+    ("p50", 50.0),
+    ("p75", 75.0),
+    ("p90", 90.0),
+    ("p99", 99.0),
+    ("p999", 99.9),
+    ("p9999", 99.99),
+];
+
+fn heatmap_percentiles(heatmap: &AtomicHeatmap<u64, AtomicU64>) -> Vec<(String, u64)> {
+    PERCENTILES
+        .iter()
+        .map(|(label, value)| (label.to_string(), heatmap.percentile(*value).unwrap_or(0)))
+        .collect()
+}
+
+/// Format latency percentiles as a single gauge family, with the `quantile`
+/// label which Prometheus expects alongside the `percentile` label used by the
+/// other outputs. The type may only be declared once per family.
+fn prometheus_percentiles(name: &str, percentiles: &[(String, u64)]) -> String {
+    let mut lines = vec![format!("# TYPE {} gauge", name)];
+    for (percentile, value) in percentiles {
+        match quantile(percentile) {
+            Some(quantile) => lines.push(format!(
+                "{}{{percentile=\"{}\",quantile=\"{}\"}} {}",
+                name, percentile, quantile, value
+            )),
+            None => lines.push(format!(
+                "{}{{percentile=\"{}\"}} {}",
+                name, percentile, value
+            )),
+        }
+    }
+    lines.join("\n")
+}
+
+/// The quantile of a reported percentile, eg: p999 is the 99.9th percentile,
+/// which is the 0.999 quantile. Rounding hides the float error of the
+/// division, and trailing zeros are trimmed.
+fn quantile(label: &str) -> Option<String> {
+    let (_, percentile) = PERCENTILES.iter().find(|(l, _)| *l == label)?;
+    let quantile = format!("{:.6}", percentile / 100.0);
+    Some(
+        quantile
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_json() {
+        let snapshot = |requests, timestamp| Snapshot {
+            counters: [(
+                "request",
+                SnapshotEntry {
+                    description: None,
+                    value: requests,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            gauges: HashMap::new(),
+            timestamp,
+            connect_percentiles: Vec::new(),
+            request_percentiles: vec![("p50".to_string(), 3), ("p90".to_string(), 4)],
+            value_percentiles: Vec::new(),
+            server_stats: Vec::new(),
+            command_stats: Vec::new(),
+            histogram: None,
+        };
+        let start = Instant::now();
+        let previous = snapshot(100, start);
+        let current = snapshot(300, start + Duration::from_secs(2));
+
+        let line = current.window_json(&previous, 7, false, LatencyUnit::Microseconds);
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["window"], 7);
+        assert_eq!(value["warmup"], false);
+        assert_eq!(value["counters"]["request"], 200);
+        assert_eq!(value["request_rate"], 100.0);
+        assert_eq!(value["response_latency_ns"]["p50"], 3000);
+        assert!(value["response_latency_ns"].get("p90").is_none());
+    }
+
+    #[test]
+    fn value_percentiles() {
+        let mut snapshot = Snapshot::new(None, None);
+        snapshot.value_percentiles = vec![
+            (16, vec![("p50".to_string(), 3)]),
+            (1024, vec![("p50".to_string(), 7)]),
+        ];
+        let json: serde_json::Value = serde_json::from_str(&snapshot.json()).unwrap();
+        assert_eq!(json["response_latency/value_16/p50"], 3);
+        assert_eq!(json["response_latency/value_1024/p50"], 7);
+        assert!(snapshot
+            .prometheus()
+            .contains("response_latency_value_1024{percentile=\"p50\",quantile=\"0.5\"} 7"));
+        assert!(snapshot
+            .human()
+            .contains("response_latency/value_16/p50: 3"));
+    }
+
+    #[test]
+    fn prometheus_quantiles() {
+        let percentiles = vec![("p50".to_string(), 10), ("p999".to_string(), 20)];
+        assert_eq!(
+            prometheus_percentiles("response_latency", &percentiles),
+            "# TYPE response_latency gauge\n\
+            response_latency{percentile=\"p50\",quantile=\"0.5\"} 10\n\
+            response_latency{percentile=\"p999\",quantile=\"0.999\"} 20"
+        );
+
+        let quantiles: Vec<String> = PERCENTILES
+            .iter()
+            .map(|(label, _)| quantile(label).unwrap())
+            .collect();
+        assert_eq!(
+            quantiles,
+            vec!["0.25", "0.5", "0.75", "0.9", "0.99", "0.999", "0.9999"]
+        );
+        assert_eq!(quantile("p42"), None);
+    }
+
+    #[test]
+    fn slow_start_doubling() {
+        let schedule: Vec<usize> = (0..6).map(|w| slow_start_rate(100, 1000, w)).collect();
+        assert_eq!(schedule, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(slow_start_rate(1, usize::MAX, 100), usize::MAX);
+    }
+
+    #[test]
+    fn error_abort_consecutive() {
+        let mut abort = ErrorAbort::new(5.0, 2);
+        assert!(!abort.observe(10.0));
+        assert!(!abort.observe(1.0));
+        assert!(!abort.observe(10.0));
+        assert!(abort.observe(5.5));
+    }
+
+    #[test]
+    fn tail_ratios() {
+        assert_eq!(tail_ratio(400, 100), "4.00");
+        assert_eq!(tail_ratio(150, 100), "1.50");
+        assert_eq!(tail_ratio(100, 0), "n/a");
+    }
+}
@@ -0,0 +1,353 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Compares the response latencies of this run against a baseline run, loaded
+//! from a file written with `--latency-samples`. The distributions are
+//! compared with a Mann-Whitney U test, which makes no assumption about their
+//! shape, and the effect size is reported alongside so that a significant but
+//! tiny shift can be told apart from a real regression.
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex, Weak};
+
+/// The most samples kept from each run
+const CAPACITY: usize = 100_000;
+
+const BATCH_SIZE: usize = 1024;
+
+/// The p-value below which the runs are reported as different
+const SIGNIFICANCE: f64 = 0.05;
+
+/// Load the latencies of the baseline run, in nanoseconds, one per line
+pub fn load(path: &str) -> Result<Vec<u64>, Error> {
+    let content = std::fs::read_to_string(path)?;
+    let mut reservoir = Vec::new();
+    let mut seen = 0;
+    let mut rng = SmallRng::seed_from_u64(0);
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let sample = line.parse::<u64>().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("bad latency on line {}: {}", number + 1, line),
+            )
+        })?;
+        sample_into(&mut reservoir, &mut seen, &mut rng, sample);
+    }
+    if reservoir.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "baseline file has no samples",
+        ));
+    }
+    Ok(reservoir)
+}
+
+/// Add a sample to a uniform reservoir of at most `CAPACITY` samples
+fn sample_into(reservoir: &mut Vec<u64>, seen: &mut u64, rng: &mut SmallRng, sample: u64) {
+    *seen += 1;
+    if reservoir.len() < CAPACITY {
+        reservoir.push(sample);
+    } else {
+        let slot = rng.gen_range(0..*seen);
+        if slot < CAPACITY as u64 {
+            reservoir[slot as usize] = sample;
+        }
+    }
+}
+
+struct Shared {
+    samples: Vec<u64>,
+    seen: u64,
+    rng: SmallRng,
+}
+
+impl Shared {
+    fn add(&mut self, batch: &[u64]) {
+        for sample in batch {
+            sample_into(&mut self.samples, &mut self.seen, &mut self.rng, *sample);
+        }
+    }
+}
+
+type Buffer = Arc<Mutex<Vec<u64>>>;
+
+/// Keeps a uniform sample of the response latencies of this run. Each clone
+/// keeps its own buffer so that worker threads only take the shared lock once
+/// per batch. The buffers of every clone are added to the sample before it is
+/// read, so that samples still buffered by a worker are not lost.
+pub struct Reservoir {
+    shared: Arc<Mutex<Shared>>,
+    buffers: Arc<Mutex<Vec<Weak<Mutex<Vec<u64>>>>>>,
+    buffer: Buffer,
+}
+
+impl Reservoir {
+    pub fn new() -> Self {
+        Self::with_shared(
+            Arc::new(Mutex::new(Shared {
+                samples: Vec::new(),
+                seen: 0,
+                rng: SmallRng::from_entropy(),
+            })),
+            Arc::new(Mutex::new(Vec::new())),
+        )
+    }
+
+    fn with_shared(
+        shared: Arc<Mutex<Shared>>,
+        buffers: Arc<Mutex<Vec<Weak<Mutex<Vec<u64>>>>>>,
+    ) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::with_capacity(BATCH_SIZE)));
+        if let Ok(mut buffers) = buffers.lock() {
+            buffers.retain(|buffer| buffer.strong_count() > 0);
+            buffers.push(Arc::downgrade(&buffer));
+        }
+        Self {
+            shared,
+            buffers,
+            buffer,
+        }
+    }
+
+    /// Record a latency, in nanoseconds
+    pub fn record(&mut self, nanos: u64) {
+        let batch = match self.buffer.lock() {
+            Ok(mut buffer) => {
+                buffer.push(nanos);
+                if buffer.len() < BATCH_SIZE {
+                    return;
+                }
+                std::mem::replace(&mut *buffer, Vec::with_capacity(BATCH_SIZE))
+            }
+            Err(_) => return,
+        };
+        self.add(&batch);
+    }
+
+    /// Add the samples buffered by every clone to the sample
+    pub fn flush(&self) {
+        let buffers: Vec<Buffer> = match self.buffers.lock() {
+            Ok(buffers) => buffers.iter().filter_map(Weak::upgrade).collect(),
+            Err(_) => return,
+        };
+        for buffer in buffers {
+            let batch = match buffer.lock() {
+                Ok(mut buffer) => std::mem::take(&mut *buffer),
+                Err(_) => continue,
+            };
+            self.add(&batch);
+        }
+    }
+
+    fn add(&self, batch: &[u64]) {
+        if batch.is_empty() {
+            return;
+        }
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.add(batch);
+        }
+    }
+
+    /// The samples kept so far, including those still buffered
+    pub fn samples(&self) -> Vec<u64> {
+        self.flush();
+        self.shared
+            .lock()
+            .map(|shared| shared.samples.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Default for Reservoir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for Reservoir {
+    fn clone(&self) -> Self {
+        Self::with_shared(self.shared.clone(), self.buffers.clone())
+    }
+}
+
+impl Drop for Reservoir {
+    fn drop(&mut self) {
+        let batch = match self.buffer.lock() {
+            Ok(mut buffer) => std::mem::take(&mut *buffer),
+            Err(_) => return,
+        };
+        self.add(&batch);
+    }
+}
+
+/// The result of comparing the latencies of this run against the baseline
+pub struct Comparison {
+    /// the two-sided p-value of the Mann-Whitney U test
+    pub p_value: f64,
+    /// the probability that a latency from this run exceeds one from the
+    /// baseline, where 0.5 means neither run is slower
+    pub superiority: f64,
+    pub baseline_median: u64,
+    pub current_median: u64,
+}
+
+impl Comparison {
+    /// Whether the difference between the runs is significant
+    pub fn differs(&self) -> bool {
+        self.p_value < SIGNIFICANCE
+    }
+
+    /// The rank-biserial correlation, from -1 when every latency of this run
+    /// is below the baseline to 1 when every latency is above it
+    pub fn effect_size(&self) -> f64 {
+        2.0 * self.superiority - 1.0
+    }
+
+    /// A one-line verdict for the end-of-run summary
+    pub fn verdict(&self) -> &'static str {
+        if !self.differs() {
+            "no significant difference"
+        } else if self.superiority > 0.5 {
+            "slower than baseline"
+        } else {
+            "faster than baseline"
+        }
+    }
+}
+
+/// Compare the latencies of this run against the baseline, returning nothing
+/// if either has no samples
+pub fn compare(baseline: &[u64], current: &[u64]) -> Option<Comparison> {
+    if baseline.is_empty() || current.is_empty() {
+        return None;
+    }
+    let n1 = current.len() as f64;
+    let n2 = baseline.len() as f64;
+
+    // rank the pooled samples, giving tied samples their average rank
+    let mut pooled: Vec<(u64, bool)> = current
+        .iter()
+        .map(|v| (*v, true))
+        .chain(baseline.iter().map(|v| (*v, false)))
+        .collect();
+    pooled.sort_unstable_by_key(|(v, _)| *v);
+    let mut rank_sum = 0.0;
+    let mut tie_correction = 0.0;
+    let mut start = 0;
+    while start < pooled.len() {
+        let mut end = start;
+        while end < pooled.len() && pooled[end].0 == pooled[start].0 {
+            end += 1;
+        }
+        let ties = (end - start) as f64;
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum += rank * pooled[start..end].iter().filter(|(_, c)| *c).count() as f64;
+        tie_correction += ties * ties * ties - ties;
+        start = end;
+    }
+
+    let u = rank_sum - n1 * (n1 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let n = n1 + n2;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)).max(1.0));
+    let p_value = if variance > 0.0 {
+        // continuity corrected normal approximation
+        let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+        erfc(z / std::f64::consts::SQRT_2).min(1.0)
+    } else {
+        1.0
+    };
+
+    Some(Comparison {
+        p_value,
+        superiority: u / (n1 * n2),
+        baseline_median: median(baseline),
+        current_median: median(current),
+    })
+}
+
+fn median(samples: &[u64]) -> u64 {
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    sorted[sorted.len() / 2]
+}
+
+/// The complementary error function, accurate to about 1e-7
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = -x * x - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let result = t * poly.exp();
+    if x >= 0.0 {
+        result
+    } else {
+        2.0 - result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservoir() {
+        let reservoir = Reservoir::new();
+        let mut worker = reservoir.clone();
+
+        // samples still buffered by a clone are included when read
+        worker.record(1);
+        worker.record(2);
+        assert_eq!(reservoir.samples(), vec![1, 2]);
+
+        // and when the clone is dropped
+        worker.record(3);
+        std::mem::drop(worker);
+        assert_eq!(reservoir.samples(), vec![1, 2, 3]);
+
+        // beyond its capacity, the reservoir stays a fixed size
+        let mut worker = reservoir.clone();
+        for nanos in 0..2 * CAPACITY as u64 {
+            worker.record(nanos);
+        }
+        assert_eq!(reservoir.samples().len(), CAPACITY);
+    }
+
+    #[test]
+    fn same_distribution() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let baseline: Vec<u64> = (0..5000).map(|_| rng.gen_range(1000..2000)).collect();
+        let current: Vec<u64> = (0..5000).map(|_| rng.gen_range(1000..2000)).collect();
+        let comparison = compare(&baseline, &current).unwrap();
+        assert!(!comparison.differs());
+        assert!(comparison.effect_size().abs() < 0.1);
+    }
+
+    #[test]
+    fn slower() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let baseline: Vec<u64> = (0..5000).map(|_| rng.gen_range(1000..2000)).collect();
+        let current: Vec<u64> = (0..5000).map(|_| rng.gen_range(1200..2200)).collect();
+        let comparison = compare(&baseline, &current).unwrap();
+        assert!(comparison.differs());
+        assert_eq!(comparison.verdict(), "slower than baseline");
+        assert!(comparison.effect_size() > 0.2);
+
+        // identical samples are all ties
+        let comparison = compare(&[5; 10], &[5; 10]).unwrap();
+        assert_eq!(comparison.p_value, 1.0);
+        assert_eq!(comparison.superiority, 0.5);
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Renders the response latency distribution as a CDF plot. The plot is an
+//! SVG image with a logarithmic latency axis so that the tail stays visible.
+
+use crate::config_file::LatencyUnit;
+use rustcommon_heatmap::AtomicHeatmap;
+use rustcommon_heatmap::AtomicU64;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 600.0;
+const MARGIN: f64 = 60.0;
+
+/// Write a CDF plot of the latencies, recorded in the given unit, in the heatmap
+pub fn write(
+    heatmap: &AtomicHeatmap<u64, AtomicU64>,
+    unit: LatencyUnit,
+    file: &str,
+) -> Result<(), std::io::Error> {
+    let mut points = Vec::new();
+    for percentile in percentiles() {
+        if let Ok(value) = heatmap.percentile(percentile) {
+            points.push((percentile, value));
+        }
+    }
+    std::fs::write(file, svg(&points, unit))
+}
+
+/// Percentiles to plot, with extra resolution in the tail
+fn percentiles() -> Vec<f64> {
+    let mut percentiles: Vec<f64> = (1..100).map(|p| p as f64).collect();
+    percentiles.extend_from_slice(&[99.9, 99.99, 99.999, 100.0]);
+    percentiles
+}
+
+/// Render the (percentile, latency) points as an SVG document
+fn svg(points: &[(f64, u64)], unit: LatencyUnit) -> String {
+    let min = points.iter().map(|(_, v)| *v).min().unwrap_or(1).max(1) as f64;
+    let max = points.iter().map(|(_, v)| *v).max().unwrap_or(1).max(1) as f64;
+    let low = min.log10().floor();
+    let mut high = max.log10().ceil();
+    if high <= low {
+        high = low + 1.0;
+    }
+
+    let x = |value: u64| {
+        let value = (value.max(1) as f64).log10();
+        MARGIN + (value - low) / (high - low) * (WIDTH - 2.0 * MARGIN)
+    };
+    let y = |percentile: f64| HEIGHT - MARGIN - percentile / 100.0 * (HEIGHT - 2.0 * MARGIN);
+
+    let mut content = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+        WIDTH, HEIGHT
+    );
+    content += &format!(
+        "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n",
+        WIDTH, HEIGHT
+    );
+
+    // latency gridlines, one per decade
+    for decade in low as u32..=high as u32 {
+        let value = 10_u64.pow(decade);
+        let px = x(value);
+        content += &format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#ddd\"/>\n",
+            px,
+            MARGIN,
+            px,
+            HEIGHT - MARGIN
+        );
+        content += &format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+            px,
+            HEIGHT - MARGIN + 20.0,
+            label(value * unit.nanos())
+        );
+    }
+
+    // percentile gridlines
+    for percentile in [0.0, 25.0, 50.0, 75.0, 100.0] {
+        let py = y(percentile);
+        content += &format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#ddd\"/>\n",
+            MARGIN,
+            py,
+            WIDTH - MARGIN,
+            py
+        );
+        content += &format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+            MARGIN - 8.0,
+            py + 4.0,
+            percentile
+        );
+    }
+
+    content += &format!(
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">Response Latency</text>\n",
+        WIDTH / 2.0,
+        HEIGHT - 15.0
+    );
+    content += &format!(
+        "<text x=\"15\" y=\"{:.1}\" text-anchor=\"middle\" transform=\"rotate(-90 15 {:.1})\">Percentile</text>\n",
+        HEIGHT / 2.0,
+        HEIGHT / 2.0
+    );
+
+    if !points.is_empty() {
+        let line: Vec<String> = points
+            .iter()
+            .map(|(p, v)| format!("{:.1},{:.1}", x(*v), y(*p)))
+            .collect();
+        content += &format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"steelblue\" stroke-width=\"2\"/>\n",
+            line.join(" ")
+        );
+    }
+
+    content += "</svg>\n";
+    content
+}
+
+/// Format a latency in nanoseconds with a human readable unit
+fn label(ns: u64) -> String {
+    if ns >= 1_000_000_000 {
+        format!("{}s", ns / 1_000_000_000)
+    } else if ns >= 1_000_000 {
+        format!("{}ms", ns / 1_000_000)
+    } else if ns >= 1_000 {
+        format!("{}us", ns / 1_000)
+    } else {
+        format!("{}ns", ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let points = vec![(50.0, 100), (99.0, 1_000), (100.0, 20_000)];
+        let output = svg(&points, LatencyUnit::Microseconds);
+        assert!(output.starts_with("<svg"));
+        assert!(output.contains("<polyline"));
+        assert!(output.contains(">100us<"));
+        assert!(output.contains(">1ms<"));
+        assert!(output.contains(">100ms<"));
+        assert!(!output.contains(">1s<"));
+
+        let output = svg(&points, LatencyUnit::Nanoseconds);
+        assert!(output.contains(">100ns<"));
+        assert!(output.contains(">100us<"));
+    }
+}
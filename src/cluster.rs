@@ -0,0 +1,267 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Slot-aware routing for Redis Cluster. Each key hashes to one of 16384
+//! slots and each slot is served by a single primary. The slot map is
+//! bootstrapped with `CLUSTER SLOTS` and updated from `MOVED` redirects.
+
+use crc::{Crc, CRC_16_XMODEM};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+pub const SLOTS: usize = 16384;
+
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
+
+/// Returns the slot for the key. If the key contains a non-empty hash tag,
+/// delimited by the first `{` and the following `}`, only the tag is hashed.
+pub fn slot(key: &[u8]) -> u16 {
+    let mut hashed = key;
+    if let Some(open) = key.iter().position(|b| *b == b'{') {
+        if let Some(len) = key[open + 1..].iter().position(|b| *b == b'}') {
+            if len > 0 {
+                hashed = &key[open + 1..open + 1 + len];
+            }
+        }
+    }
+    CRC.checksum(hashed) % SLOTS as u16
+}
+
+#[derive(Clone)]
+pub struct SlotMap {
+    slots: Vec<Option<SocketAddr>>,
+}
+
+impl SlotMap {
+    /// A map in which no slot has a known primary
+    pub fn empty() -> Self {
+        Self {
+            slots: vec![None; SLOTS],
+        }
+    }
+
+    /// Fetch the slot map from the first seed endpoint which responds
+    pub fn bootstrap(seeds: &[SocketAddr]) -> Result<Self, Error> {
+        let mut error = Error::new(ErrorKind::Other, "no seed endpoints");
+        for seed in seeds {
+            match fetch_slots(*seed) {
+                Ok(map) => return Ok(map),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// The primary serving the slot, if it is known
+    pub fn endpoint(&self, slot: u16) -> Option<SocketAddr> {
+        self.slots[slot as usize]
+    }
+
+    /// Record that the slot is now served by the endpoint
+    pub fn update(&mut self, slot: u16, endpoint: SocketAddr) {
+        self.slots[slot as usize] = Some(endpoint);
+    }
+
+    /// The distinct primaries in the map
+    pub fn endpoints(&self) -> Vec<SocketAddr> {
+        let mut endpoints = Vec::new();
+        for endpoint in self.slots.iter().flatten() {
+            if !endpoints.contains(endpoint) {
+                endpoints.push(*endpoint);
+            }
+        }
+        endpoints
+    }
+}
+
+/// Resolve an endpoint given as `host:port`, as in a redirect. The host may
+/// be a name, an IP address, or an IPv6 address in brackets.
+pub fn resolve(endpoint: &str) -> Option<SocketAddr> {
+    let (host, port) = endpoint.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    resolve_host(host, port.parse().ok()?)
+}
+
+fn resolve_host(host: &str, port: u16) -> Option<SocketAddr> {
+    (host, port).to_socket_addrs().ok()?.next()
+}
+
+fn fetch_slots(seed: SocketAddr) -> Result<SlotMap, Error> {
+    let mut stream = TcpStream::connect_timeout(&seed, Duration::from_secs(5))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(b"*2\r\n$7\r\nCLUSTER\r\n$5\r\nSLOTS\r\n")?;
+
+    let mut buf = Vec::new();
+    let mut chunk = [0; 16384];
+    loop {
+        let bytes = stream.read(&mut chunk)?;
+        if bytes == 0 {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        buf.extend_from_slice(&chunk[..bytes]);
+        if let Some((reply, _)) = parse(&buf)? {
+            return slot_map(&reply, seed.ip());
+        }
+    }
+}
+
+/// Build the slot map from a `CLUSTER SLOTS` reply. Each entry holds the
+/// first and last slot of a range, followed by the primary and then any
+/// replicas. An empty host refers to the node which was queried.
+fn slot_map(reply: &Resp, seed: IpAddr) -> Result<SlotMap, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "bad cluster slots reply");
+    let entries = match reply {
+        Resp::Array(entries) => entries,
+        Resp::Error(e) | Resp::Simple(e) => {
+            return Err(Error::new(ErrorKind::Other, e.clone()));
+        }
+        _ => return Err(invalid()),
+    };
+
+    let mut map = SlotMap::empty();
+    for entry in entries {
+        let (start, end, host, port) = match entry {
+            Resp::Array(fields) => match (fields.first(), fields.get(1), fields.get(2)) {
+                (Some(Resp::Integer(start)), Some(Resp::Integer(end)), Some(Resp::Array(node))) => {
+                    match (node.first(), node.get(1)) {
+                        (Some(Resp::Bulk(host)), Some(Resp::Integer(port))) => {
+                            (*start, *end, host, *port)
+                        }
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        };
+        if start < 0 || end >= SLOTS as i64 || start > end {
+            return Err(invalid());
+        }
+        let endpoint = if host.is_empty() {
+            SocketAddr::new(seed, port as u16)
+        } else {
+            let host = std::str::from_utf8(host).map_err(|_| invalid())?;
+            resolve_host(host, port as u16).ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, format!("failed to resolve: {}", host))
+            })?
+        };
+        for slot in start..=end {
+            map.update(slot as u16, endpoint);
+        }
+    }
+    Ok(map)
+}
+
+#[derive(Debug, PartialEq)]
+enum Resp {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Array(Vec<Resp>),
+}
+
+/// Parse a single RESP value, returning it and the number of bytes consumed,
+/// or `None` if the buffer does not yet hold a complete value
+fn parse(buf: &[u8]) -> Result<Option<(Resp, usize)>, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "bad resp value");
+    let line_end = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    if line_end == 0 {
+        return Err(invalid());
+    }
+    let line = std::str::from_utf8(&buf[1..line_end]).map_err(|_| invalid())?;
+    let consumed = line_end + 2;
+    let value = match buf[0] {
+        b'+' => Resp::Simple(line.to_string()),
+        b'-' => Resp::Error(line.to_string()),
+        b':' => Resp::Integer(line.parse().map_err(|_| invalid())?),
+        b'$' => {
+            let len: i64 = line.parse().map_err(|_| invalid())?;
+            if len < 0 {
+                return Ok(Some((Resp::Null, consumed)));
+            }
+            let end = consumed + len as usize;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+            return Ok(Some((Resp::Bulk(buf[consumed..end].to_vec()), end + 2)));
+        }
+        b'*' => {
+            let len: i64 = line.parse().map_err(|_| invalid())?;
+            if len < 0 {
+                return Ok(Some((Resp::Null, consumed)));
+            }
+            let mut values = Vec::new();
+            let mut offset = consumed;
+            for _ in 0..len {
+                match parse(&buf[offset..])? {
+                    Some((value, bytes)) => {
+                        values.push(value);
+                        offset += bytes;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            return Ok(Some((Resp::Array(values), offset)));
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(Some((value, consumed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots() {
+        // reference values from the redis cluster specification
+        assert_eq!(slot(b"123456789"), 0x31c3);
+        assert_eq!(slot(b"{user1000}.following"), slot(b"user1000"));
+        assert_eq!(slot(b"user1000"), 3443);
+        // an empty hash tag hashes the whole key
+        assert_eq!(slot(b"{}foo"), 9500);
+        assert_eq!(slot(b"foo"), 12182);
+    }
+
+    #[test]
+    fn cluster_slots() {
+        let reply = b"*2\r\n\
+            *3\r\n:0\r\n:8191\r\n*3\r\n$9\r\n127.0.0.1\r\n:7000\r\n$2\r\nid\r\n\
+            *4\r\n:8192\r\n:16383\r\n*3\r\n$0\r\n\r\n:7001\r\n$2\r\nid\r\n*3\r\n$9\r\n127.0.0.1\r\n:7002\r\n$2\r\nid\r\n";
+        assert!(parse(&reply[..reply.len() - 1]).unwrap().is_none());
+
+        let (value, consumed) = parse(reply).unwrap().unwrap();
+        assert_eq!(consumed, reply.len());
+
+        let map = slot_map(&value, "10.0.0.1".parse().unwrap()).unwrap();
+        assert_eq!(map.endpoint(0), Some("127.0.0.1:7000".parse().unwrap()));
+        assert_eq!(map.endpoint(8191), Some("127.0.0.1:7000".parse().unwrap()));
+        assert_eq!(map.endpoint(8192), Some("10.0.0.1:7001".parse().unwrap()));
+        assert_eq!(map.endpoints().len(), 2);
+    }
+
+    #[test]
+    fn hostnames() {
+        let reply = b"*1\r\n*3\r\n:0\r\n:16383\r\n*2\r\n$9\r\nlocalhost\r\n:7000\r\n";
+        let (value, _) = parse(reply).unwrap().unwrap();
+        let map = slot_map(&value, "10.0.0.1".parse().unwrap()).unwrap();
+        let endpoint = map.endpoint(0).unwrap();
+        assert!(endpoint.ip().is_loopback());
+        assert_eq!(endpoint.port(), 7000);
+
+        assert_eq!(
+            resolve("127.0.0.1:6381"),
+            Some("127.0.0.1:6381".parse().unwrap())
+        );
+        assert_eq!(resolve("[::1]:6381"), Some("[::1]:6381".parse().unwrap()));
+        assert!(resolve("localhost:6381").unwrap().ip().is_loopback());
+        assert_eq!(resolve("127.0.0.1"), None);
+    }
+}
@@ -3,7 +3,8 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use crate::codec::*;
-use crate::config::Keyspace;
+use crate::config::{Keyspace, RequestContext};
+use crate::config_file::Command;
 use crate::*;
 use crc::{Crc, CRC_32_ISO_HDLC};
 use std::io::BufRead;
@@ -16,19 +17,19 @@ use rand_distr::Alphanumeric;
 const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
 pub struct Echo {
-    config: Arc<Config>,
     rng: SmallRng,
+    validate: bool,
 }
 
 impl Echo {
     pub fn new(config: Arc<Config>) -> Self {
         Self {
-            config,
             rng: SmallRng::from_entropy(),
+            validate: config.request().validate_response(),
         }
     }
 
-    pub fn echo(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
+    pub fn echo(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) -> Vec<u8> {
         let value = rng
             .sample_iter(&Alphanumeric)
             .take(keyspace.length())
@@ -39,16 +40,39 @@ impl Echo {
         let _ = buf.write_all(&value);
         let _ = buf.write_all(&digest.finalize().to_be_bytes());
         let _ = buf.write_all(b"\r\n");
+        value
+    }
+
+    /// Check that the echoed message is the payload which was sent
+    fn validate(expected: Option<&[u8]>, message: &[u8]) -> Result<(), ParseError> {
+        if expected == Some(message) {
+            Ok(())
+        } else {
+            debug!(
+                "Response does not match request: {:?} != {:?}",
+                message, expected
+            );
+            metrics::RESPONSE_EX.increment();
+            Err(ParseError::Mismatch)
+        }
     }
 }
 
 impl Codec for Echo {
-    fn encode(&mut self, buf: &mut Session) {
-        let keyspace = self.config.choose_keyspace(&mut self.rng);
-        Self::echo(&mut self.rng, keyspace, buf)
+    fn encode(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        _command: &Command,
+        _request: &mut RequestContext,
+    ) {
+        let value = Self::echo(&mut self.rng, keyspace, buf);
+        if self.validate {
+            buf.push_expected(value);
+        }
     }
 
-    fn decode(&self, buffer: &mut Session) -> Result<(), ParseError> {
+    fn decode(&self, buffer: &mut Session) -> Result<Response, ParseError> {
         // no-copy borrow as a slice
         let buf: &[u8] = (*buffer).buffer();
 
@@ -73,8 +97,12 @@ impl Codec for Echo {
                     metrics::RESPONSE_EX.increment();
                     Err(ParseError::Error)
                 } else {
+                    if self.validate {
+                        Self::validate(buffer.expected(), message)?;
+                    }
                     let _ = buffer.consume(response_end + 2);
-                    Ok(())
+                    buffer.pop_expected();
+                    Ok(Response::default())
                 }
             }
         } else {
@@ -82,3 +110,66 @@ impl Codec for Echo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        assert_eq!(Echo::validate(Some(&b"abc"[..]), b"abc"), Ok(()));
+        assert_eq!(
+            Echo::validate(Some(&b"abc"[..]), b"abd"),
+            Err(ParseError::Mismatch)
+        );
+        assert_eq!(Echo::validate(None, b"abc"), Err(ParseError::Mismatch));
+    }
+
+    #[test]
+    fn pipeline() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "echo" }]
+            length = 16
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut codec = Echo {
+            rng: SmallRng::seed_from_u64(0),
+            validate: true,
+        };
+
+        // `cat` echoes each pipelined request back in the order it was sent
+        let stream = crate::session::PipeStream::spawn("cat").unwrap();
+        let mut session = Session::pipe_with_capacity(stream, 1024, 1024 * 1024);
+        let depth = 8;
+        for _ in 0..depth {
+            codec.encode(
+                &mut session,
+                &keyspace,
+                &keyspace.commands()[0],
+                &mut RequestContext::default(),
+            );
+        }
+        session.set_outstanding(depth);
+        while session.write_pending() > 0 {
+            session.flush().unwrap();
+        }
+
+        // each response is matched against the oldest outstanding request
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while session.outstanding() > 0 {
+            assert!(std::time::Instant::now() < deadline, "timed out");
+            match codec.decode(&mut session) {
+                Ok(_) => session.set_outstanding(session.outstanding() - 1),
+                Err(ParseError::Incomplete) => {
+                    let _ = session.fill_buf();
+                }
+                Err(e) => panic!("bad response: {:?}", e),
+            }
+        }
+        assert!(session.expected().is_none());
+        assert!(session.buffer().is_empty());
+    }
+}
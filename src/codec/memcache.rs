@@ -4,7 +4,7 @@
 
 use crate::codec::*;
 use crate::config::*;
-use crate::config_file::Verb;
+use crate::config_file::{Command, Protocol, Verb};
 use crate::*;
 use std::io::BufRead;
 use std::io::Write;
@@ -12,73 +12,158 @@ use std::io::Write;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 
+/// The fixed size header of binary protocol requests and responses
+const HEADER_LEN: usize = 24;
+const REQUEST_MAGIC: u8 = 0x80;
+const RESPONSE_MAGIC: u8 = 0x81;
+
+const OPCODE_GET: u8 = 0x00;
+const OPCODE_SET: u8 = 0x01;
+const OPCODE_ADD: u8 = 0x02;
+const OPCODE_DELETE: u8 = 0x04;
+const OPCODE_NOOP: u8 = 0x0a;
+/// A get which only responds on a hit, used for each key of a batch
+const OPCODE_GETKQ: u8 = 0x0d;
+
+const STATUS_OK: u16 = 0x00;
+const STATUS_KEY_NOT_FOUND: u16 = 0x01;
+const STATUS_KEY_EXISTS: u16 = 0x02;
+const STATUS_NOT_STORED: u16 = 0x05;
+
+pub enum Mode {
+    Text,
+    Binary,
+}
+
 pub struct Memcache {
-    config: Arc<Config>,
+    mode: Mode,
     rng: SmallRng,
 }
 
 impl Memcache {
     pub fn new(config: Arc<Config>) -> Self {
+        let mode = match config.general().protocol() {
+            Protocol::Memcache => Mode::Text,
+            Protocol::MemcacheBinary => Mode::Binary,
+            unknown => {
+                fatal!("protocol: {:?} is not a memcache protocol", unknown);
+            }
+        };
         Self {
-            config,
+            mode,
             rng: SmallRng::from_entropy(),
         }
     }
 
-    fn get(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
+    fn get(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
         let _ = buf.write_all(b"get ");
 
-        for i in 0..keyspace.batch_size() {
-            let key = keyspace.generate_key(rng);
-            let _ = buf.write_all(&key);
-            if i + 1 < keyspace.batch_size() {
-                let _ = buf.write_all(b" ");
-            }
-        }
+        let keys = keyspace.generate_keys(rng, request, keyspace.batch_size());
+        let _ = buf.write_all(&keys.join(&b' '));
 
         let _ = buf.write_all(b"\r\n");
     }
 
-    fn set(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
-        let value = keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec());
-        let ttl = keyspace.ttl();
-        let _ = buf.write_all(b"set ");
+    fn set(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        Self::store(rng, request, keyspace, buf, "set")
+    }
+
+    fn add(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        Self::store(rng, request, keyspace, buf, "add")
+    }
+
+    fn store(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+        command: &str,
+    ) {
+        let key = keyspace.generate_key(rng, request);
+        let value = keyspace
+            .generate_value(rng, request)
+            .unwrap_or_else(|| b"".to_vec());
+        let ttl = keyspace.generate_ttl(rng, request);
+        let _ = buf.write_all(command.as_bytes());
+        let _ = buf.write_all(b" ");
         let _ = buf.write_all(&key);
         let _ = buf.write_all(format!(" 0 {} {}\r\n", ttl, value.len()).as_bytes());
         let _ = buf.write_all(&value);
         let _ = buf.write_all(b"\r\n");
     }
 
-    fn delete(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
-        let _ = buf.write_all(b"delete ");
-        let _ = buf.write_all(&key);
-        let _ = buf.write_all(b"\r\n");
+    /// Write a request for the server statistics
+    pub fn stats(buf: &mut dyn Write) {
+        let _ = buf.write_all(b"stats\r\n");
     }
-}
 
-impl Codec for Memcache {
-    fn encode(&mut self, buf: &mut Session) {
-        let keyspace = self.config.choose_keyspace(&mut self.rng);
-        let command = keyspace.choose_command(&mut self.rng);
-        match command.verb() {
-            Verb::Get => {
-                metrics::REQUEST_GET.increment();
-                Self::get(&mut self.rng, keyspace, buf)
+    /// Write a `version` request, which is used as a no-op
+    pub fn version(buf: &mut dyn Write) {
+        let _ = buf.write_all(b"version\r\n");
+    }
+
+    /// Parse a complete `version` response, returning its length
+    pub fn decode_version(buf: &[u8]) -> Result<usize, ParseError> {
+        let mut windows = buf.windows(2);
+        match windows.position(|w| w == b"\r\n") {
+            Some(line_end) if buf.starts_with(b"VERSION ") => Ok(line_end + 2),
+            Some(_) => Err(ParseError::Unknown),
+            None => Err(ParseError::Incomplete),
+        }
+    }
+
+    /// Parse a complete `stats` response, returning the values for any of the
+    /// requested keys which are present and numeric.
+    pub fn decode_stats(buf: &[u8], keys: &[String]) -> Result<Vec<(String, f64)>, ParseError> {
+        let mut windows = buf.windows(5);
+        let response_end = match windows.position(|w| w == b"END\r\n") {
+            Some(response_end) => response_end,
+            None => {
+                return Err(ParseError::Incomplete);
             }
-            Verb::Set => Self::set(&mut self.rng, keyspace, buf),
-            Verb::Delete => Self::delete(&mut self.rng, keyspace, buf),
-            _ => {
-                unimplemented!()
+        };
+        let response =
+            std::str::from_utf8(&buf[0..response_end]).map_err(|_| ParseError::Unknown)?;
+
+        let mut stats = Vec::new();
+        for line in response.split("\r\n") {
+            let mut parts = line.split(' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("STAT"), Some(key), Some(value)) => {
+                    if keys.iter().any(|k| k == key) {
+                        if let Ok(value) = value.parse::<f64>() {
+                            stats.push((key.to_string(), value));
+                        }
+                    }
+                }
+                (Some(""), None, None) => {}
+                _ => {
+                    return Err(ParseError::Unknown);
+                }
             }
         }
+        Ok(stats)
     }
 
-    fn decode(&self, buffer: &mut Session) -> Result<(), ParseError> {
-        // no-copy borrow as a slice
-        let buf: &[u8] = (*buffer).buffer();
-
+    /// The length of the first complete response in the buffer, and for a get
+    /// the number of values it carries. Responses to pipelined requests arrive
+    /// in the order the requests were sent.
+    fn response_len(buf: &[u8]) -> Result<(usize, Option<usize>), ParseError> {
         for response in &[
             "STORED\r\n",
             "NOT_STORED\r\n",
@@ -89,26 +174,652 @@ impl Codec for Memcache {
         ] {
             let bytes = response.as_bytes();
             if buf.len() >= bytes.len() && &buf[0..bytes.len()] == bytes {
-                let _ = buffer.consume(bytes.len());
-                return Ok(());
+                return Ok((bytes.len(), None));
             }
         }
 
-        let mut windows = buf.windows(5);
-        if let Some(response_end) = windows.position(|w| w == b"END\r\n") {
-            let response = &buf[0..(response_end + 5)];
-            let mut start = 0;
-            let mut lines = response.windows(2);
-            while let Some(line_end) = lines.position(|w| w == b"\r\n") {
-                if response.len() >= 5 && &response[start..(start + 5)] == b"VALUE" {
-                    metrics::RESPONSE_HIT.increment();
+        // a get responds with a value for each key which is found, followed
+        // by `END`. The value is skipped by its length, as it may hold CRLF.
+        let mut pos = 0;
+        let mut values = 0;
+        loop {
+            let line_end = match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+                Some(len) => pos + len,
+                None => return Err(ParseError::Incomplete),
+            };
+            let line = &buf[pos..line_end];
+            if line == b"END" {
+                return Ok((line_end + 2, Some(values)));
+            }
+            // VALUE <key> <flags> <bytes> [<cas>]
+            let length = line
+                .strip_prefix(b"VALUE ")
+                .and_then(|header| std::str::from_utf8(header).ok())
+                .and_then(|header| header.split(' ').nth(2))
+                .and_then(|length| length.parse::<usize>().ok())
+                .ok_or(ParseError::Unknown)?;
+            pos = line_end + 2 + length + 2;
+            if buf.len() < pos {
+                return Err(ParseError::Incomplete);
+            }
+            values += 1;
+        }
+    }
+
+    fn delete(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let key = keyspace.generate_key(rng, request);
+        let _ = buf.write_all(b"delete ");
+        let _ = buf.write_all(&key);
+        let _ = buf.write_all(b"\r\n");
+    }
+
+    /// Write a binary protocol request header. The opaque and CAS fields are
+    /// left as zero.
+    fn binary_header(buf: &mut dyn Write, opcode: u8, key: usize, extras: usize, body: usize) {
+        let mut header = [0; HEADER_LEN];
+        header[0] = REQUEST_MAGIC;
+        header[1] = opcode;
+        header[2..4].copy_from_slice(&(key as u16).to_be_bytes());
+        header[4] = extras as u8;
+        header[8..12].copy_from_slice(&(body as u32).to_be_bytes());
+        let _ = buf.write_all(&header);
+    }
+
+    /// Write a binary get. A batch asks for each key with a quiet get, which
+    /// only responds on a hit, followed by a no-op which marks the end of the
+    /// responses.
+    fn binary_get(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        if keyspace.batch_size() == 1 {
+            let key = keyspace.generate_key(rng, request);
+            Self::binary_header(buf, OPCODE_GET, key.len(), 0, key.len());
+            let _ = buf.write_all(&key);
+            return;
+        }
+        for key in keyspace.generate_keys(rng, request, keyspace.batch_size()) {
+            Self::binary_header(buf, OPCODE_GETKQ, key.len(), 0, key.len());
+            let _ = buf.write_all(&key);
+        }
+        Self::binary_header(buf, OPCODE_NOOP, 0, 0, 0);
+    }
+
+    /// Write a binary set or add, with extras carrying the flags and the
+    /// expiration
+    fn binary_store(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+        opcode: u8,
+    ) {
+        let key = keyspace.generate_key(rng, request);
+        let value = keyspace
+            .generate_value(rng, request)
+            .unwrap_or_else(|| b"".to_vec());
+        let ttl = keyspace.generate_ttl(rng, request);
+        Self::binary_header(buf, opcode, key.len(), 8, 8 + key.len() + value.len());
+        let _ = buf.write_all(&0_u32.to_be_bytes());
+        let _ = buf.write_all(&(ttl as u32).to_be_bytes());
+        let _ = buf.write_all(&key);
+        let _ = buf.write_all(&value);
+    }
+
+    fn binary_delete(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let key = keyspace.generate_key(rng, request);
+        Self::binary_header(buf, OPCODE_DELETE, key.len(), 0, key.len());
+        let _ = buf.write_all(&key);
+    }
+
+    /// The length of the first complete binary response in the buffer, for a
+    /// get the number of values it carries, and whether the server replied
+    /// with an error such as a value too large or out of memory. The responses
+    /// to a batch of quiet gets end with the response to the closing no-op.
+    fn binary_response_len(buf: &[u8]) -> Result<(usize, Option<usize>, bool), ParseError> {
+        let mut start = 0;
+        let mut hits = 0;
+        let mut error = false;
+        loop {
+            let header = match buf.get(start..(start + HEADER_LEN)) {
+                Some(header) => header,
+                None => return Err(ParseError::Incomplete),
+            };
+            if header[0] != RESPONSE_MAGIC {
+                return Err(ParseError::Unknown);
+            }
+            let opcode = header[1];
+            let status = u16::from_be_bytes([header[6], header[7]]);
+            let body = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+            let end = start + HEADER_LEN + body as usize;
+            if buf.len() < end {
+                return Err(ParseError::Incomplete);
+            }
+            match status {
+                STATUS_OK => {
+                    if opcode == OPCODE_GET || opcode == OPCODE_GETKQ {
+                        hits += 1;
+                    }
+                }
+                STATUS_KEY_NOT_FOUND | STATUS_KEY_EXISTS | STATUS_NOT_STORED => {}
+                _ => {
+                    // the response is complete, so the session stays usable
+                    debug!("error response: opcode: {} status: {}", opcode, status);
+                    error = true;
                 }
-                start = line_end + 2;
             }
-            buffer.consume(response_end + 5);
-            return Ok(());
+            start = end;
+            match opcode {
+                OPCODE_GETKQ => {}
+                OPCODE_GET | OPCODE_NOOP => return Ok((start, Some(hits), error)),
+                _ => return Ok((start, None, error)),
+            }
         }
+    }
+
+    /// Write a request for the command, without recording it in the request
+    /// metrics
+    fn write_request(
+        &mut self,
+        buf: &mut dyn Write,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    ) {
+        let rng = &mut self.rng;
+        match (&self.mode, command.verb()) {
+            (Mode::Text, Verb::Get) => Self::get(rng, request, keyspace, buf),
+            (Mode::Text, Verb::Set) => Self::set(rng, request, keyspace, buf),
+            (Mode::Text, Verb::Add) => Self::add(rng, request, keyspace, buf),
+            (Mode::Text, Verb::Delete) => Self::delete(rng, request, keyspace, buf),
+            (Mode::Binary, Verb::Get) => Self::binary_get(rng, request, keyspace, buf),
+            (Mode::Binary, Verb::Set) => {
+                Self::binary_store(rng, request, keyspace, buf, OPCODE_SET)
+            }
+            (Mode::Binary, Verb::Add) => {
+                Self::binary_store(rng, request, keyspace, buf, OPCODE_ADD)
+            }
+            (Mode::Binary, Verb::Delete) => Self::binary_delete(rng, request, keyspace, buf),
+            _ => {
+                unimplemented!()
+            }
+        }
+    }
+}
+
+impl Codec for Memcache {
+    fn encode(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    ) {
+        if command.verb().is_get() {
+            metrics::REQUEST_GET.increment();
+            buf.push_key_count(keyspace.batch_size());
+        }
+        self.write_request(buf, keyspace, command, request)
+    }
+
+    fn frame(
+        &mut self,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    ) -> Option<Vec<u8>> {
+        let mut frame = Vec::new();
+        self.write_request(&mut frame, keyspace, command, request);
+        Some(frame)
+    }
+
+    fn send_frame(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        command: &Command,
+        frame: &[u8],
+    ) {
+        if command.verb().is_get() {
+            metrics::REQUEST_GET.increment();
+            buf.push_key_count(keyspace.batch_size());
+        }
+        let _ = buf.write_all(frame);
+    }
+
+    fn decode(&self, buffer: &mut Session) -> Result<Response, ParseError> {
+        // no-copy borrow as a slice
+        let buf: &[u8] = (*buffer).buffer();
+
+        let (len, values) = match self.mode {
+            Mode::Text => Memcache::response_len(buf)?,
+            Mode::Binary => {
+                let (len, values, error) = Memcache::binary_response_len(buf)?;
+                if error {
+                    metrics::RESPONSE_EX.increment();
+                }
+                (len, values)
+            }
+        };
+        buffer.consume(len);
+        match values {
+            Some(values) => {
+                let keys = buffer.pop_key_count().unwrap_or(1);
+                Ok(batch_hit(keys, values))
+            }
+            None => Ok(Response::default()),
+        }
+    }
+
+    fn parse(&self, buf: &[u8]) -> Result<(usize, Option<usize>), ParseError> {
+        match self.mode {
+            Mode::Text => Memcache::response_len(buf),
+            Mode::Binary => match Memcache::binary_response_len(buf)? {
+                (_, _, true) => Err(ParseError::Error),
+                (len, values, false) => Ok((len, values)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats() {
+        let mut buf = Vec::new();
+        Memcache::stats(&mut buf);
+        assert_eq!(&buf, b"stats\r\n");
+    }
+
+    #[test]
+    fn version() {
+        let mut buf = Vec::new();
+        Memcache::version(&mut buf);
+        assert_eq!(&buf, b"version\r\n");
+
+        assert_eq!(Memcache::decode_version(b"VERSION 1.6.9\r\n"), Ok(15));
+        assert_eq!(
+            Memcache::decode_version(b"VERSION 1.6"),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(
+            Memcache::decode_version(b"ERROR\r\n"),
+            Err(ParseError::Unknown)
+        );
+    }
+
+    #[test]
+    fn decode_stats() {
+        let keys = vec!["curr_items".to_string(), "rusage_user".to_string()];
+
+        let response = b"STAT pid 1\r\nSTAT curr_items 42\r\nSTAT rusage_user 0.5\r\n";
+        assert_eq!(
+            Memcache::decode_stats(response, &keys),
+            Err(ParseError::Incomplete)
+        );
+
+        let response =
+            b"STAT pid 1\r\nSTAT curr_items 42\r\nSTAT rusage_user 0.5\r\nSTAT version 1.6\r\nEND\r\n";
+        assert_eq!(
+            Memcache::decode_stats(response, &keys),
+            Ok(vec![
+                ("curr_items".to_string(), 42.0),
+                ("rusage_user".to_string(), 0.5)
+            ])
+        );
+
+        assert_eq!(
+            Memcache::decode_stats(b"ERROR\r\nEND\r\n", &keys),
+            Err(ParseError::Unknown)
+        );
+    }
+
+    #[test]
+    fn set_ttl() {
+        let keyspace = |ttl| {
+            let k: crate::config_file::Keyspace = toml::from_str(&format!(
+                r#"
+                commands = [{{ verb = "set" }}]
+                length = 4
+                cardinality = 1
+                key_type = "u32"
+                values = [{{ length = 2, cardinality = 1, field_type = "u32" }}]
+                ttl = {}
+                "#,
+                ttl
+            ))
+            .unwrap();
+            Keyspace::new(&k)
+        };
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Memcache::set(
+            &mut rng,
+            &mut RequestContext::default(),
+            &keyspace(0),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"set 0000 0 0 2\r\n00\r\n");
+
+        let mut buf = Vec::new();
+        Memcache::set(
+            &mut rng,
+            &mut RequestContext::default(),
+            &keyspace(60),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"set 0000 0 60 2\r\n00\r\n");
+    }
+
+    #[test]
+    fn multi_get() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }]
+            length = 4
+            cardinality = 3
+            key_type = "u32"
+            batch_size = 3
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // each key of the batch is distinct
+        let mut buf = Vec::new();
+        Memcache::get(
+            &mut rng,
+            &mut RequestContext::default(),
+            &keyspace,
+            &mut buf,
+        );
+        let request = std::str::from_utf8(&buf).unwrap();
+        let request = request.strip_suffix("\r\n").unwrap();
+        let mut keys: Vec<&str> = request.strip_prefix("get ").unwrap().split(' ').collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["0000", "0001", "0002"]);
+
+        let mut buf = Vec::new();
+        Memcache::binary_get(
+            &mut rng,
+            &mut RequestContext::default(),
+            &keyspace,
+            &mut buf,
+        );
+        assert_eq!(buf.len(), 3 * (HEADER_LEN + 4) + HEADER_LEN);
+        let mut keys: Vec<&[u8]> = (0..3)
+            .map(|i| {
+                let start = i * (HEADER_LEN + 4);
+                assert_eq!(buf[start + 1], OPCODE_GETKQ);
+                &buf[(start + HEADER_LEN)..(start + HEADER_LEN + 4)]
+            })
+            .collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![&b"0000"[..], b"0001", b"0002"]);
+        assert_eq!(buf[3 * (HEADER_LEN + 4) + 1], OPCODE_NOOP);
+
+        // the values found are counted, whatever they hold
+        let response = b"VALUE 0000 0 4\r\nEND\r\r\nVALUE 0002 0 1\r\na\r\nEND\r\n";
+        assert_eq!(
+            Memcache::response_len(response),
+            Ok((response.len(), Some(2)))
+        );
+        assert_eq!(Memcache::response_len(b"END\r\n"), Ok((5, Some(0))));
+        assert_eq!(
+            Memcache::response_len(b"ERROR\r\n"),
+            Err(ParseError::Unknown)
+        );
+    }
+
+    #[test]
+    fn binary_encode() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }, { verb = "set" }, { verb = "add" }]
+            length = 4
+            cardinality = 1
+            key_type = "u32"
+            values = [{ length = 2, cardinality = 1, field_type = "u32" }]
+            ttl = 60
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut codec = Memcache {
+            mode: Mode::Binary,
+            rng: SmallRng::seed_from_u64(0),
+        };
+
+        let mut buf = Vec::new();
+        codec.write_request(
+            &mut buf,
+            &keyspace,
+            &keyspace.commands()[0],
+            &mut RequestContext::default(),
+        );
+        let mut get = vec![
+            0x80, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        get.extend_from_slice(b"0000");
+        assert_eq!(buf, get);
+
+        // the extras carry zero flags and the ttl
+        let mut buf = Vec::new();
+        codec.write_request(
+            &mut buf,
+            &keyspace,
+            &keyspace.commands()[1],
+            &mut RequestContext::default(),
+        );
+        let mut set = vec![
+            0x80, 0x01, 0x00, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x3c,
+        ];
+        set.extend_from_slice(b"000000");
+        assert_eq!(buf, set);
+
+        let mut buf = Vec::new();
+        codec.write_request(
+            &mut buf,
+            &keyspace,
+            &keyspace.commands()[2],
+            &mut RequestContext::default(),
+        );
+        set[1] = 0x02;
+        assert_eq!(buf, set);
+
+        // text mode adds with the same arguments as a set
+        codec.mode = Mode::Text;
+        let mut buf = Vec::new();
+        codec.write_request(
+            &mut buf,
+            &keyspace,
+            &keyspace.commands()[2],
+            &mut RequestContext::default(),
+        );
+        assert_eq!(&buf, b"add 0000 0 60 2\r\n00\r\n");
+    }
+
+    #[test]
+    fn binary_decode() {
+        // a get hit with four bytes of flags and a two byte value
+        let mut get_hit = vec![
+            0x81, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        get_hit.extend_from_slice(b"00");
+        assert_eq!(
+            Memcache::binary_response_len(&get_hit),
+            Ok((30, Some(1), false))
+        );
+        assert_eq!(
+            Memcache::binary_response_len(&get_hit[..26]),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(
+            Memcache::binary_response_len(&get_hit[..20]),
+            Err(ParseError::Incomplete)
+        );
+
+        let mut get_miss = vec![
+            0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        get_miss.extend_from_slice(b"Not found");
+        assert_eq!(
+            Memcache::binary_response_len(&get_miss),
+            Ok((33, Some(0), false))
+        );
+
+        let set_ok = [
+            0x81, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        ];
+        assert_eq!(
+            Memcache::binary_response_len(&set_ok),
+            Ok((24, None, false))
+        );
+
+        // an error status, such as a value too large or out of memory, is
+        // consumed with the response, and anything else is not a response
+        let mut too_large = set_ok;
+        too_large[7] = 0x03;
+        assert_eq!(
+            Memcache::binary_response_len(&too_large),
+            Ok((24, None, true))
+        );
+        let mut out_of_memory = set_ok;
+        out_of_memory[7] = 0x82;
+        assert_eq!(
+            Memcache::binary_response_len(&out_of_memory),
+            Ok((24, None, true))
+        );
+        assert_eq!(
+            Memcache::binary_response_len(b"STORED\r\n"),
+            Err(ParseError::Unknown)
+        );
+
+        // a batch of quiet gets responds only to the hits, ending at the no-op
+        let mut batch = get_hit;
+        batch[1] = 0x0d;
+        let noop = [
+            0x81, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            Memcache::binary_response_len(&batch),
+            Err(ParseError::Incomplete)
+        );
+        batch.extend_from_slice(&noop);
+        batch.extend_from_slice(&set_ok);
+        assert_eq!(
+            Memcache::binary_response_len(&batch),
+            Ok((54, Some(1), false))
+        );
+    }
+
+    #[test]
+    fn mixed_pipeline() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }, { verb = "set" }, { verb = "delete" }]
+            length = 4
+            cardinality = 1
+            key_type = "u32"
+            values = [{ length = 3 }]
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut codec = Memcache {
+            mode: Mode::Text,
+            rng: SmallRng::seed_from_u64(0),
+        };
+
+        // one batch carrying a mix of commands
+        let mut batch = Vec::new();
+        for command in [1, 0, 2, 0] {
+            codec.write_request(
+                &mut batch,
+                &keyspace,
+                &keyspace.commands()[command],
+                &mut RequestContext::default(),
+            );
+        }
+        let batch = std::str::from_utf8(&batch).unwrap();
+        let lines: Vec<&str> = batch.split("\r\n").collect();
+        assert!(lines[0].starts_with("set 0000 0 "));
+        assert_eq!(&lines[2..5], &["get 0000", "delete 0000", "get 0000"]);
+
+        // the responses are decoded in the order the commands were sent
+        let mut responses: &[u8] = b"STORED\r\nVALUE 0000 0 3\r\nabc\r\nEND\r\nDELETED\r\nEND\r\n";
+        let mut decoded = Vec::new();
+        while !responses.is_empty() {
+            let (len, _) = Memcache::response_len(responses).unwrap();
+            decoded.push(&responses[..len]);
+            responses = &responses[len..];
+        }
+        assert_eq!(
+            decoded,
+            vec![
+                &b"STORED\r\n"[..],
+                &b"VALUE 0000 0 3\r\nabc\r\nEND\r\n"[..],
+                &b"DELETED\r\n"[..],
+                &b"END\r\n"[..],
+            ]
+        );
+        assert_eq!(
+            Memcache::response_len(b"VALUE 0000 0 3\r\nab"),
+            Err(ParseError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn precomputed_get() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }]
+            length = 4
+            cardinality = 100
+            key_type = "u32"
+            batch_size = 2
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let command = &keyspace.commands()[0];
+        let mut codec = Memcache {
+            mode: Mode::Text,
+            rng: SmallRng::seed_from_u64(0),
+        };
+        let frame = codec
+            .frame(&keyspace, command, &mut RequestContext::default())
+            .unwrap();
 
-        Err(ParseError::Incomplete)
+        // a get sent from a frame asks for the whole batch, so a single value
+        // is only a partial hit
+        let stream = crate::session::PipeStream::spawn("cat").unwrap();
+        let mut session = Session::pipe_with_capacity(stream, 1024, 1024);
+        codec.send_frame(&mut session, &keyspace, command, &frame);
+        assert_eq!(session.write_buffer(), &frame[..]);
+        assert_eq!(session.pop_key_count(), Some(2));
     }
 }
@@ -9,21 +9,121 @@ mod redis;
 mod thrift;
 mod thrift_cache;
 
+use crate::config::{Keyspace, RequestContext};
+use crate::config_file::Command;
 use crate::Session;
 pub use echo::Echo;
 pub use memcache::Memcache;
 pub use ping::Ping;
-pub use redis::Redis;
+pub use redis::{Redis, RedisError};
+use std::io::Write;
 pub use thrift_cache::ThriftCache;
 
+/// A decoded response
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Response {
+    /// whether the response returned a value for every key asked for, so that
+    /// the hit can be attributed to the command which was sent
+    pub hit: bool,
+}
+
+/// Count a hit for the response being decoded
+pub(crate) fn hit() -> Response {
+    crate::metrics::RESPONSE_HIT.increment();
+    Response { hit: true }
+}
+
+/// Count the hits for a get of `keys` keys, of which `found` were found. A
+/// batch is only a hit when every key is found, and is otherwise a partial hit
+/// if any key is found.
+pub(crate) fn batch_hit(keys: usize, found: usize) -> Response {
+    if found == 0 {
+        return Response::default();
+    }
+    if found >= keys {
+        hit()
+    } else {
+        crate::metrics::RESPONSE_PARTIAL_HIT.increment();
+        Response::default()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum ParseError {
     Incomplete,
     Error,
     Unknown,
+    /// The response did not match what was expected for the request
+    Mismatch,
+    /// A complete redis error reply was consumed, the session remains usable
+    Redis(RedisError),
 }
 
 pub trait Codec: Send {
-    fn decode(&self, buf: &mut Session) -> Result<(), ParseError>;
-    fn encode(&mut self, buf: &mut Session);
+    fn decode(&self, buf: &mut Session) -> Result<Response, ParseError>;
+    fn encode(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    );
+
+    /// Encode a request along with the time remaining until its deadline, if
+    /// one is configured. Codecs for servers which honor a client supplied
+    /// deadline can override this to carry it in the request, by default the
+    /// deadline is ignored.
+    fn encode_with_deadline(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+        _deadline: Option<std::time::Duration>,
+    ) {
+        self.encode(buf, keyspace, command, request)
+    }
+
+    /// Write `length` bytes of filler after a request, which the server skips
+    /// without replying. Only codecs whose protocol allows such filler
+    /// implement this, by default nothing is written.
+    fn pad(&self, _buf: &mut Session, _length: usize) {}
+
+    /// Encode a request into a standalone frame which may be sent any number
+    /// of times. Request metrics are not recorded, as the frame may be sent
+    /// later or not at all. Returns `None` if the codec can only encode
+    /// directly into a session.
+    fn frame(
+        &mut self,
+        _keyspace: &Keyspace,
+        _command: &Command,
+        _request: &mut RequestContext,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Send a frame encoded ahead of time by `frame`, recording the request
+    /// as `encode` would. Codecs which track state for each outstanding
+    /// request record it here as well.
+    fn send_frame(
+        &mut self,
+        buf: &mut Session,
+        _keyspace: &Keyspace,
+        command: &Command,
+        frame: &[u8],
+    ) {
+        if command.verb().is_get() {
+            crate::metrics::REQUEST_GET.increment();
+        }
+        let _ = buf.write_all(frame);
+    }
+
+    /// Parse the first complete response in the buffer, returning its length
+    /// and, for a read, the number of values found. This checks responses
+    /// received outside of a session, so nothing is recorded in the metrics
+    /// and error replies are returned as errors. By default no response can
+    /// be parsed.
+    fn parse(&self, _buf: &[u8]) -> Result<(usize, Option<usize>), ParseError> {
+        Err(ParseError::Unknown)
+    }
 }
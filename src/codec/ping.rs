@@ -3,6 +3,8 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use crate::codec::*;
+use crate::config::{Keyspace, RequestContext};
+use crate::config_file::Command;
 use crate::Session;
 use crate::*;
 use std::io::{BufRead, Write};
@@ -20,11 +22,26 @@ impl Ping {
 }
 
 impl Codec for Ping {
-    fn encode(&mut self, buf: &mut Session) {
+    fn encode(
+        &mut self,
+        buf: &mut Session,
+        _keyspace: &Keyspace,
+        _command: &Command,
+        _request: &mut RequestContext,
+    ) {
         Self::ping(buf)
     }
 
-    fn decode(&self, buffer: &mut Session) -> Result<(), ParseError> {
+    fn frame(
+        &mut self,
+        _keyspace: &Keyspace,
+        _command: &Command,
+        _request: &mut RequestContext,
+    ) -> Option<Vec<u8>> {
+        Some(b"PING\r\n".to_vec())
+    }
+
+    fn decode(&self, buffer: &mut Session) -> Result<Response, ParseError> {
         // no-copy borrow as a slice
         let buf: &[u8] = (*buffer).buffer();
 
@@ -34,7 +51,7 @@ impl Codec for Ping {
             match &buf[0..response_end] {
                 b"pong" | b"PONG" => {
                     let _ = buffer.consume(response_end + 2);
-                    Ok(())
+                    Ok(Response::default())
                 }
                 _ => Err(ParseError::Unknown),
             }
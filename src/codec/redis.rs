@@ -4,22 +4,85 @@
 
 use crate::codec::*;
 use crate::config::*;
-use crate::config_file::{Protocol, Verb};
+use crate::config_file::{Command, Protocol, Verb};
 use crate::*;
 
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 
 use std::io::{BufRead, Write};
+use std::net::SocketAddr;
 use std::str;
 
+/// The kind of a redis error reply, taken from the prefix of the message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RedisError {
+    /// The key belongs to a cluster slot served by another node, given as a
+    /// host and port
+    Moved {
+        slot: u16,
+        endpoint: String,
+    },
+    /// The key belongs to a cluster slot which is migrating to another node,
+    /// which serves the key for a request sent after `ASKING`
+    Ask {
+        slot: u16,
+        endpoint: String,
+    },
+    /// The operation does not match the type of the value stored at the key
+    WrongType,
+    /// The server is out of memory
+    Oom,
+    Other,
+}
+
+impl RedisError {
+    fn parse(msg: &[u8]) -> Self {
+        let mut parts = msg.split(|b| *b == b' ');
+        let kind = parts.next().unwrap_or_default();
+        let mut field = || {
+            parts
+                .next()
+                .and_then(|p| str::from_utf8(p).ok())
+                .map(|p| p.to_string())
+        };
+        match kind {
+            b"MOVED" | b"ASK" => {
+                let slot = field().and_then(|slot| slot.parse().ok());
+                match (slot, field()) {
+                    (Some(slot), Some(endpoint)) if kind == b"MOVED" => {
+                        Self::Moved { slot, endpoint }
+                    }
+                    (Some(slot), Some(endpoint)) => Self::Ask { slot, endpoint },
+                    _ => Self::Other,
+                }
+            }
+            b"WRONGTYPE" => Self::WrongType,
+            b"OOM" => Self::Oom,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// A complete reply, as parsed from the front of the buffer
+enum Reply {
+    /// A status or numeric reply, which carries no value
+    Status,
+    /// A bulk string or array, with the number of values asked for and the
+    /// number which were found
+    Values {
+        keys: usize,
+        found: usize,
+    },
+    Error(RedisError),
+}
+
 pub enum Mode {
     Inline,
     Resp,
 }
 
 pub struct Redis {
-    config: Arc<Config>,
     mode: Mode,
     rng: SmallRng,
 }
@@ -34,13 +97,12 @@ impl Redis {
             }
         };
         Self {
-            config,
             mode,
             rng: SmallRng::from_entropy(),
         }
     }
 
-    fn command(buf: &mut Session, mode: &Mode, command: &str, args: Vec<Vec<u8>>) {
+    fn command(buf: &mut dyn Write, mode: &Mode, command: &str, args: Vec<Vec<u8>>) {
         match mode {
             Mode::Inline => {
                 let _ = buf.write_all(command.to_string().as_bytes());
@@ -63,26 +125,281 @@ impl Redis {
         }
     }
 
-    fn get(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
-        let args = vec![keyspace.generate_key(rng)];
-        Redis::command(buf, mode, "get", args);
+    /// Write a request for the internal encoding of the value at the key
+    pub fn object_encoding(key: Vec<u8>, buf: &mut dyn Write) {
+        Redis::command(buf, &Mode::Resp, "object", vec![b"encoding".to_vec(), key]);
+    }
+
+    /// Parse a complete reply to `OBJECT ENCODING`, returning the encoding, if
+    /// the key exists, and the number of bytes consumed
+    pub fn decode_object_encoding(buf: &[u8]) -> Result<(Option<String>, usize), ParseError> {
+        let line_end = match buf.windows(2).position(|w| w == b"\r\n") {
+            Some(line_end) => line_end,
+            None => return Err(ParseError::Incomplete),
+        };
+        let line = str::from_utf8(&buf[..line_end]).map_err(|_| ParseError::Unknown)?;
+        match line.as_bytes().first() {
+            Some(b'$') => {
+                let len: i64 = line[1..].parse().map_err(|_| ParseError::Unknown)?;
+                if len < 0 {
+                    // nil, the key does not exist
+                    return Ok((None, line_end + 2));
+                }
+                let start = line_end + 2;
+                let end = start + len as usize;
+                if buf.len() < end + 2 {
+                    return Err(ParseError::Incomplete);
+                }
+                let encoding = str::from_utf8(&buf[start..end]).map_err(|_| ParseError::Unknown)?;
+                Ok((Some(encoding.to_string()), end + 2))
+            }
+            // older servers reply with an error when the key does not exist
+            Some(b'-') => Ok((None, line_end + 2)),
+            _ => Err(ParseError::Unknown),
+        }
+    }
+
+    /// Write an inline `PING`, which is used as a no-op
+    pub fn verify(buf: &mut dyn Write) {
+        Redis::ping(&Mode::Inline, buf);
+    }
+
+    /// Parse the reply to a `PING`, returning its length
+    pub fn decode_verify(buf: &[u8]) -> Result<usize, ParseError> {
+        let (line, pos) = Self::line(buf, 0)?;
+        if line == "+PONG" {
+            Ok(pos)
+        } else {
+            Err(ParseError::Unknown)
+        }
+    }
+
+    /// Write an `ASKING`, which lets the request which follows it on the
+    /// connection be served by a node a slot is migrating to
+    pub fn asking(buf: &mut dyn Write) {
+        Redis::command(buf, &Mode::Resp, "asking", Vec::new());
+    }
+
+    /// Parse the reply to an `ASKING`, returning its length
+    pub fn decode_asking(buf: &[u8]) -> Result<usize, ParseError> {
+        let (line, pos) = Self::line(buf, 0)?;
+        if line == "+OK" {
+            Ok(pos)
+        } else {
+            Err(ParseError::Unknown)
+        }
+    }
+
+    /// Write a request to subscribe to the channels
+    pub fn subscribe(channels: &[String], buf: &mut dyn Write) {
+        let args = channels.iter().map(|c| c.as_bytes().to_vec()).collect();
+        Redis::command(buf, &Mode::Resp, "subscribe", args);
+    }
+
+    /// Parse a complete push message from a subscribed connection, returning
+    /// the payload of a published message and the number of bytes consumed.
+    /// Other pushes, such as the confirmation of a subscription, have no
+    /// payload.
+    pub fn decode_push(buf: &[u8]) -> Result<(Option<&[u8]>, usize), ParseError> {
+        let (header, mut pos) = Self::line(buf, 0)?;
+        let count: usize = match header.strip_prefix('*') {
+            Some(count) => count.parse().map_err(|_| ParseError::Unknown)?,
+            None => return Err(ParseError::Unknown),
+        };
+        let mut elements = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (line, next) = Self::line(buf, pos)?;
+            match line.as_bytes().first() {
+                Some(b'$') => {
+                    let len: usize = line[1..].parse().map_err(|_| ParseError::Unknown)?;
+                    let end = next + len;
+                    if buf.len() < end + 2 {
+                        return Err(ParseError::Incomplete);
+                    }
+                    elements.push(&buf[next..end]);
+                    pos = end + 2;
+                }
+                Some(b':') => {
+                    elements.push(&buf[pos + 1..next - 2]);
+                    pos = next;
+                }
+                _ => return Err(ParseError::Unknown),
+            }
+        }
+        let payload = match elements.first().copied() {
+            Some(b"message") => elements.get(2).copied(),
+            Some(b"pmessage") => elements.get(3).copied(),
+            _ => None,
+        };
+        Ok((payload, pos))
+    }
+
+    /// Parse a complete array starting at the position, returning the number
+    /// of elements which resolved to a value, the number of elements, and the
+    /// position after it. A nested array resolves to a value when every one
+    /// of its elements does. An array is a hit when it has elements and every
+    /// element resolved, so a `MGET` with some missing keys is a partial hit
+    /// and an empty `LRANGE` is a miss.
+    fn decode_array(buf: &[u8], pos: usize) -> Result<(usize, usize, usize), ParseError> {
+        let (header, mut pos) = Self::line(buf, pos)?;
+        let count: i64 = match header.strip_prefix('*') {
+            Some(count) => count.parse().map_err(|_| ParseError::Unknown)?,
+            None => return Err(ParseError::Unknown),
+        };
+        if count < 0 {
+            // nil array
+            return Ok((0, 0, pos));
+        }
+        let mut found = 0;
+        for _ in 0..count {
+            if pos >= buf.len() {
+                return Err(ParseError::Incomplete);
+            }
+            match buf[pos] {
+                b'*' => {
+                    let (resolved, elements, next) = Self::decode_array(buf, pos)?;
+                    if elements > 0 && resolved == elements {
+                        found += 1;
+                    }
+                    pos = next;
+                }
+                b'$' => {
+                    let (line, next) = Self::line(buf, pos)?;
+                    let len: i64 = line[1..].parse().map_err(|_| ParseError::Unknown)?;
+                    if len < 0 {
+                        pos = next;
+                    } else {
+                        let end = next + len as usize;
+                        if buf.len() < end + 2 {
+                            return Err(ParseError::Incomplete);
+                        }
+                        found += 1;
+                        pos = end + 2;
+                    }
+                }
+                b'+' | b':' => {
+                    let (_, next) = Self::line(buf, pos)?;
+                    found += 1;
+                    pos = next;
+                }
+                b'-' => {
+                    let (_, next) = Self::line(buf, pos)?;
+                    pos = next;
+                }
+                _ => return Err(ParseError::Unknown),
+            }
+        }
+        Ok((found, count as usize, pos))
+    }
+
+    /// Parse the first complete reply in the buffer, returning it with its
+    /// length
+    fn reply(buf: &[u8]) -> Result<(Reply, usize), ParseError> {
+        if buf.is_empty() {
+            return Err(ParseError::Incomplete);
+        }
+        match buf[0] {
+            b'+' => {
+                // simple string response
+                let (msg, len) = Self::line(buf, 0)?;
+                match &msg[1..] {
+                    "OK" | "PONG" => Ok((Reply::Status, len)),
+                    _ => Err(ParseError::Unknown),
+                }
+            }
+            b'-' => {
+                // error response, whose message may not be utf8
+                let mut lines = buf.windows(2);
+                match lines.position(|w| w == b"\r\n") {
+                    Some(line_end) => Ok((
+                        Reply::Error(RedisError::parse(&buf[1..line_end])),
+                        line_end + 2,
+                    )),
+                    None => Err(ParseError::Incomplete),
+                }
+            }
+            b':' => {
+                // numeric response
+                let (msg, len) = Self::line(buf, 0)?;
+                msg[1..].parse::<i64>().map_err(|_| ParseError::Unknown)?;
+                Ok((Reply::Status, len))
+            }
+            b'$' => {
+                // bulk string
+                let (msg, line_len) = Self::line(buf, 0)?;
+                if &msg[1..] == "-1" {
+                    return Ok((Reply::Values { keys: 1, found: 0 }, line_len));
+                }
+                let len = msg[1..].parse::<usize>().map_err(|_| ParseError::Unknown)?;
+                let response_end = line_len + len + 2;
+                if response_end <= buf.len() {
+                    Ok((Reply::Values { keys: 1, found: 1 }, response_end))
+                } else {
+                    Err(ParseError::Incomplete)
+                }
+            }
+            b'*' => {
+                // arrays
+                let (found, keys, response_end) = Self::decode_array(buf, 0)?;
+                Ok((Reply::Values { keys, found }, response_end))
+            }
+            _ => Err(ParseError::Unknown),
+        }
     }
 
-    fn mget(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
-        let mut args = Vec::new();
-        for _ in 0..keyspace.batch_size() {
-            args.push(keyspace.generate_key(rng));
+    /// Returns the line starting at the position and the position after its
+    /// terminating CRLF
+    fn line(buf: &[u8], pos: usize) -> Result<(&str, usize), ParseError> {
+        match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+            Some(len) => {
+                let line = str::from_utf8(&buf[pos..pos + len]).map_err(|_| ParseError::Unknown)?;
+                Ok((line, pos + len + 2))
+            }
+            None => Err(ParseError::Incomplete),
         }
+    }
+
+    fn ping(mode: &Mode, buf: &mut dyn Write) {
+        Redis::command(buf, mode, "ping", Vec::new());
+    }
+
+    fn get(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = vec![keyspace.generate_key(rng, request)];
+        Redis::command(buf, mode, "get", args);
+    }
+
+    fn mget(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = keyspace.generate_keys(rng, request, keyspace.batch_size());
         Redis::command(buf, mode, "mget", args);
     }
 
-    fn set(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
+    fn set(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
         let command = "set";
         let mut args = vec![
-            keyspace.generate_key(rng),
-            keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec()),
+            keyspace.generate_key(rng, request),
+            keyspace
+                .generate_value(rng, request)
+                .unwrap_or_else(|| b"".to_vec()),
         ];
-        let ttl = keyspace.ttl();
+        let ttl = keyspace.generate_ttl(rng, request);
         if ttl != 0 {
             args.push(b"EX".to_vec());
             args.push(format!("{}", ttl).as_bytes().to_vec());
@@ -90,15 +407,170 @@ impl Redis {
         Redis::command(buf, mode, command, args);
     }
 
-    fn del(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
-        let args = vec![keyspace.generate_key(rng)];
+    fn setex(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let key = keyspace.generate_key(rng, request);
+        let value = keyspace
+            .generate_value(rng, request)
+            .unwrap_or_else(|| b"".to_vec());
+        let ttl = keyspace.generate_ttl(rng, request);
+        let args = vec![key, format!("{}", ttl).as_bytes().to_vec(), value];
+        Redis::command(buf, mode, "setex", args);
+    }
+
+    fn psetex(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let key = keyspace.generate_key(rng, request);
+        let value = keyspace
+            .generate_value(rng, request)
+            .unwrap_or_else(|| b"".to_vec());
+        let ttl = keyspace.generate_ttl(rng, request) * 1000;
+        let args = vec![key, format!("{}", ttl).as_bytes().to_vec(), value];
+        Redis::command(buf, mode, "psetex", args);
+    }
+
+    fn del(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = vec![keyspace.generate_key(rng, request)];
         Redis::command(buf, mode, "del", args);
     }
 
-    fn hget(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
+    fn incr(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = vec![keyspace.generate_key(rng, request)];
+        Redis::command(buf, mode, "incr", args);
+    }
+
+    fn decr(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = vec![keyspace.generate_key(rng, request)];
+        Redis::command(buf, mode, "decr", args);
+    }
+
+    fn incrby(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        amount: i64,
+        buf: &mut dyn Write,
+    ) {
+        let args = vec![
+            keyspace.generate_key(rng, request),
+            format!("{}", amount).as_bytes().to_vec(),
+        ];
+        Redis::command(buf, mode, "incrby", args);
+    }
+
+    fn decrby(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        amount: i64,
+        buf: &mut dyn Write,
+    ) {
+        let args = vec![
+            keyspace.generate_key(rng, request),
+            format!("{}", amount).as_bytes().to_vec(),
+        ];
+        Redis::command(buf, mode, "decrby", args);
+    }
+
+    fn getdel(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = vec![keyspace.generate_key(rng, request)];
+        Redis::command(buf, mode, "getdel", args);
+    }
+
+    /// Generate a source and a distinct destination key, unless the keyspace
+    /// has only a single key
+    fn key_pair(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+    ) -> Vec<Vec<u8>> {
+        let src = keyspace.generate_key(rng, request);
+        let mut dst = keyspace.generate_key(rng, request);
+        while dst == src && keyspace.cardinality() > 1 {
+            dst = keyspace.generate_key(rng, request);
+        }
+        vec![src, dst]
+    }
+
+    fn copy(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = Self::key_pair(rng, request, keyspace);
+        Redis::command(buf, mode, "copy", args);
+    }
+
+    fn rename(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = Self::key_pair(rng, request, keyspace);
+        Redis::command(buf, mode, "rename", args);
+    }
+
+    fn unlink(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
+        let args = keyspace.generate_keys(rng, request, keyspace.batch_size());
+        Redis::command(buf, mode, "unlink", args);
+    }
+
+    fn hget(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
         let command = "hget";
         let args = vec![
-            keyspace.generate_key(rng),
+            keyspace.generate_key(rng, request),
             keyspace
                 .generate_inner_key(rng)
                 .unwrap_or_else(|| b"".to_vec()),
@@ -106,163 +578,868 @@ impl Redis {
         Redis::command(buf, mode, command, args);
     }
 
-    fn hset(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
+    fn hset(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
         let command = "hset";
         let args = vec![
-            keyspace.generate_key(rng),
+            keyspace.generate_key(rng, request),
             keyspace
                 .generate_inner_key(rng)
                 .unwrap_or_else(|| b"".to_vec()),
-            keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec()),
+            keyspace
+                .generate_value(rng, request)
+                .unwrap_or_else(|| b"".to_vec()),
         ];
         Redis::command(buf, mode, command, args);
     }
 
-    fn hsetnx(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
+    fn hsetnx(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
         let command = "hsetnx";
         let args = vec![
-            keyspace.generate_key(rng),
+            keyspace.generate_key(rng, request),
             keyspace
                 .generate_inner_key(rng)
                 .unwrap_or_else(|| b"".to_vec()),
-            keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec()),
+            keyspace
+                .generate_value(rng, request)
+                .unwrap_or_else(|| b"".to_vec()),
         ];
         Redis::command(buf, mode, command, args);
     }
 
-    fn hdel(rng: &mut SmallRng, mode: &Mode, keyspace: &Keyspace, buf: &mut Session) {
+    fn hdel(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        mode: &Mode,
+        keyspace: &Keyspace,
+        buf: &mut dyn Write,
+    ) {
         let command = "hdel";
         let args = vec![
-            keyspace.generate_key(rng),
+            keyspace.generate_key(rng, request),
             keyspace
                 .generate_inner_key(rng)
                 .unwrap_or_else(|| b"".to_vec()),
         ];
         Redis::command(buf, mode, command, args);
     }
-}
 
-impl Codec for Redis {
-    fn encode(&mut self, buf: &mut Session) {
-        let keyspace = self.config.choose_keyspace(&mut self.rng);
-        let command = keyspace.choose_command(&mut self.rng);
+    /// Wait for the preceding writes on the connection to reach the replicas.
+    /// The reply is the number of replicas which acknowledged them.
+    fn wait(mode: &Mode, keyspace: &Keyspace, buf: &mut dyn Write) {
+        let wait = keyspace
+            .wait()
+            .expect("wait requires the keyspace wait arguments");
+        let args = vec![
+            format!("{}", wait.replicas()).into_bytes(),
+            format!("{}", wait.timeout_ms()).into_bytes(),
+        ];
+        Redis::command(buf, mode, "wait", args);
+    }
+
+    /// Write a blank line of the given length. Redis reads it as an inline
+    /// request with no arguments, which it skips without a reply.
+    fn padding(length: usize, buf: &mut dyn Write) {
+        if length == 0 {
+            return;
+        }
+        let mut line = vec![b' '; length - 1];
+        line.push(b'\n');
+        let _ = buf.write_all(&line);
+    }
+
+    /// Write a request for the command, without recording it in the request
+    /// metrics
+    fn write_request(
+        &mut self,
+        buf: &mut dyn Write,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    ) {
         match command.verb() {
+            Verb::Ping => Self::ping(&self.mode, buf),
             Verb::Get => {
-                metrics::REQUEST_GET.increment();
                 if keyspace.batch_size() == 1 {
-                    Self::get(&mut self.rng, &self.mode, keyspace, buf)
+                    Self::get(&mut self.rng, request, &self.mode, keyspace, buf)
                 } else {
-                    Self::mget(&mut self.rng, &self.mode, keyspace, buf)
+                    Self::mget(&mut self.rng, request, &self.mode, keyspace, buf)
                 }
             }
-            Verb::Set => Self::set(&mut self.rng, &self.mode, keyspace, buf),
-            Verb::Delete => Self::del(&mut self.rng, &self.mode, keyspace, buf),
-            Verb::Hget => {
-                metrics::REQUEST_GET.increment();
-                Self::hget(&mut self.rng, &self.mode, keyspace, buf)
-            }
-            Verb::Hset => Self::hset(&mut self.rng, &self.mode, keyspace, buf),
-            Verb::Hsetnx => Self::hsetnx(&mut self.rng, &self.mode, keyspace, buf),
-            Verb::Hdel => Self::hdel(&mut self.rng, &self.mode, keyspace, buf),
+            Verb::Set => Self::set(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Setex => Self::setex(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Psetex => Self::psetex(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Delete => Self::del(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Getdel => Self::getdel(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Copy => Self::copy(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Rename => Self::rename(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Unlink => Self::unlink(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Hget => Self::hget(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Hset => Self::hset(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Hsetnx => Self::hsetnx(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Hdel => Self::hdel(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Wait => Self::wait(&self.mode, keyspace, buf),
+            Verb::Incr => Self::incr(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Decr => Self::decr(&mut self.rng, request, &self.mode, keyspace, buf),
+            Verb::Incrby => Self::incrby(
+                &mut self.rng,
+                request,
+                &self.mode,
+                keyspace,
+                command.amount(),
+                buf,
+            ),
+            Verb::Decrby => Self::decrby(
+                &mut self.rng,
+                request,
+                &self.mode,
+                keyspace,
+                command.amount(),
+                buf,
+            ),
             _ => {
                 unimplemented!()
             }
         }
     }
+}
+
+impl Codec for Redis {
+    fn encode(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    ) {
+        if command.verb().is_get() {
+            metrics::REQUEST_GET.increment();
+        }
+        self.write_request(buf, keyspace, command, request)
+    }
 
-    fn decode(&self, buffer: &mut Session) -> Result<(), ParseError> {
+    fn pad(&self, buf: &mut Session, length: usize) {
+        Self::padding(length, buf)
+    }
+
+    fn frame(
+        &mut self,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    ) -> Option<Vec<u8>> {
+        let mut frame = Vec::new();
+        self.write_request(&mut frame, keyspace, command, request);
+        Some(frame)
+    }
+
+    fn decode(&self, buffer: &mut Session) -> Result<Response, ParseError> {
         // no-copy borrow as a slice
         let buf: &[u8] = (*buffer).buffer();
 
-        if buf.len() < 1 {
-            return Err(ParseError::Incomplete);
-        }
-
-        let first_char = &buf[0..1];
-        match str::from_utf8(first_char) {
-            Ok("+") => {
-                // simple string response
-                let mut lines = buf.windows(2);
-                if let Some(line_end) = lines.position(|w| w == b"\r\n") {
-                    let msg = &buf[1..line_end];
-                    match str::from_utf8(msg) {
-                        Ok("OK") | Ok("PONG") => {
-                            let _ = buffer.consume(line_end + 2);
-                            Ok(())
-                        }
-                        _ => Err(ParseError::Unknown),
-                    }
-                } else {
-                    Err(ParseError::Incomplete)
+        let (reply, len) = Self::reply(buf)?;
+        let _ = buffer.consume(len);
+        match reply {
+            Reply::Status => Ok(Response::default()),
+            Reply::Values { keys, found } => Ok(batch_hit(keys, found)),
+            Reply::Error(error) => {
+                metrics::RESPONSE_EX.increment();
+                match error {
+                    RedisError::Moved { .. } => metrics::RESPONSE_EX_MOVED.increment(),
+                    RedisError::Ask { .. } => metrics::RESPONSE_EX_ASK.increment(),
+                    RedisError::WrongType => metrics::RESPONSE_EX_WRONGTYPE.increment(),
+                    RedisError::Oom => metrics::RESPONSE_EX_OOM.increment(),
+                    RedisError::Other => {}
                 }
+                Err(ParseError::Redis(error))
             }
-            Ok("-") => {
-                // error response
-                Err(ParseError::Error)
-            }
-            Ok(":") => {
-                // numeric response
-                let mut lines = buf.windows(2);
-                if let Some(line_end) = lines.position(|w| w == b"\r\n") {
-                    let msg = &buf[1..line_end];
-                    match str::from_utf8(msg) {
-                        Ok(msg) => match msg.parse::<i64>() {
-                            Ok(_) => {
-                                let _ = buffer.consume(line_end + 2);
-                                Ok(())
-                            }
-                            Err(_) => Err(ParseError::Unknown),
-                        },
-                        Err(_) => Err(ParseError::Unknown),
-                    }
-                } else {
-                    Err(ParseError::Incomplete)
-                }
+        }
+    }
+
+    fn parse(&self, buf: &[u8]) -> Result<(usize, Option<usize>), ParseError> {
+        match Self::reply(buf)? {
+            (Reply::Status, len) => Ok((len, None)),
+            (Reply::Values { found, .. }, len) => Ok((len, Some(found))),
+            (Reply::Error(error), _) => Err(ParseError::Redis(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn keyspace(batch_size: usize) -> Keyspace {
+        let k: crate::config_file::Keyspace = toml::from_str(&format!(
+            r#"
+            commands = [{{ verb = "unlink" }}]
+            length = 4
+            cardinality = 1
+            key_type = "u32"
+            batch_size = {}
+            "#,
+            batch_size
+        ))
+        .unwrap();
+        Keyspace::new(&k)
+    }
+
+    fn keyspace_pair() -> Keyspace {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "copy" }]
+            length = 4
+            cardinality = 2
+            key_type = "u32"
+            "#,
+        )
+        .unwrap();
+        Keyspace::new(&k)
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            RedisError::parse(b"MOVED 3999 127.0.0.1:6381"),
+            RedisError::Moved {
+                slot: 3999,
+                endpoint: "127.0.0.1:6381".to_string()
             }
-            Ok("$") => {
-                // bulk string
-                let mut lines = buf.windows(2);
-                if let Some(line_end) = lines.position(|w| w == b"\r\n") {
-                    let msg = &buf[1..line_end];
-                    match str::from_utf8(msg) {
-                        Ok("-1") => {
-                            let _ = buffer.consume(line_end + 2);
-                            Ok(())
-                        }
-                        Ok(n) => {
-                            let len = n.parse::<usize>().map_err(|_| ParseError::Unknown)?;
-                            let response_end = len + line_end + 4;
-                            if response_end <= buf.len() {
-                                metrics::RESPONSE_HIT.increment();
-                                let _ = buffer.consume(response_end);
-                                Ok(())
-                            } else {
-                                Err(ParseError::Incomplete)
-                            }
-                        }
-                        Err(_) => Err(ParseError::Unknown),
-                    }
-                } else {
-                    Err(ParseError::Incomplete)
-                }
+        );
+        assert_eq!(
+            RedisError::parse(b"MOVED 3999 redis-1:6381"),
+            RedisError::Moved {
+                slot: 3999,
+                endpoint: "redis-1:6381".to_string()
             }
-            Ok("*") => {
-                // arrays
-                let msg = &buf[1..buf.len() - 2];
-                match str::from_utf8(msg) {
-                    Ok("-1") => {
-                        let response_end = buf.len();
-                        let _ = buffer.consume(response_end);
-                        Ok(())
-                    }
-                    Ok(_) => {
-                        // TODO: implement array parsing
-                        Err(ParseError::Unknown)
-                    }
-                    Err(_) => Err(ParseError::Unknown),
-                }
+        );
+        assert_eq!(
+            RedisError::parse(b"ASK 3999 127.0.0.1:6381"),
+            RedisError::Ask {
+                slot: 3999,
+                endpoint: "127.0.0.1:6381".to_string()
             }
-            _ => Err(ParseError::Unknown),
+        );
+        assert_eq!(RedisError::parse(b"MOVED 3999"), RedisError::Other);
+        assert_eq!(
+            RedisError::parse(b"WRONGTYPE Operation against a key holding the wrong kind of value"),
+            RedisError::WrongType
+        );
+        assert_eq!(
+            RedisError::parse(b"OOM command not allowed when used memory > 'maxmemory'"),
+            RedisError::Oom
+        );
+        assert_eq!(RedisError::parse(b"ERR unknown command"), RedisError::Other);
+        assert_eq!(RedisError::parse(b""), RedisError::Other);
+    }
+
+    #[test]
+    fn object_encoding() {
+        let mut buf = Vec::new();
+        Redis::object_encoding(b"0000".to_vec(), &mut buf);
+        assert_eq!(
+            &buf,
+            b"*3\r\n$6\r\nobject\r\n$8\r\nencoding\r\n$4\r\n0000\r\n"
+        );
+
+        assert_eq!(
+            Redis::decode_object_encoding(b"$8\r\nlistpack\r\n$-1\r\n"),
+            Ok((Some("listpack".to_string()), 14))
+        );
+        assert_eq!(Redis::decode_object_encoding(b"$-1\r\n"), Ok((None, 5)));
+        assert_eq!(
+            Redis::decode_object_encoding(b"-ERR no such key\r\n"),
+            Ok((None, 18))
+        );
+        assert_eq!(
+            Redis::decode_object_encoding(b"$8\r\nlist"),
+            Err(ParseError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn push() {
+        let mut buf = Vec::new();
+        Redis::subscribe(&["news".to_string()], &mut buf);
+        assert_eq!(&buf, b"*2\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n");
+
+        let confirm = b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n";
+        assert_eq!(Redis::decode_push(confirm), Ok((None, confirm.len())));
+
+        let message = b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n";
+        assert_eq!(
+            Redis::decode_push(message),
+            Ok((Some(&b"hello"[..]), message.len()))
+        );
+        assert_eq!(
+            Redis::decode_push(&message[..message.len() - 3]),
+            Err(ParseError::Incomplete)
+        );
+
+        let pattern = b"*4\r\n$8\r\npmessage\r\n$2\r\nn*\r\n$4\r\nnews\r\n$2\r\nhi\r\n";
+        assert_eq!(
+            Redis::decode_push(pattern),
+            Ok((Some(&b"hi"[..]), pattern.len()))
+        );
+    }
+
+    #[test]
+    fn ping() {
+        let mut buf = Vec::new();
+        Redis::ping(&Mode::Inline, &mut buf);
+        assert_eq!(&buf, b"ping\r\n");
+
+        let mut buf = Vec::new();
+        Redis::ping(&Mode::Resp, &mut buf);
+        assert_eq!(&buf, b"*1\r\n$4\r\nping\r\n");
+
+        assert_eq!(Redis::decode_verify(b"+PONG\r\n"), Ok(7));
+        assert_eq!(Redis::decode_verify(b"+PO"), Err(ParseError::Incomplete));
+        assert_eq!(
+            Redis::decode_verify(b"-NOAUTH Authentication required.\r\n"),
+            Err(ParseError::Unknown)
+        );
+    }
+
+    #[test]
+    fn decode_array() {
+        let buf = b"*2\r\n$3\r\nabc\r\n$3\r\ndef\r\n";
+        assert_eq!(Redis::decode_array(buf, 0), Ok((2, 2, buf.len())));
+
+        // missing elements are not found, and empty or nil arrays have none
+        let buf = b"*2\r\n$3\r\nabc\r\n$-1\r\n";
+        assert_eq!(Redis::decode_array(buf, 0), Ok((1, 2, buf.len())));
+        assert_eq!(Redis::decode_array(b"*0\r\n", 0), Ok((0, 0, 4)));
+        assert_eq!(Redis::decode_array(b"*-1\r\n", 0), Ok((0, 0, 5)));
+
+        // nested arrays and other element types are consumed
+        let buf = b"*2\r\n*2\r\n:1\r\n+OK\r\n$1\r\na\r\n";
+        assert_eq!(Redis::decode_array(buf, 0), Ok((2, 2, buf.len())));
+        let buf = b"*2\r\n*2\r\n:1\r\n-ERR\r\n$1\r\na\r\n";
+        assert_eq!(Redis::decode_array(buf, 0), Ok((1, 2, buf.len())));
+
+        // truncated arrays are incomplete
+        let buf = b"*2\r\n$3\r\nabc\r\n$3\r\nde";
+        assert_eq!(Redis::decode_array(buf, 0), Err(ParseError::Incomplete));
+        assert_eq!(
+            Redis::decode_array(b"*2\r\n$3\r\nabc\r\n", 0),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(Redis::decode_array(b"*2", 0), Err(ParseError::Incomplete));
+    }
+
+    #[test]
+    fn padding() {
+        let mut buf = Vec::new();
+        Redis::ping(&Mode::Inline, &mut buf);
+        Redis::padding(10, &mut buf);
+        assert_eq!(buf.len(), 16);
+        assert_eq!(&buf, b"ping\r\n         \n");
+
+        let mut buf = Vec::new();
+        Redis::padding(1, &mut buf);
+        assert_eq!(&buf, b"\n");
+    }
+
+    fn keyspace_ttl() -> Keyspace {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "setex" }]
+            length = 4
+            cardinality = 1
+            key_type = "u32"
+            values = [{ length = 2, cardinality = 1, field_type = "u32" }]
+            ttl = 60
+            "#,
+        )
+        .unwrap();
+        Keyspace::new(&k)
+    }
+
+    #[test]
+    fn setex() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::setex(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace_ttl(),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"setex 0000 60 00\r\n");
+
+        let mut buf = Vec::new();
+        Redis::setex(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace_ttl(),
+            &mut buf,
+        );
+        assert_eq!(
+            &buf,
+            b"*4\r\n$5\r\nsetex\r\n$4\r\n0000\r\n$2\r\n60\r\n$2\r\n00\r\n"
+        );
+    }
+
+    #[test]
+    fn wait() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "set" }]
+            length = 4
+            cardinality = 1
+            values = [{ length = 2 }]
+            wait = { replicas = 2, timeout_ms = 100, after_writes = true }
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        assert_eq!(keyspace.wait_command(), Some(1));
+
+        let mut buf = Vec::new();
+        Redis::wait(&Mode::Inline, &keyspace, &mut buf);
+        assert_eq!(&buf, b"wait 2 100\r\n");
+
+        let mut buf = Vec::new();
+        Redis::wait(&Mode::Resp, &keyspace, &mut buf);
+        assert_eq!(&buf, b"*3\r\n$4\r\nwait\r\n$1\r\n2\r\n$3\r\n100\r\n");
+    }
+
+    #[test]
+    fn psetex() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::psetex(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace_ttl(),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"psetex 0000 60000 00\r\n");
+
+        let mut buf = Vec::new();
+        Redis::psetex(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace_ttl(),
+            &mut buf,
+        );
+        assert_eq!(
+            &buf,
+            b"*4\r\n$6\r\npsetex\r\n$4\r\n0000\r\n$5\r\n60000\r\n$2\r\n00\r\n"
+        );
+    }
+
+    #[test]
+    fn set_ttl() {
+        let keyspace = |ttl| {
+            let k: crate::config_file::Keyspace = toml::from_str(&format!(
+                r#"
+                commands = [{{ verb = "set" }}]
+                length = 4
+                cardinality = 1
+                key_type = "u32"
+                values = [{{ length = 2, cardinality = 1, field_type = "u32" }}]
+                ttl = {}
+                "#,
+                ttl
+            ))
+            .unwrap();
+            Keyspace::new(&k)
+        };
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::set(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(0),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"set 0000 00\r\n");
+
+        let mut buf = Vec::new();
+        Redis::set(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(60),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"set 0000 00 EX 60\r\n");
+
+        let mut buf = Vec::new();
+        Redis::set(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace(60),
+            &mut buf,
+        );
+        assert_eq!(
+            &buf,
+            b"*5\r\n$3\r\nset\r\n$4\r\n0000\r\n$2\r\n00\r\n$2\r\nEX\r\n$2\r\n60\r\n"
+        );
+    }
+
+    #[test]
+    fn incr() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::incr(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(1),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"incr 0000\r\n");
+
+        let mut buf = Vec::new();
+        Redis::incr(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace(1),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"*2\r\n$4\r\nincr\r\n$4\r\n0000\r\n");
+
+        let mut buf = Vec::new();
+        Redis::decr(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(1),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"decr 0000\r\n");
+    }
+
+    #[test]
+    fn decrby() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::decrby(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(1),
+            5,
+            &mut buf,
+        );
+        assert_eq!(&buf, b"decrby 0000 5\r\n");
+
+        let mut buf = Vec::new();
+        Redis::decrby(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace(1),
+            5,
+            &mut buf,
+        );
+        assert_eq!(&buf, b"*3\r\n$6\r\ndecrby\r\n$4\r\n0000\r\n$1\r\n5\r\n");
+
+        let mut buf = Vec::new();
+        Redis::incrby(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(1),
+            -2,
+            &mut buf,
+        );
+        assert_eq!(&buf, b"incrby 0000 -2\r\n");
+    }
+
+    #[test]
+    fn getdel() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::getdel(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(1),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"getdel 0000\r\n");
+
+        let mut buf = Vec::new();
+        Redis::getdel(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace(1),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"*2\r\n$6\r\ngetdel\r\n$4\r\n0000\r\n");
+    }
+
+    #[test]
+    fn copy() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::copy(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace_pair(),
+            &mut buf,
+        );
+        assert!(&buf == b"copy 0000 0001\r\n" || &buf == b"copy 0001 0000\r\n");
+
+        let mut buf = Vec::new();
+        Redis::copy(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace_pair(),
+            &mut buf,
+        );
+        assert!(
+            &buf == b"*3\r\n$4\r\ncopy\r\n$4\r\n0000\r\n$4\r\n0001\r\n"
+                || &buf == b"*3\r\n$4\r\ncopy\r\n$4\r\n0001\r\n$4\r\n0000\r\n"
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut buf = Vec::new();
+        Redis::rename(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace_pair(),
+            &mut buf,
+        );
+        assert!(&buf == b"rename 0000 0001\r\n" || &buf == b"rename 0001 0000\r\n");
+
+        let mut buf = Vec::new();
+        Redis::rename(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace_pair(),
+            &mut buf,
+        );
+        assert!(
+            &buf == b"*3\r\n$6\r\nrename\r\n$4\r\n0000\r\n$4\r\n0001\r\n"
+                || &buf == b"*3\r\n$6\r\nrename\r\n$4\r\n0001\r\n$4\r\n0000\r\n"
+        );
+    }
+
+    fn unlink_keyspace(batch_size: usize) -> Keyspace {
+        let k: crate::config_file::Keyspace = toml::from_str(&format!(
+            r#"
+            commands = [{{ verb = "unlink" }}]
+            length = 4
+            cardinality = 100
+            key_type = "u32"
+            batch_size = {}
+            "#,
+            batch_size
+        ))
+        .unwrap();
+        Keyspace::new(&k)
+    }
+
+    /// Parse the keys of an inline unlink request
+    fn unlink_keys(buf: &[u8]) -> Vec<u32> {
+        let request = std::str::from_utf8(buf).unwrap();
+        let request = request.strip_suffix("\r\n").unwrap();
+        request
+            .strip_prefix("unlink ")
+            .unwrap()
+            .split(' ')
+            .map(|key| {
+                assert_eq!(key.len(), 4);
+                key.parse().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unlink() {
+        let keyspace = unlink_keyspace(1);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // keys are drawn from the whole keyspace
+        let mut seen = HashSet::new();
+        for _ in 0..1000 {
+            let mut buf = Vec::new();
+            Redis::unlink(
+                &mut rng,
+                &mut RequestContext::default(),
+                &Mode::Inline,
+                &keyspace,
+                &mut buf,
+            );
+            let keys = unlink_keys(&buf);
+            assert_eq!(keys.len(), 1);
+            assert!(keys[0] < 100);
+            seen.insert(keys[0]);
         }
+        assert!(seen.len() > 50);
+
+        let mut buf = Vec::new();
+        Redis::unlink(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace,
+            &mut buf,
+        );
+        assert!(buf.starts_with(b"*2\r\n$6\r\nunlink\r\n$4\r\n"));
+        assert_eq!(
+            buf.len(),
+            b"*2\r\n$6\r\nunlink\r\n".len() + b"$4\r\n0000\r\n".len()
+        );
+    }
+
+    #[test]
+    fn mget() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }]
+            length = 4
+            cardinality = 3
+            key_type = "u32"
+            batch_size = 3
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // each key of the batch is distinct
+        let mut buf = Vec::new();
+        Redis::mget(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace,
+            &mut buf,
+        );
+        let request = std::str::from_utf8(&buf).unwrap();
+        let request = request.strip_suffix("\r\n").unwrap();
+        let mut keys: Vec<&str> = request.strip_prefix("mget ").unwrap().split(' ').collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["0000", "0001", "0002"]);
+
+        let mut buf = Vec::new();
+        Redis::mget(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace,
+            &mut buf,
+        );
+        assert!(buf.starts_with(b"*4\r\n$4\r\nmget\r\n$4\r\n"));
+        assert_eq!(
+            buf.len(),
+            b"*4\r\n$4\r\nmget\r\n".len() + 3 * b"$4\r\n0000\r\n".len()
+        );
+
+        // a keyspace with fewer keys than the batch repeats them
+        let mut buf = Vec::new();
+        Redis::mget(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Inline,
+            &keyspace(2),
+            &mut buf,
+        );
+        assert_eq!(&buf, b"mget 0000 0000\r\n");
+    }
+
+    #[test]
+    fn unlink_multi() {
+        let keyspace = unlink_keyspace(3);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // each key of the batch is distinct and within the keyspace
+        for _ in 0..100 {
+            let mut buf = Vec::new();
+            Redis::unlink(
+                &mut rng,
+                &mut RequestContext::default(),
+                &Mode::Inline,
+                &keyspace,
+                &mut buf,
+            );
+            let mut keys = unlink_keys(&buf);
+            assert_eq!(keys.len(), 3);
+            assert!(keys.iter().all(|key| *key < 100));
+            keys.sort_unstable();
+            keys.dedup();
+            assert_eq!(keys.len(), 3);
+        }
+
+        let mut buf = Vec::new();
+        Redis::unlink(
+            &mut rng,
+            &mut RequestContext::default(),
+            &Mode::Resp,
+            &keyspace,
+            &mut buf,
+        );
+        assert!(buf.starts_with(b"*4\r\n$6\r\nunlink\r\n$4\r\n"));
+        assert_eq!(
+            buf.len(),
+            b"*4\r\n$6\r\nunlink\r\n".len() + 3 * b"$4\r\n0000\r\n".len()
+        );
+    }
+
+    #[test]
+    fn decode_hit() {
+        let redis = Redis {
+            mode: Mode::Resp,
+            rng: SmallRng::seed_from_u64(0),
+        };
+        let stream = crate::session::PipeStream::spawn("cat").unwrap();
+        let mut session = Session::pipe_with_capacity(stream, 1024, 1024 * 1024);
+        session
+            .write_all(b"$3\r\nabc\r\n$-1\r\n*2\r\n$1\r\na\r\n$-1\r\n+OK\r\n")
+            .unwrap();
+        session.flush().unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while session.buffer().len() < 35 {
+            assert!(std::time::Instant::now() < deadline, "timed out");
+            let _ = session.fill_buf();
+        }
+
+        // only a response with a value for every key is a hit
+        assert_eq!(redis.decode(&mut session), Ok(Response { hit: true }));
+        assert_eq!(redis.decode(&mut session), Ok(Response { hit: false }));
+        assert_eq!(redis.decode(&mut session), Ok(Response { hit: false }));
+        assert_eq!(redis.decode(&mut session), Ok(Response { hit: false }));
+        assert!(session.buffer().is_empty());
     }
 }
@@ -7,7 +7,7 @@ use std::io::BufRead;
 
 use crate::codec::*;
 use crate::config::*;
-use crate::config_file::Verb;
+use crate::config_file::{Command, Verb};
 use crate::*;
 
 use std::io::Write;
@@ -16,23 +16,30 @@ use rand::rngs::SmallRng;
 use rand::SeedableRng;
 
 pub struct ThriftCache {
-    config: Arc<Config>,
     rng: SmallRng,
 }
 
 impl ThriftCache {
-    pub fn new(config: Arc<Config>) -> Self {
+    pub fn new(_config: Arc<Config>) -> Self {
         Self {
-            config,
             rng: SmallRng::from_entropy(),
         }
     }
 
-    fn append(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn append(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let mut values = Vec::new();
         for _ in 0..keyspace.batch_size() {
-            values.push(keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec()));
+            values.push(
+                keyspace
+                    .generate_value(rng, request)
+                    .unwrap_or_else(|| b"".to_vec()),
+            );
         }
 
         let mut buffer = thrift::ThriftBuffer::new();
@@ -74,11 +81,20 @@ impl ThriftCache {
         let _ = buf.write(buffer.as_bytes());
     }
 
-    fn appendx(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn appendx(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let mut values = Vec::new();
         for _ in 0..keyspace.batch_size() {
-            values.push(keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec()));
+            values.push(
+                keyspace
+                    .generate_value(rng, request)
+                    .unwrap_or_else(|| b"".to_vec()),
+            );
         }
 
         let mut buffer = thrift::ThriftBuffer::new();
@@ -120,9 +136,14 @@ impl ThriftCache {
         let _ = buf.write(buffer.as_bytes());
     }
 
-    fn count(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
-        let timeout = None;
+    fn count(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+        timeout: Option<i32>,
+    ) {
+        let key = keyspace.generate_key(rng, request);
 
         let mut buffer = thrift::ThriftBuffer::new();
         buffer.protocol_header();
@@ -159,8 +180,14 @@ impl ThriftCache {
         let _ = buf.write(buffer.as_bytes());
     }
 
-    fn get(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn get(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+        timeout: Option<i32>,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let mut fields = Vec::new();
         for _ in 0..keyspace.batch_size() {
             fields.push(
@@ -169,7 +196,6 @@ impl ThriftCache {
                     .unwrap_or_else(|| b"".to_vec()),
             );
         }
-        let timeout = None;
 
         let mut buffer = thrift::ThriftBuffer::new();
         buffer.protocol_header();
@@ -216,8 +242,14 @@ impl ThriftCache {
         let _ = buf.write(buffer.as_bytes());
     }
 
-    fn put(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn put(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+        timeout: Option<i32>,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let mut fields = Vec::new();
         for _ in 0..keyspace.batch_size() {
             fields.push(
@@ -228,9 +260,12 @@ impl ThriftCache {
         }
         let mut values = Vec::new();
         for _ in 0..keyspace.batch_size() {
-            values.push(keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec()));
+            values.push(
+                keyspace
+                    .generate_value(rng, request)
+                    .unwrap_or_else(|| b"".to_vec()),
+            );
         }
-        let timeout = None;
         let timestamp = None;
         let ttl = keyspace.ttl();
 
@@ -301,8 +336,14 @@ impl ThriftCache {
         let _ = buf.write(buffer.as_bytes());
     }
 
-    fn remove(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn remove(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+        timeout: Option<i32>,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let mut fields = Vec::new();
         for _ in 0..keyspace.batch_size() {
             fields.push(
@@ -311,7 +352,6 @@ impl ThriftCache {
                     .unwrap_or_else(|| b"".to_vec()),
             );
         }
-        let timeout = None;
         let timestamp = None;
         let count = None;
 
@@ -372,8 +412,13 @@ impl ThriftCache {
         let _ = buf.write(buffer.as_bytes());
     }
 
-    fn range(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn range(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let mut fields = Vec::new();
         for _ in 0..keyspace.batch_size() {
             fields.push(
@@ -384,7 +429,11 @@ impl ThriftCache {
         }
         let mut values = Vec::new();
         for _ in 0..keyspace.batch_size() {
-            values.push(keyspace.generate_value(rng).unwrap_or_else(|| b"".to_vec()));
+            values.push(
+                keyspace
+                    .generate_value(rng, request)
+                    .unwrap_or_else(|| b"".to_vec()),
+            );
         }
         let start = None;
         let stop = None;
@@ -431,13 +480,18 @@ impl ThriftCache {
     }
 
     #[allow(dead_code)]
-    fn scan(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn scan(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+        timeout: Option<i32>,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let start_field = None;
         let end_field = None;
         let ascending = None;
         let limit = None;
-        let timeout = None;
 
         let mut buffer = thrift::ThriftBuffer::new();
         buffer.protocol_header();
@@ -498,11 +552,16 @@ impl ThriftCache {
         let _ = buf.write(buffer.as_bytes());
     }
 
-    fn trim(rng: &mut SmallRng, keyspace: &Keyspace, buf: &mut Session) {
-        let key = keyspace.generate_key(rng);
+    fn trim(
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        keyspace: &Keyspace,
+        buf: &mut Session,
+        timeout: Option<i32>,
+    ) {
+        let key = keyspace.generate_key(rng, request);
         let target_size = 1;
         let trim_from_smallest = true;
-        let timeout = None;
 
         let mut buffer = thrift::ThriftBuffer::new();
         buffer.protocol_header();
@@ -549,25 +608,44 @@ impl ThriftCache {
 }
 
 impl Codec for ThriftCache {
-    fn encode(&mut self, buf: &mut Session) {
-        let keyspace = self.config.choose_keyspace(&mut self.rng);
-        let command = keyspace.choose_command(&mut self.rng);
+    fn encode(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+    ) {
+        self.encode_with_deadline(buf, keyspace, command, request, None)
+    }
+
+    /// The deadline is sent in the timeout field of the request, in
+    /// milliseconds, for the methods which have one
+    fn encode_with_deadline(
+        &mut self,
+        buf: &mut Session,
+        keyspace: &Keyspace,
+        command: &Command,
+        request: &mut RequestContext,
+        deadline: Option<std::time::Duration>,
+    ) {
+        let timeout = deadline.map(|d| d.as_millis().min(i32::MAX as u128) as i32);
+        let rng = &mut self.rng;
         match command.verb() {
-            Verb::Rpush => Self::append(&mut self.rng, keyspace, buf),
-            Verb::Rpushx => Self::appendx(&mut self.rng, keyspace, buf),
-            Verb::Count => Self::count(&mut self.rng, keyspace, buf),
-            Verb::Hget => Self::get(&mut self.rng, keyspace, buf),
-            Verb::Hset => Self::put(&mut self.rng, keyspace, buf),
-            Verb::Hdel => Self::remove(&mut self.rng, keyspace, buf),
-            Verb::Lrange => Self::range(&mut self.rng, keyspace, buf),
-            Verb::Ltrim => Self::trim(&mut self.rng, keyspace, buf),
+            Verb::Rpush => Self::append(rng, request, keyspace, buf),
+            Verb::Rpushx => Self::appendx(rng, request, keyspace, buf),
+            Verb::Count => Self::count(rng, request, keyspace, buf, timeout),
+            Verb::Hget => Self::get(rng, request, keyspace, buf, timeout),
+            Verb::Hset => Self::put(rng, request, keyspace, buf, timeout),
+            Verb::Hdel => Self::remove(rng, request, keyspace, buf, timeout),
+            Verb::Lrange => Self::range(rng, request, keyspace, buf),
+            Verb::Ltrim => Self::trim(rng, request, keyspace, buf, timeout),
             _ => {
                 unimplemented!()
             }
         }
     }
 
-    fn decode(&self, buffer: &mut Session) -> Result<(), ParseError> {
+    fn decode(&self, buffer: &mut Session) -> Result<Response, ParseError> {
         // no-copy borrow as a slice
         let buf: &[u8] = (*buffer).buffer();
 
@@ -579,7 +657,7 @@ impl Codec for ThriftCache {
                 Some(b) => {
                     if b == bytes {
                         buffer.consume(b as usize);
-                        Ok(())
+                        Ok(Response::default())
                     } else {
                         Err(ParseError::Incomplete)
                     }
@@ -591,3 +669,57 @@ impl Codec for ThriftCache {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "hget" }]
+            length = 8
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut codec = ThriftCache {
+            rng: SmallRng::seed_from_u64(0),
+        };
+        let stream = crate::session::PipeStream::spawn("cat").unwrap();
+        let mut session = Session::pipe_with_capacity(stream, 1024, 1024 * 1024);
+
+        // the deadline is carried in the timeout field, in milliseconds
+        codec.encode_with_deadline(
+            &mut session,
+            &keyspace,
+            &keyspace.commands()[0],
+            &mut RequestContext::default(),
+            Some(std::time::Duration::from_micros(100_500)),
+        );
+        assert!(session.write_buffer().ends_with(&[
+            thrift::I32,
+            0,
+            11,
+            0,
+            0,
+            0,
+            100,
+            thrift::STOP,
+            thrift::STOP
+        ]));
+        let with_deadline = session.write_buffer().len();
+
+        // without a deadline the field is left out
+        let stream = crate::session::PipeStream::spawn("cat").unwrap();
+        let mut session = Session::pipe_with_capacity(stream, 1024, 1024 * 1024);
+        codec.encode(
+            &mut session,
+            &keyspace,
+            &keyspace.commands()[0],
+            &mut RequestContext::default(),
+        );
+        assert_eq!(session.write_buffer().len(), with_deadline - 7);
+    }
+}
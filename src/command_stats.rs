@@ -0,0 +1,218 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Optional request, response, error, and hit counts and response latency for
+//! each command of a target. A pipeline may carry a mix of commands, so each
+//! response is matched to the command which was sent in the same position.
+
+use crate::config::Keyspace;
+use crate::config_file::Command;
+use crate::Config;
+use crate::Instant;
+use core::time::Duration;
+use rustcommon_heatmap::AtomicHeatmap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type Heatmap = AtomicHeatmap<u64, rustcommon_heatmap::AtomicU64>;
+
+struct Counts {
+    label: String,
+    /// the label in the form used for metric names
+    key: String,
+    get: bool,
+    request: AtomicU64,
+    response: AtomicU64,
+    error: AtomicU64,
+    hit: AtomicU64,
+    latency: Heatmap,
+}
+
+/// The counts for one command since the previous window
+pub struct CommandWindow<'a> {
+    pub label: &'a str,
+    pub key: &'a str,
+    /// whether the command is a get, for which the hit-rate is meaningful
+    pub get: bool,
+    pub requests: u64,
+    pub responses: u64,
+    pub errors: u64,
+    pub hits: u64,
+    pub percentiles: Vec<(&'static str, u64)>,
+}
+
+impl CommandWindow<'_> {
+    /// The percentage of responses which were hits
+    pub fn hitrate(&self) -> f64 {
+        if self.responses == 0 {
+            0.0
+        } else {
+            100.0 * self.hits as f64 / self.responses as f64
+        }
+    }
+}
+
+pub struct CommandStats {
+    /// counts for each command, indexed by keyspace and then by command
+    keyspaces: Vec<Vec<Counts>>,
+}
+
+impl CommandStats {
+    pub fn new(config: &Config, prefix: Option<&str>) -> Self {
+        let keyspaces = config
+            .keyspaces()
+            .iter()
+            .enumerate()
+            .map(|(k, keyspace)| {
+                keyspace
+                    .commands()
+                    .iter()
+                    .enumerate()
+                    .map(|(c, command)| {
+                        let verb = verb_label(keyspace, c, command);
+                        let label = match prefix {
+                            Some(prefix) => format!("{} keyspace {} {}", prefix, k, verb),
+                            None => format!("keyspace {} {}", k, verb),
+                        };
+                        let key = format!("command/{}", label.replace(' ', "_"));
+                        Counts {
+                            label,
+                            key,
+                            get: command.verb().is_get(),
+                            request: AtomicU64::new(0),
+                            response: AtomicU64::new(0),
+                            error: AtomicU64::new(0),
+                            hit: AtomicU64::new(0),
+                            latency: Heatmap::new(
+                                config.general().latency_max(),
+                                3,
+                                config.general().latency_window(),
+                                Duration::from_millis(1000),
+                            ),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { keyspaces }
+    }
+
+    fn counts(&self, index: (usize, usize)) -> &Counts {
+        &self.keyspaces[index.0][index.1]
+    }
+
+    pub fn increment_request(&self, index: (usize, usize)) {
+        self.counts(index).request.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a successful response and record its latency, in the configured
+    /// latency unit
+    pub fn increment_response(&self, index: (usize, usize), hit: bool, now: Instant, latency: u64) {
+        let counts = self.counts(index);
+        counts.response.fetch_add(1, Ordering::Relaxed);
+        if hit {
+            counts.hit.fetch_add(1, Ordering::Relaxed);
+        }
+        counts.latency.increment(now, latency, 1);
+    }
+
+    /// Count an error response, which also completes the request
+    pub fn increment_error(&self, index: (usize, usize)) {
+        let counts = self.counts(index);
+        counts.response.fetch_add(1, Ordering::Relaxed);
+        counts.error.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the counts for each command since the previous call, along with
+    /// the latency percentiles over the latency window
+    pub fn take(&self) -> Vec<CommandWindow> {
+        self.keyspaces
+            .iter()
+            .flatten()
+            .map(|counts| CommandWindow {
+                label: counts.label.as_str(),
+                key: counts.key.as_str(),
+                get: counts.get,
+                requests: counts.request.swap(0, Ordering::Relaxed),
+                responses: counts.response.swap(0, Ordering::Relaxed),
+                errors: counts.error.swap(0, Ordering::Relaxed),
+                hits: counts.hit.swap(0, Ordering::Relaxed),
+                percentiles: [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("p999", 99.9)]
+                    .iter()
+                    .map(|(label, p)| (*label, counts.latency.percentile(*p).unwrap_or(0)))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// The name of the command, after the name of its workload if it belongs to
+/// one
+pub fn verb_label(keyspace: &Keyspace, index: usize, command: &Command) -> String {
+    let verb = serde_json::to_value(command.verb())
+        .ok()
+        .and_then(|v| v.as_str().map(|v| v.to_string()))
+        .unwrap_or_default();
+    match keyspace.workload(index) {
+        Some(workload) => format!("{} {}", workload.name(), verb),
+        None => verb,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_command() {
+        let config = Config::parse(
+            r#"
+            [general]
+            protocol = "memcache"
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [[keyspace]]
+            commands = [{ verb = "get" }]
+
+            [[keyspace]]
+            commands = [{ verb = "set" }]
+            values = [{ length = 16 }]
+            "#,
+        );
+        let stats = CommandStats::new(&config, None);
+
+        let now = Instant::now();
+        for hit in [true, true, false, true] {
+            stats.increment_request((0, 0));
+            stats.increment_response((0, 0), hit, now, 100);
+        }
+        stats.increment_request((1, 0));
+        stats.increment_response((1, 0), false, now, 200);
+        stats.increment_request((1, 0));
+        stats.increment_error((1, 0));
+
+        let windows = stats.take();
+        assert_eq!(windows.len(), 2);
+        let (get, set) = (&windows[0], &windows[1]);
+        assert_eq!(get.label, "keyspace 0 get");
+        assert_eq!(get.key, "command/keyspace_0_get");
+        assert!(get.get);
+        assert_eq!(
+            (get.requests, get.responses, get.errors, get.hits),
+            (4, 4, 0, 3)
+        );
+        assert_eq!(get.hitrate(), 75.0);
+        assert_eq!(set.label, "keyspace 1 set");
+        assert!(!set.get);
+        assert_eq!(
+            (set.requests, set.responses, set.errors, set.hits),
+            (2, 2, 1, 0)
+        );
+        assert!(get.percentiles[0].1 < set.percentiles[0].1);
+
+        // the counts are for each window
+        assert_eq!(stats.take()[0].requests, 0);
+    }
+}
@@ -3,17 +3,25 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use crate::config_file::*;
+use crate::think_time::ThinkTimes;
 use rand::rngs::SmallRng;
 use rand::Rng;
 use rand_distr::Alphanumeric;
 use rand_distr::Uniform;
 use rand_distr::{Distribution, WeightedAliasIndex};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use zipf::ZipfDistribution;
 
 pub const NAME: &str = env!("CARGO_PKG_NAME");
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The length of the client id and sequence number embedded in unique values
+const UNIQUE_TAG_LEN: usize = 24;
+
 pub struct Config {
     general: General,
     debug: Debug,
@@ -22,8 +30,137 @@ pub struct Config {
     request: Request,
     tls: Option<Tls>,
     endpoints: Vec<SocketAddr>,
+    read_endpoints: Vec<SocketAddr>,
+    write_endpoints: Vec<SocketAddr>,
     keyspaces: Vec<Keyspace>,
+    keyspace_configs: Vec<crate::config_file::Keyspace>,
     keyspace_dist: WeightedAliasIndex<usize>,
+    chaos: HashMap<SocketAddr, f64>,
+    /// the TLS settings of endpoints which override the `[tls]` section, where
+    /// `None` is plaintext
+    tls_endpoints: HashMap<SocketAddr, Option<Tls>>,
+    tls_endpoint_configs: Vec<TlsEndpoint>,
+    name: String,
+    targets: Vec<Config>,
+    cluster: bool,
+    exec: Option<String>,
+    think_times: Option<Arc<ThinkTimes>>,
+}
+
+/// The longest request padding, which is the redis limit on the length of an
+/// inline request
+const MAX_PAD_REQUEST_TO: usize = 64 * 1024;
+
+/// The placeholder endpoint of a target which runs a command instead of
+/// connecting to endpoints
+pub fn exec_endpoint() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 0))
+}
+
+/// Whether the verb sets a value, and so can be used to fill a keyspace
+fn sets_value(verb: Verb) -> bool {
+    matches!(verb, Verb::Set | Verb::Setex | Verb::Psetex)
+}
+
+/// The name of a config enum as it is written in the config file
+fn serde_name<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(|v| v.to_string()))
+        .unwrap_or_default()
+}
+
+/// The state of a single request as its keys and values are generated
+#[derive(Default)]
+pub struct RequestContext {
+    /// a key chosen before the request was encoded, which is used for its
+    /// first key, so that the request can be routed by it
+    key: Option<Vec<u8>>,
+    /// whether the request writes the keys being filled during warmup, so
+    /// that its keys are taken in order from the fill
+    fill: bool,
+    /// the id of the request, which is prefixed to the keys generated for it
+    /// so that the request can be found in server logs
+    id: Option<u64>,
+    /// the index of the key most recently generated for the request, which
+    /// chooses the size of the value written to it when sizes follow
+    /// popularity
+    key_index: Option<u64>,
+    /// the configured length of the value generated for the request, used
+    /// to tag the request with the size class of its value
+    value_length: Option<usize>,
+    /// the TTL of the value generated for the request, if its template has
+    /// one, so that a set carries the TTL of its value
+    value_ttl: Option<usize>,
+}
+
+impl RequestContext {
+    /// A request whose first key is the given key
+    pub fn with_key(key: Vec<u8>) -> Self {
+        Self {
+            key: Some(key),
+            ..Default::default()
+        }
+    }
+
+    /// Mark the request as one which fills the keyspace during warmup
+    pub fn set_fill(&mut self, fill: bool) {
+        self.fill = fill;
+    }
+
+    /// Tag the keys generated for the request with its id
+    pub fn set_id(&mut self, id: Option<u64>) {
+        self.id = id;
+    }
+
+    /// The configured length of the value generated for the request, if any
+    pub fn value_length(&self) -> Option<usize> {
+        self.value_length
+    }
+}
+
+/// Append the epoch of the rotation period containing `now`, in seconds since
+/// the unix epoch, so that every client uses the same suffix at the same time
+fn rotate_key(mut key: Vec<u8>, now: u64, period: u64) -> Vec<u8> {
+    key.extend_from_slice(format!(":{:x}", now / period).as_bytes());
+    key
+}
+
+/// The fully resolved configuration, after any overrides, in the layout of
+/// the config file
+#[derive(Serialize)]
+struct Resolved<'a> {
+    general: &'a General,
+    debug: &'a Debug,
+    waterfall: &'a Waterfall,
+    connection: &'a Connection,
+    request: &'a Request,
+    tls: Option<&'a Tls>,
+    target: Vec<ResolvedTarget<'a>>,
+    chaos: Vec<ResolvedChaos>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tls_endpoint: &'a [TlsEndpoint],
+}
+
+#[derive(Serialize)]
+struct ResolvedTarget<'a> {
+    name: &'a str,
+    protocol: Protocol,
+    endpoints: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    read_endpoints: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    write_endpoints: Vec<String>,
+    keyspace: &'a [crate::config_file::Keyspace],
+    cluster: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exec: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ResolvedChaos {
+    endpoint: String,
+    probability: f64,
 }
 
 #[derive(Clone)]
@@ -44,6 +181,7 @@ impl KeyDistribution {
 #[derive(Clone)]
 pub struct Keyspace {
     length: usize,
+    length_max: usize,
     weight: usize,
     cardinality: u32,
     commands: Vec<Command>,
@@ -53,12 +191,197 @@ pub struct Keyspace {
     values: Vec<Value>,
     value_dist: Option<WeightedAliasIndex<usize>>,
     ttl: usize,
+    ttls: Vec<Ttl>,
+    ttl_dist: Option<WeightedAliasIndex<usize>>,
     key_type: FieldType,
     batch_size: usize,
     key_distribution: KeyDistribution,
+    unique: Option<(u32, Arc<AtomicU64>)>,
+    fill: Option<(u64, Arc<AtomicU64>)>,
+    /// the commands of each sequence, by index
+    sequences: Vec<Vec<usize>>,
+    wait: Option<Wait>,
+    /// the index of the `WAIT` command which follows each write, if enabled
+    wait_command: Option<usize>,
+    /// the distribution of the next command after each command, when commands
+    /// are chosen by a markov model
+    transitions: Vec<WeightedAliasIndex<f64>>,
+    /// the seconds between changes of the key suffix, if keys are rotated
+    key_rotation_secs: Option<u64>,
+    workloads: Vec<Workload>,
+    /// when value sizes follow key popularity, the value for each band of key
+    /// indices, as the end of the band and the index of the value
+    size_bands: Vec<(u64, usize)>,
+}
+
+/// A workload of the keyspace, whose commands follow the configured commands
+/// and are only chosen by the mix of the workload
+pub struct Workload {
+    name: String,
+    ratelimit: usize,
+    commands: std::ops::Range<usize>,
+    command_dist: WeightedAliasIndex<usize>,
+}
+
+impl Workload {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn ratelimit(&self) -> usize {
+        self.ratelimit
+    }
+
+    /// Whether the command, by its index in the keyspace, belongs to this
+    /// workload
+    pub fn contains(&self, command: usize) -> bool {
+        self.commands.contains(&command)
+    }
+
+    /// Choose a command of the workload by weight, returning its index in the
+    /// keyspace
+    pub fn choose_command(&self, rng: &mut SmallRng) -> usize {
+        self.commands.start + self.command_dist.sample(rng)
+    }
 }
 
 impl Keyspace {
+    pub fn new(k: &crate::config_file::Keyspace) -> Self {
+        let inner_keys = k.inner_keys();
+        let inner_key_weights: Vec<usize> = if inner_keys.is_empty() {
+            Vec::new()
+        } else {
+            inner_keys.iter().map(|v| v.weight()).collect()
+        };
+        let inner_key_dist = if inner_keys.is_empty() {
+            None
+        } else {
+            Some(WeightedAliasIndex::new(inner_key_weights).unwrap())
+        };
+
+        // the commands of each sequence follow the configured commands and are
+        // never chosen on their own, while the sequences are chosen by the
+        // indices which follow all of the commands
+        let mut commands = k.commands();
+        let mut command_weights: Vec<usize> = commands.iter().map(|v| v.weight()).collect();
+        let mut sequences = Vec::new();
+        for sequence in k.sequences() {
+            let start = commands.len();
+            commands.extend_from_slice(sequence.commands());
+            command_weights.resize(commands.len(), 0);
+            sequences.push((start..commands.len()).collect());
+        }
+        // the wait which follows each write is never chosen on its own
+        let wait_command = match k.wait() {
+            Some(wait) if wait.after_writes() => {
+                commands.push(Command::new(Verb::Wait, 0));
+                command_weights.push(0);
+                Some(commands.len() - 1)
+            }
+            _ => None,
+        };
+        // nor are the commands of each workload, which are chosen at the rate
+        // of the workload instead
+        let mut workloads = Vec::new();
+        for workload in k.workloads() {
+            let start = commands.len();
+            commands.extend_from_slice(workload.commands());
+            command_weights.resize(commands.len(), 0);
+            let weights: Vec<usize> = workload.commands().iter().map(|c| c.weight()).collect();
+            workloads.push(Workload {
+                name: workload.name().to_string(),
+                ratelimit: workload.ratelimit(),
+                commands: start..commands.len(),
+                command_dist: WeightedAliasIndex::new(weights).unwrap(),
+            });
+        }
+        command_weights.extend(k.sequences().iter().map(|s| s.weight()));
+        let command_dist = WeightedAliasIndex::new(command_weights).unwrap();
+        let transitions = k
+            .transitions()
+            .into_iter()
+            .map(|row| WeightedAliasIndex::new(row).unwrap())
+            .collect();
+
+        let values = k.values();
+        let value_weights: Vec<usize> = if values.is_empty() {
+            Vec::new()
+        } else {
+            values.iter().map(|v| v.weight()).collect()
+        };
+        let value_dist = if values.is_empty() {
+            None
+        } else {
+            Some(WeightedAliasIndex::new(value_weights).unwrap())
+        };
+
+        let ttls = k.ttls();
+        let ttl_dist = if ttls.is_empty() {
+            None
+        } else {
+            let ttl_weights: Vec<usize> = ttls.iter().map(|v| v.weight()).collect();
+            Some(WeightedAliasIndex::new(ttl_weights).unwrap())
+        };
+
+        let key_distribution = match k.key_distribution {
+            None => KeyDistribution::Uniform(Uniform::new(0, k.cardinality() as usize)),
+            Some(ref kd) => match kd.model {
+                KeyDistributionModel::Uniform => {
+                    KeyDistribution::Uniform(Uniform::new(0, k.cardinality() as usize))
+                }
+                KeyDistributionModel::Zipf => {
+                    let exponent = kd
+                        .parameters
+                        .get("exponent")
+                        .unwrap_or(&"1.0".to_owned())
+                        .parse::<f64>()
+                        .expect("bad exponent for zipf distribution");
+                    KeyDistribution::Zipf(
+                        ZipfDistribution::new(k.cardinality() as usize, exponent)
+                            .expect("bad zipf config"),
+                    )
+                }
+            },
+        };
+
+        let size_bands = match k.size_by_popularity() {
+            Some(order) => size_bands(&values, k.cardinality(), order),
+            None => Vec::new(),
+        };
+
+        Self {
+            length: k.length(),
+            length_max: k.length_max().unwrap_or_else(|| k.length()),
+            weight: k.weight(),
+            cardinality: k.cardinality(),
+            commands,
+            command_dist,
+            inner_keys: k.inner_keys(),
+            inner_key_dist,
+            values: k.values(),
+            value_dist,
+            ttl: k.ttl(),
+            ttls,
+            ttl_dist,
+            key_type: k.key_type(),
+            batch_size: k.batch_size(),
+            key_distribution,
+            unique: if k.unique_values() {
+                Some((rand::random(), Arc::new(AtomicU64::new(0))))
+            } else {
+                None
+            },
+            fill: None,
+            sequences,
+            wait: k.wait(),
+            wait_command,
+            transitions,
+            key_rotation_secs: k.key_rotation_secs(),
+            workloads,
+            size_bands,
+        }
+    }
+
     pub fn length(&self) -> usize {
         self.length
     }
@@ -68,19 +391,149 @@ impl Keyspace {
     }
 
     // TODO(aetimmes): implement cardinality for Alphanumeric fields
-    pub fn generate_key(&self, rng: &mut SmallRng) -> Vec<u8> {
+    pub fn generate_key(&self, rng: &mut SmallRng, request: &mut RequestContext) -> Vec<u8> {
+        if let Some(key) = request.key.take() {
+            return key;
+        }
+        request.key_index = None;
+        let key = match self.key_type {
+            FieldType::Alphanumeric => {
+                let length = if self.length_max > self.length {
+                    rng.gen_range(self.length..=self.length_max)
+                } else {
+                    self.length
+                };
+                rng.sample_iter(&Alphanumeric)
+                    .take(length)
+                    .collect::<Vec<u8>>()
+            }
+            FieldType::U32 => {
+                let fill = if request.fill {
+                    self.next_fill_index()
+                } else {
+                    None
+                };
+                let index = match fill {
+                    Some(index) => index,
+                    None => self.key_distribution.sample(rng) as u32,
+                };
+                request.key_index = Some(index as u64);
+                self.key(index).unwrap()
+            }
+        };
+        let key = match self.key_rotation_secs {
+            Some(period) => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                rotate_key(key, now, period)
+            }
+            None => key,
+        };
+        match request.id {
+            Some(id) => {
+                let mut tagged = format!("{:x}:", id).into_bytes();
+                tagged.extend_from_slice(&key);
+                tagged
+            }
+            None => key,
+        }
+    }
+
+    /// Generate the keys for a batch, redrawing any key already in the batch
+    /// so that each key is asked for once. Keys repeat only when the keyspace
+    /// has fewer keys than the batch, or when the key distribution is so
+    /// skewed that the redraws run out.
+    pub fn generate_keys(
+        &self,
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+        count: usize,
+    ) -> Vec<Vec<u8>> {
+        let mut keys: Vec<Vec<u8>> = Vec::with_capacity(count);
+        let mut drawn = HashSet::with_capacity(count);
+        let mut redraws = 4 * count;
+        while keys.len() < count {
+            let key = self.generate_key(rng, request);
+            if !drawn.insert(key.clone()) && redraws > 0 {
+                redraws -= 1;
+                continue;
+            }
+            keys.push(key);
+        }
+        keys
+    }
+
+    /// Warm up by writing distinct keys, in order, until this fraction of the
+    /// keyspace has been written
+    pub fn set_fill_fraction(&mut self, fraction: f64) {
+        let target = (self.cardinality as f64 * fraction).ceil() as u64;
+        self.fill = Some((target, Arc::new(AtomicU64::new(0))));
+    }
+
+    /// Whether the keyspace is still being filled during warmup
+    pub fn filling(&self) -> bool {
+        self.fill
+            .as_ref()
+            .map(|(target, cursor)| cursor.load(Ordering::Relaxed) < *target)
+            .unwrap_or(false)
+    }
+
+    /// The number of distinct keys written while filling and the number
+    /// which must be written, if the keyspace is filled during warmup
+    pub fn filled(&self) -> Option<(u64, u64)> {
+        self.fill
+            .as_ref()
+            .map(|(target, cursor)| (cursor.load(Ordering::Relaxed).min(*target), *target))
+    }
+
+    /// Claim the index of the next key to be written while filling
+    fn next_fill_index(&self) -> Option<u32> {
+        let (target, ref cursor) = *self.fill.as_ref()?;
+        let index = cursor.fetch_add(1, Ordering::Relaxed);
+        if index < target {
+            Some(index as u32)
+        } else {
+            None
+        }
+    }
+
+    /// The index of the command used to fill the keyspace, which is the first
+    /// command which sets a value
+    fn fill_command(&self) -> Option<usize> {
+        self.commands.iter().position(|c| sets_value(c.verb()))
+    }
+
+    /// Whether a request for the command fills the keyspace, which is only the
+    /// case for commands which set a value while the keyspace is filling
+    pub fn fills(&self, command: &Command) -> bool {
+        sets_value(command.verb()) && self.filling()
+    }
+
+    /// The key for an index within the keyspace. Only keyspaces with `u32`
+    /// keys can be enumerated this way.
+    pub fn key(&self, index: u32) -> Option<Vec<u8>> {
         match self.key_type {
-            FieldType::Alphanumeric => rng
-                .sample_iter(&Alphanumeric)
-                .take(self.length())
-                .collect::<Vec<u8>>(),
-            FieldType::U32 => format!(
-                "{:0>len$}",
-                self.key_distribution.sample(rng) as u32,
-                len = self.length()
-            )
-            .as_bytes()
-            .to_vec(),
+            FieldType::Alphanumeric => None,
+            FieldType::U32 => Some(
+                format!("{:0>len$}", index, len = self.key_length(index))
+                    .as_bytes()
+                    .to_vec(),
+            ),
+        }
+    }
+
+    /// The padded length of the key for an index. This is fixed for each
+    /// index so that a key is always generated the same way, preserving hits.
+    fn key_length(&self, index: u32) -> usize {
+        if self.length_max > self.length {
+            let span = (self.length_max - self.length + 1) as u64;
+            // spread neighboring indices across lengths
+            let hash = (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+            self.length + (hash % span) as usize
+        } else {
+            self.length
         }
     }
 
@@ -109,10 +562,19 @@ impl Keyspace {
     }
 
     //#TODO(atimmes): implement cardinality for Alphanumeric fields
-    pub fn generate_value(&self, rng: &mut SmallRng) -> Option<Vec<u8>> {
+    pub fn generate_value(
+        &self,
+        rng: &mut SmallRng,
+        request: &mut RequestContext,
+    ) -> Option<Vec<u8>> {
         if let Some(ref value_dist) = self.value_dist {
-            let value_idx = value_dist.sample(rng);
+            let value_idx = match request.key_index.take() {
+                Some(index) if !self.size_bands.is_empty() => self.size_class(index),
+                _ => value_dist.sample(rng),
+            };
             let value_conf = &self.values[value_idx];
+            request.value_length = Some(value_conf.length());
+            request.value_ttl = value_conf.ttl();
             let value = match value_conf.field_type() {
                 FieldType::Alphanumeric => rng
                     .sample_iter(&Alphanumeric)
@@ -126,133 +588,930 @@ impl Keyspace {
                 .as_bytes()
                 .to_vec(),
             };
-            Some(value)
+            Some(self.make_unique(value))
         } else {
+            request.value_ttl = None;
             None
         }
     }
 
+    /// With unique values, overwrite the start of the value with the id of
+    /// this client and a sequence number so that no two writes are identical
+    fn make_unique(&self, mut value: Vec<u8>) -> Vec<u8> {
+        if let Some((id, ref sequence)) = self.unique {
+            let sequence = sequence.fetch_add(1, Ordering::Relaxed);
+            let tag = format!("{:08x}{:016x}", id, sequence);
+            value[..UNIQUE_TAG_LEN].copy_from_slice(tag.as_bytes());
+        }
+        value
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// The arguments of the `WAIT` command
+    pub fn wait(&self) -> Option<Wait> {
+        self.wait
+    }
+
+    /// Whether commands are chosen by a markov model of the previous command
+    pub fn has_transitions(&self) -> bool {
+        !self.transitions.is_empty()
+    }
+
+    /// Choose the index of the next command. With a markov model, the choice
+    /// depends on the previous command, if there was one.
+    fn choose_command_after(&self, rng: &mut SmallRng, previous: Option<usize>) -> usize {
+        match previous.and_then(|previous| self.transitions.get(previous)) {
+            Some(transitions) => transitions.sample(rng),
+            None => self.command_dist.sample(rng),
+        }
+    }
+
+    pub fn workloads(&self) -> &[Workload] {
+        &self.workloads
+    }
+
+    /// The workload the command belongs to, by its index, if any
+    pub fn workload(&self, command: usize) -> Option<&Workload> {
+        self.workloads.iter().find(|w| w.contains(command))
+    }
+
+    /// The index of the `WAIT` command to send after each write, if any
+    pub fn wait_command(&self) -> Option<usize> {
+        self.wait_command
+    }
+
+    /// The configured value lengths, which are the size classes for latency
+    pub fn value_lengths(&self) -> Vec<usize> {
+        self.values.iter().map(|v| v.length()).collect()
+    }
+
     pub fn choose_command(&self, rng: &mut SmallRng) -> &Command {
-        &self.commands[self.command_dist.sample(rng)]
+        loop {
+            if let Some(command) = self.commands.get(self.command_dist.sample(rng)) {
+                return command;
+            }
+        }
     }
 
-    pub fn choose_value(&self, rng: &mut SmallRng) -> Option<&Value> {
+    /// The indices of the commands of the sequence at this command index, if
+    /// the index is for a sequence
+    pub fn sequence(&self, index: usize) -> Option<&[usize]> {
+        index
+            .checked_sub(self.commands.len())
+            .and_then(|s| self.sequences.get(s))
+            .map(|s| s.as_slice())
+    }
+
+    /// The number of sequences in the keyspace
+    pub fn sequence_count(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn choose_value(&self, rng: &mut SmallRng, request: &RequestContext) -> Option<&Value> {
         if self.value_dist.is_some() {
-            Some(&self.values[self.value_dist.as_ref().unwrap().sample(rng)])
+            let index = match request.key_index {
+                Some(index) if !self.size_bands.is_empty() => self.size_class(index),
+                _ => self.value_dist.as_ref().unwrap().sample(rng),
+            };
+            Some(&self.values[index])
         } else {
             None
         }
     }
 
+    /// The index of the value for a key index when value sizes follow key
+    /// popularity
+    fn size_class(&self, index: u64) -> usize {
+        let band = self.size_bands.partition_point(|(end, _)| *end <= index);
+        self.size_bands[band.min(self.size_bands.len() - 1)].1
+    }
+
     pub fn ttl(&self) -> usize {
         self.ttl
     }
 
+    /// Returns a TTL for a set. The TTL of the value generated for the set is
+    /// used if its template has one, otherwise the TTL is sampled from the
+    /// weighted `ttls` if they are configured and falling back to the fixed
+    /// `ttl` otherwise.
+    pub fn generate_ttl(&self, rng: &mut SmallRng, request: &mut RequestContext) -> usize {
+        if let Some(ttl) = request.value_ttl.take() {
+            ttl
+        } else if let Some(ref dist) = self.ttl_dist {
+            self.ttls[dist.sample(rng)].ttl()
+        } else {
+            self.ttl()
+        }
+    }
+
     pub fn batch_size(&self) -> usize {
         self.batch_size
     }
 }
 
+/// Split the key indices into a band for each value, ordered by value length
+/// so that the lowest, most popular, indices get the smallest values with
+/// `hot_small` or the largest with `hot_large`. Each band covers a share of
+/// the keys in proportion to the weight of its value, so the sizes of distinct
+/// keys keep the configured mix while the sizes of requests follow popularity.
+fn size_bands(values: &[Value], cardinality: u32, order: SizeByPopularity) -> Vec<(u64, usize)> {
+    let mut indices: Vec<usize> = (0..values.len()).collect();
+    indices.sort_by_key(|i| values[*i].length());
+    if order == SizeByPopularity::HotLarge {
+        indices.reverse();
+    }
+    let total: usize = values.iter().map(|v| v.weight()).sum();
+    let mut cumulative = 0;
+    let mut bands: Vec<(u64, usize)> = indices
+        .into_iter()
+        .map(|i| {
+            cumulative += values[i].weight();
+            let end = (cumulative as f64 / total.max(1) as f64 * cardinality as f64).ceil();
+            (end as u64, i)
+        })
+        .collect();
+    if let Some(last) = bands.last_mut() {
+        last.0 = u64::MAX;
+    }
+    bands
+}
+
 impl Config {
     pub fn new(file: Option<&str>) -> Self {
-        let config_file = if let Some(file) = file {
+        Self::with_exec(file, None)
+    }
+
+    /// Load the config, replacing the endpoints of the target with a command
+    /// whose stdin and stdout are used as the transport, if one is given
+    pub fn with_exec(file: Option<&str>, exec: Option<&str>) -> Self {
+        let mut config_file = if let Some(file) = file {
             ConfigFile::load_from_file(file)
         } else {
             fatal!("need a config file");
         };
 
-        let mut keyspaces = Vec::new();
-        for k in config_file.keyspaces() {
-            let inner_keys = k.inner_keys();
-            let inner_key_weights: Vec<usize> = if inner_keys.is_empty() {
-                Vec::new()
-            } else {
-                inner_keys.iter().map(|v| v.weight()).collect()
-            };
-            let inner_key_dist = if inner_keys.is_empty() {
-                None
-            } else {
-                Some(WeightedAliasIndex::new(inner_key_weights).unwrap())
-            };
+        if let Some(exec) = exec {
+            if config_file.targets().len() > 1 {
+                fatal!("exec can not be used with more than one target");
+            }
+            config_file.set_exec(exec.to_string());
+        }
 
-            let command_weights: Vec<usize> = k.commands().iter().map(|v| v.weight()).collect();
-            let command_dist = WeightedAliasIndex::new(command_weights).unwrap();
+        Self::from_config_file(config_file)
+    }
 
-            let values = k.values();
-            let value_weights: Vec<usize> = if values.is_empty() {
-                Vec::new()
-            } else {
-                values.iter().map(|v| v.weight()).collect()
-            };
-            let value_dist = if values.is_empty() {
-                None
-            } else {
-                Some(WeightedAliasIndex::new(value_weights).unwrap())
-            };
+    /// Parse the config from a string, for tests
+    #[cfg(test)]
+    pub(crate) fn parse(toml: &str) -> Self {
+        Self::from_config_file(toml::from_str(toml).expect("bad config"))
+    }
+
+    /// Validate the parsed config file and resolve it into the config
+    fn from_config_file(config_file: ConfigFile) -> Self {
+        if config_file.general().latency_window().as_secs() == 0 {
+            fatal!("latency window must be positive, check latency_window_secs");
+        }
+
+        // with three significant figures, fewer than a thousand distinct values
+        // would lose precision, so a finer unit should be used instead
+        let unit = config_file.general().latency_unit();
+        let latency_max = config_file.general().latency_max();
+        if latency_max < 1_000 {
+            fatal!(
+                "latency_max must be at least 1000 {}, use a finer latency_unit",
+                unit.suffix()
+            );
+        }
+        if latency_max.saturating_mul(unit.nanos()) > 3_600_000_000_000 {
+            fatal!("latency_max must not exceed one hour, use a coarser latency_unit");
+        }
+
+        if config_file.request().read_idle_timeout() == Some(std::time::Duration::ZERO) {
+            fatal!("read_idle_timeout_us must be positive");
+        }
+
+        if let Some(schedule) = config_file.request().rate_schedule() {
+            if schedule.start() == 0 || schedule.end() == 0 {
+                fatal!("rate_schedule rates must be positive");
+            }
+            if schedule.duration().is_zero() {
+                fatal!("rate_schedule duration_secs must be positive");
+            }
+            if schedule.steps() == Some(0) {
+                fatal!("rate_schedule steps must be positive");
+            }
+            if config_file.request().slow_start().is_some() {
+                fatal!("rate_schedule can not be used with slow_start");
+            }
+            if config_file.request().poisson() {
+                fatal!("rate_schedule can not be used with the Poisson ratelimit_model");
+            }
+        }
+
+        if config_file.connection().poisson() {
+            fatal!("the Poisson ratelimit_model is only supported for requests");
+        }
+        if config_file.request().poisson() {
+            if config_file.request().ratelimit().is_none() {
+                fatal!("the Poisson ratelimit_model requires a request ratelimit");
+            }
+            if config_file.request().slow_start().is_some() {
+                fatal!("the Poisson ratelimit_model can not be used with slow_start");
+            }
+        }
+
+        if let Some(tls) = config_file.tls() {
+            for protocol in tls.alpn() {
+                if protocol.is_empty() || protocol.len() > 255 {
+                    fatal!("alpn protocols must be between 1 and 255 bytes");
+                }
+            }
+            if tls.key_update() == Some(std::time::Duration::ZERO) {
+                fatal!("tls key_update_secs must be positive");
+            }
+        }
+
+        let mut chaos = HashMap::new();
+        for entry in config_file.chaos() {
+            let endpoint = entry.endpoint();
+            if !(0.0..=1.0).contains(&entry.probability()) {
+                fatal!("chaos probability must be between 0 and 1");
+            }
+            chaos.insert(endpoint, entry.probability());
+        }
+
+        let mut tls_endpoints = HashMap::new();
+        for entry in config_file.tls_endpoints() {
+            let endpoint = entry.endpoint();
+            let tls = entry.tls(config_file.tls().as_ref());
+            if let Some(ref tls) = tls {
+                if let Err(e) = crate::worker::ssl_connector(tls) {
+                    fatal!("bad tls config for endpoint: {}: {}", endpoint, e);
+                }
+                if tls.key_update() == Some(std::time::Duration::ZERO) {
+                    fatal!("tls key_update_secs must be positive");
+                }
+            }
+            tls_endpoints.insert(endpoint, tls);
+        }
+
+        let think_times = config_file.request().think_time_file().map(|file| {
+            if config_file.request().think_time().is_some() {
+                fatal!("think_time_file can not be used with think_time_us");
+            }
+            match ThinkTimes::load(file) {
+                Ok(times) => Arc::new(times),
+                Err(e) => {
+                    fatal!("failed to load think_time_file: {}: {}", file, e);
+                }
+            }
+        });
+
+        let mut targets: Vec<Config> = config_file
+            .targets()
+            .iter()
+            .enumerate()
+            .map(|(index, target)| {
+                Self::from_target(
+                    &config_file,
+                    target,
+                    index,
+                    &chaos,
+                    &tls_endpoints,
+                    &think_times,
+                )
+            })
+            .collect();
+        if targets.is_empty() {
+            fatal!("no targets configured");
+        }
+
+        // the first target is the primary, which holds any additional targets
+        let mut config = targets.remove(0);
+        config.targets = targets;
+        config
+    }
+
+    /// Build the config for a single target. The target may override the
+    /// general protocol and the top-level keyspaces.
+    fn from_target(
+        config_file: &ConfigFile,
+        target: &Target,
+        index: usize,
+        chaos: &HashMap<SocketAddr, f64>,
+        tls_endpoints: &HashMap<SocketAddr, Option<Tls>>,
+        think_times: &Option<Arc<ThinkTimes>>,
+    ) -> Self {
+        let mut general = config_file.general();
+        if let Some(protocol) = target.protocol() {
+            general.set_protocol(protocol);
+        }
+        let name = target.name().unwrap_or_else(|| format!("target{}", index));
+        if !general.has_protocol() {
+            fatal!("no protocol configured for target: {}", name);
+        }
 
-            let key_distribution = match k.key_distribution {
-                None => KeyDistribution::Uniform(Uniform::new(0, k.cardinality() as usize)),
-                Some(ref kd) => match kd.model {
-                    KeyDistributionModel::Uniform => {
-                        KeyDistribution::Uniform(Uniform::new(0, k.cardinality() as usize))
+        let keyspace_configs = if target.keyspaces().is_empty() {
+            config_file.keyspaces()
+        } else {
+            target.keyspaces()
+        };
+        if keyspace_configs.is_empty() {
+            fatal!("no keyspaces configured for target: {}", name);
+        }
+        for k in &keyspace_configs {
+            if k.unique_values() {
+                for v in k.values() {
+                    if v.field_type() != FieldType::Alphanumeric {
+                        fatal!("unique_values requires alphanumeric values");
                     }
-                    KeyDistributionModel::Zipf => {
-                        let exponent = kd
-                            .parameters
-                            .get("exponent")
-                            .unwrap_or(&"1.0".to_owned())
-                            .parse::<f64>()
-                            .expect("bad exponent for zipf distribution");
-                        KeyDistribution::Zipf(
-                            ZipfDistribution::new(k.cardinality() as usize, exponent)
-                                .expect("bad zipf config"),
-                        )
+                    if v.length() < UNIQUE_TAG_LEN {
+                        fatal!(
+                            "unique_values requires values of at least {} bytes",
+                            UNIQUE_TAG_LEN
+                        );
                     }
-                },
-            };
+                }
+            }
+            let sequences = k.sequences();
+            if sequences.iter().any(|s| s.commands().is_empty()) {
+                fatal!("a sequence must have at least one command");
+            }
+            let workloads = k.workloads();
+            for workload in &workloads {
+                if workload.ratelimit() == 0 {
+                    fatal!(
+                        "workload: {} must have a positive ratelimit",
+                        workload.name()
+                    );
+                }
+                if workload.commands().iter().all(|c| c.weight() == 0) {
+                    fatal!(
+                        "workload: {} must have a command with a positive weight",
+                        workload.name()
+                    );
+                }
+                if workload.commands().iter().any(|c| c.ratelimit().is_some()) {
+                    fatal!(
+                        "the commands of workload: {} can not have a ratelimit",
+                        workload.name()
+                    );
+                }
+            }
+            let wait_verb = k
+                .commands()
+                .iter()
+                .chain(sequences.iter().flat_map(|s| s.commands()))
+                .chain(workloads.iter().flat_map(|w| w.commands()))
+                .any(|c| c.verb() == Verb::Wait);
+            let unsupported = k
+                .commands()
+                .iter()
+                .chain(sequences.iter().flat_map(|s| s.commands()))
+                .chain(workloads.iter().flat_map(|w| w.commands()))
+                .find(|c| !general.protocol().supports(c.verb()));
+            if let Some(command) = unsupported {
+                fatal!(
+                    "protocol: {} does not support the command: {}",
+                    serde_name(&general.protocol()),
+                    serde_name(&command.verb())
+                );
+            }
+            if k.key_rotation_secs() == Some(0) {
+                fatal!("key_rotation_secs must be positive");
+            }
+            let amount_ignored = k
+                .commands()
+                .iter()
+                .chain(sequences.iter().flat_map(|s| s.commands()))
+                .chain(workloads.iter().flat_map(|w| w.commands()))
+                .any(|c| c.has_amount() && !matches!(c.verb(), Verb::Incrby | Verb::Decrby));
+            if amount_ignored {
+                fatal!("amount is only used by the incrby and decrby commands");
+            }
+            if k.size_by_popularity().is_some() {
+                if k.key_type() != FieldType::U32 {
+                    fatal!("size_by_popularity requires u32 keys");
+                }
+                if k.values().is_empty() {
+                    fatal!("size_by_popularity requires values");
+                }
+            }
+            let transitions = k.transitions();
+            if !transitions.is_empty() {
+                let commands = k.commands().len();
+                if transitions.len() != commands || transitions.iter().any(|r| r.len() != commands)
+                {
+                    fatal!("transitions must have a row and a column for each command");
+                }
+                for row in &transitions {
+                    if row.iter().any(|p| !p.is_finite() || *p < 0.0) {
+                        fatal!("transition probabilities must not be negative");
+                    }
+                    let sum: f64 = row.iter().sum();
+                    if (sum - 1.0).abs() > 0.001 {
+                        fatal!("each row of transitions must sum to 1.0, not: {}", sum);
+                    }
+                }
+                if !sequences.is_empty() {
+                    fatal!("transitions can not be used with sequences");
+                }
+            }
+            if wait_verb || k.wait().is_some() {
+                if !matches!(
+                    general.protocol(),
+                    Protocol::Redis | Protocol::RedisInline | Protocol::RedisResp
+                ) {
+                    fatal!("wait is only supported for the redis protocol");
+                }
+                if wait_verb && k.wait().is_none() {
+                    fatal!("the wait command requires the keyspace wait arguments");
+                }
+            }
+            // setex and psetex require an expiry on every set
+            if k.commands()
+                .iter()
+                .chain(sequences.iter().flat_map(|s| s.commands()))
+                .any(|c| matches!(c.verb(), Verb::Setex | Verb::Psetex))
+            {
+                let values = k.values();
+                let value_ttls = !values.is_empty() && values.iter().all(|v| v.ttl().is_some());
+                let ttls = k.ttls();
+                let keyspace_ttls = if ttls.is_empty() {
+                    k.ttl() > 0
+                } else {
+                    ttls.iter().all(|t| t.ttl() > 0)
+                };
+                if !(value_ttls || keyspace_ttls) || values.iter().any(|v| v.ttl() == Some(0)) {
+                    fatal!("setex and psetex require a positive ttl");
+                }
+            }
+            // memcache treats expirations beyond 30 days as unix timestamps
+            if general.protocol().is_memcache()
+                && k.values()
+                    .iter()
+                    .any(|v| v.ttl().unwrap_or(0) > 30 * 24 * 60 * 60)
+            {
+                fatal!("memcache value ttl must be at most 30 days");
+            }
+            if let Some(length_max) = k.length_max() {
+                if length_max < k.length() {
+                    fatal!("keyspace length_max must be at least the length");
+                }
+                if k.key_type() == FieldType::U32
+                    && format!("{}", k.cardinality().saturating_sub(1)).len() > k.length()
+                {
+                    fatal!("keyspace length is too short for the cardinality");
+                }
+            }
+        }
 
-            let keyspace = Keyspace {
-                length: k.length(),
-                weight: k.weight(),
-                cardinality: k.cardinality(),
-                commands: k.commands(),
-                command_dist,
-                inner_keys: k.inner_keys(),
-                inner_key_dist,
-                values: k.values(),
-                value_dist,
-                ttl: k.ttl(),
-                key_type: k.key_type(),
-                batch_size: k.batch_size(),
-                key_distribution,
-            };
+        if let Some(fraction) = general.min_ready_fraction() {
+            if fraction <= 0.0 || fraction > 1.0 {
+                fatal!("min_ready_fraction must be greater than 0 and at most 1");
+            }
+        }
+
+        if general.response_size_buckets().contains(&0) {
+            fatal!("response_size_buckets must be positive");
+        }
+
+        if !general.subscribe().is_empty() {
+            if !general.protocol().is_redis() {
+                fatal!("subscribe requires a redis protocol");
+            }
+            if target.cluster() {
+                fatal!("subscribe is not supported in cluster mode");
+            }
+        }
+
+        if general.object_encoding_samples().is_some() && !general.protocol().is_redis() {
+            fatal!("object_encoding_samples requires a redis protocol");
+        }
+
+        if let Some(max) = general.max_value_size() {
+            for k in &keyspace_configs {
+                for v in k.values() {
+                    if v.length() > max {
+                        fatal!(
+                            "value length: {} exceeds the maximum of {} bytes for protocol: {:?}, set max_value_size to override",
+                            v.length(),
+                            max,
+                            general.protocol()
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(hitrate) = general.warmup_hitrate() {
+            if !(0.0..=1.0).contains(&hitrate) {
+                fatal!("warmup_hitrate is a ratio and must be between 0 and 1");
+            }
+            if general.warmup_stable_windows() == 0 {
+                fatal!("warmup_stable_windows must be at least 1");
+            }
+        }
+
+        if let Some(fraction) = general.warmup_fill_fraction() {
+            if fraction <= 0.0 || fraction > 1.0 {
+                fatal!("warmup_fill_fraction must be greater than 0 and at most 1");
+            }
+            if general.warmup_hitrate().is_some() {
+                fatal!("warmup_fill_fraction can not be used with warmup_hitrate");
+            }
+        }
+
+        let mut keyspaces = Vec::new();
+        for k in &keyspace_configs {
+            let mut keyspace = Keyspace::new(k);
+            if let Some(fraction) = general.warmup_fill_fraction() {
+                if keyspace.key(0).is_none() {
+                    fatal!("warmup_fill_fraction requires u32 keys");
+                }
+                if keyspace.fill_command().is_none() {
+                    fatal!("warmup_fill_fraction requires a set command in each keyspace");
+                }
+                keyspace.set_fill_fraction(fraction);
+            }
             keyspaces.push(keyspace);
         }
 
         let weights: Vec<usize> = keyspaces.iter().map(|k| k.weight).collect();
         let keyspace_dist = WeightedAliasIndex::new(weights).unwrap();
 
-        if config_file.target().endpoints().is_empty() {
+        // a target which runs a command has no endpoints, so it is given a
+        // placeholder to key its connections by
+        let exec = target.exec();
+        let (endpoints, mut read_endpoints, mut write_endpoints) = if let Some(ref command) = exec {
+            if command.trim().is_empty() {
+                fatal!("exec command must not be empty");
+            }
+            if config_file.tls().is_some() || !tls_endpoints.is_empty() {
+                fatal!("exec can not be used with tls");
+            }
+            if target.cluster() {
+                fatal!("exec is not supported in cluster mode");
+            }
+            (vec![exec_endpoint()], Vec::new(), Vec::new())
+        } else {
+            (
+                target.endpoints(),
+                target.read_endpoints(),
+                target.write_endpoints(),
+            )
+        };
+
+        if endpoints.is_empty() && read_endpoints.is_empty() && write_endpoints.is_empty() {
             fatal!("no target endpoints configured");
         }
 
+        // reads and writes fall back to the general endpoints when there are
+        // no endpoints dedicated to the role
+        let routing = !read_endpoints.is_empty() || !write_endpoints.is_empty();
+        if read_endpoints.is_empty() {
+            read_endpoints = endpoints.clone();
+        }
+        if write_endpoints.is_empty() {
+            write_endpoints = endpoints.clone();
+        }
+
+        for keyspace in &keyspaces {
+            for (index, command) in keyspace.commands.iter().enumerate() {
+                // a wait which follows a write is sent to the write endpoint,
+                // but one chosen on its own can not be routed
+                if routing && command.verb() == Verb::Wait && keyspace.wait_command != Some(index) {
+                    fatal!("the wait command does not support read and write endpoints");
+                }
+                if command.ratelimit() == Some(0) {
+                    fatal!("command ratelimit must be positive");
+                }
+                if command.verb().is_write() && write_endpoints.is_empty() {
+                    fatal!("no target endpoints configured for write commands");
+                }
+                if !command.verb().is_write() && read_endpoints.is_empty() {
+                    fatal!("no target endpoints configured for read commands");
+                }
+            }
+        }
+
+        if routing && keyspaces.iter().any(|k| k.has_transitions()) {
+            fatal!("transitions do not support read and write endpoints");
+        }
+
+        // workload commands skip the choice of a command by which a session is
+        // picked for the endpoint the command must go to
+        if keyspaces.iter().any(|k| !k.workloads().is_empty()) {
+            if routing {
+                fatal!("workloads do not support read and write endpoints");
+            }
+            if target.cluster() {
+                fatal!("workloads are not supported in cluster mode");
+            }
+        }
+
+        if keyspaces.iter().any(|k| k.sequence_count() > 0) {
+            if routing {
+                fatal!("sequences do not support read and write endpoints");
+            }
+            if target.cluster() {
+                fatal!("sequences are not supported in cluster mode");
+            }
+            if config_file.request().precompute_requests().is_some() {
+                fatal!("precompute_requests can not be used with sequences");
+            }
+        }
+
+        if target.cluster() {
+            if !matches!(
+                general.protocol(),
+                Protocol::Redis | Protocol::RedisInline | Protocol::RedisResp
+            ) {
+                fatal!("cluster mode requires a redis protocol");
+            }
+            if routing {
+                fatal!("cluster mode does not support read and write endpoints");
+            }
+            if config_file.connection().pipeline() != 1 {
+                fatal!("cluster mode requires a pipeline depth of 1");
+            }
+        }
+
+        if let Some(fraction) = config_file.connection().recycle_per_window_fraction() {
+            if !(0.0..=1.0).contains(&fraction) {
+                fatal!("recycle_per_window_fraction must be between 0 and 1");
+            }
+        }
+
+        if config_file.connection().verify_on_connect() {
+            if !matches!(general.protocol(), Protocol::Memcache) && !general.protocol().is_redis() {
+                fatal!("verify_on_connect requires the memcache text or a redis protocol");
+            }
+            if !general.subscribe().is_empty() {
+                fatal!("verify_on_connect can not be used with subscribe");
+            }
+            if config_file.connection().verify_timeout().is_zero() {
+                fatal!("verify_timeout_ms must be greater than zero");
+            }
+        }
+
+        if let Some(max) = config_file.connection().max_connections_per_client() {
+            if max == 0 {
+                fatal!("max_connections_per_client must be positive");
+            }
+        }
+
+        if config_file.connection().endpoint_affinity() {
+            if routing {
+                fatal!("endpoint_affinity does not support read and write endpoints");
+            }
+            if target.cluster() {
+                fatal!("endpoint_affinity is not supported in cluster mode");
+            }
+        }
+
+        if config_file.request().think_time() == Some(std::time::Duration::ZERO) {
+            fatal!("think_time_us must be positive");
+        }
+        if config_file.request().deadline().is_some() && general.protocol() != Protocol::ThriftCache
+        {
+            fatal!("deadline_us is only supported for the thrift_cache protocol");
+        }
+
+        if config_file.request().max_incomplete_reads() == Some(0) {
+            fatal!("max_incomplete_reads must be positive");
+        }
+
+        if let Some(frames) = config_file.request().precompute_requests() {
+            if frames == 0 {
+                fatal!("precompute_requests must be positive");
+            }
+            if !matches!(
+                general.protocol(),
+                Protocol::Ping
+                    | Protocol::Memcache
+                    | Protocol::MemcacheBinary
+                    | Protocol::Redis
+                    | Protocol::RedisInline
+                    | Protocol::RedisResp
+            ) {
+                fatal!("precompute_requests is not supported for this protocol");
+            }
+            if target.cluster() {
+                fatal!("precompute_requests is not supported in cluster mode");
+            }
+            if keyspace_configs.iter().any(|k| k.unique_values()) {
+                fatal!("precompute_requests can not be used with unique_values");
+            }
+            if config_file.request().deadline().is_some() {
+                fatal!("precompute_requests can not be used with a request deadline");
+            }
+            if config_file.request().request_id() {
+                fatal!("precompute_requests can not be used with request_id");
+            }
+            if general.warmup_fill_fraction().is_some() {
+                fatal!("precompute_requests can not be used with warmup_fill_fraction");
+            }
+        }
+
+        if let Some(threshold) = config_file.request().slow_request_threshold() {
+            if threshold.is_zero() {
+                fatal!("slow_request_threshold_us must be greater than zero");
+            }
+        }
+
+        if let Some(length) = config_file.request().pad_request_to() {
+            // only redis skips blank lines between requests, which lets the
+            // padding go unanswered. The shortest request is a bare ping.
+            let shortest = match general.protocol() {
+                Protocol::Redis | Protocol::RedisInline => b"ping\r\n".len(),
+                Protocol::RedisResp => b"*1\r\n$4\r\nping\r\n".len(),
+                _ => fatal!("pad_request_to requires a redis protocol"),
+            };
+            if length <= shortest {
+                fatal!("pad_request_to must be greater than {} bytes", shortest);
+            }
+            // the padding is a single inline line, which redis rejects once
+            // it grows past its inline request limit
+            if length > MAX_PAD_REQUEST_TO {
+                fatal!(
+                    "pad_request_to must be at most {} bytes",
+                    MAX_PAD_REQUEST_TO
+                );
+            }
+        }
+
+        // when routing, each endpoint is connected to once regardless of how
+        // many roles it serves
+        let endpoints = if routing {
+            let mut all = endpoints;
+            for endpoint in read_endpoints.iter().chain(write_endpoints.iter()) {
+                if !all.contains(endpoint) {
+                    all.push(*endpoint);
+                }
+            }
+            all
+        } else {
+            endpoints
+        };
+
         Self {
-            general: config_file.general(),
+            general,
             debug: config_file.debug(),
             waterfall: config_file.waterfall(),
             tls: config_file.tls(),
             connection: config_file.connection(),
             request: config_file.request(),
-            endpoints: config_file.target().endpoints(),
+            endpoints,
+            read_endpoints,
+            write_endpoints,
             keyspaces,
+            keyspace_configs,
             keyspace_dist,
+            chaos: chaos.clone(),
+            tls_endpoints: tls_endpoints.clone(),
+            tls_endpoint_configs: config_file.tls_endpoints(),
+            name,
+            targets: Vec::new(),
+            cluster: target.cluster(),
+            exec,
+            think_times: think_times.clone(),
+        }
+    }
+
+    fn resolved(&self) -> Resolved {
+        let addrs = |endpoints: &[SocketAddr]| endpoints.iter().map(|e| e.to_string()).collect();
+        let target = |config: &Config| {
+            // dedicated endpoints are only listed when they differ, otherwise
+            // reads and writes use the general endpoints
+            let routing = config.read_endpoints != config.endpoints
+                || config.write_endpoints != config.endpoints;
+            ResolvedTarget {
+                name: &config.name,
+                protocol: config.general.protocol(),
+                // the placeholder endpoint of a command is not listed
+                endpoints: if config.exec.is_some() {
+                    Vec::new()
+                } else {
+                    addrs(&config.endpoints)
+                },
+                read_endpoints: if routing {
+                    addrs(&config.read_endpoints)
+                } else {
+                    Vec::new()
+                },
+                write_endpoints: if routing {
+                    addrs(&config.write_endpoints)
+                } else {
+                    Vec::new()
+                },
+                keyspace: &config.keyspace_configs,
+                cluster: config.cluster,
+                exec: config.exec(),
+            }
+        };
+        let mut chaos: Vec<ResolvedChaos> = self
+            .chaos
+            .iter()
+            .map(|(endpoint, probability)| ResolvedChaos {
+                endpoint: endpoint.to_string(),
+                probability: *probability,
+            })
+            .collect();
+        chaos.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+
+        Resolved {
+            general: &self.general,
+            debug: &self.debug,
+            waterfall: &self.waterfall,
+            connection: &self.connection,
+            request: &self.request,
+            tls: self.tls.as_ref(),
+            target: std::iter::once(self)
+                .chain(self.targets.iter())
+                .map(target)
+                .collect(),
+            chaos,
+            tls_endpoint: &self.tls_endpoint_configs,
         }
     }
 
+    /// The effective configuration, after any overrides, as a config file
+    pub fn to_toml(&self) -> String {
+        // converting to a value first places plain values ahead of tables
+        let value = toml::Value::try_from(self.resolved()).expect("failed to serialize config");
+        toml::to_string(&value).expect("failed to serialize config")
+    }
+
+    /// The effective configuration, after any overrides, as JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.resolved()).expect("failed to serialize config")
+    }
+
+    /// The probability of injecting a failure for the endpoint, if chaos mode
+    /// is configured for it
+    pub fn chaos(&self, endpoint: &SocketAddr) -> Option<f64> {
+        self.chaos.get(endpoint).copied()
+    }
+
+    /// The name of the target, which defaults to its position in the config
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Take the configs for any targets beyond the first, each with their own
+    /// protocol, endpoints, and keyspaces
+    pub fn take_targets(&mut self) -> Vec<Config> {
+        std::mem::take(&mut self.targets)
+    }
+
     pub fn general(&self) -> &General {
         &self.general
     }
 
+    /// Override the number of windows, which also disables service mode
+    pub fn set_windows(&mut self, windows: usize) {
+        self.general.set_windows(windows);
+        for target in self.targets.iter_mut() {
+            target.set_windows(windows);
+        }
+    }
+
+    /// Override the window duration, in seconds
+    pub fn set_interval(&mut self, interval: usize) {
+        self.general.set_interval(interval);
+        for target in self.targets.iter_mut() {
+            target.set_interval(interval);
+        }
+    }
+
+    /// Override the duration covered by the latency histograms, in seconds
+    pub fn set_latency_window(&mut self, secs: usize) {
+        self.general.set_latency_window(secs);
+        for target in self.targets.iter_mut() {
+            target.set_latency_window(secs);
+        }
+    }
+
+    /// Override the number of worker threads
+    pub fn set_threads(&mut self, threads: usize) {
+        self.general.set_threads(threads);
+        for target in self.targets.iter_mut() {
+            target.set_threads(threads);
+        }
+    }
+
+    /// Override the format of the output for each window
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.general.set_output_format(format);
+        for target in self.targets.iter_mut() {
+            target.set_output_format(format);
+        }
+    }
+
     pub fn debug(&self) -> &Debug {
         &self.debug
     }
@@ -265,6 +1524,25 @@ impl Config {
         self.tls.as_ref()
     }
 
+    /// The TLS settings of endpoints which override the `[tls]` section, where
+    /// `None` means connections to the endpoint are plaintext
+    pub fn tls_endpoints(&self) -> &HashMap<SocketAddr, Option<Tls>> {
+        &self.tls_endpoints
+    }
+
+    /// The empirical think times loaded from the think_time_file, if any
+    pub fn think_times(&self) -> Option<&ThinkTimes> {
+        self.think_times.as_deref()
+    }
+
+    /// The TLS settings for connections to the endpoint, if any
+    pub fn endpoint_tls(&self, endpoint: &SocketAddr) -> Option<&Tls> {
+        match self.tls_endpoints.get(endpoint) {
+            Some(tls) => tls.as_ref(),
+            None => self.tls.as_ref(),
+        }
+    }
+
     pub fn connection(&self) -> &Connection {
         &self.connection
     }
@@ -277,7 +1555,561 @@ impl Config {
         self.endpoints.clone()
     }
 
+    /// Endpoints which can receive read commands
+    pub fn read_endpoints(&self) -> &[SocketAddr] {
+        &self.read_endpoints
+    }
+
+    /// Endpoints which can receive write commands
+    pub fn write_endpoints(&self) -> &[SocketAddr] {
+        &self.write_endpoints
+    }
+
+    /// Returns true if requests are routed by the cluster slot of their key
+    pub fn cluster(&self) -> bool {
+        self.cluster
+    }
+
+    /// The command whose stdin and stdout are used in place of connections
+    pub fn exec(&self) -> Option<&str> {
+        self.exec.as_deref()
+    }
+
+    /// Returns true if reads and writes are routed to different endpoints
+    pub fn routing(&self) -> bool {
+        self.read_endpoints != self.write_endpoints
+    }
+
+    /// The length of the largest value in any keyspace
+    pub fn max_value_length(&self) -> usize {
+        self.keyspaces
+            .iter()
+            .flat_map(|k| k.value_lengths())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn keyspaces(&self) -> &[Keyspace] {
+        &self.keyspaces
+    }
+
     pub fn choose_keyspace(&self, rng: &mut SmallRng) -> &Keyspace {
         &self.keyspaces[self.keyspace_dist.sample(rng)]
     }
+
+    /// Choose a keyspace and a command within it. If `write` is provided, the
+    /// choice is restricted to only write or only read commands while keeping
+    /// their relative weights.
+    pub fn choose_command(&self, rng: &mut SmallRng, write: Option<bool>) -> (&Keyspace, &Command) {
+        self.command(self.choose_command_index(rng, write))
+    }
+
+    /// Like `choose_command`, but returns the index of the keyspace and the
+    /// index of the command within it so the choice can be held on to.
+    pub fn choose_command_index(&self, rng: &mut SmallRng, write: Option<bool>) -> (usize, usize) {
+        self.choose_command_index_after(rng, write, &[])
+    }
+
+    /// Like `choose_command_index`, but given the previous command chosen in
+    /// each keyspace, by keyspace index, for keyspaces with a markov model.
+    pub fn choose_command_index_after(
+        &self,
+        rng: &mut SmallRng,
+        write: Option<bool>,
+        previous: &[Option<usize>],
+    ) -> (usize, usize) {
+        loop {
+            let k = self.keyspace_dist.sample(rng);
+            // while filling, only sets are sent so that each writes a new key
+            let c = match self.keyspaces[k].fill_command() {
+                Some(c) if write != Some(false) && self.keyspaces[k].filling() => c,
+                _ => {
+                    self.keyspaces[k].choose_command_after(rng, previous.get(k).copied().flatten())
+                }
+            };
+            match (write, self.keyspaces[k].commands.get(c)) {
+                (Some(write), Some(command)) if command.verb().is_write() != write => continue,
+                (Some(_), None) => continue,
+                _ => return (k, c),
+            }
+        }
+    }
+
+    /// The indices of the commands of a sequence, if the indices are for a
+    /// sequence rather than a single command
+    pub fn sequence(&self, index: (usize, usize)) -> Option<&[usize]> {
+        self.keyspaces[index.0].sequence(index.1)
+    }
+
+    /// Lookup a keyspace and command by their indices
+    pub fn command(&self, index: (usize, usize)) -> (&Keyspace, &Command) {
+        let keyspace = &self.keyspaces[index.0];
+        (keyspace, &keyspace.commands[index.1])
+    }
+
+    /// The workloads of each keyspace by their indices, along with their rate
+    pub fn workload_ratelimits(&self) -> Vec<((usize, usize), usize)> {
+        let mut ret = Vec::new();
+        for (k, keyspace) in self.keyspaces.iter().enumerate() {
+            for (w, workload) in keyspace.workloads.iter().enumerate() {
+                ret.push(((k, w), workload.ratelimit()));
+            }
+        }
+        ret
+    }
+
+    /// The commands which have a dedicated ratelimit, by index, along with
+    /// their rate
+    pub fn command_ratelimits(&self) -> Vec<((usize, usize), usize)> {
+        let mut ret = Vec::new();
+        for (k, keyspace) in self.keyspaces.iter().enumerate() {
+            for (c, command) in keyspace.commands.iter().enumerate() {
+                if let Some(ratelimit) = command.ratelimit() {
+                    ret.push(((k, c), ratelimit));
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn weighted_ttls() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "set" }]
+            ttls = [{ ttl = 60, weight = 9 }, { ttl = 3600, weight = 1 }]
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let samples = 100_000;
+        let mut short = 0;
+        for _ in 0..samples {
+            match keyspace.generate_ttl(&mut rng, &mut RequestContext::default()) {
+                60 => short += 1,
+                3600 => {}
+                ttl => panic!("unexpected ttl: {}", ttl),
+            }
+        }
+        let ratio = short as f64 / samples as f64;
+        assert!((ratio - 0.9).abs() < 0.01, "ratio: {}", ratio);
+    }
+
+    #[test]
+    fn value_ttls() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "set" }]
+            ttl = 60
+            values = [{ length = 8, ttl = 3600 }, { length = 16 }]
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let mut request = RequestContext::default();
+            let value = keyspace.generate_value(&mut rng, &mut request).unwrap();
+            let ttl = keyspace.generate_ttl(&mut rng, &mut request);
+            match value.len() {
+                8 => assert_eq!(ttl, 3600),
+                16 => assert_eq!(ttl, 60),
+                len => panic!("unexpected value length: {}", len),
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_ttl() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "set" }]
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert_eq!(
+            keyspace.generate_ttl(&mut rng, &mut RequestContext::default()),
+            0
+        );
+    }
+
+    #[test]
+    fn variable_key_length() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }]
+            length = 4
+            length_max = 8
+            cardinality = 1000
+            key_type = "u32"
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut lengths = std::collections::HashMap::new();
+        for _ in 0..10_000 {
+            let key = keyspace.generate_key(&mut rng, &mut RequestContext::default());
+            assert!((4..=8).contains(&key.len()));
+            let index: u32 = std::str::from_utf8(&key).unwrap().parse().unwrap();
+            // each index always maps to the same key
+            assert_eq!(*lengths.entry(index).or_insert(key.len()), key.len());
+        }
+        for length in 4..=8 {
+            assert!(lengths.values().any(|l| *l == length));
+        }
+    }
+
+    #[test]
+    fn read_write_endpoints() {
+        let a: SocketAddr = "127.0.0.1:6379".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:6380".parse().unwrap();
+
+        // without dedicated endpoints, every endpoint serves both roles
+        let config = Config::parse(
+            r#"
+            [general]
+            protocol = "redis"
+
+            [target]
+            endpoints = ["127.0.0.1:6379"]
+
+            [[keyspace]]
+            commands = [{ verb = "get" }, { verb = "set" }]
+            "#,
+        );
+        assert!(!config.routing());
+        assert_eq!(config.read_endpoints(), &[a]);
+        assert_eq!(config.write_endpoints(), &[a]);
+
+        // reads go to the dedicated endpoints, writes fall back to the rest,
+        // and each endpoint is connected to once
+        let config = Config::parse(
+            r#"
+            [general]
+            protocol = "redis"
+
+            [target]
+            endpoints = ["127.0.0.1:6379"]
+            read_endpoints = ["127.0.0.1:6380", "127.0.0.1:6379"]
+
+            [[keyspace]]
+            commands = [{ verb = "get" }, { verb = "set" }]
+            "#,
+        );
+        assert!(config.routing());
+        assert_eq!(config.read_endpoints(), &[b, a]);
+        assert_eq!(config.write_endpoints(), &[a]);
+        assert_eq!(config.endpoints(), vec![a, b]);
+    }
+
+    #[test]
+    fn multiple_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("multiple-targets.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [general]
+            threads = 2
+
+            [[target]]
+            name = "cache"
+            protocol = "memcache"
+            endpoints = ["127.0.0.1:11211"]
+
+            [[target]]
+            protocol = "redis"
+            endpoints = ["127.0.0.1:6379"]
+            keyspace = [{ commands = [{ verb = "get" }, { verb = "set" }] }]
+
+            [[keyspace]]
+            commands = [{ verb = "get" }]
+            "#,
+        )
+        .unwrap();
+        let mut config = Config::new(path.to_str());
+        config.set_threads(4);
+        assert_eq!(config.name(), "cache");
+        assert_eq!(config.general().protocol(), Protocol::Memcache);
+        assert_eq!(config.keyspaces()[0].commands.len(), 1);
+
+        let targets = config.take_targets();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].name(), "target1");
+        assert_eq!(targets[0].general().protocol(), Protocol::Redis);
+        assert_eq!(targets[0].general().threads(), 4);
+        assert_eq!(targets[0].keyspaces()[0].commands.len(), 2);
+    }
+
+    #[test]
+    fn resolved_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("resolved.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [general]
+            protocol = "redis"
+
+            [target]
+            endpoints = ["127.0.0.1:6379"]
+
+            [[keyspace]]
+            commands = [{ verb = "get" }, { verb = "set", weight = 2 }]
+            values = [{ length = 16 }]
+            "#,
+        )
+        .unwrap();
+        let mut config = Config::new(path.to_str());
+        config.set_windows(7);
+        let resolved = config.to_toml();
+
+        std::fs::write(&path, &resolved).unwrap();
+        let reloaded = Config::new(path.to_str());
+        assert_eq!(reloaded.general().windows(), Some(7));
+        assert_eq!(reloaded.keyspaces()[0].commands.len(), 2);
+        assert_eq!(reloaded.to_toml(), resolved);
+    }
+
+    #[test]
+    fn request_id_prefix() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }]
+            length = 4
+            cardinality = 1
+            key_type = "u32"
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut request = RequestContext::default();
+        request.set_id(Some(42));
+        assert_eq!(keyspace.generate_key(&mut rng, &mut request), b"2a:0000");
+        assert_eq!(
+            keyspace.generate_key(&mut rng, &mut RequestContext::default()),
+            b"0000"
+        );
+    }
+
+    #[test]
+    fn fill() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get", weight = 4 }, { verb = "set" }]
+            length = 2
+            cardinality = 10
+            key_type = "u32"
+            "#,
+        )
+        .unwrap();
+        let mut keyspace = Keyspace::new(&k);
+        keyspace.set_fill_fraction(0.25);
+        assert_eq!(keyspace.fill_command(), Some(1));
+        assert_eq!(keyspace.filled(), Some((0, 3)));
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let fill = || {
+            let mut request = RequestContext::default();
+            request.set_fill(true);
+            request
+        };
+        assert!(keyspace.fills(&keyspace.commands[1]));
+        assert!(!keyspace.fills(&keyspace.commands[0]));
+        assert_eq!(keyspace.generate_key(&mut rng, &mut fill()), b"00");
+
+        // keys of requests which do not fill leave the fill where it is
+        for _ in 0..100 {
+            keyspace.generate_key(&mut rng, &mut RequestContext::default());
+        }
+        assert_eq!(keyspace.filled(), Some((1, 3)));
+        assert_eq!(keyspace.generate_key(&mut rng, &mut fill()), b"01");
+        assert!(keyspace.filling());
+        assert_eq!(keyspace.generate_key(&mut rng, &mut fill()), b"02");
+        assert!(!keyspace.filling());
+        assert_eq!(keyspace.filled(), Some((3, 3)));
+    }
+
+    #[test]
+    fn sequences() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "delete" }]
+            length = 4
+            cardinality = 1000
+            key_type = "u32"
+
+            [[sequence]]
+            commands = [{ verb = "get" }, { verb = "set" }]
+            weight = 3
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        assert_eq!(keyspace.sequence_count(), 1);
+        assert_eq!(keyspace.sequence(0), None);
+        assert_eq!(keyspace.sequence(2), None);
+        assert_eq!(keyspace.sequence(3), Some(&[1, 2][..]));
+        assert!(matches!(keyspace.commands()[2].verb(), Verb::Set));
+
+        // the key of the sequence is used for the first key of each command,
+        // and the keys after it are drawn as usual
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut request = RequestContext::with_key(b"0042".to_vec());
+        let keys = keyspace.generate_keys(&mut rng, &mut request, 3);
+        assert_eq!(keys[0], b"0042");
+        assert!(keys[1..].iter().all(|key| key != b"0042" && key.len() == 4));
+    }
+
+    #[test]
+    fn key_rotation() {
+        assert_eq!(rotate_key(b"0042".to_vec(), 0, 60), b"0042:0");
+        assert_eq!(rotate_key(b"0042".to_vec(), 59, 60), b"0042:0");
+        assert_eq!(rotate_key(b"0042".to_vec(), 60, 60), b"0042:1");
+        assert_eq!(rotate_key(b"0042".to_vec(), 600, 60), b"0042:a");
+    }
+
+    #[test]
+    fn size_by_popularity() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "set" }]
+            length = 4
+            cardinality = 1000
+            key_type = "u32"
+            key_distribution = { model = "zipf" }
+            values = [{ length = 1024 }, { length = 16, weight = 3 }]
+            size_by_popularity = "hot_small"
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let mut request = RequestContext::default();
+            let key = keyspace.generate_key(&mut rng, &mut request);
+            let index: u32 = std::str::from_utf8(&key).unwrap().parse().unwrap();
+            let value = keyspace.generate_value(&mut rng, &mut request).unwrap();
+            assert_eq!(request.value_length(), Some(value.len()));
+            // the hottest three quarters of the keys get the small values
+            if index < 750 {
+                assert_eq!(value.len(), 16);
+            } else {
+                assert_eq!(value.len(), 1024);
+            }
+        }
+    }
+
+    #[test]
+    fn workloads() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }]
+            length = 4
+
+            [[workload]]
+            name = "scan"
+            ratelimit = 10
+            commands = [{ verb = "delete" }, { verb = "hget", weight = 0 }]
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        assert_eq!(keyspace.commands().len(), 3);
+        assert!(keyspace.workload(0).is_none());
+        assert_eq!(keyspace.workload(1).map(|w| w.name()), Some("scan"));
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            // workload commands are only chosen by their workload
+            assert!(keyspace.choose_command(&mut rng).verb() == Verb::Get);
+            assert_eq!(keyspace.workloads()[0].choose_command(&mut rng), 1);
+        }
+    }
+
+    #[test]
+    fn transitions() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "get" }, { verb = "set" }, { verb = "delete" }]
+            length = 4
+            transitions = [
+                [0.0, 1.0, 0.0],
+                [0.8, 0.0, 0.2],
+                [0.5, 0.5, 0.0],
+            ]
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        assert!(keyspace.has_transitions());
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut counts = [[0_usize; 3]; 3];
+        let mut previous = keyspace.choose_command_after(&mut rng, None);
+        for _ in 0..100_000 {
+            let next = keyspace.choose_command_after(&mut rng, Some(previous));
+            counts[previous][next] += 1;
+            previous = next;
+        }
+        let expected = k.transitions();
+        for (row, expected) in counts.iter().zip(expected.iter()) {
+            let total: usize = row.iter().sum();
+            for (count, p) in row.iter().zip(expected.iter()) {
+                let frequency = *count as f64 / total as f64;
+                assert!((frequency - p).abs() < 0.02, "{} != {}", frequency, p);
+            }
+        }
+    }
+
+    #[test]
+    fn unique_values() {
+        let k: crate::config_file::Keyspace = toml::from_str(
+            r#"
+            commands = [{ verb = "set" }]
+            values = [{ length = 32 }]
+            unique_values = true
+            "#,
+        )
+        .unwrap();
+        let keyspace = Keyspace::new(&k);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let a = keyspace
+            .generate_value(&mut rng, &mut RequestContext::default())
+            .unwrap();
+        let b = keyspace
+            .generate_value(&mut rng, &mut RequestContext::default())
+            .unwrap();
+        assert_eq!(a.len(), 32);
+        assert_eq!(b.len(), 32);
+        assert_ne!(a[..UNIQUE_TAG_LEN], b[..UNIQUE_TAG_LEN]);
+    }
+
+    #[test]
+    fn unsupported_names() {
+        // the names used when rejecting a command the protocol can not encode
+        assert!(!Protocol::Redis.supports(Verb::Add));
+        assert_eq!(serde_name(&Protocol::Redis), "redis");
+        assert_eq!(serde_name(&Verb::Add), "add");
+        assert!(!Protocol::ThriftCache.supports(Verb::Get));
+        assert_eq!(serde_name(&Protocol::ThriftCache), "thrift_cache");
+        assert_eq!(serde_name(&Verb::Get), "get");
+    }
 }
@@ -13,7 +13,7 @@ use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 use zookeeper::*;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigFile {
     general: General,
@@ -21,13 +21,18 @@ pub struct ConfigFile {
     debug: Debug,
     #[serde(default)]
     waterfall: Waterfall,
-    target: Target,
+    target: Targets,
     #[serde(default)]
     connection: Connection,
     #[serde(default)]
     request: Request,
     tls: Option<Tls>,
+    #[serde(default)]
     keyspace: Vec<Keyspace>,
+    #[serde(default)]
+    chaos: Vec<Chaos>,
+    #[serde(default, rename = "tls_endpoint")]
+    tls_endpoints: Vec<TlsEndpoint>,
 }
 
 impl ConfigFile {
@@ -55,8 +60,35 @@ impl ConfigFile {
         self.keyspace.clone()
     }
 
-    pub fn target(&self) -> Target {
-        self.target.clone()
+    pub fn chaos(&self) -> Vec<Chaos> {
+        self.chaos.clone()
+    }
+
+    /// TLS settings for individual endpoints, overriding the `[tls]` section
+    pub fn tls_endpoints(&self) -> Vec<TlsEndpoint> {
+        self.tls_endpoints.clone()
+    }
+
+    /// Run the command for the first target instead of connecting to its
+    /// endpoints
+    pub fn set_exec(&mut self, command: String) {
+        match &mut self.target {
+            Targets::One(target) => target.exec = Some(command),
+            Targets::Many(targets) => {
+                if let Some(target) = targets.first_mut() {
+                    target.exec = Some(command);
+                }
+            }
+        }
+    }
+
+    /// Returns the targets, whether a single `[target]` table or multiple
+    /// `[[target]]` blocks are configured
+    pub fn targets(&self) -> Vec<Target> {
+        match &self.target {
+            Targets::One(target) => vec![target.clone()],
+            Targets::Many(targets) => targets.clone(),
+        }
     }
 
     pub fn waterfall(&self) -> Waterfall {
@@ -110,6 +142,10 @@ fn u32_max() -> u32 {
     u32::MAX
 }
 
+fn default_warmup_stable_windows() -> usize {
+    3
+}
+
 fn default_nodelay() -> bool {
     false
 }
@@ -122,7 +158,7 @@ fn empty_map() -> HashMap<String, String> {
     HashMap::new()
 }
 
-#[derive(Deserialize, Clone, Copy, Eq, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum FieldType {
@@ -130,23 +166,149 @@ pub enum FieldType {
     U32,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum Protocol {
     Ping,
     Echo,
     Memcache,
+    /// memcache over the binary protocol
+    MemcacheBinary,
     Redis,
     RedisInline,
     RedisResp,
     ThriftCache,
 }
 
-#[derive(Deserialize, Clone)]
+impl Protocol {
+    /// The largest value the server accepts by default, for protocols which
+    /// have a limit
+    pub fn max_value_size(&self) -> Option<usize> {
+        match self {
+            // memcached rejects items larger than its 1MB slab page size
+            Self::Memcache | Self::MemcacheBinary => Some(1024 * 1024),
+            // redis strings are limited to 512MB
+            Self::Redis | Self::RedisInline | Self::RedisResp => Some(512 * 1024 * 1024),
+            Self::Ping | Self::Echo | Self::ThriftCache => None,
+        }
+    }
+
+    pub fn is_memcache(&self) -> bool {
+        matches!(self, Self::Memcache | Self::MemcacheBinary)
+    }
+
+    pub fn is_redis(&self) -> bool {
+        matches!(self, Self::Redis | Self::RedisInline | Self::RedisResp)
+    }
+
+    /// Whether the protocol can encode requests for the verb
+    pub fn supports(&self, verb: Verb) -> bool {
+        match self {
+            // the text and binary codecs encode the same commands
+            Self::Memcache | Self::MemcacheBinary => {
+                matches!(verb, Verb::Get | Verb::Set | Verb::Add | Verb::Delete)
+            }
+            Self::Redis | Self::RedisInline | Self::RedisResp => matches!(
+                verb,
+                Verb::Ping
+                    | Verb::Get
+                    | Verb::Set
+                    | Verb::Setex
+                    | Verb::Psetex
+                    | Verb::Delete
+                    | Verb::Getdel
+                    | Verb::Copy
+                    | Verb::Rename
+                    | Verb::Unlink
+                    | Verb::Hget
+                    | Verb::Hset
+                    | Verb::Hsetnx
+                    | Verb::Hdel
+                    | Verb::Wait
+                    | Verb::Incr
+                    | Verb::Decr
+                    | Verb::Incrby
+                    | Verb::Decrby
+            ),
+            Self::ThriftCache => matches!(
+                verb,
+                Verb::Rpush
+                    | Verb::Rpushx
+                    | Verb::Count
+                    | Verb::Hget
+                    | Verb::Hset
+                    | Verb::Hdel
+                    | Verb::Lrange
+                    | Verb::Ltrim
+            ),
+            Self::Ping => verb == Verb::Ping,
+            Self::Echo => verb == Verb::Echo,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum LatencyUnit {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+}
+
+/// The format of the output for each window
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum OutputFormat {
+    /// Log lines for people to read
+    Human,
+    /// One JSON object per window on stdout, with the log moved to stderr
+    Json,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self::Human
+    }
+}
+
+impl Default for LatencyUnit {
+    fn default() -> Self {
+        Self::Microseconds
+    }
+}
+
+impl LatencyUnit {
+    /// The number of nanoseconds in one unit
+    pub fn nanos(&self) -> u64 {
+        match self {
+            Self::Nanoseconds => 1,
+            Self::Microseconds => 1_000,
+            Self::Milliseconds => 1_000_000,
+        }
+    }
+
+    /// Convert a latency in nanoseconds into this unit
+    pub fn convert(&self, nanos: u64) -> u64 {
+        nanos / self.nanos()
+    }
+
+    /// The abbreviation used when labeling latencies
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Nanoseconds => "ns",
+            Self::Microseconds => "us",
+            Self::Milliseconds => "ms",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct General {
-    protocol: Protocol,
+    protocol: Option<Protocol>,
     #[serde(default = "default_interval")]
     interval: usize,
     #[serde(default = "default_windows")]
@@ -156,17 +318,108 @@ pub struct General {
     #[serde(default)]
     service: bool,
     admin: Option<String>,
+    warmup_hitrate: Option<f64>,
+    #[serde(default = "default_warmup_stable_windows")]
+    warmup_stable_windows: usize,
+    warmup_fill_fraction: Option<f64>,
+    #[serde(default)]
+    server_stats: Vec<String>,
+    latency_window_secs: Option<usize>,
+    #[serde(default)]
+    latency_unit: LatencyUnit,
+    #[serde(default)]
+    output_format: OutputFormat,
+    latency_max: Option<u64>,
+    max_value_size: Option<usize>,
+    object_encoding_samples: Option<usize>,
+    min_ready_fraction: Option<f64>,
+    #[serde(default)]
+    subscribe: Vec<String>,
+    #[serde(default)]
+    response_size_buckets: Vec<usize>,
+    #[serde(default)]
+    command_stats: bool,
+    ready_timeout_secs: Option<usize>,
 }
 
 impl General {
     pub fn protocol(&self) -> Protocol {
-        self.protocol
+        self.protocol.expect("no protocol configured")
+    }
+
+    pub fn has_protocol(&self) -> bool {
+        self.protocol.is_some()
+    }
+
+    /// Override the protocol, used to apply the protocol of a target
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = Some(protocol);
     }
 
     pub fn interval(&self) -> std::time::Duration {
         std::time::Duration::from_secs(self.interval as u64)
     }
 
+    /// The duration covered by the latency histograms, which defaults to the
+    /// stats interval
+    pub fn latency_window(&self) -> std::time::Duration {
+        match self.latency_window_secs {
+            Some(secs) => std::time::Duration::from_secs(secs as u64),
+            None => self.interval(),
+        }
+    }
+
+    /// The unit latencies are recorded in. This applies to the heatmaps and
+    /// the percentiles reported from them, while the exemplar histogram has
+    /// fixed bucket bounds and is always in microseconds.
+    pub fn latency_unit(&self) -> LatencyUnit {
+        self.latency_unit
+    }
+
+    /// The format of the output for each window
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// The largest latency which can be recorded, in the latency unit. This
+    /// defaults to one second. Slower responses are counted as clipped, and
+    /// the heatmaps keep this maximum for the whole run.
+    pub fn latency_max(&self) -> u64 {
+        self.latency_max
+            .unwrap_or(1_000_000_000 / self.latency_unit.nanos())
+    }
+
+    /// The response sizes, in bytes, which divide responses into buckets for
+    /// a summary of latency by response size at the end of the run
+    pub fn response_size_buckets(&self) -> &[usize] {
+        &self.response_size_buckets
+    }
+
+    /// Report the requests, responses, and errors for each command every
+    /// window
+    pub fn command_stats(&self) -> bool {
+        self.command_stats
+    }
+
+    /// For redis, the channels each connection subscribes to instead of
+    /// sending requests
+    pub fn subscribe(&self) -> &[String] {
+        &self.subscribe
+    }
+
+    /// For redis, the number of keys to sample with `OBJECT ENCODING` each
+    /// window, counting the internal encoding of their values
+    pub fn object_encoding_samples(&self) -> Option<usize> {
+        self.object_encoding_samples
+    }
+
+    /// The largest value, in bytes, which may be configured. This defaults to
+    /// the limit of the protocol, if it has one.
+    pub fn max_value_size(&self) -> Option<usize> {
+        self.max_value_size
+            .or_else(|| self.protocol.and_then(|p| p.max_value_size()))
+    }
+
     pub fn windows(&self) -> Option<usize> {
         if self.service {
             None
@@ -179,9 +432,65 @@ impl General {
         self.threads
     }
 
+    /// Keys from the memcache `stats` response to record each window
+    pub fn server_stats(&self) -> &[String] {
+        &self.server_stats
+    }
+
+    pub fn set_windows(&mut self, windows: usize) {
+        self.windows = windows;
+        self.service = false;
+    }
+
+    pub fn set_interval(&mut self, interval: usize) {
+        self.interval = interval;
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.output_format = format;
+    }
+
+    pub fn set_latency_window(&mut self, secs: usize) {
+        self.latency_window_secs = Some(secs);
+    }
+
     pub fn admin(&self) -> Option<String> {
         self.admin.clone()
     }
+
+    /// The hit-rate, as a ratio, which must be reached before windows start
+    /// counting towards the test duration
+    pub fn warmup_hitrate(&self) -> Option<f64> {
+        self.warmup_hitrate
+    }
+
+    /// The number of consecutive windows which must meet the warmup hit-rate
+    /// before the warmup is complete
+    pub fn warmup_stable_windows(&self) -> usize {
+        self.warmup_stable_windows
+    }
+
+    /// The fraction of each keyspace which must be written with distinct keys
+    /// before windows start counting towards the test duration
+    pub fn warmup_fill_fraction(&self) -> Option<f64> {
+        self.warmup_fill_fraction
+    }
+
+    /// The fraction of connections which must be established before the
+    /// first window starts
+    pub fn min_ready_fraction(&self) -> Option<f64> {
+        self.min_ready_fraction
+    }
+
+    /// How long to wait for connections to be established before starting
+    /// the first window anyway. This defaults to 30 seconds.
+    pub fn ready_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ready_timeout_secs.unwrap_or(30) as u64)
+    }
 }
 
 fn log_level() -> Level {
@@ -200,6 +509,10 @@ fn log_single_message_size() -> usize {
     1024
 }
 
+fn log_rate_limit() -> usize {
+    100
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Debug {
     #[serde(with = "LevelDef")]
@@ -213,6 +526,8 @@ pub struct Debug {
     log_queue_depth: usize,
     #[serde(default = "log_single_message_size")]
     log_single_message_size: usize,
+    #[serde(default = "log_rate_limit")]
+    log_rate_limit: usize,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -255,6 +570,13 @@ impl Debug {
     pub fn log_single_message_size(&self) -> usize {
         self.log_single_message_size
     }
+
+    /// The most error messages each worker logs per second for failed
+    /// connects and error responses. The number not logged is summarized once
+    /// each second.
+    pub fn log_rate_limit(&self) -> usize {
+        self.log_rate_limit
+    }
 }
 
 impl Default for Debug {
@@ -266,19 +588,42 @@ impl Default for Debug {
             log_max_size: log_max_size(),
             log_queue_depth: log_queue_depth(),
             log_single_message_size: log_single_message_size(),
+            log_rate_limit: log_rate_limit(),
         }
     }
 }
 
-#[derive(Deserialize, Copy, Clone)]
+/// How the think time between a response and the next request on a session
+/// is chosen
+#[derive(Deserialize, Serialize, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkTimeModel {
+    /// always pause for the configured time
+    Fixed,
+    /// pause uniformly between zero and twice the configured time
+    Uniform,
+    /// pause for an exponentially distributed time with the configured mean
+    Exponential,
+}
+
+impl Default for ThinkTimeModel {
+    fn default() -> Self {
+        Self::Fixed
+    }
+}
+
+#[derive(Deserialize, Serialize, Copy, Clone)]
 #[serde(deny_unknown_fields)]
 pub enum RatelimitModel {
     Smooth,
     Uniform,
     Normal,
+    /// Exponentially distributed gaps between requests, only for the request
+    /// ratelimit
+    Poisson,
 }
 
-#[derive(Deserialize, Copy, Clone)]
+#[derive(Deserialize, Serialize, Copy, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Connection {
     #[serde(default = "one")]
@@ -288,10 +633,30 @@ pub struct Connection {
     reconnect: Option<usize>,
     #[serde(default = "default_nodelay")]
     tcp_nodelay: bool,
+    #[serde(default)]
+    tcp_quickack: bool,
+    #[serde(default)]
+    tcp_cork: bool,
     #[allow(dead_code)]
     timeout: Option<usize>,
     #[serde(default = "one")]
     pipeline: usize,
+    #[serde(default = "one")]
+    connects_per_tick: usize,
+    connect_retries: Option<usize>,
+    #[serde(default)]
+    drop_abandoned: bool,
+    max_ready_queue: Option<usize>,
+    #[serde(default)]
+    endpoint_affinity: bool,
+    #[serde(default)]
+    lifetime: bool,
+    #[serde(default)]
+    verify_on_connect: bool,
+    verify_timeout_ms: Option<u64>,
+    reconnect_delay_ms: Option<u64>,
+    recycle_per_window_fraction: Option<f64>,
+    max_connections_per_client: Option<usize>,
 }
 
 impl Default for Connection {
@@ -302,8 +667,21 @@ impl Default for Connection {
             ratelimit_model: None,
             reconnect: None,
             tcp_nodelay: false,
+            tcp_quickack: false,
+            tcp_cork: false,
             timeout: None,
             pipeline: 1,
+            connects_per_tick: 1,
+            connect_retries: None,
+            drop_abandoned: false,
+            max_ready_queue: None,
+            endpoint_affinity: false,
+            lifetime: false,
+            verify_on_connect: false,
+            verify_timeout_ms: None,
+            reconnect_delay_ms: None,
+            recycle_per_window_fraction: None,
+            max_connections_per_client: None,
         }
     }
 }
@@ -315,12 +693,19 @@ impl Connection {
 
     pub fn ratelimit_model(&self) -> rustcommon_ratelimiter::Refill {
         match self.ratelimit_model {
-            None | Some(RatelimitModel::Smooth) => rustcommon_ratelimiter::Refill::Smooth,
+            None | Some(RatelimitModel::Smooth) | Some(RatelimitModel::Poisson) => {
+                rustcommon_ratelimiter::Refill::Smooth
+            }
             Some(RatelimitModel::Uniform) => rustcommon_ratelimiter::Refill::Uniform,
             Some(RatelimitModel::Normal) => rustcommon_ratelimiter::Refill::Normal,
         }
     }
 
+    /// Whether connects are paced as a Poisson process, which is not supported
+    pub fn poisson(&self) -> bool {
+        matches!(self.ratelimit_model, Some(RatelimitModel::Poisson))
+    }
+
     pub fn reconnect(&self) -> Option<usize> {
         self.reconnect
     }
@@ -333,19 +718,100 @@ impl Connection {
         self.tcp_nodelay
     }
 
+    /// Send ACKs immediately rather than delaying them, only on Linux. It is
+    /// set again after each read, as the kernel may clear it.
+    pub fn tcp_quickack(&self) -> bool {
+        self.tcp_quickack
+    }
+
+    /// Coalesce the writes of each flush into full segments, only on Linux.
+    /// The socket is uncorked after each flush so that no request is held
+    /// back waiting for more data.
+    pub fn tcp_cork(&self) -> bool {
+        self.tcp_cork
+    }
+
+    /// The number of requests a connection may have in flight. Requests are
+    /// written together and responses are matched to them in the order they
+    /// were sent, each with its latency measured from the write.
     pub fn pipeline(&self) -> usize {
         self.pipeline
     }
+
+    /// The maximum number of new connections each worker will attempt in a
+    /// single iteration of its event loop
+    pub fn connects_per_tick(&self) -> usize {
+        self.connects_per_tick
+    }
+
+    /// The number of consecutive connect failures for an address before it is
+    /// counted as abandoned
+    pub fn connect_retries(&self) -> Option<usize> {
+        self.connect_retries
+    }
+
+    /// Whether abandoned addresses are removed from the connect queue rather
+    /// than retried
+    pub fn drop_abandoned(&self) -> bool {
+        self.drop_abandoned
+    }
+
+    /// A safety cap on the number of sessions waiting to send a request in
+    /// each worker. Sessions past the cap are deferred until there is room.
+    pub fn max_ready_queue(&self) -> Option<usize> {
+        self.max_ready_queue
+    }
+
+    /// How long to wait before reconnecting when the server closes a
+    /// connection, such as during a graceful restart
+    pub fn reconnect_delay(&self) -> Option<Duration> {
+        self.reconnect_delay_ms.map(Duration::from_millis)
+    }
+
+    /// The fraction of established connections to close for reconnect at the
+    /// start of each window, keeping the pool fresh
+    pub fn recycle_per_window_fraction(&self) -> Option<f64> {
+        self.recycle_per_window_fraction
+    }
+
+    /// A cap on the number of open connections across all worker threads,
+    /// regardless of the number of endpoints
+    pub fn max_connections_per_client(&self) -> Option<usize> {
+        self.max_connections_per_client
+    }
+
+    /// Record how long each connection is open before it is closed
+    pub fn lifetime(&self) -> bool {
+        self.lifetime
+    }
+
+    /// Send a no-op request on each new connection and only use it once a
+    /// valid reply arrives, counting a failure as a connect error
+    pub fn verify_on_connect(&self) -> bool {
+        self.verify_on_connect
+    }
+
+    /// How long to wait for the reply to the verification request before
+    /// counting the connect as timed out, one second by default
+    pub fn verify_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.verify_timeout_ms.unwrap_or(1000))
+    }
+
+    /// Pin each worker to a single endpoint, assigned round-robin, instead of
+    /// spreading its pool across all endpoints
+    pub fn endpoint_affinity(&self) -> bool {
+        self.endpoint_affinity
+    }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum KeyDistributionModel {
     Uniform,
     Zipf,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct KeyDistribution {
     pub(crate) model: KeyDistributionModel,
@@ -355,11 +821,21 @@ pub struct KeyDistribution {
     pub(crate) parameters: HashMap<String, String>,
 }
 
-#[derive(Deserialize, Clone)]
+/// Which value sizes go to the most popular keys when value sizes follow key
+/// popularity
+#[derive(Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeByPopularity {
+    HotSmall,
+    HotLarge,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Keyspace {
     #[serde(default = "one")]
     length: usize,
+    length_max: Option<usize>,
     #[serde(default = "one")]
     weight: usize,
     #[serde(default = "u32_max")]
@@ -371,11 +847,24 @@ pub struct Keyspace {
     values: Vec<Value>,
     #[serde(default = "zero")]
     ttl: usize,
+    #[serde(default)]
+    ttls: Vec<Ttl>,
     #[serde(default = "alphanumeric")]
     key_type: FieldType,
     #[serde(default = "one")]
     batch_size: usize,
     pub(crate) key_distribution: Option<KeyDistribution>,
+    #[serde(default)]
+    unique_values: bool,
+    #[serde(default, rename = "sequence")]
+    sequences: Vec<Sequence>,
+    wait: Option<Wait>,
+    #[serde(default)]
+    transitions: Vec<Vec<f64>>,
+    key_rotation_secs: Option<u64>,
+    size_by_popularity: Option<SizeByPopularity>,
+    #[serde(default, rename = "workload")]
+    workloads: Vec<Workload>,
 }
 
 impl Keyspace {
@@ -383,6 +872,16 @@ impl Keyspace {
         self.length
     }
 
+    /// When set, key lengths vary uniformly between `length` and this value
+    pub fn length_max(&self) -> Option<usize> {
+        self.length_max
+    }
+
+    /// When set, every generated value embeds a unique sequence number
+    pub fn unique_values(&self) -> bool {
+        self.unique_values
+    }
+
     pub fn weight(&self) -> usize {
         self.weight
     }
@@ -399,6 +898,42 @@ impl Keyspace {
         self.commands.clone()
     }
 
+    /// Ordered lists of commands which are chosen alongside the commands and
+    /// share a key
+    pub fn sequences(&self) -> Vec<Sequence> {
+        self.sequences.clone()
+    }
+
+    /// Workloads which share the keys of the keyspace, each with its own mix
+    /// of commands and ratelimit
+    pub fn workloads(&self) -> Vec<Workload> {
+        self.workloads.clone()
+    }
+
+    /// The probability of each command following each other command, as a
+    /// row for each command. When empty, commands are chosen independently by
+    /// their weights.
+    pub fn transitions(&self) -> Vec<Vec<f64>> {
+        self.transitions.clone()
+    }
+
+    /// When set, keys carry a suffix which changes every this many seconds, so
+    /// the working set shifts over time
+    pub fn key_rotation_secs(&self) -> Option<u64> {
+        self.key_rotation_secs
+    }
+
+    /// When set, the size of each value is chosen by the popularity rank of
+    /// its key rather than independently
+    pub fn size_by_popularity(&self) -> Option<SizeByPopularity> {
+        self.size_by_popularity
+    }
+
+    /// The arguments of the redis `WAIT` command
+    pub fn wait(&self) -> Option<Wait> {
+        self.wait
+    }
+
     pub fn values(&self) -> Vec<Value> {
         self.values.clone()
     }
@@ -407,6 +942,10 @@ impl Keyspace {
         self.ttl
     }
 
+    pub fn ttls(&self) -> Vec<Ttl> {
+        self.ttls.clone()
+    }
+
     pub fn key_type(&self) -> FieldType {
         self.key_type
     }
@@ -416,11 +955,12 @@ impl Keyspace {
     }
 }
 
-#[derive(Deserialize, Clone, Copy, Eq, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum Verb {
-    /// Sends a simple 'ping' to a pingserver.
+    /// Sends a simple 'ping' to a pingserver, or a PING within a redis
+    /// keyspace to measure round-trip time alongside other commands.
     Ping,
     /// Sends a payload with a CRC to an echo server and checks for corruption.
     Echo,
@@ -429,8 +969,25 @@ pub enum Verb {
     Get,
     /// Simple key-value set which will overwrite the value for a key.
     Set,
+    /// Set the value for a key only if the key does not already exist.
+    Add,
+    /// Set the value for a key with an expiry in seconds, using the keyspace
+    /// TTL.
+    Setex,
+    /// Set the value for a key with an expiry in milliseconds, using the
+    /// keyspace TTL.
+    Psetex,
     /// Remove a key.
     Delete,
+    /// Read the value for a key and remove it.
+    Getdel,
+    /// Copy the value stored at one key to another key.
+    Copy,
+    /// Rename a key, overwriting any value stored at the new key.
+    Rename,
+    /// Remove one or more keys, depending on the batch size, with the memory
+    /// being reclaimed asynchronously by the server.
+    Unlink,
     /// Hash get, reads the value for one or more fields within the hash stored
     /// at the key.
     Hget,
@@ -455,17 +1012,148 @@ pub enum Verb {
     Lrange,
     /// Trims the elements of the list sotred at the key
     Ltrim,
+    /// Blocks until the preceding writes on the connection are acknowledged
+    /// by a number of replicas, using the keyspace `wait` arguments.
+    Wait,
+    /// Increment the integer stored at a key by one.
+    Incr,
+    /// Decrement the integer stored at a key by one.
+    Decr,
+    /// Increment the integer stored at a key by the command `amount`.
+    Incrby,
+    /// Decrement the integer stored at a key by the command `amount`.
+    Decrby,
+}
+
+impl Verb {
+    /// Returns true if the command modifies data on the server. This is used
+    /// to route commands when reads and writes go to separate endpoints.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Self::Set
+                | Self::Add
+                | Self::Setex
+                | Self::Psetex
+                | Self::Delete
+                | Self::Getdel
+                | Self::Copy
+                | Self::Rename
+                | Self::Unlink
+                | Self::Hset
+                | Self::Hsetnx
+                | Self::Hdel
+                | Self::Rpush
+                | Self::Rpushx
+                | Self::Ltrim
+                | Self::Incr
+                | Self::Decr
+                | Self::Incrby
+                | Self::Decrby
+        )
+    }
+
+    /// Returns true if the command reads values, counting towards the
+    /// hit-rate
+    pub fn is_get(&self) -> bool {
+        matches!(self, Self::Get | Self::Getdel | Self::Hget)
+    }
+}
+
+/// An ordered list of commands which operate on the same generated key, such
+/// as a read followed by a write. The commands are sent one after another on
+/// the same connection, with as many at once as the pipeline depth allows.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Sequence {
+    commands: Vec<Command>,
+    #[serde(default = "one")]
+    weight: usize,
+}
+
+impl Sequence {
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+}
+
+/// A named mix of commands with its own request rate, which runs alongside the
+/// commands of its keyspace and shares its keys, such as a slow scan next to
+/// fast point reads. Its requests are not subject to the request ratelimit.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Workload {
+    name: String,
+    ratelimit: usize,
+    commands: Vec<Command>,
+}
+
+impl Workload {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn ratelimit(&self) -> usize {
+        self.ratelimit
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
 }
 
-#[derive(Deserialize, Copy, Clone)]
+/// The arguments of the redis `WAIT` command, which blocks until the writes
+/// of the connection are acknowledged by the replicas or the timeout elapses.
+#[derive(Deserialize, Serialize, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Wait {
+    replicas: usize,
+    #[serde(default)]
+    timeout_ms: u64,
+    #[serde(default)]
+    after_writes: bool,
+}
+
+impl Wait {
+    pub fn replicas(&self) -> usize {
+        self.replicas
+    }
+
+    /// The timeout, where zero waits forever
+    pub fn timeout_ms(&self) -> u64 {
+        self.timeout_ms
+    }
+
+    /// Send a `WAIT` after each write, on the same connection
+    pub fn after_writes(&self) -> bool {
+        self.after_writes
+    }
+}
+
+#[derive(Deserialize, Serialize, Copy, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Command {
     verb: Verb,
     #[serde(default = "one")]
     weight: usize,
+    ratelimit: Option<usize>,
+    amount: Option<i64>,
 }
 
 impl Command {
+    pub fn new(verb: Verb, weight: usize) -> Self {
+        Self {
+            verb,
+            weight,
+            ratelimit: None,
+            amount: None,
+        }
+    }
+
     pub fn verb(&self) -> Verb {
         self.verb
     }
@@ -473,9 +1161,25 @@ impl Command {
     pub fn weight(&self) -> usize {
         self.weight
     }
+
+    /// A dedicated request rate for this command, which is then not subject
+    /// to the global request ratelimit
+    pub fn ratelimit(&self) -> Option<usize> {
+        self.ratelimit
+    }
+
+    /// The amount to change the counter by for `incrby` and `decrby`
+    pub fn amount(&self) -> i64 {
+        self.amount.unwrap_or(1)
+    }
+
+    /// Whether an amount was configured for the command
+    pub fn has_amount(&self) -> bool {
+        self.amount.is_some()
+    }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct InnerKey {
     length: usize,
@@ -505,7 +1209,7 @@ impl InnerKey {
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Value {
     length: usize,
@@ -515,6 +1219,7 @@ pub struct Value {
     cardinality: u32,
     #[serde(default = "alphanumeric")]
     field_type: FieldType,
+    ttl: Option<usize>,
 }
 
 impl Value {
@@ -533,15 +1238,88 @@ impl Value {
     pub fn field_type(&self) -> FieldType {
         self.field_type
     }
+
+    /// The TTL for sets which carry this value, overriding the keyspace TTL
+    pub fn ttl(&self) -> Option<usize> {
+        self.ttl
+    }
 }
 
-#[derive(Deserialize, Copy, Clone, Default)]
+#[derive(Deserialize, Serialize, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Ttl {
+    ttl: usize,
+    #[serde(default = "one")]
+    weight: usize,
+}
+
+impl Ttl {
+    pub fn ttl(&self) -> usize {
+        self.ttl
+    }
+
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+}
+
+#[derive(Deserialize, Serialize, Copy, Clone, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Request {
     #[allow(dead_code)]
     timeout: Option<usize>,
     ratelimit: Option<usize>,
     ratelimit_model: Option<RatelimitModel>,
+    #[serde(default)]
+    validate_response: bool,
+    slow_start: Option<usize>,
+    read_idle_timeout_us: Option<usize>,
+    #[serde(default)]
+    rate_profile_repeat: bool,
+    precompute_requests: Option<usize>,
+    max_incomplete_reads: Option<usize>,
+    think_time_us: Option<u64>,
+    #[serde(default)]
+    think_time_model: ThinkTimeModel,
+    think_time_file: Option<String>,
+    deadline_us: Option<u64>,
+    #[serde(default)]
+    request_id: bool,
+    slow_request_threshold_us: Option<u64>,
+    pad_request_to: Option<usize>,
+    #[serde(default)]
+    count_failures_in_rate: bool,
+    rate_schedule: Option<RateSchedule>,
+}
+
+/// A linear ramp of the request rate from a start to an end rate
+#[derive(Deserialize, Serialize, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RateSchedule {
+    start: usize,
+    end: usize,
+    duration_secs: u64,
+    steps: Option<usize>,
+}
+
+impl RateSchedule {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration_secs)
+    }
+
+    /// The number of equal steps to reach the end rate in, when the rate does
+    /// not change continuously
+    pub fn steps(&self) -> Option<usize> {
+        self.steps
+    }
 }
 
 impl Request {
@@ -549,16 +1327,119 @@ impl Request {
         self.ratelimit
     }
 
+    /// The initial request rate when using slow-start. The rate doubles each
+    /// window until it reaches the configured ratelimit.
+    pub fn slow_start(&self) -> Option<usize> {
+        self.slow_start
+    }
+
+    /// Whether a rate profile repeats from the start once it is exhausted,
+    /// rather than holding the final rate
+    pub fn rate_profile_repeat(&self) -> bool {
+        self.rate_profile_repeat
+    }
+
+    /// Abandon a session when no bytes of an outstanding response arrive for
+    /// this long. Unlike the request timeout, this resets on partial reads.
+    pub fn read_idle_timeout(&self) -> Option<Duration> {
+        self.read_idle_timeout_us
+            .map(|us| Duration::from_micros(us as u64))
+    }
+
+    /// The number of requests to encode for each command at startup. Requests
+    /// are then sent by cycling through these frames instead of being encoded
+    /// as they are sent, trading key and value variety for lower overhead.
+    pub fn precompute_requests(&self) -> Option<usize> {
+        self.precompute_requests
+    }
+
+    /// The pause between a response and the next request on a session, which
+    /// models closed-loop clients. This is in addition to any ratelimit.
+    pub fn think_time(&self) -> Option<Duration> {
+        self.think_time_us.map(Duration::from_micros)
+    }
+
+    pub fn think_time_model(&self) -> ThinkTimeModel {
+        self.think_time_model
+    }
+
+    /// A file of think times in microseconds, one per line, which the pause
+    /// before each request is drawn from instead of the think time model
+    pub fn think_time_file(&self) -> Option<&str> {
+        self.think_time_file.as_deref()
+    }
+
+    /// Tag each request with a unique id, which is prefixed to its keys and
+    /// logged with any error response
+    pub fn request_id(&self) -> bool {
+        self.request_id
+    }
+
+    /// Log the request and response bytes of any request whose response
+    /// takes longer than this
+    pub fn slow_request_threshold(&self) -> Option<Duration> {
+        self.slow_request_threshold_us.map(Duration::from_micros)
+    }
+
+    /// Pad each request with filler the server ignores until it is this many
+    /// bytes on the wire, up to 64KB. Requests which are already longer are
+    /// sent as is.
+    pub fn pad_request_to(&self) -> Option<usize> {
+        self.pad_request_to
+    }
+
+    /// Count the requests which come due at each worker's share of the request
+    /// ratelimit while none of its connections is ready to send them, so that
+    /// failing or busy connections show up as a shortfall in the offered load.
+    pub fn count_failures_in_rate(&self) -> bool {
+        self.count_failures_in_rate
+    }
+
+    /// Ramp the request rate linearly from a start to an end rate when the
+    /// run begins
+    pub fn rate_schedule(&self) -> Option<RateSchedule> {
+        self.rate_schedule
+    }
+
+    /// The deadline for each request, which is passed to the codec so that it
+    /// can be propagated to servers which honor it. Only thrift cache carries
+    /// it, as the timeout of the request.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline_us.map(Duration::from_micros)
+    }
+
+    /// Close a session after this many consecutive reads which add no bytes
+    /// while responses are outstanding, guarding against servers which stall
+    /// part way through a response
+    pub fn max_incomplete_reads(&self) -> Option<usize> {
+        self.max_incomplete_reads
+    }
+
+    /// For the echo protocol, check that each response is exactly the payload
+    /// which was sent
+    pub fn validate_response(&self) -> bool {
+        self.validate_response
+    }
+
     pub fn ratelimit_model(&self) -> rustcommon_ratelimiter::Refill {
         match self.ratelimit_model {
-            None | Some(RatelimitModel::Smooth) => rustcommon_ratelimiter::Refill::Smooth,
+            // poisson arrivals are paced by the workers instead
+            None | Some(RatelimitModel::Smooth) | Some(RatelimitModel::Poisson) => {
+                rustcommon_ratelimiter::Refill::Smooth
+            }
             Some(RatelimitModel::Uniform) => rustcommon_ratelimiter::Refill::Uniform,
             Some(RatelimitModel::Normal) => rustcommon_ratelimiter::Refill::Normal,
         }
     }
+
+    /// Whether requests arrive as a Poisson process at the ratelimit, rather
+    /// than from the ratelimiter
+    pub fn poisson(&self) -> bool {
+        matches!(self.ratelimit_model, Some(RatelimitModel::Poisson))
+    }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Tls {
     ca_file: Option<String>,
@@ -567,6 +1448,10 @@ pub struct Tls {
     private_key: Option<String>,
     verify: bool,
     session_cache: Option<u32>,
+    #[serde(default)]
+    alpn: Vec<String>,
+    sni: Option<String>,
+    key_update_secs: Option<u64>,
 }
 
 impl Tls {
@@ -593,6 +1478,77 @@ impl Tls {
     pub fn session_cache(&self) -> Option<u32> {
         self.session_cache
     }
+
+    /// The protocols advertised with ALPN, in order of preference
+    pub fn alpn(&self) -> &[String] {
+        &self.alpn
+    }
+
+    /// The server name sent with SNI and used to verify the certificate
+    pub fn sni(&self) -> Option<String> {
+        self.sni.clone()
+    }
+
+    /// Update the keys of each TLS 1.3 session this often, measuring the
+    /// latency of the request sent along with each update
+    pub fn key_update(&self) -> Option<Duration> {
+        self.key_update_secs.map(Duration::from_secs)
+    }
+}
+
+/// TLS settings for a single endpoint. Settings which are not given are taken
+/// from the `[tls]` section, if there is one, so an endpoint may use TLS when
+/// others do not, or use its own CA or client certificate.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsEndpoint {
+    endpoint: String,
+    #[serde(default)]
+    plaintext: bool,
+    ca_file: Option<String>,
+    certificate: Option<String>,
+    certificate_chain: Option<String>,
+    private_key: Option<String>,
+    verify: Option<bool>,
+    sni: Option<String>,
+    key_update_secs: Option<u64>,
+}
+
+impl TlsEndpoint {
+    pub fn endpoint(&self) -> SocketAddr {
+        resolve_endpoint(&self.endpoint)
+    }
+
+    /// The TLS settings for the endpoint, or `None` if connections to it are
+    /// plaintext
+    pub fn tls(&self, global: Option<&Tls>) -> Option<Tls> {
+        if self.plaintext {
+            return None;
+        }
+        let mut tls = global.cloned().unwrap_or_default();
+        if self.ca_file.is_some() {
+            tls.ca_file = self.ca_file.clone();
+        }
+        if self.certificate.is_some() {
+            tls.certificate = self.certificate.clone();
+        }
+        if self.certificate_chain.is_some() {
+            tls.certificate_chain = self.certificate_chain.clone();
+        }
+        if self.private_key.is_some() {
+            tls.private_key = self.private_key.clone();
+        }
+        if let Some(verify) = self.verify {
+            tls.verify = verify;
+        }
+        if self.sni.is_some() {
+            tls.sni = self.sni.clone();
+        }
+        if self.key_update_secs.is_some() {
+            tls.key_update_secs = self.key_update_secs;
+        }
+        Some(tls)
+    }
 }
 
 struct ExitWatcher;
@@ -602,58 +1558,232 @@ impl Watcher for ExitWatcher {
     }
 }
 
-#[derive(Deserialize, Default, Clone)]
+/// Read the `host:port` of the named endpoint from each child of the path,
+/// skipping children which do not have the endpoint
+fn zk_hosts(server: &str, path: &str, name: &str) -> Result<Vec<String>, String> {
+    let zk = ZooKeeper::connect(server, Duration::from_secs(15), ExitWatcher)
+        .map_err(|e| format!("failed to connect to {}: {}", server, e))?;
+    let children = zk
+        .get_children(path, true)
+        .map_err(|e| format!("failed to list children: {}", e))?;
+    let mut hosts = Vec::new();
+    for child in children {
+        let child_path = format!("{}/{}", path, child);
+        let (data, _) = zk
+            .get_data(&child_path, true)
+            .map_err(|e| format!("failed to read {}: {}", child_path, e))?;
+        let entry: JsonValue = serde_json::from_slice(&data)
+            .map_err(|e| format!("bad entry at {}: {}", child_path, e))?;
+        if let Some(host) = zk_host(&entry, name) {
+            hosts.push(host);
+        }
+    }
+    Ok(hosts)
+}
+
+/// The `host:port` of the named endpoint in a ZooKeeper serverset entry
+fn zk_host(entry: &JsonValue, name: &str) -> Option<String> {
+    let endpoint = &entry["additionalEndpoints"][name];
+    let host = endpoint["host"].as_str()?;
+    let port = endpoint["port"].as_u64()?;
+    Some(format!("{}:{}", host, port))
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Target {
+    name: Option<String>,
+    protocol: Option<Protocol>,
+    #[serde(default)]
     endpoints: Vec<String>,
+    #[serde(default)]
+    read_endpoints: Vec<String>,
+    #[serde(default)]
+    write_endpoints: Vec<String>,
     zk_path: Option<String>,
     zk_server: Option<String>,
     zk_endpoint_name: Option<String>,
+    #[serde(default)]
+    keyspace: Vec<Keyspace>,
+    #[serde(default)]
+    cluster: bool,
+    exec: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+enum Targets {
+    One(Target),
+    Many(Vec<Target>),
+}
+
+// A single `[target]` is a table and multiple `[[target]]` blocks are an array
+// of tables. Each form is deserialized as a target directly, rather than by
+// trying each variant in turn, so that an error within a target, such as an
+// unknown field, is reported as it is for the other sections.
+impl<'de> Deserialize<'de> for Targets {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct TargetsVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for TargetsVisitor {
+            type Value = Targets;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a [target] table or [[target]] blocks")
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Targets, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                Target::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+                    .map(Targets::One)
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Targets, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                Vec::<Target>::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))
+                    .map(Targets::Many)
+            }
+        }
+
+        deserializer.deserialize_any(TargetsVisitor)
+    }
 }
 
 impl Target {
+    pub fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    /// The protocol for this target, overriding the general protocol
+    pub fn protocol(&self) -> Option<Protocol> {
+        self.protocol
+    }
+
+    /// Keyspaces for this target, which replace the top-level keyspaces
+    pub fn keyspaces(&self) -> Vec<Keyspace> {
+        self.keyspace.clone()
+    }
+
+    /// When set, the endpoints are seeds for a redis cluster and requests are
+    /// routed to the primary serving the slot for their key
+    pub fn cluster(&self) -> bool {
+        self.cluster
+    }
+
+    /// A command, run with `sh -c`, whose stdin and stdout are used in place
+    /// of a connection to an endpoint. Each connection runs its own process.
+    pub fn exec(&self) -> Option<String> {
+        self.exec.clone()
+    }
+
+    /// The endpoints of the target. With ZooKeeper, the hosts are read from
+    /// the children of the path, and a failure to read them exits with a
+    /// readable message, as a failure to resolve a host does.
     pub fn endpoints(&self) -> Vec<SocketAddr> {
-        if self.zk_path.is_some() && self.zk_server.is_some() && self.zk_endpoint_name.is_some() {
-            let zk_endpoint_name = self.zk_endpoint_name.as_deref().unwrap();
-            let mut ret = Vec::new();
-            let zk = ZooKeeper::connect(
-                self.zk_server.as_ref().unwrap(),
-                Duration::from_secs(15),
-                ExitWatcher,
-            )
-            .unwrap();
-            let children = zk
-                .get_children(self.zk_path.as_ref().unwrap(), true)
-                .unwrap();
-            for child in children {
-                let child_path = format!("{}/{}", self.zk_path.as_ref().unwrap(), child);
-                let data = zk.get_data(&child_path, true).unwrap();
-                let data = std::str::from_utf8(&data.0).unwrap();
-                let entry: JsonValue = serde_json::from_str(data).unwrap();
-                let host = &entry["additionalEndpoints"][zk_endpoint_name]["host"];
-                let host = host.to_string();
-                let host_parts: Vec<&str> = host.split('"').collect();
-                let port = &entry["additionalEndpoints"][zk_endpoint_name]["port"];
-                if let Some(host) = host_parts.get(1) {
-                    let host = format!("{}:{}", host, port);
-                    if let Ok(mut addrs) = host.to_socket_addrs() {
-                        if let Some(socket_addr) = addrs.next() {
-                            ret.push(socket_addr);
-                        }
-                    }
-                }
-            }
-            ret
-        } else {
-            let mut ret = Vec::new();
-            for host in &self.endpoints {
-                if let Ok(mut addrs) = host.to_socket_addrs() {
-                    if let Some(socket_addr) = addrs.next() {
-                        ret.push(socket_addr);
-                    }
+        match (&self.zk_server, &self.zk_path, &self.zk_endpoint_name) {
+            (Some(server), Some(path), Some(name)) => match zk_hosts(server, path, name) {
+                Ok(hosts) => resolve(&hosts),
+                Err(e) => {
+                    eprintln!("failed to read endpoints from zookeeper: {}: {}", path, e);
+                    std::process::exit(1);
                 }
+            },
+            _ => resolve(&self.endpoints),
+        }
+    }
+
+    /// Endpoints dedicated to read commands
+    pub fn read_endpoints(&self) -> Vec<SocketAddr> {
+        resolve(&self.read_endpoints)
+    }
+
+    /// Endpoints dedicated to write commands
+    pub fn write_endpoints(&self) -> Vec<SocketAddr> {
+        resolve(&self.write_endpoints)
+    }
+}
+
+/// Deliberately fail a fraction of connection activity for an endpoint to
+/// simulate a partial outage
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Chaos {
+    endpoint: String,
+    probability: f64,
+}
+
+impl Chaos {
+    pub fn endpoint(&self) -> SocketAddr {
+        resolve_endpoint(&self.endpoint)
+    }
+
+    /// The probability, between 0 and 1, that a connect attempt or response
+    /// for the endpoint is treated as a failure
+    pub fn probability(&self) -> f64 {
+        self.probability
+    }
+}
+
+/// The number of times to try resolving an endpoint before giving up
+const RESOLVE_ATTEMPTS: usize = 4;
+
+/// The delay before the first retry of a failed resolution, which doubles
+/// with each attempt
+const RESOLVE_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Resolve each host to its first address, exiting if any can not be resolved
+fn resolve(hosts: &[String]) -> Vec<SocketAddr> {
+    hosts.iter().map(|host| resolve_endpoint(host)).collect()
+}
+
+/// Resolve a host to its first address. A host which can not be resolved is
+/// retried with backoff, as DNS failures are often transient, and then exits
+/// with a readable message. The logger may not be running yet, so the message
+/// is written to stderr directly.
+pub fn resolve_endpoint(host: &str) -> SocketAddr {
+    match resolve_host(host, RESOLVE_ATTEMPTS, RESOLVE_BACKOFF) {
+        Ok(socket_addr) => socket_addr,
+        Err(e) => {
+            eprintln!("failed to resolve endpoint: {}: {}", host, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn resolve_host(
+    host: &str,
+    attempts: usize,
+    backoff: Duration,
+) -> Result<SocketAddr, std::io::Error> {
+    let mut delay = backoff;
+    let mut attempt = 1;
+    loop {
+        let result = host.to_socket_addrs().and_then(|mut addrs| {
+            addrs.next().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses found")
+            })
+        });
+        match result {
+            Ok(socket_addr) => return Ok(socket_addr),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                eprintln!(
+                    "failed to resolve endpoint: {}: {}, retrying in {} ms",
+                    host,
+                    e,
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
             }
-            ret
         }
     }
 }
@@ -730,3 +1860,112 @@ impl Waterfall {
         self.scale
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_failure() {
+        let addr = resolve_host("127.0.0.1:12345", 1, Duration::ZERO).unwrap();
+        assert_eq!(addr, "127.0.0.1:12345".parse().unwrap());
+
+        // the .invalid top level domain is reserved and never resolves
+        assert!(resolve_host("rpc-perf.invalid:12345", 2, Duration::ZERO).is_err());
+        assert!(resolve_host("missing-port", 1, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn zk_entry() {
+        let entry: JsonValue = serde_json::from_str(
+            r#"{"additionalEndpoints": {"cache": {"host": "10.0.0.1", "port": 11211}}}"#,
+        )
+        .unwrap();
+        assert_eq!(zk_host(&entry, "cache"), Some("10.0.0.1:11211".to_string()));
+        assert_eq!(zk_host(&entry, "other"), None);
+    }
+
+    #[test]
+    fn tls_endpoint_key_update() {
+        let global: Tls = toml::from_str("verify = true\nkey_update_secs = 60").unwrap();
+        let endpoint: TlsEndpoint = toml::from_str(
+            r#"
+            endpoint = "127.0.0.1:6380"
+            key_update_secs = 30
+            "#,
+        )
+        .unwrap();
+        let tls = endpoint.tls(Some(&global)).unwrap();
+        assert_eq!(tls.key_update(), Some(Duration::from_secs(30)));
+
+        // an endpoint without its own interval uses the one from [tls]
+        let endpoint: TlsEndpoint = toml::from_str(r#"endpoint = "127.0.0.1:6380""#).unwrap();
+        let tls = endpoint.tls(Some(&global)).unwrap();
+        assert_eq!(tls.key_update(), Some(Duration::from_secs(60)));
+        let tls = endpoint.tls(None).unwrap();
+        assert_eq!(tls.key_update(), None);
+    }
+
+    #[test]
+    fn protocol_verbs() {
+        for protocol in [Protocol::Memcache, Protocol::MemcacheBinary] {
+            assert!(protocol.supports(Verb::Get));
+            assert!(protocol.supports(Verb::Delete));
+            assert!(!protocol.supports(Verb::Hget));
+            assert!(!protocol.supports(Verb::Incr));
+        }
+        for protocol in [Protocol::Redis, Protocol::RedisInline, Protocol::RedisResp] {
+            assert!(protocol.supports(Verb::Hget));
+            assert!(protocol.supports(Verb::Unlink));
+            assert!(!protocol.supports(Verb::Add));
+            assert!(!protocol.supports(Verb::Rpush));
+        }
+        assert!(Protocol::ThriftCache.supports(Verb::Lrange));
+        assert!(!Protocol::ThriftCache.supports(Verb::Get));
+        assert!(!Protocol::ThriftCache.supports(Verb::Set));
+        assert!(Protocol::Ping.supports(Verb::Ping));
+        assert!(!Protocol::Ping.supports(Verb::Get));
+        assert!(Protocol::Echo.supports(Verb::Echo));
+        assert!(!Protocol::Echo.supports(Verb::Get));
+    }
+
+    #[test]
+    fn targets() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [general]
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.targets().len(), 1);
+
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [general]
+            [[target]]
+            endpoints = ["127.0.0.1:11211"]
+            [[target]]
+            endpoints = ["127.0.0.1:6379"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.targets().len(), 2);
+
+        // an unknown field is named in the error, whichever form is used
+        for target in ["[target]", "[[target]]"] {
+            let error = toml::from_str::<ConfigFile>(&format!(
+                "[general]\n{}\nendpoint = [\"127.0.0.1:11211\"]\n",
+                target
+            ))
+            .err()
+            .unwrap();
+            assert!(
+                error.to_string().contains("unknown field `endpoint`"),
+                "{}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A response latency histogram which keeps an exemplar for each bucket. Each
+//! exemplar is a sampled latency tagged with a random trace id, exposed in the
+//! OpenMetrics format so that scrapers can correlate latency with traces.
+//! Histograms with the same bucket bounds can be merged, so that histograms
+//! kept separately, such as for each worker, can be reported in aggregate.
+
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default upper bounds of the buckets, in microseconds. A final bucket holds
+/// any latency above the last bound.
+const BOUNDS: &[u64] = &[
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000,
+];
+
+/// Only one in this many samples replaces the exemplar for its bucket
+const SAMPLE_RATE: u64 = 64;
+
+#[derive(Clone, Copy)]
+struct Exemplar {
+    trace_id: u128,
+    value: u64,
+    timestamp: f64,
+}
+
+struct Bucket {
+    count: AtomicU64,
+    exemplar: Mutex<Option<Exemplar>>,
+}
+
+pub struct ExemplarHistogram {
+    bounds: Vec<u64>,
+    buckets: Vec<Bucket>,
+    sum: AtomicU64,
+}
+
+impl Default for ExemplarHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExemplarHistogram {
+    pub fn new() -> Self {
+        Self::with_bounds(BOUNDS)
+    }
+
+    /// A histogram with the given ascending bucket upper bounds, in
+    /// microseconds
+    pub fn with_bounds(bounds: &[u64]) -> Self {
+        let buckets = (0..=bounds.len())
+            .map(|_| Bucket {
+                count: AtomicU64::new(0),
+                exemplar: Mutex::new(None),
+            })
+            .collect();
+        Self {
+            bounds: bounds.to_vec(),
+            buckets,
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    /// Merge the histograms into a new histogram. All of the histograms must
+    /// have the same bucket bounds.
+    pub fn aggregate(histograms: &[Arc<ExemplarHistogram>]) -> Result<Self, Error> {
+        let bounds = histograms
+            .first()
+            .map(|h| h.bounds.as_slice())
+            .unwrap_or(BOUNDS);
+        let aggregate = Self::with_bounds(bounds);
+        for histogram in histograms {
+            aggregate.merge(histogram)?;
+        }
+        Ok(aggregate)
+    }
+
+    /// Add the counts and sum of another histogram to this one. Exemplars are
+    /// taken from the other histogram for buckets which have none.
+    pub fn merge(&self, other: &ExemplarHistogram) -> Result<(), Error> {
+        if self.bounds != other.bounds {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "can not merge histograms with different bucket bounds",
+            ));
+        }
+        for (bucket, other) in self.buckets.iter().zip(other.buckets.iter()) {
+            bucket
+                .count
+                .fetch_add(other.count.load(Ordering::Relaxed), Ordering::Relaxed);
+            let theirs = *other.exemplar.lock().unwrap();
+            let mut ours = bucket.exemplar.lock().unwrap();
+            if ours.is_none() {
+                *ours = theirs;
+            }
+        }
+        self.sum
+            .fetch_add(other.sum.load(Ordering::Relaxed), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Record a latency, in microseconds
+    pub fn record(&self, us: u64) {
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| us <= *bound)
+            .unwrap_or(self.bounds.len());
+        let bucket = &self.buckets[index];
+        self.sum.fetch_add(us, Ordering::Relaxed);
+        let count = bucket.count.fetch_add(1, Ordering::Relaxed);
+        if count % SAMPLE_RATE == 0 {
+            if let Ok(mut exemplar) = bucket.exemplar.try_lock() {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs_f64())
+                    .unwrap_or(0.0);
+                *exemplar = Some(Exemplar {
+                    trace_id: rand::random(),
+                    value: us,
+                    timestamp,
+                });
+            }
+        }
+    }
+
+    /// Render the histogram, with exemplars, in the OpenMetrics text format
+    pub fn openmetrics(&self, name: &str) -> String {
+        let mut data = vec![format!("# TYPE {} histogram", name)];
+        let mut cumulative = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.count.load(Ordering::Relaxed);
+            let le = match self.bounds.get(index) {
+                Some(bound) => format!("{}", bound),
+                None => "+Inf".to_string(),
+            };
+            let mut line = format!("{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+            if let Some(exemplar) = *bucket.exemplar.lock().unwrap() {
+                line += &format!(
+                    " # {{trace_id=\"{:032x}\"}} {} {:.3}",
+                    exemplar.trace_id, exemplar.value, exemplar.timestamp
+                );
+            }
+            data.push(line);
+        }
+        data.push(format!("{}_sum {}", name, self.sum.load(Ordering::Relaxed)));
+        data.push(format!("{}_count {}", name, cumulative));
+        data.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openmetrics() {
+        let histogram = ExemplarHistogram::new();
+        histogram.record(50);
+        histogram.record(300);
+        histogram.record(2_000_000);
+
+        let output = histogram.openmetrics("response_latency_us");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "# TYPE response_latency_us histogram");
+        assert!(lines[1].starts_with("response_latency_us_bucket{le=\"100\"} 1 # {trace_id=\""));
+        assert!(lines[1].contains("\"} 50 "));
+        assert_eq!(lines[2], "response_latency_us_bucket{le=\"250\"} 1");
+        assert!(lines[3].starts_with("response_latency_us_bucket{le=\"500\"} 2 # "));
+        assert!(lines[14].starts_with("response_latency_us_bucket{le=\"+Inf\"} 3 # "));
+        assert_eq!(lines[15], "response_latency_us_sum 2000350");
+        assert_eq!(lines[16], "response_latency_us_count 3");
+    }
+
+    #[test]
+    fn aggregate() {
+        let a = Arc::new(ExemplarHistogram::new());
+        a.record(50);
+        a.record(300);
+        let b = Arc::new(ExemplarHistogram::new());
+        b.record(60);
+        b.record(2_000_000);
+
+        let aggregate = ExemplarHistogram::aggregate(&[a, b]).unwrap();
+        let output = aggregate.openmetrics("latency");
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[1].starts_with("latency_bucket{le=\"100\"} 2 # "));
+        assert!(lines[3].starts_with("latency_bucket{le=\"500\"} 3 # "));
+        assert!(lines[14].starts_with("latency_bucket{le=\"+Inf\"} 4 # "));
+        assert_eq!(lines[15], "latency_sum 2000410");
+
+        let other = Arc::new(ExemplarHistogram::with_bounds(&[100, 1_000]));
+        assert!(ExemplarHistogram::aggregate(&[Arc::new(aggregate), other]).is_err());
+    }
+}
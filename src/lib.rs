@@ -9,25 +9,66 @@ extern crate rustcommon_logger;
 mod macros;
 
 mod admin;
+mod baseline;
+mod cdf;
+mod cluster;
+mod command_stats;
 // mod buffer;
 mod codec;
 mod config;
 mod config_file;
+mod exemplars;
 mod metrics;
+mod phases;
+mod poisson;
+mod populate;
+mod profile;
+mod ramp;
+mod report;
+mod response_sizes;
+mod samples;
+mod self_stats;
+mod server_stats;
 mod session;
+mod signal;
+mod sqlite;
+mod statsd;
+mod sweep;
+mod target_stats;
+mod think_time;
 mod time;
 mod worker;
 
 pub use crate::admin::Admin;
 pub use crate::config::Config;
+pub use crate::config_file::{resolve_endpoint, OutputFormat};
 pub use crate::metrics::*;
+pub use crate::self_stats::CountingAllocator;
 pub use crate::session::{Session, TcpStream};
+pub use crate::signal::stop_on_signal;
+pub use crate::sweep::RateSweep;
 pub use crate::time::*;
 
+use baseline::Reservoir;
+use cluster::SlotMap;
+use command_stats::CommandStats;
+use exemplars::ExemplarHistogram;
+use phases::Phases;
+use profile::RateProfile;
+use ramp::RateRamp;
+use report::RunLatency;
+use response_sizes::ResponseSizes;
 use rustcommon_heatmap::{AtomicHeatmap, AtomicU64};
-use rustcommon_logger::{File, LogBuilder, MultiLogBuilder, Output, Stdout};
+use rustcommon_logger::{File, LogBuilder, MultiLogBuilder, Output, Stderr, Stdout};
 use rustcommon_ratelimiter::Ratelimiter;
+use samples::LatencySamples;
+use self_stats::SelfStats;
+use sqlite::Sqlite;
+use statsd::Statsd;
+use target_stats::TargetStats;
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::Arc;
 use std::thread::JoinHandle;
 use worker::Worker;
@@ -36,15 +77,28 @@ use worker::Worker;
 pub struct Builder {
     admin: Admin,
     workers: Vec<Worker>,
+    config: Arc<Config>,
+    /// the config of each target, the first of which is `config`
+    targets: Vec<Arc<Config>>,
+    populate_verify: bool,
+    request_ratelimit: Option<Arc<Ratelimiter>>,
 }
 
 impl Builder {
-    /// Create a new runtime builder from the given config
+    /// Create a new runtime builder from the given config file
     pub fn new(config: Option<&str>) -> Self {
-        let config = Config::new(config);
+        Self::from_config(Config::new(config))
+    }
 
+    /// Create a new runtime builder from an already loaded config
+    pub fn from_config(mut config: Config) -> Self {
+        let additional = config.take_targets();
         let config = Arc::new(config);
 
+        // each target is driven by its own set of workers
+        let mut targets = vec![config.clone()];
+        targets.extend(additional.into_iter().map(Arc::new));
+
         let log_level = config.debug().log_level();
 
         let debug_output: Box<dyn Output> = if let Some(file) = config.debug().log_file() {
@@ -56,6 +110,9 @@ impl Builder {
                 File::new(&file, &backup, config.debug().log_max_size())
                     .expect("failed to open debug log file"),
             )
+        } else if config.general().output_format() == OutputFormat::Json {
+            // keep stdout for the json output
+            Box::new(Stderr::new())
         } else {
             Box::new(Stdout::new())
         };
@@ -73,7 +130,7 @@ impl Builder {
             .build()
             .start();
 
-        let threads = config.general().threads() as u64;
+        let threads = config.general().threads() as u64 * targets.len() as u64;
 
         let connect_ratelimit = if let Some(r) = config.connection().ratelimit() {
             let r = Ratelimiter::new(threads, 1, r as u64);
@@ -91,7 +148,16 @@ impl Builder {
             None
         };
 
-        let request_ratelimit = if let Some(r) = config.request().ratelimit() {
+        // a rate schedule starts from its own rate, with or without a ratelimit
+        let schedule = config.request().rate_schedule();
+        let ratelimit = schedule
+            .map(|s| s.start())
+            .or_else(|| config.request().ratelimit());
+        let request_ratelimit = if let Some(r) = ratelimit {
+            let r = match config.request().slow_start() {
+                Some(initial) => std::cmp::min(initial, r),
+                None => r,
+            };
             let r = Ratelimiter::new(threads, 1, r as u64);
             r.set_strategy(config.request().ratelimit_model());
             Some(Arc::new(r))
@@ -100,16 +166,64 @@ impl Builder {
         };
 
         let connect_heatmap = Some(Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
-            1_000_000,
+            config.general().latency_max(),
             3,
-            Duration::from_secs(config.general().interval().as_secs()),
+            config.general().latency_window(),
             Duration::from_millis(1000),
         )));
 
+        // connection lifetimes are recorded in milliseconds, up to a day
+        let lifetime_heatmap = if config.connection().lifetime() {
+            Some(Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
+                86_400_000,
+                3,
+                config.general().latency_window(),
+                Duration::from_millis(1000),
+            )))
+        } else {
+            None
+        };
+
+        let response_sizes = if config.general().response_size_buckets().is_empty() {
+            None
+        } else {
+            Some(Arc::new(ResponseSizes::new(&config)))
+        };
+
+        // delivery latency of messages on subscribed connections
+        let delivery_heatmap = if targets.iter().any(|t| !t.general().subscribe().is_empty()) {
+            Some(Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
+                config.general().latency_max(),
+                3,
+                config.general().latency_window(),
+                Duration::from_millis(1000),
+            )))
+        } else {
+            None
+        };
+
+        // latency of the requests sent along with a tls key update
+        let key_update = config.tls().and_then(|t| t.key_update()).is_some()
+            || config
+                .tls_endpoints()
+                .values()
+                .flatten()
+                .any(|t| t.key_update().is_some());
+        let key_update_heatmap = if key_update {
+            Some(Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
+                config.general().latency_max(),
+                3,
+                config.general().latency_window(),
+                Duration::from_millis(1000),
+            )))
+        } else {
+            None
+        };
+
         let request_heatmap = Some(Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
-            1_000_000,
+            config.general().latency_max(),
             3,
-            Duration::from_secs(config.general().interval().as_secs()),
+            config.general().latency_window(),
             Duration::from_millis(1000),
         )));
 
@@ -128,37 +242,557 @@ impl Builder {
                 None
             };
 
-        for endpoint in config.endpoints() {
-            info!("endpoint: {}", endpoint);
+        // when values of more than one length are sent, latency is also
+        // recorded for each length to show how payload size drives latency
+        let mut value_lengths: Vec<usize> = targets
+            .iter()
+            .flat_map(|t| t.keyspaces().iter().flat_map(|k| k.value_lengths()))
+            .collect();
+        value_lengths.sort_unstable();
+        value_lengths.dedup();
+        let mut value_heatmaps = HashMap::new();
+        if value_lengths.len() > 1 {
+            for length in value_lengths {
+                value_heatmaps.insert(
+                    length,
+                    Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
+                        config.general().latency_max(),
+                        3,
+                        config.general().latency_window(),
+                        Duration::from_millis(1000),
+                    )),
+                );
+            }
+        }
+
+        if cfg!(not(target_os = "linux"))
+            && (config.connection().tcp_quickack() || config.connection().tcp_cork())
+        {
+            warn!("tcp_quickack and tcp_cork are only supported on Linux and will be ignored");
         }
 
+        // warn before running into EMFILE when the pools need more sockets
+        // than the process is allowed to open
+        let connections: usize = targets
+            .iter()
+            .map(|target| {
+                let pool = target.general().threads()
+                    * target.connection().poolsize()
+                    * target.endpoints().len();
+                match target.connection().max_connections_per_client() {
+                    Some(max) => pool.min(max),
+                    None => pool,
+                }
+            })
+            .sum();
+        let capped = targets
+            .iter()
+            .all(|target| target.connection().max_connections_per_client().is_some());
+        if let Some(limit) = raise_fd_limit() {
+            // leave headroom for the files and sockets used outside the pools
+            if connections as libc::rlim_t > limit / 10 * 9 {
+                if capped {
+                    warn!(
+                        "max_connections_per_client allows {} connections, which is close to the open file limit of {}, raise it with ulimit -n or lower the cap",
+                        connections, limit
+                    );
+                } else {
+                    warn!(
+                        "{} connections is close to the open file limit of {}, raise it with ulimit -n or set max_connections_per_client",
+                        connections, limit
+                    );
+                }
+            }
+        }
+
+        let recycle = config
+            .connection()
+            .recycle_per_window_fraction()
+            .map(|_| Arc::new(AtomicUsize::new(0)));
+
+        // response latencies are held back until enough connections are ready
+        let ready = config
+            .general()
+            .min_ready_fraction()
+            .map(|_| Arc::new(AtomicBool::new(false)));
+
         let mut workers = Vec::new();
-        for _ in 0..threads {
-            let mut worker = Worker::new(config.clone()).unwrap();
-            worker.set_connect_ratelimit(connect_ratelimit.clone());
-            worker.set_reconnect_ratelimit(reconnect_ratelimit.clone());
-            worker.set_request_ratelimit(request_ratelimit.clone());
-            worker.set_connect_heatmap(connect_heatmap.clone());
-            worker.set_request_heatmap(request_heatmap.clone());
-            worker.set_request_waterfall(request_waterfall.clone());
-            workers.push(worker);
-        }
-
-        let mut admin = Admin::new(config, log);
+        let mut target_stats = Vec::new();
+        let mut command_stats = Vec::new();
+        let mut workload_stats = Vec::new();
+        let mut all_sequence_heatmaps = Vec::new();
+        let mut wait_heatmaps = Vec::new();
+        for target in &targets {
+            if targets.len() > 1 {
+                info!(
+                    "target: {} protocol: {:?}",
+                    target.name(),
+                    target.general().protocol()
+                );
+            }
+
+            // large redis values are stored in their own allocations and can
+            // fragment memory on the server
+            if target.general().protocol().is_redis() && target.max_value_length() > 1024 * 1024 {
+                warn!(
+                    "values of up to {} bytes may fragment memory on redis servers",
+                    target.max_value_length()
+                );
+            }
+            for endpoint in target.endpoints() {
+                info!("endpoint: {}", endpoint);
+                if let Some(probability) = target.chaos(&endpoint) {
+                    warn!(
+                        "chaos mode: failing {}% of activity for endpoint: {}",
+                        probability * 100.0,
+                        endpoint
+                    );
+                }
+            }
+
+            let cluster = if target.cluster() {
+                match SlotMap::bootstrap(&target.endpoints()) {
+                    Ok(slots) => {
+                        for endpoint in slots.endpoints() {
+                            info!("cluster primary: {}", endpoint);
+                        }
+                        Some(slots)
+                    }
+                    Err(e) => {
+                        fatal!("failed to fetch cluster slots: {}", e);
+                    }
+                }
+            } else {
+                None
+            };
+
+            // command ratelimits are per-target and shared by its workers
+            let target_threads = target.general().threads() as u64;
+            let mut command_ratelimits = HashMap::new();
+            for (index, r) in target.command_ratelimits() {
+                let r = Ratelimiter::new(target_threads, 1, r as u64);
+                r.set_strategy(target.request().ratelimit_model());
+                command_ratelimits.insert(index, Arc::new(r));
+            }
+
+            // workloads also have a ratelimit per-target, and their stats are
+            // recorded for each of their commands
+            let mut workload_ratelimits = Vec::new();
+            let mut workload_commands = HashMap::new();
+            for ((k, w), rate) in target.workload_ratelimits() {
+                let r = Ratelimiter::new(target_threads, 1, rate as u64);
+                r.set_strategy(target.request().ratelimit_model());
+                workload_ratelimits.push(((k, w), Arc::new(r)));
+
+                let keyspace = &target.keyspaces()[k];
+                let workload = &keyspace.workloads()[w];
+                let label = if targets.len() > 1 {
+                    format!("{} keyspace {} {}", target.name(), k, workload.name())
+                } else {
+                    format!("keyspace {} {}", k, workload.name())
+                };
+                let stats = Arc::new(TargetStats::new(&label));
+                workload_stats.push(stats.clone());
+                for c in 0..keyspace.commands().len() {
+                    if workload.contains(c) {
+                        workload_commands.insert((k, c), stats.clone());
+                    }
+                }
+            }
+
+            let stats = if targets.len() > 1 {
+                let stats = Arc::new(TargetStats::new(target.name()));
+                target_stats.push(stats.clone());
+                Some(stats)
+            } else {
+                None
+            };
+
+            let commands = if target.general().command_stats() {
+                let prefix = if targets.len() > 1 {
+                    Some(target.name())
+                } else {
+                    None
+                };
+                let stats = Arc::new(CommandStats::new(target, prefix));
+                command_stats.push(stats.clone());
+                Some(stats)
+            } else {
+                None
+            };
+
+            // latency of each sequence, from its first command to the response
+            // to its last
+            let mut sequence_heatmaps = HashMap::new();
+            for (k, keyspace) in target.keyspaces().iter().enumerate() {
+                for s in 0..keyspace.sequence_count() {
+                    let heatmap = Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
+                        config.general().latency_max(),
+                        3,
+                        config.general().latency_window(),
+                        Duration::from_millis(1000),
+                    ));
+                    let label = if targets.len() > 1 {
+                        format!("{} keyspace {} sequence {}", target.name(), k, s)
+                    } else {
+                        format!("keyspace {} sequence {}", k, s)
+                    };
+                    all_sequence_heatmaps.push((label, heatmap.clone()));
+                    // sequences follow the commands of the keyspace
+                    sequence_heatmaps.insert((k, keyspace.commands().len() + s), heatmap);
+                }
+            }
+
+            // latency of the waits for replication acknowledgement
+            let wait_heatmap = if target.keyspaces().iter().any(|k| k.wait().is_some()) {
+                let heatmap = Arc::new(AtomicHeatmap::<u64, AtomicU64>::new(
+                    config.general().latency_max(),
+                    3,
+                    config.general().latency_window(),
+                    Duration::from_millis(1000),
+                ));
+                wait_heatmaps.push((target.name().to_string(), heatmap.clone()));
+                Some(heatmap)
+            } else {
+                None
+            };
+
+            // with endpoint affinity, each worker connects to only one of the
+            // endpoints, assigned round-robin
+            let affinity = target.connection().endpoint_affinity();
+            if affinity && (target_threads as usize) < target.endpoints().len() {
+                warn!(
+                    "endpoint_affinity: {} threads for {} endpoints, some endpoints will not be tested",
+                    target_threads,
+                    target.endpoints().len()
+                );
+            }
+
+            let max_connections = target.connection().max_connections_per_client();
+            if let Some(max) = max_connections {
+                if max < target_threads as usize {
+                    warn!(
+                        "max_connections_per_client: {} is less than the {} threads, some threads will not connect",
+                        max, target_threads
+                    );
+                }
+            }
+
+            for thread in 0..target_threads as usize {
+                let mut worker = Worker::new(target.clone()).unwrap();
+                if affinity {
+                    let endpoints = target.endpoints();
+                    worker.set_endpoint(endpoints[thread % endpoints.len()]);
+                }
+                // the cap is split between the threads so that the total is
+                // exactly the cap, with the first threads taking the remainder
+                worker.set_max_connections(max_connections.map(|max| {
+                    let threads = target_threads as usize;
+                    max / threads + usize::from(thread < max % threads)
+                }));
+                worker.set_connect_ratelimit(connect_ratelimit.clone());
+                worker.set_reconnect_ratelimit(reconnect_ratelimit.clone());
+                worker.set_request_ratelimit(request_ratelimit.clone());
+                worker.set_command_ratelimits(command_ratelimits.clone());
+                worker.set_workload_ratelimits(workload_ratelimits.clone());
+                worker.set_workload_stats(workload_commands.clone());
+                worker.set_connect_heatmap(connect_heatmap.clone());
+                worker.set_lifetime_heatmap(lifetime_heatmap.clone());
+                worker.set_delivery_heatmap(delivery_heatmap.clone());
+                worker.set_key_update_heatmap(key_update_heatmap.clone());
+                worker.set_response_sizes(response_sizes.clone());
+                worker.set_request_heatmap(request_heatmap.clone());
+                worker.set_request_waterfall(request_waterfall.clone());
+                worker.set_value_heatmaps(value_heatmaps.clone());
+                worker.set_sequence_heatmaps(sequence_heatmaps.clone());
+                worker.set_wait_heatmap(wait_heatmap.clone());
+                worker.set_ready(ready.clone());
+                if let Some(ref recycle) = recycle {
+                    worker.set_recycle(recycle.clone());
+                }
+                worker.set_target_stats(stats.clone());
+                worker.set_command_stats(commands.clone());
+                if let Some(ref slots) = cluster {
+                    worker.set_cluster(slots.clone());
+                }
+                workers.push(worker);
+            }
+        }
+
+        let mut admin = Admin::new(config.clone(), log);
         admin.set_connect_heatmap(connect_heatmap);
+        admin.set_lifetime_heatmap(lifetime_heatmap);
+        admin.set_delivery_heatmap(delivery_heatmap);
+        admin.set_key_update_heatmap(key_update_heatmap);
+        admin.set_response_sizes(response_sizes);
         admin.set_reconnect_ratelimit(reconnect_ratelimit);
         admin.set_request_heatmap(request_heatmap);
-        admin.set_request_ratelimit(request_ratelimit);
+        admin.set_request_ratelimit(request_ratelimit.clone());
         admin.set_request_waterfall(request_waterfall);
+        admin.set_value_heatmaps(value_heatmaps);
+        admin.set_sequence_heatmaps(all_sequence_heatmaps);
+        admin.set_wait_heatmaps(wait_heatmaps);
+        admin.set_recycle(recycle);
+        admin.set_ready(ready);
+        admin.set_expected_connections(connections);
+        admin.set_targets(targets.clone());
+        admin.set_target_stats(target_stats);
+        admin.set_command_stats(command_stats);
+        admin.set_workload_stats(workload_stats);
+
+        Self {
+            admin,
+            workers,
+            config,
+            targets,
+            populate_verify: false,
+            request_ratelimit,
+        }
+    }
 
-        Self { admin, workers }
+    /// Write a summary report to the given file at the end of the run. The
+    /// format is markdown for a `.md` extension and JSON otherwise.
+    pub fn report(mut self, file: Option<&str>) -> Self {
+        if let Some(file) = file {
+            let latency = Arc::new(RunLatency::new(&self.targets));
+            // the workers of each target follow those of the target before
+            let mut workers = self.workers.iter_mut();
+            for (index, target) in self.targets.iter().enumerate() {
+                let threads = target.general().threads();
+                for worker in workers.by_ref().take(threads) {
+                    worker.set_run_latency(Some((latency.clone(), index)));
+                }
+            }
+            self.admin.set_report(Some((file.to_string(), latency)));
+        }
+        self
+    }
+
+    /// Write a CDF plot of response latency, as an SVG image, to the given
+    /// file at the end of the run
+    pub fn cdf_plot(mut self, file: Option<&str>) -> Self {
+        self.admin.set_cdf_plot(file.map(|f| f.to_string()));
+        self
+    }
+
+    /// Record every response latency, in nanoseconds, to the given file
+    pub fn latency_samples(mut self, file: Option<&str>) -> Self {
+        if let Some(file) = file {
+            warn!(
+                "recording every latency sample to: {}, this file may grow very large",
+                file
+            );
+            let samples =
+                LatencySamples::create(file).expect("failed to open latency samples file");
+            for worker in self.workers.iter_mut() {
+                worker.set_latency_samples(Some(samples.clone()));
+            }
+            self.admin.set_latency_samples(Some(samples));
+        }
+        self
+    }
+
+    /// Compare response latency against a baseline run, loaded from a file
+    /// written with `latency_samples`, at the end of the run
+    pub fn baseline(mut self, file: Option<&str>) -> Self {
+        if let Some(file) = file {
+            let baseline = match baseline::load(file) {
+                Ok(baseline) => baseline,
+                Err(e) => {
+                    fatal!("failed to load baseline: {}: {}", file, e);
+                }
+            };
+            let samples = Reservoir::new();
+            for worker in self.workers.iter_mut() {
+                worker.set_baseline_samples(Some(samples.clone()));
+            }
+            self.admin.set_baseline(Some((baseline, samples)));
+        }
+        self
+    }
+
+    /// Push per-window metrics to a StatsD collector at the given address
+    pub fn statsd(mut self, addr: Option<&str>, prefix: &str) -> Self {
+        if let Some(addr) = addr {
+            let statsd = Statsd::new(addr, prefix).expect("failed to initialize statsd sink");
+            self.admin.set_statsd(Some(statsd));
+        }
+        self
+    }
+
+    /// Record the counters and latency percentiles of each window in a SQLite
+    /// database, which is created if it does not exist
+    pub fn sqlite(mut self, file: Option<&str>) -> Self {
+        if let Some(file) = file {
+            let sqlite = Sqlite::new(file, &self.config).expect("failed to initialize sqlite sink");
+            info!("sqlite: recording run: {} to: {}", sqlite.run_id(), file);
+            self.admin.set_sqlite(Some(sqlite));
+        }
+        self
+    }
+
+    /// Include a response latency histogram with trace id exemplars in the
+    /// Prometheus output, using the OpenMetrics format
+    pub fn exemplars(mut self, enabled: bool) -> Self {
+        if enabled {
+            let histogram = Arc::new(ExemplarHistogram::new());
+            for worker in self.workers.iter_mut() {
+                worker.set_latency_histogram(Some(histogram.clone()));
+            }
+            self.admin.set_latency_histogram(Some(histogram));
+        }
+        self
+    }
+
+    /// Break request latency down into the phases of a request and log the
+    /// percentiles for each phase every window
+    pub fn phases(mut self, enabled: bool) -> Self {
+        if enabled {
+            let phases = Arc::new(Phases::new(&self.config));
+            for worker in self.workers.iter_mut() {
+                worker.set_phases(Some(phases.clone()));
+            }
+            self.admin.set_phases(Some(phases));
+        }
+        self
+    }
+
+    /// Log memory usage, bytes allocated, and event loop iterations of
+    /// rpc-perf itself each window. Bytes allocated are only counted when the
+    /// binary installs `CountingAllocator` as its global allocator.
+    pub fn self_stats(mut self, enabled: bool) -> Self {
+        if enabled {
+            let mut stats = SelfStats::new();
+            for worker in self.workers.iter_mut() {
+                worker.set_iterations(Some(stats.iterations()));
+            }
+            self.admin.set_self_stats(Some(stats));
+        }
+        self
+    }
+
+    /// Drive the request rate from a file of `second,rate` pairs, updating the
+    /// rate each window
+    pub fn rate_profile(mut self, file: Option<&str>) -> Self {
+        if let Some(file) = file {
+            if self.config.request().poisson() {
+                fatal!("a rate profile can not be used with the Poisson ratelimit_model");
+            }
+            if self.config.request().rate_schedule().is_some() {
+                fatal!("a rate profile can not be used with a rate_schedule");
+            }
+            let profile = RateProfile::load(file, self.config.request().rate_profile_repeat())
+                .expect("failed to load rate profile");
+            let rate = profile.rate(0.0);
+            if let Some(ref ratelimiter) = self.request_ratelimit {
+                ratelimiter.set_rate(rate);
+            } else {
+                let threads = self.workers.len() as u64;
+                let r = Ratelimiter::new(threads, 1, rate);
+                r.set_strategy(self.config.request().ratelimit_model());
+                let r = Some(Arc::new(r));
+                for worker in self.workers.iter_mut() {
+                    worker.set_request_ratelimit(r.clone());
+                }
+                self.admin.set_request_ratelimit(r.clone());
+                self.request_ratelimit = r;
+            }
+            self.admin.set_rate_profile(Some(profile));
+        }
+        self
+    }
+
+    /// Step the request rate through the levels of the sweep, reporting the
+    /// latency at each level. The run should be configured with enough
+    /// windows to cover every level.
+    pub fn sweep(mut self, sweep: Option<RateSweep>) -> Self {
+        if let Some(sweep) = sweep {
+            if self.config.request().poisson() {
+                fatal!("a sweep can not be used with the Poisson ratelimit_model");
+            }
+            if self.config.request().rate_schedule().is_some() {
+                fatal!("a sweep can not be used with a rate_schedule");
+            }
+            let rate = sweep.rate(0).unwrap();
+            if let Some(ref ratelimiter) = self.request_ratelimit {
+                ratelimiter.set_rate(rate);
+            } else {
+                let threads = self.workers.len() as u64;
+                let r = Ratelimiter::new(threads, 1, rate);
+                r.set_strategy(self.config.request().ratelimit_model());
+                let r = Some(Arc::new(r));
+                for worker in self.workers.iter_mut() {
+                    worker.set_request_ratelimit(r.clone());
+                }
+                self.admin.set_request_ratelimit(r.clone());
+                self.request_ratelimit = r;
+            }
+            info!("Sweep: request rate: {}", rate);
+            self.admin.set_sweep(Some(sweep));
+        }
+        self
+    }
+
+    /// Abort the run once the percentage of requests and connects which error
+    /// exceeds the threshold for consecutive windows. The end-of-run summary
+    /// is still written, and `Runtime::wait` then returns a nonzero code.
+    pub fn abort_error_rate(mut self, threshold: Option<f64>, windows: usize) -> Self {
+        if let Some(threshold) = threshold {
+            if !(0.0..=100.0).contains(&threshold) {
+                fatal!("abort error rate must be a percentage between 0 and 100");
+            }
+            if windows == 0 {
+                fatal!("abort error windows must be at least 1");
+            }
+        }
+        self.admin.set_error_abort(threshold, windows);
+        self
+    }
+
+    /// Stop after sending this many requests in total, split evenly across
+    /// the workers, rather than after a number of windows
+    pub fn requests(mut self, requests: Option<u64>) -> Self {
+        if let Some(requests) = requests {
+            let complete = Arc::new(AtomicUsize::new(0));
+            let workers = self.workers.len() as u64;
+            for (index, worker) in self.workers.iter_mut().enumerate() {
+                let mut share = requests / workers;
+                if (index as u64) < requests % workers {
+                    share += 1;
+                }
+                worker.set_request_budget(share, complete.clone());
+            }
+            self.admin.set_request_budget(complete, self.workers.len());
+        }
+        self
+    }
+
+    /// Set every key of each target and then verify each can be read back
+    /// before the run starts, exiting if any keys are missing
+    pub fn populate_verify(mut self, enabled: bool) -> Self {
+        self.populate_verify = enabled;
+        self
     }
 
     /// Launch the runtime
     pub fn spawn(mut self) -> Runtime {
+        if self.populate_verify {
+            for target in &self.targets {
+                populate::populate_verify(target);
+            }
+        }
+
         let admin = self.admin;
         let admin_thread = std::thread::spawn(move || admin.run());
 
+        if let (Some(schedule), Some(ratelimiter)) = (
+            self.config.request().rate_schedule(),
+            self.request_ratelimit.clone(),
+        ) {
+            let ramp = RateRamp::new(&schedule);
+            std::thread::spawn(move || ramp.run(ratelimiter));
+        }
+
         let mut worker_threads = Vec::new();
         for mut worker in self.workers.drain(..) {
             worker_threads.push(std::thread::spawn(move || worker.run()));
@@ -174,13 +808,54 @@ impl Builder {
 #[allow(dead_code)]
 /// Holds the runtime threads
 pub struct Runtime {
-    admin_thread: JoinHandle<()>,
+    admin_thread: JoinHandle<i32>,
     worker_threads: Vec<JoinHandle<()>>,
 }
 
 impl Runtime {
-    /// Run the threads to completion
-    pub fn wait(self) {
-        let _ = self.admin_thread.join();
+    /// Run the threads to completion, returning the exit code for the process
+    pub fn wait(self) -> i32 {
+        self.admin_thread.join().unwrap_or(1)
+    }
+}
+
+/// Log the limits on the number of open files for this process and try to
+/// raise the soft limit to the hard limit. Returns the resulting soft limit,
+/// if it is known.
+fn raise_fd_limit() -> Option<libc::rlim_t> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    info!(
+        "open file limit: soft: {} hard: {}",
+        limit.rlim_cur, limit.rlim_max
+    );
+
+    // an unlimited hard limit can not be applied as the soft limit, as the
+    // kernel has its own cap on the number of open files
+    if limit.rlim_cur < limit.rlim_max && limit.rlim_max != libc::RLIM_INFINITY {
+        let raised = libc::rlimit {
+            rlim_cur: limit.rlim_max,
+            rlim_max: limit.rlim_max,
+        };
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            info!(
+                "raised open file soft limit from {} to {}",
+                limit.rlim_cur, raised.rlim_cur
+            );
+            limit = raised;
+        } else {
+            warn!("failed to raise open file soft limit");
+        }
+    }
+
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        None
+    } else {
+        Some(limit.rlim_cur)
     }
 }
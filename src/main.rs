@@ -3,8 +3,12 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use backtrace::Backtrace;
-use clap::{App, Arg};
-use rpc_perf::Builder;
+use clap::{App, Arg, ArgMatches};
+use rpc_perf::{stop_on_signal, Builder, Config, CountingAllocator, OutputFormat, RateSweep};
+
+// counts the bytes allocated, but only once enabled with --self-stats
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 fn main() {
     // custom panic hook to terminate whole process after unwinding
@@ -14,8 +18,65 @@ fn main() {
         std::process::exit(101);
     }));
 
+    // stop on SIGINT/SIGTERM, still writing the end-of-run summary
+    stop_on_signal();
+
     // parse command line options load configuration
-    let matches = App::new(env!("CARGO_BIN_NAME"))
+    let matches = app().get_matches();
+    let sweep = sweep(&matches);
+    let config = config(&matches, sweep.as_ref());
+
+    if let Some(format) = matches.value_of("dump-config") {
+        match format {
+            "json" => println!("{}", config.to_json()),
+            _ => print!("{}", config.to_toml()),
+        }
+        return;
+    }
+
+    // launch
+    let code = Builder::from_config(config)
+        .report(matches.value_of("report"))
+        .cdf_plot(matches.value_of("cdf-plot"))
+        .latency_samples(matches.value_of("latency-samples"))
+        .baseline(matches.value_of("baseline"))
+        .statsd(
+            matches.value_of("statsd"),
+            matches.value_of("statsd-prefix").unwrap(),
+        )
+        .sqlite(matches.value_of("sqlite"))
+        .self_stats(matches.is_present("self-stats"))
+        .exemplars(matches.is_present("exemplars"))
+        .phases(matches.is_present("phases"))
+        .populate_verify(matches.is_present("populate-verify"))
+        .requests(
+            matches
+                .value_of("requests")
+                .map(|r| r.parse().expect("invalid value for 'requests'")),
+        )
+        .rate_profile(matches.value_of("rate-profile"))
+        .sweep(sweep)
+        .abort_error_rate(
+            matches
+                .value_of("abort-error-rate")
+                .map(|r| r.parse().expect("invalid value for 'abort-error-rate'")),
+            matches
+                .value_of("abort-error-windows")
+                .unwrap()
+                .parse()
+                .expect("invalid value for 'abort-error-windows'"),
+        )
+        .spawn()
+        .wait();
+
+    // an aborted run exits nonzero, after the final report has been written
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+fn app() -> App<'static> {
+    App::new(env!("CARGO_BIN_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .version_short('v')
         .long_about(
@@ -26,8 +87,287 @@ fn main() {
         )
         .about("Measure RPC performance using synthetic traffic")
         .arg(Arg::with_name("CONFIG").help("Configuration file").index(1))
-        .get_matches();
+        .arg(
+            Arg::with_name("windows")
+                .long("windows")
+                .value_name("INT")
+                .help("Number of windows to run for, overrides the config file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .help("Duration of each window, overrides the config file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clients")
+                .long("clients")
+                .value_name("INT")
+                .help("Number of client worker threads, overrides the config file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .help("Format of the output for each window, overrides the config file")
+                .possible_values(["human", "json"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("requests")
+                .long("requests")
+                .value_name("INT")
+                .help("Stop after this many requests in total, or the windows, whichever is first")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exec")
+                .long("exec")
+                .value_name("COMMAND")
+                .help("Run the command for each connection and use its stdin and stdout instead of the target endpoints")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Write an end-of-run summary as JSON, or markdown for .md files")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cdf-plot")
+                .long("cdf-plot")
+                .value_name("FILE")
+                .help("Render a response latency CDF as an SVG image at the end of the run")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("latency-samples")
+                .long("latency-samples")
+                .value_name("FILE")
+                .help("Append every response latency in nanoseconds to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("baseline")
+                .long("baseline")
+                .value_name("FILE")
+                .help(
+                    "Compare response latency against a baseline run, from a file written with \
+                    --latency-samples, and report whether they differ significantly",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("statsd")
+                .long("statsd")
+                .value_name("HOST:PORT")
+                .help("Push metrics to a StatsD collector each window")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sqlite")
+                .long("sqlite")
+                .value_name("FILE")
+                .help("Record each window in a SQLite database, keyed by a run id")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("statsd-prefix")
+                .long("statsd-prefix")
+                .value_name("PREFIX")
+                .help("Prefix for metric names sent to StatsD")
+                .default_value("rpcperf")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate-profile")
+                .long("rate-profile")
+                .value_name("FILE")
+                .help("Vary the request rate over time from a file of second,rate pairs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sweep")
+                .long("sweep")
+                .value_name("START:STOP:STEP")
+                .help("Step the request rate through a series of levels and summarize latency at each, setting the number of windows and the latency window")
+                .conflicts_with_all(&["rate-profile", "windows"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sweep-windows")
+                .long("sweep-windows")
+                .value_name("INT")
+                .help("Number of windows to hold each level of the sweep for")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("abort-error-rate")
+                .long("abort-error-rate")
+                .value_name("PERCENT")
+                .help("Exit nonzero once the per-window error rate exceeds this percentage")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("abort-error-windows")
+                .long("abort-error-windows")
+                .value_name("INT")
+                .help("Consecutive windows above the error rate before aborting")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dump-config")
+                .long("dump-config")
+                .value_name("FORMAT")
+                .help("Print the effective config after all overrides, as toml or json, and exit")
+                .possible_values(["toml", "json"])
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value("toml")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("populate-verify")
+                .long("populate-verify")
+                .help("Set every key, then verify each reads back as a hit before measuring"),
+        )
+        .arg(
+            Arg::with_name("exemplars")
+                .long("exemplars")
+                .help("Add a response latency histogram, in microseconds, with trace id exemplars to the Prometheus output"),
+        )
+        .arg(
+            Arg::with_name("phases")
+                .long("phases")
+                .help(
+                    "Log latency for each phase of a request: connect, handshake, write, first byte, and decode",
+                ),
+        )
+        .arg(
+            Arg::with_name("self-stats")
+                .long("self-stats")
+                .help("Log memory usage, allocation rate, open files and event loop rate of rpc-perf each window"),
+        )
+}
 
-    // launch
-    Builder::new(matches.value_of("CONFIG")).spawn().wait()
+/// Parse the rate sweep, if one was requested
+fn sweep(matches: &ArgMatches) -> Option<RateSweep> {
+    matches.value_of("sweep").map(|spec| {
+        let windows = matches
+            .value_of("sweep-windows")
+            .unwrap()
+            .parse()
+            .expect("invalid value for 'sweep-windows'");
+        RateSweep::parse(spec, windows).expect("invalid value for 'sweep'")
+    })
+}
+
+/// Load the config file and apply any overrides from the command line, which
+/// take precedence over values in the file. A sweep sets the number of windows
+/// and measures latency over each level of the sweep.
+fn config(matches: &ArgMatches, sweep: Option<&RateSweep>) -> Config {
+    let mut config = Config::with_exec(matches.value_of("CONFIG"), matches.value_of("exec"));
+    if let Some(windows) = matches.value_of("windows") {
+        config.set_windows(windows.parse().expect("invalid value for 'windows'"));
+    }
+    if let Some(interval) = matches.value_of("interval") {
+        config.set_interval(interval.parse().expect("invalid value for 'interval'"));
+    }
+    if let Some(clients) = matches.value_of("clients") {
+        config.set_threads(clients.parse().expect("invalid value for 'clients'"));
+    }
+    match matches.value_of("output-format") {
+        Some("json") => config.set_output_format(OutputFormat::Json),
+        Some("human") => config.set_output_format(OutputFormat::Human),
+        _ => {}
+    }
+    if let Some(sweep) = sweep {
+        let windows = sweep.total_windows();
+        match config.general().windows() {
+            Some(configured) if configured != windows => {
+                eprintln!(
+                    "warning: the sweep runs for {} windows, ignoring windows = {} from the config",
+                    windows, configured
+                );
+            }
+            None => {
+                eprintln!("warning: the sweep ends after its last level, ignoring service mode");
+            }
+            _ => {}
+        }
+        config.set_windows(windows);
+        let interval = config.general().interval();
+        if config.general().latency_window() != interval {
+            eprintln!(
+                "warning: the sweep measures latency over each level, ignoring latency_window_secs"
+            );
+        }
+        config.set_latency_window(interval.as_secs() as usize * sweep.windows());
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config_file() -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            r#"
+            [general]
+            protocol = "ping"
+            interval = 60
+            windows = 100
+            threads = 4
+
+            [target]
+            endpoints = ["127.0.0.1:12321"]
+
+            [[keyspace]]
+            commands = [{ verb = "ping" }]
+            "#,
+        )
+        .unwrap();
+        file
+    }
+
+    #[test]
+    fn file_values() {
+        let file = config_file();
+        let path = file.path().to_str().unwrap();
+        let matches = app().get_matches_from(vec!["rpc-perf", path]);
+        let config = config(&matches, None);
+        assert_eq!(config.general().windows(), Some(100));
+        assert_eq!(config.general().interval(), Duration::from_secs(60));
+        assert_eq!(config.general().threads(), 4);
+    }
+
+    #[test]
+    fn cli_overrides() {
+        let file = config_file();
+        let path = file.path().to_str().unwrap();
+        let matches = app().get_matches_from(vec![
+            "rpc-perf",
+            "--windows",
+            "2",
+            "--interval",
+            "1",
+            "--clients",
+            "2",
+            path,
+        ]);
+        let config = config(&matches, None);
+        assert_eq!(config.general().windows(), Some(2));
+        assert_eq!(config.general().interval(), Duration::from_secs(1));
+        assert_eq!(config.general().threads(), 2);
+    }
 }
@@ -11,9 +11,72 @@ pub static CONNECT: Counter = Counter::new();
 #[metric(name = "connect_ex", description = "exceptions when calling connect")]
 pub static CONNECT_EX: Counter = Counter::new();
 
+#[metric(
+    name = "connect_ex_refused",
+    description = "connects refused by the endpoint"
+)]
+pub static CONNECT_EX_REFUSED: Counter = Counter::new();
+
+#[metric(
+    name = "connect_ex_timedout",
+    description = "connects which timed out in the kernel"
+)]
+pub static CONNECT_EX_TIMEDOUT: Counter = Counter::new();
+
+#[metric(
+    name = "connect_ex_addrnotavail",
+    description = "connects which found no local address or port available"
+)]
+pub static CONNECT_EX_ADDRNOTAVAIL: Counter = Counter::new();
+
+#[metric(
+    name = "connect_ex_unreachable",
+    description = "connects to an unreachable network or host"
+)]
+pub static CONNECT_EX_UNREACHABLE: Counter = Counter::new();
+
+#[metric(
+    name = "connect_ex_reset",
+    description = "connects reset by the endpoint"
+)]
+pub static CONNECT_EX_RESET: Counter = Counter::new();
+
+#[metric(
+    name = "connect_ex_nofile",
+    description = "connects which failed for lack of file descriptors"
+)]
+pub static CONNECT_EX_NOFILE: Counter = Counter::new();
+
+#[metric(
+    name = "connect_ex_tls",
+    description = "connects which failed the tls handshake"
+)]
+pub static CONNECT_EX_TLS: Counter = Counter::new();
+
+#[metric(
+    name = "connect_ex_other",
+    description = "connects which failed for any other reason"
+)]
+pub static CONNECT_EX_OTHER: Counter = Counter::new();
+
 #[metric(name = "connect_timeout", description = "connect timeouts")]
 pub static CONNECT_TIMEOUT: Counter = Counter::new();
 
+#[metric(
+    name = "connections_abandoned",
+    description = "addresses abandoned after repeated connect failures"
+)]
+pub static CONNECTIONS_ABANDONED: Counter = Counter::new();
+
+#[metric(name = "chaos", description = "failures injected by chaos mode")]
+pub static CHAOS: Counter = Counter::new();
+
+#[metric(
+    name = "ready_queue_full",
+    description = "sessions deferred because the ready queue was full"
+)]
+pub static READY_QUEUE_FULL: Counter = Counter::new();
+
 #[metric(name = "request", description = "requests sent")]
 pub static REQUEST: Counter = Counter::new();
 
@@ -23,6 +86,24 @@ pub static REQUEST_EX: Counter = Counter::new();
 #[metric(name = "request_get", description = "get requests sent")]
 pub static REQUEST_GET: Counter = Counter::new();
 
+#[metric(
+    name = "read_idle_timeout",
+    description = "sessions closed after no response bytes arrived within the idle timeout"
+)]
+pub static READ_IDLE_TIMEOUT: Counter = Counter::new();
+
+#[metric(
+    name = "response_stall",
+    description = "sessions closed after too many reads without completing a response"
+)]
+pub static RESPONSE_STALL: Counter = Counter::new();
+
+#[metric(
+    name = "subscribe_message",
+    description = "messages received on subscribed connections"
+)]
+pub static SUBSCRIBE_MESSAGE: Counter = Counter::new();
+
 #[metric(name = "response", description = "responses received")]
 pub static RESPONSE: Counter = Counter::new();
 
@@ -32,12 +113,45 @@ pub static RESPONSE: Counter = Counter::new();
 )]
 pub static RESPONSE_EX: Counter = Counter::new();
 
+#[metric(
+    name = "response_ex_moved",
+    description = "redis MOVED error replies, which redirect a cluster slot"
+)]
+pub static RESPONSE_EX_MOVED: Counter = Counter::new();
+
+#[metric(
+    name = "response_ex_ask",
+    description = "redis ASK error replies, which redirect a migrating slot"
+)]
+pub static RESPONSE_EX_ASK: Counter = Counter::new();
+
+#[metric(
+    name = "request_redirect",
+    description = "requests retried on the cluster node given by a redirect"
+)]
+pub static REQUEST_REDIRECT: Counter = Counter::new();
+
+#[metric(
+    name = "response_ex_wrongtype",
+    description = "redis WRONGTYPE error replies"
+)]
+pub static RESPONSE_EX_WRONGTYPE: Counter = Counter::new();
+
+#[metric(name = "response_ex_oom", description = "redis OOM error replies")]
+pub static RESPONSE_EX_OOM: Counter = Counter::new();
+
 #[metric(
     name = "response_hit",
     description = "responses that indicated a cache hit"
 )]
 pub static RESPONSE_HIT: Counter = Counter::new();
 
+#[metric(
+    name = "response_partial_hit",
+    description = "batched get responses where only some of the keys were found"
+)]
+pub static RESPONSE_PARTIAL_HIT: Counter = Counter::new();
+
 /// distribution of response latencies
 // #[metric(name = "response_latency")]
 // pub static RESPONSE_LATENCY: Relaxed<Heatmap> = Relaxed::new(||
@@ -47,6 +161,45 @@ pub static RESPONSE_HIT: Counter = Counter::new();
 #[metric(name = "close", description = "closed connections")]
 pub static CLOSE: Counter = Counter::new();
 
+#[metric(
+    name = "server_closed_active",
+    description = "connections closed by the server with responses outstanding"
+)]
+pub static SERVER_CLOSED_ACTIVE: Counter = Counter::new();
+
+#[metric(
+    name = "server_closed_idle",
+    description = "connections closed by the server with no responses outstanding"
+)]
+pub static SERVER_CLOSED_IDLE: Counter = Counter::new();
+
+#[metric(
+    name = "recycled",
+    description = "connections closed for reconnect at the start of a window"
+)]
+pub static RECYCLED: Counter = Counter::new();
+
+#[metric(
+    name = "request_unpadded",
+    description = "requests longer than pad_request_to which were sent unpadded"
+)]
+pub static REQUEST_UNPADDED: Counter = Counter::new();
+
+#[metric(
+    name = "response_latency_clipped",
+    description = "responses whose latency exceeded latency_max"
+)]
+pub static RESPONSE_LATENCY_CLIPPED: Counter = Counter::new();
+
+#[metric(name = "response_byte", description = "bytes of successful responses")]
+pub static RESPONSE_BYTE: Counter = Counter::new();
+
+#[metric(
+    name = "request_dropped",
+    description = "requests which came due with no connection ready to send them"
+)]
+pub static REQUEST_DROPPED: Counter = Counter::new();
+
 #[metric(name = "window", description = "elapsed windows")]
 pub static WINDOW: Counter = Counter::new();
 
@@ -56,6 +209,12 @@ pub static SESSION: Counter = Counter::new();
 #[metric(name = "open", description = "open connections")]
 pub static OPEN: Gauge = Gauge::new();
 
+#[metric(
+    name = "open_target",
+    description = "connections the pools are configured to open"
+)]
+pub static OPEN_TARGET: Gauge = Gauge::new();
+
 #[metric(name = "session_recv", description = "session receive attempts")]
 pub static SESSION_RECV: Counter = Counter::new();
 
@@ -91,3 +250,6 @@ pub static SESSION_SEND_BYTE: Counter = Counter::new();
     description = "session reused with abbreviated TLS handshake"
 )]
 pub static SESSION_REUSE: Counter = Counter::new();
+
+#[metric(name = "tls_key_update", description = "tls key updates requested")]
+pub static TLS_KEY_UPDATE: Counter = Counter::new();
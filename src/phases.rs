@@ -0,0 +1,95 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Optional breakdown of request latency into the phases of a request: the TCP
+//! connect and the TLS handshake which establish the connection, from the
+//! connection being established to the first request being written, from the
+//! write to the first byte of the response, from the first byte until the
+//! response is complete, and the time spent decoding the response.
+
+use crate::config_file::LatencyUnit;
+use crate::Config;
+use rustcommon_heatmap::{AtomicHeatmap, AtomicU64};
+use std::time::Duration;
+
+pub struct Phases {
+    unit: LatencyUnit,
+    tcp_connect: AtomicHeatmap<u64, AtomicU64>,
+    tls_handshake: AtomicHeatmap<u64, AtomicU64>,
+    connect_to_write: AtomicHeatmap<u64, AtomicU64>,
+    write_to_first_byte: AtomicHeatmap<u64, AtomicU64>,
+    first_byte_to_complete: AtomicHeatmap<u64, AtomicU64>,
+    decode: AtomicHeatmap<u64, AtomicU64>,
+}
+
+impl Phases {
+    pub fn new(config: &Config) -> Self {
+        let heatmap = || {
+            AtomicHeatmap::<u64, AtomicU64>::new(
+                config.general().latency_max(),
+                3,
+                config.general().latency_window(),
+                Duration::from_millis(1000),
+            )
+        };
+        Self {
+            unit: config.general().latency_unit(),
+            tcp_connect: heatmap(),
+            tls_handshake: heatmap(),
+            connect_to_write: heatmap(),
+            write_to_first_byte: heatmap(),
+            first_byte_to_complete: heatmap(),
+            decode: heatmap(),
+        }
+    }
+
+    fn record(&self, heatmap: &AtomicHeatmap<u64, AtomicU64>, nanos: u64) {
+        heatmap.increment(crate::Instant::now(), self.unit.convert(nanos), 1);
+    }
+
+    /// Record the time from starting a connect until the TCP connection is
+    /// complete
+    pub fn tcp_connect(&self, nanos: u64) {
+        self.record(&self.tcp_connect, nanos)
+    }
+
+    /// Record the time from the TCP connection completing until the TLS
+    /// handshake is complete
+    pub fn tls_handshake(&self, nanos: u64) {
+        self.record(&self.tls_handshake, nanos)
+    }
+
+    /// Record the time from a session connecting until its first request
+    pub fn connect_to_write(&self, nanos: u64) {
+        self.record(&self.connect_to_write, nanos)
+    }
+
+    /// Record the time from writing a request until the first byte of its
+    /// response arrives
+    pub fn write_to_first_byte(&self, nanos: u64) {
+        self.record(&self.write_to_first_byte, nanos)
+    }
+
+    /// Record the time from the first byte of a response until it completes
+    pub fn first_byte_to_complete(&self, nanos: u64) {
+        self.record(&self.first_byte_to_complete, nanos)
+    }
+
+    /// Record the time spent decoding a response
+    pub fn decode(&self, nanos: u64) {
+        self.record(&self.decode, nanos)
+    }
+
+    /// The heatmap for each phase along with its name, in request order
+    pub fn heatmaps(&self) -> [(&'static str, &AtomicHeatmap<u64, AtomicU64>); 6] {
+        [
+            ("TCP-Connect", &self.tcp_connect),
+            ("TLS-Handshake", &self.tls_handshake),
+            ("Connect-to-Write", &self.connect_to_write),
+            ("Write-to-First-Byte", &self.write_to_first_byte),
+            ("First-Byte-to-Complete", &self.first_byte_to_complete),
+            ("Decode", &self.decode),
+        ]
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Admits requests as a Poisson process, with exponentially distributed gaps
+//! between arrivals. Each worker runs its own process at its share of the
+//! request rate, and together they form a Poisson process at the full rate.
+
+use rand::Rng;
+use rand_distr::{Distribution, Exp};
+use std::time::{Duration, Instant};
+
+/// How far arrivals may fall behind before those due are dropped, so that a
+/// stalled worker does not send a burst once it resumes
+const MAX_LAG: Duration = Duration::from_secs(1);
+
+pub struct PoissonArrivals {
+    gap: Exp<f64>,
+    next: Instant,
+}
+
+impl PoissonArrivals {
+    /// Arrivals at the given rate, per second
+    pub fn new(rate: f64) -> Self {
+        Self {
+            gap: Exp::new(rate).expect("poisson rate must be positive"),
+            next: Instant::now(),
+        }
+    }
+
+    /// Admit a request if an arrival is due, scheduling the next one. Arrivals
+    /// are scheduled from the previous arrival rather than from now, so the
+    /// long-run rate holds even when requests are admitted late.
+    pub fn try_wait<R: Rng>(&mut self, now: Instant, rng: &mut R) -> Result<(), ()> {
+        if now < self.next {
+            return Err(());
+        }
+        if now - self.next > MAX_LAG {
+            self.next = now - MAX_LAG;
+        }
+        self.next += Duration::from_secs_f64(self.gap.sample(rng));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn rate() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut arrivals = PoissonArrivals::new(1000.0);
+        let start = arrivals.next;
+
+        // poll every 100us for 100 seconds
+        let mut admitted = 0;
+        for tick in 0..1_000_000 {
+            let now = start + Duration::from_micros(tick * 100);
+            while arrivals.try_wait(now, &mut rng).is_ok() {
+                admitted += 1;
+            }
+        }
+        assert!((99_000..=101_000).contains(&admitted), "{}", admitted);
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Populates every key in the keyspaces before measurement and then verifies
+//! that each key can be read back. This catches servers which silently drop
+//! writes, for instance due to eviction, before a measurement run is wasted.
+//!
+//! Requests are encoded and their responses parsed by the codec of the
+//! protocol, over blocking connections. This is only supported for memcache
+//! and redis keyspaces with `u32` keys, as other key types cannot be
+//! enumerated.
+
+use crate::codec::{Codec, Memcache, ParseError, Redis};
+use crate::config::{Keyspace, RequestContext};
+use crate::config_file::{Command, Protocol, Verb};
+use crate::Config;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The number of commands sent before reading their responses
+const PIPELINE: u32 = 64;
+
+/// The largest keyspace which is populated. Every key is written and read
+/// back, so the default cardinality of `u32::MAX` would never finish.
+const MAX_CARDINALITY: u32 = 100_000_000;
+
+/// Populate then verify every key, exiting if any key reads back as a miss
+pub fn populate_verify(config: &Arc<Config>) {
+    let mut codec = match config.general().protocol() {
+        Protocol::Memcache | Protocol::MemcacheBinary => {
+            Box::new(Memcache::new(config.clone())) as Box<dyn Codec>
+        }
+        Protocol::Redis | Protocol::RedisResp | Protocol::RedisInline => {
+            Box::new(Redis::new(config.clone())) as Box<dyn Codec>
+        }
+        _ => {
+            fatal!("populate-verify is only supported for memcache and redis");
+        }
+    };
+    if config.tls().is_some() || !config.tls_endpoints().is_empty() {
+        fatal!("populate-verify is not supported with tls");
+    }
+    if config.exec().is_some() {
+        fatal!("populate-verify is not supported with exec");
+    }
+    for keyspace in config.keyspaces() {
+        if keyspace.key(0).is_none() {
+            fatal!("populate-verify requires keyspaces with u32 keys");
+        }
+        if keyspace.cardinality() > MAX_CARDINALITY {
+            fatal!(
+                "populate-verify requires each keyspace to set a cardinality of at most {}",
+                MAX_CARDINALITY
+            );
+        }
+    }
+
+    for endpoint in config.write_endpoints() {
+        info!("populate: endpoint: {}", endpoint);
+        let mut stored = 0;
+        for keyspace in config.keyspaces() {
+            match populate(&mut *codec, endpoint, keyspace) {
+                Ok(count) => stored += count,
+                Err(e) => {
+                    fatal!("populate failed for endpoint: {}: {}", endpoint, e);
+                }
+            }
+        }
+        info!("populate: endpoint: {} stored: {}", endpoint, stored);
+    }
+
+    let mut misses = 0;
+    for endpoint in config.read_endpoints() {
+        info!("verify: endpoint: {}", endpoint);
+        for keyspace in config.keyspaces() {
+            match verify(&mut *codec, endpoint, keyspace) {
+                Ok(count) => misses += count,
+                Err(e) => {
+                    fatal!("verify failed for endpoint: {}: {}", endpoint, e);
+                }
+            }
+        }
+    }
+    if misses > 0 {
+        fatal!("verify: {} keys were unexpectedly missing", misses);
+    }
+    info!("verify: all keys present");
+}
+
+fn connect(endpoint: SocketAddr) -> Result<TcpStream, Error> {
+    let stream = TcpStream::connect_timeout(&endpoint, Duration::from_secs(5))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    Ok(stream)
+}
+
+/// Set every key in the keyspace, returning the number of keys stored
+fn populate(
+    codec: &mut dyn Codec,
+    endpoint: SocketAddr,
+    keyspace: &Keyspace,
+) -> Result<u32, Error> {
+    let mut stream = connect(endpoint)?;
+    let set = Command::new(Verb::Set, 1);
+    let mut stored = 0;
+    let mut start = 0;
+    while start < keyspace.cardinality() {
+        let end = keyspace.cardinality().min(start.saturating_add(PIPELINE));
+        stream.write_all(&requests(codec, keyspace, &set, start, end))?;
+        for values in read_responses(codec, &mut stream, end - start)? {
+            if values.is_some() {
+                return Err(Error::new(ErrorKind::InvalidData, "unexpected response"));
+            }
+            stored += 1;
+        }
+        start = end;
+    }
+    Ok(stored)
+}
+
+/// Read every key in the keyspace, returning the number of misses. The other
+/// keys of a batched get have also been set, so a response which is missing
+/// any of them is counted as a miss.
+fn verify(codec: &mut dyn Codec, endpoint: SocketAddr, keyspace: &Keyspace) -> Result<u32, Error> {
+    let mut stream = connect(endpoint)?;
+    let get = Command::new(Verb::Get, 1);
+    let mut misses = 0;
+    let mut start = 0;
+    while start < keyspace.cardinality() {
+        let end = keyspace.cardinality().min(start.saturating_add(PIPELINE));
+        stream.write_all(&requests(codec, keyspace, &get, start, end))?;
+        for values in read_responses(codec, &mut stream, end - start)? {
+            match values {
+                Some(found) if found >= keyspace.batch_size() => {}
+                Some(_) => misses += 1,
+                None => {
+                    return Err(Error::new(ErrorKind::InvalidData, "unexpected response"));
+                }
+            }
+        }
+        start = end;
+    }
+    Ok(misses)
+}
+
+/// Encode the command for each key index in the range
+fn requests(
+    codec: &mut dyn Codec,
+    keyspace: &Keyspace,
+    command: &Command,
+    start: u32,
+    end: u32,
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for index in start..end {
+        let mut request = RequestContext::with_key(keyspace.key(index).unwrap());
+        match codec.frame(keyspace, command, &mut request) {
+            Some(frame) => buf.extend_from_slice(&frame),
+            None => {
+                fatal!("populate-verify is not supported for this protocol");
+            }
+        }
+    }
+    buf
+}
+
+/// Read the responses to `count` pipelined requests, returning the number of
+/// values found by each
+fn read_responses(
+    codec: &dyn Codec,
+    stream: &mut dyn Read,
+    count: u32,
+) -> Result<Vec<Option<usize>>, Error> {
+    let mut responses = Vec::with_capacity(count as usize);
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    while responses.len() < count as usize {
+        match codec.parse(&response) {
+            Ok((len, values)) => {
+                response.drain(..len);
+                responses.push(values);
+                continue;
+            }
+            Err(ParseError::Incomplete) => {}
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{:?}", e)));
+            }
+        }
+        let bytes = stream.read(&mut buf)?;
+        if bytes == 0 {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        response.extend_from_slice(&buf[0..bytes]);
+    }
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(protocol: &str) -> Arc<Config> {
+        Arc::new(Config::parse(&format!(
+            r#"
+            [general]
+            protocol = "{}"
+
+            [target]
+            endpoints = ["127.0.0.1:12321"]
+
+            [[keyspace]]
+            commands = [{{ verb = "get" }}, {{ verb = "set" }}]
+            key_type = "u32"
+            length = 4
+            cardinality = 100
+            values = [{{ length = 3 }}]
+            "#,
+            protocol
+        )))
+    }
+
+    #[test]
+    fn encode() {
+        let config = config("memcache");
+        let keyspace = &config.keyspaces()[0];
+        let mut codec = Memcache::new(config.clone());
+        let buf = requests(&mut codec, keyspace, &Command::new(Verb::Get, 1), 7, 9);
+        assert_eq!(&buf, b"get 0007\r\nget 0008\r\n");
+
+        let buf = requests(&mut codec, keyspace, &Command::new(Verb::Set, 1), 7, 8);
+        assert!(buf.starts_with(b"set 0007 0 0 3\r\n"));
+
+        let config = config("redis_resp");
+        let keyspace = &config.keyspaces()[0];
+        let mut codec = Redis::new(config.clone());
+        let buf = requests(&mut codec, keyspace, &Command::new(Verb::Get, 1), 42, 43);
+        assert_eq!(&buf, b"*2\r\n$3\r\nget\r\n$4\r\n0042\r\n");
+    }
+
+    #[test]
+    fn responses() {
+        let codec = Memcache::new(config("memcache"));
+        let mut reader: &[u8] = b"STORED\r\nVALUE 0001 0 3\r\nabc\r\nEND\r\nEND\r\n";
+        assert_eq!(
+            read_responses(&codec, &mut reader, 3).unwrap(),
+            vec![None, Some(1), Some(0)]
+        );
+
+        let codec = Redis::new(config("redis_resp"));
+        let mut reader: &[u8] = b"+OK\r\n$3\r\nabc\r\n$-1\r\n";
+        assert_eq!(
+            read_responses(&codec, &mut reader, 3).unwrap(),
+            vec![None, Some(1), Some(0)]
+        );
+
+        // error replies and truncated responses fail the run
+        let mut reader: &[u8] = b"-OOM command not allowed\r\n";
+        assert!(read_responses(&codec, &mut reader, 1).is_err());
+        let mut reader: &[u8] = b"$3\r\nab";
+        assert!(read_responses(&codec, &mut reader, 1).is_err());
+    }
+}
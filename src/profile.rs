@@ -0,0 +1,113 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A request rate which varies over time, loaded from a file with one
+//! `second,rate` pair per line. The rate is linearly interpolated between the
+//! points. Past the final point the rate either holds at the final value or
+//! the profile repeats from the start.
+
+use std::io::{Error, ErrorKind};
+
+pub struct RateProfile {
+    points: Vec<(f64, f64)>,
+    repeat: bool,
+}
+
+impl RateProfile {
+    /// Load the profile from a file
+    pub fn load(path: &str, repeat: bool) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content, repeat)
+    }
+
+    /// Parse the profile, skipping blank lines, comments, and a header line
+    fn parse(content: &str, repeat: bool) -> Result<Self, Error> {
+        let mut points = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split(',').map(|p| p.trim().parse::<f64>());
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(Ok(second)), Some(Ok(rate)), None) if second >= 0.0 && rate >= 0.0 => {
+                    points.push((second, rate));
+                }
+                _ if number == 0 => {
+                    // header
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("bad rate profile entry on line {}: {}", number + 1, line),
+                    ));
+                }
+            }
+        }
+        if points.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "rate profile has no entries",
+            ));
+        }
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Ok(Self { points, repeat })
+    }
+
+    /// The request rate at the elapsed time, in seconds, since the start
+    pub fn rate(&self, elapsed: f64) -> u64 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+
+        let elapsed = if self.repeat && last.0 > 0.0 {
+            elapsed % last.0
+        } else {
+            elapsed
+        };
+
+        let rate = if elapsed <= first.0 {
+            first.1
+        } else if elapsed >= last.0 {
+            last.1
+        } else {
+            let next = self.points.iter().position(|p| p.0 > elapsed).unwrap();
+            let (x0, y0) = self.points[next - 1];
+            let (x1, y1) = self.points[next];
+            y0 + (y1 - y0) * (elapsed - x0) / (x1 - x0)
+        };
+
+        // a ratelimiter cannot be set to zero
+        std::cmp::max(1, rate.round() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate() {
+        let profile = RateProfile::parse("second,rate\n0,100\n10,200\n\n20,0\n", false).unwrap();
+        assert_eq!(profile.rate(0.0), 100);
+        assert_eq!(profile.rate(5.0), 150);
+        assert_eq!(profile.rate(10.0), 200);
+        assert_eq!(profile.rate(15.0), 100);
+        assert_eq!(profile.rate(20.0), 1);
+        assert_eq!(profile.rate(100.0), 1);
+    }
+
+    #[test]
+    fn repeat() {
+        let profile = RateProfile::parse("0,100\n10,200\n", true).unwrap();
+        assert_eq!(profile.rate(5.0), 150);
+        assert_eq!(profile.rate(15.0), 150);
+        assert_eq!(profile.rate(25.0), 150);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(RateProfile::parse("", false).is_err());
+        assert!(RateProfile::parse("0,100\nten,200\n", false).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Ramps the request rate linearly from a start to an end rate over a
+//! duration, either continuously or in a number of equal steps, to warm up
+//! servers gradually. The ramp runs on a thread of its own which updates the
+//! shared request ratelimiter, so every worker follows it.
+
+use crate::config_file::RateSchedule;
+use rustcommon_ratelimiter::Ratelimiter;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often a continuous ramp updates the rate
+const CONTINUOUS_PERIOD: Duration = Duration::from_millis(100);
+
+pub struct RateRamp {
+    start: f64,
+    end: f64,
+    duration: f64,
+    steps: Option<usize>,
+}
+
+impl RateRamp {
+    pub fn new(schedule: &RateSchedule) -> Self {
+        Self {
+            start: schedule.start() as f64,
+            end: schedule.end() as f64,
+            duration: schedule.duration().as_secs_f64(),
+            steps: schedule.steps(),
+        }
+    }
+
+    /// The request rate at the elapsed time, in seconds, since the start
+    pub fn rate(&self, elapsed: f64) -> u64 {
+        let mut fraction = (elapsed / self.duration).clamp(0.0, 1.0);
+        if let Some(steps) = self.steps {
+            fraction = (fraction * steps as f64).floor() / steps as f64;
+        }
+        (self.start + (self.end - self.start) * fraction).round() as u64
+    }
+
+    /// How often the rate changes
+    fn period(&self) -> Duration {
+        match self.steps {
+            Some(steps) => Duration::from_secs_f64(self.duration / steps as f64),
+            None => CONTINUOUS_PERIOD,
+        }
+    }
+
+    /// Update the ratelimiter until the end rate is reached
+    pub fn run(self, ratelimiter: Arc<Ratelimiter>) {
+        let start = Instant::now();
+        let mut current = self.rate(0.0);
+        ratelimiter.set_rate(current);
+        loop {
+            std::thread::sleep(self.period());
+            let elapsed = start.elapsed().as_secs_f64();
+            let rate = self.rate(elapsed);
+            if rate != current {
+                debug!("Rate schedule: request rate: {}", rate);
+                ratelimiter.set_rate(rate);
+                current = rate;
+            }
+            if elapsed >= self.duration {
+                info!("Rate schedule: reached request rate: {}", current);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation() {
+        let ramp = RateRamp {
+            start: 1000.0,
+            end: 5000.0,
+            duration: 60.0,
+            steps: None,
+        };
+        assert_eq!(ramp.rate(0.0), 1000);
+        assert_eq!(ramp.rate(30.0), 3000);
+        assert_eq!(ramp.rate(60.0), 5000);
+        assert_eq!(ramp.rate(120.0), 5000);
+        let rates: Vec<u64> = (0..=600).map(|t| ramp.rate(t as f64 / 10.0)).collect();
+        assert!(rates.windows(2).all(|w| w[0] <= w[1]));
+
+        let ramp = RateRamp {
+            steps: Some(4),
+            ..ramp
+        };
+        assert_eq!(ramp.period(), Duration::from_secs(15));
+        let rates: Vec<u64> = [0.0, 14.9, 15.0, 30.0, 45.0, 60.0]
+            .iter()
+            .map(|t| ramp.rate(*t))
+            .collect();
+        assert_eq!(rates, vec![1000, 1000, 2000, 3000, 4000, 5000]);
+
+        // ramping down
+        let ramp = RateRamp {
+            start: 5000.0,
+            end: 1000.0,
+            duration: 60.0,
+            steps: None,
+        };
+        let rates: Vec<u64> = (0..=60).map(|t| ramp.rate(t as f64)).collect();
+        assert!(rates.windows(2).all(|w| w[0] >= w[1]));
+    }
+}
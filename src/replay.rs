@@ -26,7 +26,7 @@ use zstd::Decoder;
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{BufRead, BufReader, ErrorKind};
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::SocketAddr;
 
 use rpc_perf::*;
 
@@ -72,6 +72,14 @@ fn main() {
                 .takes_value(false)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("command-log")
+                .long("command-log")
+                .help("indicates the trace is a log of timestamped commands")
+                .takes_value(false)
+                .required(false)
+                .conflicts_with("binary-trace"),
+        )
         .arg(
             Arg::with_name("endpoint")
                 .long("endpoint")
@@ -154,7 +162,13 @@ fn main() {
         .unwrap_or("1")
         .parse()
         .expect("invalid value for 'workers'");
-    let binary = matches.is_present("binary-trace");
+    let format = if matches.is_present("binary-trace") {
+        TraceFormat::Binary
+    } else if matches.is_present("command-log") {
+        TraceFormat::CommandLog
+    } else {
+        TraceFormat::Ascii
+    };
 
     // configure tls connector
     let key = matches.value_of("tls-key");
@@ -187,7 +201,7 @@ fn main() {
     };
 
     // lookup socket address
-    let sockaddr = endpoint.to_socket_addrs().unwrap().next().unwrap();
+    let sockaddr = resolve_endpoint(endpoint);
 
     // initialize work queue
     let work = Queue::with_capacity(1024 * 1024); // arbitrarily large
@@ -223,14 +237,22 @@ fn main() {
         Box::new(SpeedController::new(speed))
     };
 
-    let mut generator = Generator::new(trace, work, binary, controller);
+    let mut generator = Generator::new(trace, work, format, controller);
     generator.run()
 }
 
 pub trait Controller {
+    /// Wait until the record with the timestamp, in nanoseconds, is due
     fn delay(&mut self, ts: u64);
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Ascii,
+    Binary,
+    CommandLog,
+}
+
 pub struct GeneratorStats {
     sent: usize,
     skip: usize,
@@ -266,9 +288,12 @@ impl Controller for RateController {
     }
 }
 
+/// Sends each record at its original offset from the start of the trace,
+/// scaled by the speed, so the arrival pattern of the trace is preserved
 pub struct SpeedController {
-    ts_sec: u64,
-    next: Instant,
+    start: Option<(u64, Instant)>,
+    ts: u64,
+    behind: bool,
     speed: f64,
 }
 
@@ -281,8 +306,9 @@ impl Default for SpeedController {
 impl SpeedController {
     pub fn new(speed: f64) -> Self {
         Self {
-            ts_sec: 0,
-            next: Instant::now(),
+            start: None,
+            ts: 0,
+            behind: false,
             speed,
         }
     }
@@ -290,25 +316,29 @@ impl SpeedController {
 
 impl Controller for SpeedController {
     fn delay(&mut self, ts: u64) {
-        // handle new timestamp in log
-        if ts > self.ts_sec {
-            let mut now = Instant::now();
-            // info!("ts: {} sent: {} skip: {}", ts, sent, skip);
-            if self.ts_sec != 0 {
-                let log_dur = Duration::from_nanos(
-                    (((ts - self.ts_sec) * 1_000_000_000) as f64 / self.speed) as u64,
-                );
-                self.next += log_dur;
-                if now > self.next {
-                    warn!("falling behind... try reducing replay rate");
-                }
-            }
-            self.ts_sec = ts;
-            // delay if needed
-            while now < self.next {
-                std::thread::sleep(core::time::Duration::from_micros(100));
-                now = Instant::now();
+        // records sharing a timestamp are sent back-to-back
+        if ts <= self.ts {
+            return;
+        }
+        self.ts = ts;
+        let (start_ts, start) = *self.start.get_or_insert((ts, Instant::now()));
+        let offset = ((ts - start_ts) as f64 / self.speed) as u64;
+        let next = start + Duration::from_nanos(offset);
+
+        let mut now = Instant::now();
+        if now > next {
+            if !self.behind {
+                warn!("falling behind... try reducing replay rate");
             }
+            self.behind = true;
+            return;
+        }
+        self.behind = false;
+
+        // delay until the record is due
+        while now < next {
+            std::thread::sleep(core::time::Duration::from_micros(100));
+            now = Instant::now();
         }
     }
 }
@@ -318,14 +348,14 @@ pub struct Generator {
     controller: Box<dyn Controller>,
     trace: String,
     work: Queue<Request>,
-    binary: bool,
+    format: TraceFormat,
 }
 
 impl Generator {
     pub fn new(
         trace: &str,
         work: Queue<Request>,
-        binary: bool,
+        format: TraceFormat,
         controller: Box<dyn Controller>,
     ) -> Self {
         Self {
@@ -333,15 +363,15 @@ impl Generator {
             controller,
             trace: trace.to_string(),
             work,
-            binary,
+            format,
         }
     }
 
     pub fn run(&mut self) {
-        if self.binary {
-            self.binary()
-        } else {
-            self.ascii()
+        match self.format {
+            TraceFormat::Ascii => self.ascii(),
+            TraceFormat::Binary => self.binary(),
+            TraceFormat::CommandLog => self.command_log(),
         }
     }
 
@@ -376,6 +406,38 @@ impl Generator {
                 }
             };
 
+            self.controller.delay(ts * NANOS_PER_SEC);
+
+            while let Err(r) = self.work.push(request) {
+                request = r;
+            }
+
+            self.stats.sent += 1;
+        }
+    }
+
+    fn command_log(&mut self) {
+        // open files
+        let zlog = File::open(&self.trace).expect("failed to open input zlog");
+        let zbuf = BufReader::new(zlog);
+        let log = Decoder::with_buffer(zbuf).expect("failed to init zstd decoder");
+        let buf_log = BufReader::new(log);
+        let mut lines = buf_log.lines();
+
+        while let Some(Ok(line)) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (ts, mut request) = match parse_command(line) {
+                Some(record) => record,
+                None => {
+                    self.stats.skip += 1;
+                    continue;
+                }
+            };
+
             self.controller.delay(ts);
 
             while let Err(r) = self.work.push(request) {
@@ -420,7 +482,7 @@ impl Generator {
                 }
             };
 
-            self.controller.delay(ts);
+            self.controller.delay(ts * NANOS_PER_SEC);
 
             while let Err(r) = self.work.push(request) {
                 request = r;
@@ -431,6 +493,57 @@ impl Generator {
     }
 }
 
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Parse a timestamp in seconds, with an optional fractional part of up to
+/// nanosecond precision, into nanoseconds
+fn parse_timestamp(ts: &str) -> Option<u64> {
+    let (secs, frac) = match ts.split_once('.') {
+        Some((secs, frac)) => (secs, frac),
+        None => (ts, ""),
+    };
+    if frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs: u64 = secs.parse().ok()?;
+    let nanos: u64 = format!("{:0<9}", frac).parse().ok()?;
+    secs.checked_mul(NANOS_PER_SEC)?.checked_add(nanos)
+}
+
+/// Parse a line of a command log into its timestamp, in nanoseconds, and the
+/// request. Each line is a timestamp followed by a memcache text command:
+///
+/// ```text
+/// 1650000000.000125 get key
+/// 1650000000.000250 set key 0 3600 100
+/// ```
+///
+/// Storage commands give the flags, ttl and value length in place of the
+/// value itself, which is generated when the request is sent.
+fn parse_command(line: &str) -> Option<(u64, Request)> {
+    let mut parts = line.split_whitespace();
+    let ts = parse_timestamp(parts.next()?)?;
+    let verb = parts.next()?;
+    let key = parts.next()?.to_string();
+    let request = match verb {
+        "get" => Request::Get { key },
+        "gets" => Request::Gets { key },
+        "delete" => Request::Delete { key },
+        "set" | "add" | "replace" => {
+            let _flags: u32 = parts.next()?.parse().ok()?;
+            let ttl: u32 = parts.next()?.parse().ok()?;
+            let vlen: usize = parts.next()?.parse().ok()?;
+            match verb {
+                "set" => Request::Set { key, vlen, ttl },
+                "add" => Request::Add { key, vlen, ttl },
+                _ => Request::Replace { key, vlen, ttl },
+            }
+        }
+        _ => return None,
+    };
+    Some((ts, request))
+}
+
 // A very fast PRNG
 pub fn rng() -> rand_xoshiro::Xoshiro256PlusPlus {
     rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(0)
@@ -690,3 +803,33 @@ fn decode(buffer: &mut Session) -> Result<(), ParseError> {
 
     Err(ParseError::Incomplete)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_log() {
+        assert_eq!(parse_timestamp("12"), Some(12_000_000_000));
+        assert_eq!(parse_timestamp("12.5"), Some(12_500_000_000));
+        assert_eq!(parse_timestamp("12.000000001"), Some(12_000_000_001));
+        assert_eq!(parse_timestamp("12.0000000001"), None);
+        assert_eq!(parse_timestamp("12.-5"), None);
+
+        match parse_command("1.000125 set foo 0 3600 100") {
+            Some((ts, Request::Set { key, vlen, ttl })) => {
+                assert_eq!(ts, 1_000_125_000);
+                assert_eq!(key, "foo");
+                assert_eq!(vlen, 100);
+                assert_eq!(ttl, 3600);
+            }
+            _ => panic!("expected set"),
+        }
+        assert!(matches!(
+            parse_command("2 get foo"),
+            Some((2_000_000_000, Request::Get { .. }))
+        ));
+        assert!(parse_command("2 set foo").is_none());
+        assert!(parse_command("2 incr foo 1").is_none());
+    }
+}
@@ -0,0 +1,407 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A structured end-of-run summary which can be written to a file. The output
+//! format is selected by the file extension: `.md` produces markdown and any
+//! other extension produces JSON.
+
+use crate::command_stats::verb_label;
+use crate::config_file::LatencyUnit;
+use crate::metrics::*;
+use crate::{Config, Instant};
+use rustcommon_heatmap::AtomicHeatmap;
+use rustcommon_heatmap::AtomicU64;
+use serde_json::{Map, Value};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Heatmap = AtomicHeatmap<u64, AtomicU64>;
+
+const PERCENTILES: &[(&str, f64)] = &[
+    ("p25", 25.0),
+    ("p50", 50.0),
+    ("p75", 75.0),
+    ("p90", 90.0),
+    ("p99", 99.0),
+    ("p999", 99.9),
+    ("p9999", 99.99),
+];
+
+/// The time covered by the latency of the report, which is the whole run when
+/// it has a fixed duration. Otherwise it is the latency window, as a service
+/// may run indefinitely.
+pub fn run_span(config: &Config) -> Duration {
+    match config.general().windows() {
+        Some(windows) => config.general().interval() * windows as u32,
+        None => config.general().latency_window(),
+    }
+}
+
+/// Connect and response latency over the whole run, along with the response
+/// latency of each command, for the report. The latency heatmaps reported
+/// each window only cover the latency window, so these are recorded apart
+/// from them.
+pub struct RunLatency {
+    connect: Heatmap,
+    response: Heatmap,
+    /// a label and heatmap for each command, by target, then keyspace, and
+    /// then command
+    commands: Vec<Vec<Vec<(String, Heatmap)>>>,
+}
+
+impl RunLatency {
+    pub fn new(targets: &[Arc<Config>]) -> Self {
+        let config = &targets[0];
+        let heatmap = || {
+            Heatmap::new(
+                config.general().latency_max(),
+                3,
+                run_span(config),
+                config.general().interval(),
+            )
+        };
+        let commands = targets
+            .iter()
+            .map(|target| {
+                target
+                    .keyspaces()
+                    .iter()
+                    .enumerate()
+                    .map(|(k, keyspace)| {
+                        keyspace
+                            .commands()
+                            .iter()
+                            .enumerate()
+                            .map(|(c, command)| {
+                                let verb = verb_label(keyspace, c, command);
+                                let label = if targets.len() > 1 {
+                                    format!("{} keyspace {} {}", target.name(), k, verb)
+                                } else {
+                                    format!("keyspace {} {}", k, verb)
+                                };
+                                (label, heatmap())
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            connect: heatmap(),
+            response: heatmap(),
+            commands,
+        }
+    }
+
+    /// Record the latency of a connect, in the configured latency unit
+    pub fn record_connect(&self, now: Instant, latency: u64) {
+        self.connect.increment(now, latency, 1);
+    }
+
+    /// Record the latency of a response to a command of the target, in the
+    /// configured latency unit. The command is not known for responses which
+    /// were not matched to their request.
+    pub fn record_response(
+        &self,
+        target: usize,
+        command: Option<(usize, usize)>,
+        now: Instant,
+        latency: u64,
+    ) {
+        self.response.increment(now, latency, 1);
+        if let Some((k, c)) = command {
+            if let Some((_, heatmap)) = self.commands[target].get(k).and_then(|k| k.get(c)) {
+                heatmap.increment(now, latency, 1);
+            }
+        }
+    }
+
+    /// The percentiles of each command which has any responses
+    fn command_percentiles(&self) -> Vec<(String, Vec<(&'static str, u64)>)> {
+        self.commands
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|(_, heatmap)| heatmap.percentile(100.0).is_ok())
+            .map(|(label, heatmap)| (label.clone(), percentiles(heatmap)))
+            .collect()
+    }
+}
+
+pub struct Report {
+    config: Vec<(&'static str, String)>,
+    elapsed: Duration,
+    counters: Vec<(&'static str, u64)>,
+    connect_latency: Vec<(&'static str, u64)>,
+    response_latency: Vec<(&'static str, u64)>,
+    command_latency: Vec<(String, Vec<(&'static str, u64)>)>,
+    latency_unit: LatencyUnit,
+}
+
+impl Report {
+    /// Capture the current metrics and the percentiles over the run into a
+    /// report
+    pub fn new(config: &Config, elapsed: Duration, latency: &RunLatency) -> Self {
+        let endpoints: Vec<String> = config.endpoints().iter().map(|e| e.to_string()).collect();
+        let latency_unit = config.general().latency_unit();
+        let config = vec![
+            ("protocol", format!("{:?}", config.general().protocol())),
+            ("endpoints", endpoints.join(",")),
+            ("threads", config.general().threads().to_string()),
+            ("poolsize", config.connection().poolsize().to_string()),
+            ("pipeline", config.connection().pipeline().to_string()),
+            ("tcp_nodelay", config.connection().tcp_nodelay().to_string()),
+            (
+                "tcp_quickack",
+                config.connection().tcp_quickack().to_string(),
+            ),
+            ("tcp_cork", config.connection().tcp_cork().to_string()),
+            (
+                "interval",
+                config.general().interval().as_secs().to_string(),
+            ),
+            (
+                "windows",
+                config
+                    .general()
+                    .windows()
+                    .map(|w| w.to_string())
+                    .unwrap_or_else(|| "unlimited".to_string()),
+            ),
+            (
+                "request_ratelimit",
+                config
+                    .request()
+                    .ratelimit()
+                    .map(|r| r.to_string())
+                    .unwrap_or_else(|| "unlimited".to_string()),
+            ),
+        ];
+
+        let counters = vec![
+            (CONNECT.name(), CONNECT.value()),
+            (CONNECT_EX.name(), CONNECT_EX.value()),
+            (CONNECT_TIMEOUT.name(), CONNECT_TIMEOUT.value()),
+            (CONNECTIONS_ABANDONED.name(), CONNECTIONS_ABANDONED.value()),
+            (REQUEST.name(), REQUEST.value()),
+            (REQUEST_EX.name(), REQUEST_EX.value()),
+            (REQUEST_GET.name(), REQUEST_GET.value()),
+            (RESPONSE.name(), RESPONSE.value()),
+            (RESPONSE_EX.name(), RESPONSE_EX.value()),
+            (RESPONSE_HIT.name(), RESPONSE_HIT.value()),
+            (RESPONSE_BYTE.name(), RESPONSE_BYTE.value()),
+        ];
+
+        Self {
+            config,
+            elapsed,
+            counters,
+            connect_latency: percentiles(&latency.connect),
+            response_latency: percentiles(&latency.response),
+            command_latency: latency.command_percentiles(),
+            latency_unit,
+        }
+    }
+
+    fn counter(&self, name: &str) -> u64 {
+        self.counters
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| *v)
+            .unwrap_or(0)
+    }
+
+    /// The rate of the counter over the entire run
+    fn per_second(&self, name: &str) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.counter(name) as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// The achieved response rate over the entire run
+    fn qps(&self) -> f64 {
+        self.per_second(RESPONSE.name())
+    }
+
+    /// The bytes of successful responses received per second over the
+    /// entire run
+    fn goodput(&self) -> f64 {
+        self.per_second(RESPONSE_BYTE.name())
+    }
+
+    pub fn json(&self) -> String {
+        let mut config = Map::new();
+        for (k, v) in &self.config {
+            config.insert(k.to_string(), Value::from(v.clone()));
+        }
+        let mut counters = Map::new();
+        for (k, v) in &self.counters {
+            counters.insert(k.to_string(), Value::from(*v));
+        }
+        let mut connect = Map::new();
+        for (k, v) in &self.connect_latency {
+            connect.insert(k.to_string(), Value::from(*v));
+        }
+        let mut response = Map::new();
+        for (k, v) in &self.response_latency {
+            response.insert(k.to_string(), Value::from(*v));
+        }
+        let mut commands = Map::new();
+        for (label, latency) in &self.command_latency {
+            let mut percentiles = Map::new();
+            for (k, v) in latency {
+                percentiles.insert(k.to_string(), Value::from(*v));
+            }
+            commands.insert(label.clone(), Value::Object(percentiles));
+        }
+
+        let mut report = Map::new();
+        report.insert("config".to_string(), Value::Object(config));
+        report.insert(
+            "elapsed_secs".to_string(),
+            Value::from(self.elapsed.as_secs_f64()),
+        );
+        report.insert("qps".to_string(), Value::from(self.qps()));
+        report.insert("goodput".to_string(), Value::from(self.goodput()));
+        report.insert("counters".to_string(), Value::Object(counters));
+        let suffix = self.latency_unit.suffix();
+        report.insert(
+            format!("connect_latency_{}", suffix),
+            Value::Object(connect),
+        );
+        report.insert(
+            format!("response_latency_{}", suffix),
+            Value::Object(response),
+        );
+        report.insert(
+            format!("command_latency_{}", suffix),
+            Value::Object(commands),
+        );
+
+        let mut content = Value::Object(report).to_string();
+        content += "\n";
+        content
+    }
+
+    pub fn markdown(&self) -> String {
+        let mut content = format!("# {} summary\n\n", crate::config::NAME);
+
+        content += "## Config\n\n| option | value |\n| --- | --- |\n";
+        for (k, v) in &self.config {
+            content += &format!("| {} | {} |\n", k, v);
+        }
+
+        content += "\n## Totals\n\n| metric | value |\n| --- | --- |\n";
+        content += &format!("| elapsed_secs | {:.2} |\n", self.elapsed.as_secs_f64());
+        content += &format!("| qps | {:.2} |\n", self.qps());
+        content += &format!("| goodput | {:.2} |\n", self.goodput());
+        for (k, v) in &self.counters {
+            content += &format!("| {} | {} |\n", k, v);
+        }
+
+        for (title, latency) in [
+            ("Connect Latency", &self.connect_latency),
+            ("Response Latency", &self.response_latency),
+        ] {
+            if latency.is_empty() {
+                continue;
+            }
+            content += &format!(
+                "\n## {} ({})\n\n| percentile | value |\n| --- | --- |\n",
+                title,
+                self.latency_unit.suffix()
+            );
+            for (k, v) in latency {
+                content += &format!("| {} | {} |\n", k, v);
+            }
+        }
+
+        if let Some((_, first)) = self.command_latency.first() {
+            content += &format!(
+                "\n## Command Latency ({})\n\n| command |",
+                self.latency_unit.suffix()
+            );
+            for (k, _) in first {
+                content += &format!(" {} |", k);
+            }
+            content += "\n| --- |";
+            content += &" --- |".repeat(first.len());
+            content += "\n";
+            for (label, latency) in &self.command_latency {
+                content += &format!("| {} |", label);
+                for (_, v) in latency {
+                    content += &format!(" {} |", v);
+                }
+                content += "\n";
+            }
+        }
+
+        content
+    }
+
+    /// Write the report to the file, choosing the format from the extension
+    pub fn write(&self, file: &str) -> Result<(), std::io::Error> {
+        let content = match Path::new(file).extension().and_then(|e| e.to_str()) {
+            Some("md") | Some("markdown") => self.markdown(),
+            _ => self.json(),
+        };
+        std::fs::write(file, content)
+    }
+}
+
+fn percentiles(heatmap: &Heatmap) -> Vec<(&'static str, u64)> {
+    PERCENTILES
+        .iter()
+        .map(|(label, value)| (*label, heatmap.percentile(*value).unwrap_or(0)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_latency() {
+        let config = Arc::new(Config::parse(
+            r#"
+            [general]
+            protocol = "memcache"
+            interval = 1
+            windows = 2
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [[keyspace]]
+            commands = [{ verb = "get" }, { verb = "set" }]
+            values = [{ length = 16 }]
+            "#,
+        ));
+        let latency = RunLatency::new(&[config.clone()]);
+        let now = Instant::now();
+        latency.record_connect(now, 50);
+        for _ in 0..10 {
+            latency.record_response(0, Some((0, 0)), now, 100);
+        }
+        latency.record_response(0, None, now, 300);
+
+        // only commands with responses are reported
+        let report = Report::new(&config, Duration::from_secs(2), &latency);
+        assert_eq!(report.command_latency.len(), 1);
+        let (label, percentiles) = &report.command_latency[0];
+        assert_eq!(label, "keyspace 0 get");
+        assert!(percentiles.iter().all(|(_, v)| *v == 100));
+        assert_eq!(report.response_latency.last(), Some(&("p9999", 300)));
+
+        let json: Value = serde_json::from_str(&report.json()).unwrap();
+        assert_eq!(json["command_latency_us"]["keyspace 0 get"]["p50"], 100);
+        assert!(report
+            .markdown()
+            .contains("| keyspace 0 get | 100 | 100 | 100 | 100 | 100 | 100 | 100 |"));
+    }
+}
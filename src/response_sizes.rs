@@ -0,0 +1,117 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Optional breakdown of response latency by the size of the response, using
+//! configurable thresholds to divide responses into size buckets. This shows
+//! tail behavior which depends on the response size.
+
+use crate::config_file::LatencyUnit;
+use crate::report::run_span;
+use crate::Config;
+use rustcommon_heatmap::{AtomicHeatmap, AtomicU64};
+use std::time::Duration;
+
+pub struct ResponseSizes {
+    unit: LatencyUnit,
+    thresholds: Vec<usize>,
+    heatmaps: Vec<AtomicHeatmap<u64, AtomicU64>>,
+}
+
+impl ResponseSizes {
+    /// Create a heatmap for each bucket, covering the whole run when it has a
+    /// fixed duration so that the summary describes the entire test
+    pub fn new(config: &Config) -> Self {
+        let span = run_span(config);
+        let mut thresholds = config.general().response_size_buckets().to_vec();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        let heatmaps = (0..=thresholds.len())
+            .map(|_| {
+                AtomicHeatmap::<u64, AtomicU64>::new(
+                    config.general().latency_max(),
+                    3,
+                    span,
+                    Duration::from_millis(1000),
+                )
+            })
+            .collect();
+        Self {
+            unit: config.general().latency_unit(),
+            thresholds,
+            heatmaps,
+        }
+    }
+
+    /// The bucket for a response of this many bytes
+    fn bucket(&self, bytes: usize) -> usize {
+        self.thresholds
+            .iter()
+            .position(|threshold| bytes < *threshold)
+            .unwrap_or(self.thresholds.len())
+    }
+
+    /// A description of the sizes in the bucket
+    fn label(&self, bucket: usize) -> String {
+        match (bucket.checked_sub(1), self.thresholds.get(bucket)) {
+            (None, Some(upper)) => format!("< {}", upper),
+            (Some(lower), Some(upper)) => format!("{}-{}", self.thresholds[lower], upper - 1),
+            (Some(lower), None) => format!(">= {}", self.thresholds[lower]),
+            (None, None) => "all".to_string(),
+        }
+    }
+
+    /// Record the latency of a response of this many bytes
+    pub fn record(&self, bytes: usize, nanos: u64) {
+        self.heatmaps[self.bucket(bytes)].increment(
+            crate::Instant::now(),
+            self.unit.convert(nanos),
+            1,
+        );
+    }
+
+    /// Render the latency percentiles for each bucket as a table, one line
+    /// per bucket
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{:>24} {:>10} {:>10} {:>10} {:>10}",
+            "size (bytes)",
+            format!("p50 ({})", self.unit.suffix()),
+            format!("p90 ({})", self.unit.suffix()),
+            format!("p99 ({})", self.unit.suffix()),
+            format!("p999 ({})", self.unit.suffix()),
+        )];
+        for (bucket, heatmap) in self.heatmaps.iter().enumerate() {
+            lines.push(format!(
+                "{:>24} {:>10} {:>10} {:>10} {:>10}",
+                self.label(bucket),
+                heatmap.percentile(50.0).unwrap_or(0),
+                heatmap.percentile(90.0).unwrap_or(0),
+                heatmap.percentile(99.0).unwrap_or(0),
+                heatmap.percentile(99.9).unwrap_or(0),
+            ));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let sizes = ResponseSizes {
+            unit: LatencyUnit::Microseconds,
+            thresholds: vec![1024, 65536],
+            heatmaps: Vec::new(),
+        };
+        assert_eq!(sizes.bucket(0), 0);
+        assert_eq!(sizes.bucket(1023), 0);
+        assert_eq!(sizes.bucket(1024), 1);
+        assert_eq!(sizes.bucket(65536), 2);
+        assert_eq!(sizes.label(0), "< 1024");
+        assert_eq!(sizes.label(1), "1024-65535");
+        assert_eq!(sizes.label(2), ">= 65536");
+    }
+}
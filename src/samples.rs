@@ -0,0 +1,160 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Records raw response latencies to a file, one sample in nanoseconds per
+//! line. Each clone keeps its own buffer so that worker threads only take the
+//! shared lock once per batch. The buffers of every clone are written out by
+//! `flush`, so samples still buffered by a worker are not lost at the end of
+//! the run.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex, Weak};
+
+const BATCH_SIZE: usize = 1024;
+
+type Buffer = Arc<Mutex<Vec<u64>>>;
+
+struct Shared {
+    file: Mutex<BufWriter<File>>,
+    /// the buffers of all clones, so that any of them can be flushed
+    buffers: Mutex<Vec<Weak<Mutex<Vec<u64>>>>>,
+}
+
+pub struct LatencySamples {
+    shared: Arc<Shared>,
+    buffer: Buffer,
+}
+
+impl LatencySamples {
+    /// Open the file for appending, creating it if it does not exist
+    pub fn create(path: &str) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let shared = Arc::new(Shared {
+            file: Mutex::new(BufWriter::new(file)),
+            buffers: Mutex::new(Vec::new()),
+        });
+        Ok(Self::with_shared(shared))
+    }
+
+    fn with_shared(shared: Arc<Shared>) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::with_capacity(BATCH_SIZE)));
+        if let Ok(mut buffers) = shared.buffers.lock() {
+            buffers.retain(|buffer| buffer.strong_count() > 0);
+            buffers.push(Arc::downgrade(&buffer));
+        }
+        Self { shared, buffer }
+    }
+
+    /// Record a latency sample, writing out the batch once it is full
+    pub fn record(&mut self, nanos: u64) {
+        let batch = match self.buffer.lock() {
+            Ok(mut buffer) => {
+                buffer.push(nanos);
+                if buffer.len() < BATCH_SIZE {
+                    return;
+                }
+                std::mem::replace(&mut *buffer, Vec::with_capacity(BATCH_SIZE))
+            }
+            Err(_) => return,
+        };
+        self.write(&batch);
+    }
+
+    /// Write the samples buffered by every clone and flush the file
+    pub fn flush(&mut self) {
+        let buffers: Vec<Buffer> = match self.shared.buffers.lock() {
+            Ok(buffers) => buffers.iter().filter_map(Weak::upgrade).collect(),
+            Err(_) => return,
+        };
+        for buffer in buffers {
+            let batch = match buffer.lock() {
+                Ok(mut buffer) => std::mem::take(&mut *buffer),
+                Err(_) => continue,
+            };
+            self.write(&batch);
+        }
+        if let Ok(mut file) = self.shared.file.lock() {
+            let _ = file.flush();
+        }
+    }
+
+    fn write(&self, batch: &[u64]) {
+        if batch.is_empty() {
+            return;
+        }
+        if let Ok(mut file) = self.shared.file.lock() {
+            for sample in batch {
+                let _ = writeln!(file, "{}", sample);
+            }
+        }
+    }
+}
+
+impl Clone for LatencySamples {
+    fn clone(&self) -> Self {
+        Self::with_shared(self.shared.clone())
+    }
+}
+
+impl Drop for LatencySamples {
+    fn drop(&mut self) {
+        let batch = match self.buffer.lock() {
+            Ok(mut buffer) => std::mem::take(&mut *buffer),
+            Err(_) => return,
+        };
+        self.write(&batch);
+        if let Ok(mut file) = self.shared.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(path: &std::path::Path) -> Vec<u64> {
+        let mut lines: Vec<u64> = std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        lines.sort_unstable();
+        lines
+    }
+
+    #[test]
+    fn flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("samples");
+        let mut samples = LatencySamples::create(path.to_str().unwrap()).unwrap();
+        let mut worker = samples.clone();
+
+        // full batches are written as they fill, and the rest by a flush
+        for nanos in 0..BATCH_SIZE as u64 {
+            worker.record(nanos);
+        }
+        worker.record(BATCH_SIZE as u64);
+        samples.flush();
+        assert_eq!(lines(&path).len(), BATCH_SIZE + 1);
+
+        // the partial buffers of other clones are written by a flush
+        worker.record(1);
+        samples.flush();
+        assert_eq!(lines(&path).len(), BATCH_SIZE + 2);
+    }
+
+    #[test]
+    fn dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("samples");
+        let samples = LatencySamples::create(path.to_str().unwrap()).unwrap();
+        let mut worker = samples.clone();
+        worker.record(2);
+        worker.record(1);
+        std::mem::drop(worker);
+        assert_eq!(lines(&path), vec![1, 2]);
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Optional instrumentation of rpc-perf itself, used to tell apart server
+//! latency from saturation of the load generator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting the bytes allocated once self stats are
+/// enabled. Until then the only overhead is a relaxed load per allocation.
+/// This must be installed as the global allocator of the binary for the bytes
+/// allocated to be reported.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // only growth is counted, as the rest was counted when allocated
+        count(new_size.saturating_sub(layout.size()));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count(bytes: usize) {
+    if COUNTING.load(Ordering::Relaxed) {
+        ALLOCATED.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+pub struct SelfStats {
+    iterations: Vec<Arc<AtomicU64>>,
+    previous: Vec<u64>,
+    allocated: u64,
+    last: Instant,
+}
+
+impl Default for SelfStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SelfStats {
+    pub fn new() -> Self {
+        COUNTING.store(true, Ordering::Relaxed);
+        Self {
+            iterations: Vec::new(),
+            previous: Vec::new(),
+            allocated: ALLOCATED.load(Ordering::Relaxed),
+            last: Instant::now(),
+        }
+    }
+
+    /// Create a counter for the event loop iterations of a worker
+    pub fn iterations(&mut self) -> Arc<AtomicU64> {
+        let iterations = Arc::new(AtomicU64::new(0));
+        self.iterations.push(iterations.clone());
+        self.previous.push(0);
+        iterations
+    }
+
+    /// Log the memory usage and open files of the process, and the rate of
+    /// allocation and of the event loop of each worker since the previous call
+    pub fn report(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.last).as_secs_f64();
+        self.last = now;
+
+        // nothing is counted unless the binary installs the allocator
+        let allocated = ALLOCATED.load(Ordering::Relaxed);
+        if allocated > 0 {
+            info!(
+                "Self: Allocated (MB/s): {:.1}",
+                (allocated - self.allocated) as f64 / 1_048_576.0 / elapsed.max(f64::EPSILON)
+            );
+        }
+        self.allocated = allocated;
+
+        if let Some((rss, peak)) = rss() {
+            info!(
+                "Self: RSS (MB): {:.1} Peak RSS (MB): {:.1}",
+                rss as f64 / 1_048_576.0,
+                peak as f64 / 1_048_576.0
+            );
+        }
+
+        if let Some(fds) = open_files() {
+            info!("Self: Open Files: {}", fds);
+        }
+
+        let mut rates = Vec::new();
+        for (iterations, previous) in self.iterations.iter().zip(self.previous.iter_mut()) {
+            let current = iterations.load(Ordering::Relaxed);
+            rates.push(format!(
+                "{:.0}",
+                (current - *previous) as f64 / elapsed.max(f64::EPSILON)
+            ));
+            *previous = current;
+        }
+        if !rates.is_empty() {
+            info!("Self: Event Loop Iterations/s: {}", rates.join(" "));
+        }
+    }
+}
+
+/// Returns the current and peak resident set size of the process, in bytes.
+/// This is only available on Linux.
+fn rss() -> Option<(u64, u64)> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_status(&status)
+}
+
+/// Returns the number of files the process has open. This is only available
+/// on Linux.
+fn open_files() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count())
+}
+
+fn parse_status(status: &str) -> Option<(u64, u64)> {
+    let mut rss = None;
+    let mut peak = None;
+    for line in status.lines() {
+        let mut parts = line.split_whitespace();
+        let key = parts.next();
+        let kb = parts.next().and_then(|v| v.parse::<u64>().ok());
+        match (key, kb) {
+            (Some("VmRSS:"), Some(kb)) => rss = Some(kb * 1024),
+            (Some("VmHWM:"), Some(kb)) => peak = Some(kb * 1024),
+            _ => {}
+        }
+    }
+    Some((rss?, peak?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn allocated() {
+        let _stats = SelfStats::new();
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let mut buf: Vec<u8> = Vec::with_capacity(4096);
+        buf.reserve_exact(8192);
+        // other tests may allocate concurrently, so this is a lower bound
+        assert!(ALLOCATED.load(Ordering::Relaxed) - before >= 8192);
+    }
+
+    #[test]
+    fn status() {
+        let status = "Name:\trpc-perf\nVmHWM:\t    2048 kB\nVmRSS:\t    1024 kB\nThreads:\t5\n";
+        assert_eq!(parse_status(status), Some((1024 * 1024, 2048 * 1024)));
+        assert_eq!(parse_status("Name:\trpc-perf\n"), None);
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Polls the endpoints for server side stats on a dedicated thread, so that a
+//! slow or unreachable server does not hold up the admin thread. Each poll
+//! opens a short lived connection to every endpoint, with the TLS settings of
+//! the endpoint, and the admin reports the result of the latest poll.
+
+use crate::codec::{Memcache, ParseError, Redis};
+use crate::config::RequestContext;
+use crate::config_file::Protocol;
+use crate::worker::ssl_connector;
+use crate::Config;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long to wait on an endpoint before skipping it for this poll
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+pub struct ServerStats {
+    latest: Arc<Mutex<Vec<(String, f64)>>>,
+}
+
+impl ServerStats {
+    /// Start polling the endpoints of each target once each interval, if
+    /// server stats or object encoding samples are configured for any of
+    /// them. With more than one target, the stats are labelled by target.
+    pub fn spawn(targets: &[Arc<Config>]) -> Option<Self> {
+        let labelled = targets.len() > 1;
+        let targets: Vec<(Option<String>, Arc<Config>)> = targets
+            .iter()
+            .filter(|config| {
+                stats_enabled(config) || config.general().object_encoding_samples().is_some()
+            })
+            .map(|config| (labelled.then(|| config.name().to_string()), config.clone()))
+            .collect();
+        let interval = targets.first()?.1.general().interval();
+        let latest = Arc::new(Mutex::new(Vec::new()));
+        let shared = latest.clone();
+        std::thread::spawn(move || loop {
+            let mut stats = Vec::new();
+            for (name, config) in &targets {
+                if stats_enabled(config) {
+                    stats.extend(poll(config, name.as_deref()));
+                }
+                if let Some(samples) = config.general().object_encoding_samples() {
+                    stats.extend(object_encodings(config, samples, name.as_deref()));
+                }
+            }
+            if let Ok(mut latest) = shared.lock() {
+                *latest = stats;
+            }
+            std::thread::sleep(interval);
+        });
+        Some(Self { latest })
+    }
+
+    /// The stats from the latest poll
+    pub fn latest(&self) -> Vec<(String, f64)> {
+        self.latest
+            .lock()
+            .map(|latest| latest.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Server stats are only fetched from memcache targets
+fn stats_enabled(config: &Config) -> bool {
+    !config.general().server_stats().is_empty() && config.general().protocol() == Protocol::Memcache
+}
+
+/// The label of a stat, which names the target when there is more than one
+fn label(kind: &str, target: Option<&str>, key: &str) -> String {
+    match target {
+        Some(target) => format!("{}/{}/{}", kind, target, key),
+        None => format!("{}/{}", kind, key),
+    }
+}
+
+/// Query each endpoint for the configured memcache server stats, summing the
+/// values across endpoints
+fn poll(config: &Config, target: Option<&str>) -> Vec<(String, f64)> {
+    let keys = config.general().server_stats();
+    let mut totals: Vec<(String, f64)> = Vec::new();
+    for endpoint in config.endpoints() {
+        let stats = connect(config, endpoint).and_then(|mut stream| fetch_stats(&mut stream, keys));
+        match stats {
+            Ok(stats) => {
+                for (key, value) in stats {
+                    if let Some(total) = totals.iter_mut().find(|(k, _)| *k == key) {
+                        total.1 += value;
+                    } else {
+                        totals.push((key, value));
+                    }
+                }
+            }
+            Err(e) => {
+                debug!("failed to fetch stats from: {}: {}", endpoint, e);
+            }
+        }
+    }
+    totals
+        .drain(..)
+        .map(|(key, value)| (label("server", target, &key), value))
+        .collect()
+}
+
+/// Sample the internal encoding of values in the keyspace with `OBJECT
+/// ENCODING`, spreading the samples across the endpoints and counting the
+/// keys found with each encoding
+fn object_encodings(config: &Config, samples: usize, target: Option<&str>) -> Vec<(String, f64)> {
+    let endpoints = config.endpoints();
+    if endpoints.is_empty() {
+        return Vec::new();
+    }
+    let mut rng = SmallRng::from_entropy();
+    let mut totals: Vec<(String, f64)> = Vec::new();
+    for (index, endpoint) in endpoints.iter().enumerate() {
+        // the first endpoints take any remainder
+        let share = samples / endpoints.len() + usize::from(index < samples % endpoints.len());
+        if share == 0 {
+            continue;
+        }
+        let keys = (0..share)
+            .map(|_| {
+                config
+                    .choose_keyspace(&mut rng)
+                    .generate_key(&mut rng, &mut RequestContext::default())
+            })
+            .collect();
+        let encodings = connect(config, *endpoint)
+            .and_then(|mut stream| fetch_object_encodings(&mut stream, keys));
+        match encodings {
+            Ok(encodings) => {
+                for (encoding, count) in encodings {
+                    match totals.iter_mut().find(|(e, _)| *e == encoding) {
+                        Some(total) => total.1 += count as f64,
+                        None => totals.push((encoding, count as f64)),
+                    }
+                }
+            }
+            Err(e) => {
+                debug!(
+                    "failed to sample object encodings from: {}: {}",
+                    endpoint, e
+                );
+            }
+        }
+    }
+    totals
+        .drain(..)
+        .map(|(encoding, count)| (label("object_encoding", target, &encoding), count))
+        .collect()
+}
+
+/// Open a blocking connection to the endpoint, with TLS if it is configured
+/// for the endpoint
+fn connect(config: &Config, addr: SocketAddr) -> Result<Box<dyn Stream>, Error> {
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let tls = match config.endpoint_tls(&addr) {
+        Some(tls) => tls,
+        None => return Ok(Box::new(stream)),
+    };
+    let connector = match ssl_connector(tls)? {
+        Some(connector) => connector,
+        None => return Ok(Box::new(stream)),
+    };
+    let sni = tls.sni().unwrap_or_else(|| "localhost".to_string());
+    let stream = connector
+        .configure()
+        .map_err(|_| Error::new(ErrorKind::Other, "bad tls config"))?
+        .connect(&sni, stream)
+        .map_err(|e| Error::new(ErrorKind::Other, format!("tls handshake failed: {}", e)))?;
+    Ok(Box::new(stream))
+}
+
+/// Fetch the stats over a connection to the endpoint
+fn fetch_stats(stream: &mut dyn Stream, keys: &[String]) -> Result<Vec<(String, f64)>, Error> {
+    let mut request = Vec::new();
+    Memcache::stats(&mut request);
+    stream.write_all(&request)?;
+
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        let bytes = stream.read(&mut buf)?;
+        if bytes == 0 {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        response.extend_from_slice(&buf[0..bytes]);
+        match Memcache::decode_stats(&response, keys) {
+            Ok(stats) => {
+                return Ok(stats);
+            }
+            Err(ParseError::Incomplete) => {}
+            Err(_) => {
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+        }
+    }
+}
+
+/// Sample the encodings over a connection to the endpoint, pipelining a
+/// request for each key
+fn fetch_object_encodings(
+    stream: &mut dyn Stream,
+    keys: Vec<Vec<u8>>,
+) -> Result<Vec<(String, usize)>, Error> {
+    let mut request = Vec::new();
+    let mut outstanding = keys.len();
+    for key in keys {
+        Redis::object_encoding(key, &mut request);
+    }
+    stream.write_all(&request)?;
+
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    while outstanding > 0 {
+        let bytes = stream.read(&mut buf)?;
+        if bytes == 0 {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        response.extend_from_slice(&buf[0..bytes]);
+        loop {
+            match Redis::decode_object_encoding(&response) {
+                Ok((encoding, consumed)) => {
+                    response.drain(..consumed);
+                    outstanding -= 1;
+                    if let Some(encoding) = encoding {
+                        match counts.iter_mut().find(|(e, _)| *e == encoding) {
+                            Some(count) => count.1 += 1,
+                            None => counts.push((encoding, 1)),
+                        }
+                    }
+                    if outstanding == 0 {
+                        break;
+                    }
+                }
+                Err(ParseError::Incomplete) => break,
+                Err(_) => {
+                    return Err(Error::from(ErrorKind::InvalidData));
+                }
+            }
+        }
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A connection which replies with a fixed response, a few bytes at a time
+    struct Replay {
+        response: &'static [u8],
+        request: Vec<u8>,
+    }
+
+    impl Read for Replay {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let bytes = std::cmp::min(std::cmp::min(buf.len(), 8), self.response.len());
+            buf[0..bytes].copy_from_slice(&self.response[0..bytes]);
+            self.response = &self.response[bytes..];
+            Ok(bytes)
+        }
+    }
+
+    impl Write for Replay {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.request.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let keys = vec!["curr_items".to_string()];
+        let mut stream = Replay {
+            response: b"STAT pid 1\r\nSTAT curr_items 42\r\nEND\r\n",
+            request: Vec::new(),
+        };
+        assert_eq!(
+            fetch_stats(&mut stream, &keys).unwrap(),
+            vec![("curr_items".to_string(), 42.0)]
+        );
+        assert_eq!(stream.request, b"stats\r\n");
+
+        // a connection closed before the end of the stats is an error
+        let mut stream = Replay {
+            response: b"STAT pid 1\r\n",
+            request: Vec::new(),
+        };
+        assert!(fetch_stats(&mut stream, &keys).is_err());
+    }
+
+    #[test]
+    fn object_encodings() {
+        let mut stream = Replay {
+            response: b"$8\r\nlistpack\r\n$-1\r\n$8\r\nlistpack\r\n$3\r\nraw\r\n",
+            request: Vec::new(),
+        };
+        let keys = vec![b"0".to_vec(), b"1".to_vec(), b"2".to_vec(), b"3".to_vec()];
+        assert_eq!(
+            fetch_object_encodings(&mut stream, keys).unwrap(),
+            vec![("listpack".to_string(), 2), ("raw".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(label("server", None, "curr_items"), "server/curr_items");
+        assert_eq!(
+            label("server", Some("cache"), "curr_items"),
+            "server/cache/curr_items"
+        );
+    }
+}
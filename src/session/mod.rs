@@ -4,9 +4,10 @@
 
 //! This crate provides buffered TCP sessions with or without TLS which can be
 //! used with [`::mio`]. TLS/SSL is provided by BoringSSL with the [`::boring`]
-//! crate.
+//! crate. A session may instead use the stdin and stdout of a child process.
 
 mod buffer;
+mod pipe_stream;
 mod stream;
 mod tcp_stream;
 
@@ -15,6 +16,7 @@ use crate::*;
 use boring::ssl::SslSession;
 
 use std::borrow::{Borrow, BorrowMut};
+use std::collections::VecDeque;
 use std::io::{BufRead, ErrorKind, Read, Write};
 use std::net::SocketAddr;
 
@@ -25,6 +27,7 @@ use mio::{Interest, Poll, Token};
 use buffer::Buffer;
 use stream::Stream;
 
+pub use pipe_stream::PipeStream;
 pub use tcp_stream::TcpStream;
 
 pub struct Session {
@@ -39,8 +42,42 @@ pub struct Session {
     interest: Interest,
     /// A timestamp which is used to calculate response latency
     timestamp: Instant,
+    /// The last time the request was sent or response bytes were received
+    last_read: Instant,
     /// the number of outstanding responses
     outstanding: usize,
+    /// payloads which outstanding responses are expected to match
+    expected: VecDeque<Vec<u8>>,
+    /// consecutive reads which added no bytes
+    incomplete_reads: usize,
+    /// the value size class of each outstanding request, when latency is
+    /// broken down by value size
+    value_lengths: VecDeque<Option<usize>>,
+    /// the id of each outstanding request, when requests are tagged
+    request_ids: VecDeque<u64>,
+    /// the keyspace and command of each outstanding request, when stats are
+    /// kept for each command
+    commands: VecDeque<(usize, usize)>,
+    /// the number of keys each outstanding get asks for, for protocols which
+    /// omit the keys which are not found
+    key_counts: VecDeque<usize>,
+    /// the encoded bytes of each outstanding request, when slow requests are
+    /// logged
+    requests: VecDeque<Vec<u8>>,
+    /// when the session connected, until its first request is sent
+    connected_at: Option<Instant>,
+    /// when the session connected, kept for the lifetime of the session
+    established: Option<Instant>,
+    /// when the first byte of the outstanding responses arrived
+    first_byte: Option<Instant>,
+    /// when the TCP connection completed and the TLS handshake could proceed
+    handshake_started: Option<Instant>,
+    /// how often the TLS keys are updated, as configured for the endpoint
+    key_update_interval: Option<std::time::Duration>,
+    /// when the TLS keys were last updated
+    key_updated: Option<Instant>,
+    /// whether the outstanding request was sent along with a key update
+    key_update_pending: bool,
 }
 
 impl std::fmt::Debug for Session {
@@ -65,6 +102,15 @@ impl Session {
         Self::new(Stream::plain(stream), min_capacity, max_capacity)
     }
 
+    /// Create a new `Session` over the stdin and stdout of a child process
+    pub fn pipe_with_capacity(
+        stream: PipeStream,
+        min_capacity: usize,
+        max_capacity: usize,
+    ) -> Self {
+        Self::new(Stream::pipe(stream), min_capacity, max_capacity)
+    }
+
     /// Create a new `Session` representing a negotiated `SslStream`
     pub fn tls_with_capacity(
         stream: SslStream<TcpStream>,
@@ -95,7 +141,22 @@ impl Session {
             max_capacity,
             interest: Interest::WRITABLE,
             timestamp: Instant::now(),
+            last_read: Instant::now(),
             outstanding: 0,
+            expected: VecDeque::new(),
+            incomplete_reads: 0,
+            value_lengths: VecDeque::new(),
+            request_ids: VecDeque::new(),
+            commands: VecDeque::new(),
+            key_counts: VecDeque::new(),
+            requests: VecDeque::new(),
+            connected_at: None,
+            established: None,
+            first_byte: None,
+            handshake_started: None,
+            key_update_interval: None,
+            key_updated: None,
+            key_update_pending: false,
         }
     }
 
@@ -104,9 +165,40 @@ impl Session {
             // OPEN.increment();
             // SESSION.increment();
             self.connected = true;
+            self.connected_at = Some(Instant::now());
+            self.established = self.connected_at;
         }
     }
 
+    /// The time the session connected, if it was ever established
+    pub fn established(&self) -> Option<Instant> {
+        self.established
+    }
+
+    /// The time the TCP connection completed, once it has been seen
+    pub fn handshake_started(&self) -> Option<Instant> {
+        self.handshake_started
+    }
+
+    pub fn set_handshake_started(&mut self, started: Option<Instant>) {
+        self.handshake_started = started;
+    }
+
+    /// The time the session connected, returned only once so that it can be
+    /// used to time the first request
+    pub fn take_connected_at(&mut self) -> Option<Instant> {
+        self.connected_at.take()
+    }
+
+    /// The time the first byte of the outstanding responses arrived
+    pub fn first_byte(&self) -> Option<Instant> {
+        self.first_byte
+    }
+
+    pub fn set_first_byte(&mut self, first_byte: Option<Instant>) {
+        self.first_byte = first_byte;
+    }
+
     pub fn is_connecting(&self) -> bool {
         !self.connected
     }
@@ -211,10 +303,56 @@ impl Session {
         self.read_buffer.borrow()
     }
 
+    /// Returns a reference to the bytes which are waiting to be written.
+    pub fn write_buffer(&self) -> &[u8] {
+        self.write_buffer.borrow()
+    }
+
     pub fn peer_addr(&self) -> Result<SocketAddr, std::io::Error> {
         self.stream.peer_addr()
     }
 
+    pub fn take_error(&self) -> Result<Option<std::io::Error>, std::io::Error> {
+        self.stream.take_error()
+    }
+
+    /// Update the TLS keys this often, as configured for the endpoint
+    pub fn set_key_update_interval(&mut self, interval: Option<std::time::Duration>) {
+        self.key_update_interval = interval;
+    }
+
+    /// Update the TLS keys if the session has gone the key update interval
+    /// since it was established or last updated them. Returns true if an
+    /// update was queued, in which case it is sent with the next request.
+    pub fn update_keys_if_due(&mut self) -> bool {
+        let interval = match self.key_update_interval {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let last = match self.key_updated.or(self.established) {
+            Some(last) => last,
+            None => return false,
+        };
+        let now = Instant::now();
+        if now - last < interval {
+            return false;
+        }
+        // retry only after another interval if the update is not supported
+        self.key_updated = Some(now);
+        if self.stream.key_update().is_ok() {
+            self.key_update_pending = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the response being completed is the first since a key update,
+    /// returned only once
+    pub fn take_key_update_pending(&mut self) -> bool {
+        std::mem::take(&mut self.key_update_pending)
+    }
+
     pub fn timestamp(&self) -> Instant {
         self.timestamp
     }
@@ -223,10 +361,23 @@ impl Session {
         self.timestamp = timestamp;
     }
 
+    pub fn last_read(&self) -> Instant {
+        self.last_read
+    }
+
+    pub fn set_last_read(&mut self, last_read: Instant) {
+        self.last_read = last_read;
+    }
+
     pub fn ssl_session(&self) -> Option<SslSession> {
         self.stream.ssl_session()
     }
 
+    /// The protocol negotiated with ALPN, once the handshake is complete
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.stream.alpn_protocol()
+    }
+
     pub fn set_outstanding(&mut self, count: usize) {
         self.outstanding = count;
     }
@@ -234,6 +385,83 @@ impl Session {
     pub fn outstanding(&self) -> usize {
         self.outstanding
     }
+
+    /// Count a read which made no progress, returning the number of
+    /// consecutive reads without progress
+    pub fn incomplete_read(&mut self) -> usize {
+        self.incomplete_reads += 1;
+        self.incomplete_reads
+    }
+
+    /// Reset the count of incomplete reads once a read makes progress
+    pub fn reset_incomplete_reads(&mut self) {
+        self.incomplete_reads = 0;
+    }
+
+    /// Tag the next outstanding request with the length of its value
+    pub fn push_value_length(&mut self, length: Option<usize>) {
+        self.value_lengths.push_back(length);
+    }
+
+    /// The value length of the oldest outstanding request
+    pub fn pop_value_length(&mut self) -> Option<usize> {
+        self.value_lengths.pop_front().flatten()
+    }
+
+    /// Remember the id of the next outstanding request
+    pub fn push_request_id(&mut self, id: u64) {
+        self.request_ids.push_back(id);
+    }
+
+    /// The id of the oldest outstanding request
+    pub fn pop_request_id(&mut self) -> Option<u64> {
+        self.request_ids.pop_front()
+    }
+
+    /// Remember the command of the next outstanding request
+    pub fn push_command(&mut self, index: (usize, usize)) {
+        self.commands.push_back(index);
+    }
+
+    /// The command of the oldest outstanding request
+    pub fn pop_command(&mut self) -> Option<(usize, usize)> {
+        self.commands.pop_front()
+    }
+
+    /// Remember the number of keys the next outstanding get asks for
+    pub fn push_key_count(&mut self, count: usize) {
+        self.key_counts.push_back(count);
+    }
+
+    /// The number of keys the oldest outstanding get asks for
+    pub fn pop_key_count(&mut self) -> Option<usize> {
+        self.key_counts.pop_front()
+    }
+
+    /// Remember the encoded bytes of the next outstanding request
+    pub fn push_request(&mut self, bytes: Vec<u8>) {
+        self.requests.push_back(bytes);
+    }
+
+    /// The encoded bytes of the oldest outstanding request
+    pub fn pop_request(&mut self) -> Option<Vec<u8>> {
+        self.requests.pop_front()
+    }
+
+    /// Remember a payload which a future response is expected to match
+    pub fn push_expected(&mut self, payload: Vec<u8>) {
+        self.expected.push_back(payload);
+    }
+
+    /// Returns the payload the next response is expected to match
+    pub fn expected(&self) -> Option<&[u8]> {
+        self.expected.front().map(|v| v.as_slice())
+    }
+
+    /// Discard the payload for the response which was just received
+    pub fn pop_expected(&mut self) -> Option<Vec<u8>> {
+        self.expected.pop_front()
+    }
 }
 
 impl Read for Session {
@@ -315,8 +543,9 @@ impl Write for Session {
                 // let flushed_bytes = bytes;
                 SESSION_SEND_BYTE.add(bytes as _);
                 self.write_buffer.consume(bytes);
-
-                Ok(())
+                // a corked socket holds back the tail of the request until
+                // it is uncorked
+                self.stream.uncork()
             }
             Err(e) => {
                 SESSION_SEND_EX.increment();
@@ -327,3 +556,31 @@ impl Write for Session {
 }
 
 // metrics::test_no_duplicates!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn key_update_interval() {
+        let stream = PipeStream::spawn("cat").unwrap();
+        let mut session = Session::pipe_with_capacity(stream, 1024, 1024);
+        session.connected();
+
+        // keys are only updated when an interval is configured for the
+        // endpoint
+        assert!(!session.update_keys_if_due());
+
+        // a session without TLS can not update its keys, and does not try
+        // again until another interval has passed
+        session.set_key_update_interval(Some(Duration::ZERO));
+        assert!(!session.update_keys_if_due());
+        assert!(session.key_updated.is_some());
+        session.set_key_update_interval(Some(Duration::from_secs(60)));
+        let updated = session.key_updated;
+        assert!(!session.update_keys_if_due());
+        assert!(session.key_updated == updated);
+        assert!(!session.take_key_update_pending());
+    }
+}
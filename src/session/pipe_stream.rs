@@ -0,0 +1,97 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A stream over the stdin and stdout of a child process, for servers which
+//! speak their protocol over stdio. The process exiting closes its stdout,
+//! which is seen as the server closing the connection.
+
+use mio::unix::pipe::{Receiver, Sender};
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+
+pub struct PipeStream {
+    child: Child,
+    stdin: Sender,
+    stdout: Receiver,
+}
+
+impl PipeStream {
+    /// Run the command with `sh -c` and take its stdin and stdout. Its stderr
+    /// is inherited.
+    pub fn spawn(command: &str) -> Result<Self, std::io::Error> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = Sender::from(child.stdin.take().unwrap());
+        let stdout = Receiver::from(child.stdout.take().unwrap());
+        stdin.set_nonblocking(true)?;
+        stdout.set_nonblocking(true)?;
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Stop the process
+    pub fn shutdown(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error> {
+        self.stdin.write(buf)
+    }
+    fn flush(&mut self) -> std::result::Result<(), std::io::Error> {
+        self.stdin.flush()
+    }
+}
+
+// Each pipe is only ever readable or writable, so stdout is registered for
+// reads and stdin for writes regardless of the interest which is requested.
+// The first writable event on stdin marks the session as connected.
+impl mio::event::Source for PipeStream {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        _interest: mio::Interest,
+    ) -> std::result::Result<(), std::io::Error> {
+        registry.register(&mut self.stdout, token, mio::Interest::READABLE)?;
+        registry.register(&mut self.stdin, token, mio::Interest::WRITABLE)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        _interest: mio::Interest,
+    ) -> std::result::Result<(), std::io::Error> {
+        registry.reregister(&mut self.stdout, token, mio::Interest::READABLE)?;
+        registry.reregister(&mut self.stdin, token, mio::Interest::WRITABLE)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::result::Result<(), std::io::Error> {
+        registry.deregister(&mut self.stdout)?;
+        registry.deregister(&mut self.stdin)
+    }
+}
@@ -2,16 +2,18 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
-//! Encapsulates plaintext and TLS TCP streams into a single type.
+//! Encapsulates plaintext and TLS TCP streams, and the pipes of a child
+//! process, into a single type.
 
 use boring::ssl::SslSession;
+use foreign_types::ForeignTypeRef;
 use std::io::{Error, ErrorKind};
 use std::io::{Read, Write};
 use std::net::SocketAddr;
 
 use boring::ssl::{HandshakeError, MidHandshakeSslStream, SslStream};
 
-use super::TcpStream;
+use super::{PipeStream, TcpStream};
 // use crate::{TCP_CLOSE, TCP_CONN_CURR};
 
 pub struct Stream {
@@ -25,6 +27,8 @@ pub enum StreamType {
     Tls(SslStream<TcpStream>),
     /// A TLS/SSL TCP stream which is still handshaking
     Handshaking(MidHandshakeSslStream<TcpStream>),
+    /// The stdin and stdout of a child process
+    Pipe(PipeStream),
 }
 
 impl Stream {
@@ -40,6 +44,12 @@ impl Stream {
         }
     }
 
+    pub fn pipe(pipe_stream: PipeStream) -> Self {
+        Self {
+            inner: Some(StreamType::Pipe(pipe_stream)),
+        }
+    }
+
     pub fn handshaking(handshaking_ssl_stream: MidHandshakeSslStream<TcpStream>) -> Self {
         Self {
             inner: Some(StreamType::Handshaking(handshaking_ssl_stream)),
@@ -98,6 +108,10 @@ impl Stream {
                     let _ = s.get_mut().shutdown(std::net::Shutdown::Both);
                     Some(StreamType::Handshaking(s))
                 }
+                StreamType::Pipe(mut s) => {
+                    s.shutdown();
+                    Some(StreamType::Pipe(s))
+                }
             }
         }
     }
@@ -108,6 +122,12 @@ impl Stream {
                 StreamType::Plain(s) => s.peer_addr()?,
                 StreamType::Tls(s) => s.get_ref().peer_addr()?,
                 StreamType::Handshaking(s) => s.get_ref().peer_addr()?,
+                StreamType::Pipe(_) => {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        "pipe has no peer address",
+                    ));
+                }
             })
         } else {
             Err(Error::new(
@@ -117,6 +137,66 @@ impl Stream {
         }
     }
 
+    /// Push out any partial segment held back on a corked socket
+    pub fn uncork(&self) -> Result<(), Error> {
+        match self.inner.as_ref() {
+            Some(StreamType::Plain(s)) => s.uncork(),
+            Some(StreamType::Tls(s)) => s.get_ref().uncork(),
+            Some(StreamType::Handshaking(_)) | Some(StreamType::Pipe(_)) | None => Ok(()),
+        }
+    }
+
+    /// Take the pending error on the socket, such as the reason a connect
+    /// failed
+    pub fn take_error(&self) -> Result<Option<Error>, Error> {
+        match self.inner.as_ref() {
+            Some(StreamType::Plain(s)) => s.take_error(),
+            Some(StreamType::Tls(s)) => s.get_ref().take_error(),
+            Some(StreamType::Handshaking(s)) => s.get_ref().take_error(),
+            Some(StreamType::Pipe(_)) | None => Ok(None),
+        }
+    }
+
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        if let Some(StreamType::Tls(s)) = &self.inner {
+            s.ssl().selected_alpn_protocol().map(|p| p.to_vec())
+        } else {
+            None
+        }
+    }
+
+    /// Queue a TLS 1.3 key update, which also asks the server to update its
+    /// keys. It is sent with the next write.
+    pub fn key_update(&mut self) -> Result<(), Error> {
+        if let Some(StreamType::Tls(s)) = &self.inner {
+            if s.ssl().version_str() != "TLSv1.3" {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "key update requires TLS 1.3",
+                ));
+            }
+            // boring has no safe wrapper for the key update, so it is called
+            // directly. The stream owns the SSL object, so the pointer is
+            // valid for the duration of the call.
+            let result = unsafe {
+                boring_sys::SSL_key_update(
+                    s.ssl().as_ptr(),
+                    boring_sys::SSL_KEY_UPDATE_REQUESTED as _,
+                )
+            };
+            if result == 1 {
+                Ok(())
+            } else {
+                Err(Error::new(ErrorKind::Other, "key update failed"))
+            }
+        } else {
+            Err(Error::new(
+                ErrorKind::Unsupported,
+                "key update requires an established TLS session",
+            ))
+        }
+    }
+
     pub fn ssl_session(&self) -> Option<SslSession> {
         if let Some(StreamType::Tls(s)) = &self.inner {
             if let Some(session) = s.ssl().session() {
@@ -153,6 +233,7 @@ impl Read for StreamType {
                 ErrorKind::WouldBlock,
                 "handshaking tls stream would block on read",
             )),
+            Self::Pipe(s) => s.read(buf),
         }
     }
 }
@@ -186,6 +267,7 @@ impl Write for StreamType {
                 ErrorKind::WouldBlock,
                 "handshaking tls stream would block on write",
             )),
+            Self::Pipe(s) => s.write(buf),
         }
     }
 
@@ -250,6 +332,7 @@ impl mio::event::Source for StreamType {
             Self::Plain(s) => registry.register(s, token, interest),
             Self::Tls(s) => registry.register(s.get_mut(), token, interest),
             Self::Handshaking(s) => registry.register(s.get_mut(), token, interest),
+            Self::Pipe(s) => registry.register(s, token, interest),
         }
     }
 
@@ -263,6 +346,7 @@ impl mio::event::Source for StreamType {
             Self::Plain(s) => registry.reregister(s, token, interest),
             Self::Tls(s) => registry.reregister(s.get_mut(), token, interest),
             Self::Handshaking(s) => registry.reregister(s.get_mut(), token, interest),
+            Self::Pipe(s) => registry.reregister(s, token, interest),
         }
     }
 
@@ -271,6 +355,7 @@ impl mio::event::Source for StreamType {
             Self::Plain(s) => registry.deregister(s),
             Self::Tls(s) => registry.deregister(s.get_mut()),
             Self::Handshaking(s) => registry.deregister(s.get_mut()),
+            Self::Pipe(s) => registry.deregister(s),
         }
     }
 }
@@ -13,12 +13,95 @@ use std::net::SocketAddr;
 
 pub struct TcpStream {
     inner: mio::net::TcpStream,
+    /// the kernel clears `TCP_QUICKACK` as it sees fit, so it is set again
+    /// after each read
+    quickack: bool,
+    /// corked sockets are uncorked after each flush, so that the last partial
+    /// segment of a request is not held back
+    cork: bool,
 }
 
 impl TcpStream {
     pub fn connect(addr: SocketAddr) -> Result<Self, std::io::Error> {
         let stream = mio::net::TcpStream::connect(addr)?;
-        Ok(Self { inner: stream })
+        Ok(Self {
+            inner: stream,
+            quickack: false,
+            cork: false,
+        })
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<(), std::io::Error> {
+        self.inner.set_nodelay(nodelay)
+    }
+
+    /// Set `TCP_QUICKACK` on the socket, and again after each read. This is a
+    /// no-op on platforms other than Linux.
+    pub fn set_quickack(&mut self, quickack: bool) -> Result<(), std::io::Error> {
+        self.quickack = quickack;
+        self.apply_quickack()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn apply_quickack(&self) -> Result<(), std::io::Error> {
+        self.setsockopt(libc::TCP_QUICKACK, self.quickack)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn apply_quickack(&self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    /// Set `TCP_CORK` on the socket. This is a no-op on platforms other than
+    /// Linux.
+    #[cfg(target_os = "linux")]
+    pub fn set_cork(&mut self, cork: bool) -> Result<(), std::io::Error> {
+        self.cork = cork;
+        self.setsockopt(libc::TCP_CORK, cork)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn set_cork(&mut self, cork: bool) -> Result<(), std::io::Error> {
+        self.cork = cork;
+        Ok(())
+    }
+
+    /// Push out any partial segment held back by `TCP_CORK`, leaving the
+    /// socket corked for the next request. This is a no-op if the socket is
+    /// not corked.
+    #[cfg(target_os = "linux")]
+    pub fn uncork(&self) -> Result<(), std::io::Error> {
+        if !self.cork {
+            return Ok(());
+        }
+        self.setsockopt(libc::TCP_CORK, false)?;
+        self.setsockopt(libc::TCP_CORK, true)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn uncork(&self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn setsockopt(&self, option: libc::c_int, enabled: bool) -> Result<(), std::io::Error> {
+        use std::os::unix::io::AsRawFd;
+
+        let value: libc::c_int = enabled as libc::c_int;
+        let result = unsafe {
+            libc::setsockopt(
+                self.inner.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
     }
 
     pub fn shutdown(&self, how: std::net::Shutdown) -> Result<(), std::io::Error> {
@@ -28,6 +111,10 @@ impl TcpStream {
     pub fn peer_addr(&self) -> Result<SocketAddr, std::io::Error> {
         self.inner.peer_addr()
     }
+
+    pub fn take_error(&self) -> Result<Option<std::io::Error>, std::io::Error> {
+        self.inner.take_error()
+    }
 }
 
 impl TryFrom<mio::net::TcpStream> for TcpStream {
@@ -35,17 +122,24 @@ impl TryFrom<mio::net::TcpStream> for TcpStream {
 
     fn try_from(other: mio::net::TcpStream) -> Result<Self, std::io::Error> {
         let _ = other.peer_addr()?;
-        Ok(Self { inner: other })
+        Ok(Self {
+            inner: other,
+            quickack: false,
+            cork: false,
+        })
     }
 }
 
 impl Read for TcpStream {
     fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
-        self.inner.read(buf)
-        // if let Ok(bytes) = result {
-        //     // TCP_RECV_BYTE.add(bytes as _);
-        // }
-        // result
+        let result = self.inner.read(buf);
+        if let Ok(bytes) = result {
+            if bytes > 0 && self.quickack {
+                let _ = self.apply_quickack();
+            }
+            // TCP_RECV_BYTE.add(bytes as _);
+        }
+        result
     }
 }
 
@@ -88,3 +182,35 @@ impl mio::event::Source for TcpStream {
         self.inner.deregister(registry)
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn uncork() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        // wait for the non-blocking connect to complete
+        while stream.peer_addr().is_err() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        stream.set_cork(true).unwrap();
+        stream.set_quickack(true).unwrap();
+
+        // a corked partial segment is held back for up to 200ms, so it only
+        // arrives within the timeout once it is uncorked
+        assert_eq!(stream.write(b"get 0\r\n").unwrap(), 7);
+        stream.uncork().unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(server.read(&mut buf).unwrap(), 7);
+        assert_eq!(&buf[0..7], b"get 0\r\n");
+    }
+}
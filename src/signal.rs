@@ -0,0 +1,31 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Stops the run on SIGINT or SIGTERM so the end-of-run summary and exports
+//! are still written. A second signal exits immediately.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(signal: libc::c_int) {
+    // only async-signal-safe operations are allowed here
+    if STOP.swap(true, Ordering::Relaxed) {
+        unsafe { libc::_exit(128 + signal) };
+    }
+}
+
+/// Install handlers which stop the run on SIGINT or SIGTERM
+pub fn stop_on_signal() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            libc::signal(signal, handle as libc::sighandler_t);
+        }
+    }
+}
+
+/// Returns true once a signal has asked the run to stop
+pub fn stopped() -> bool {
+    STOP.load(Ordering::Relaxed)
+}
@@ -0,0 +1,137 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A sink which records the counters and response latency percentiles of each
+//! window in a SQLite database. Each run has its own run id, so the results of
+//! many runs can be kept in one database and queried together.
+
+use crate::Config;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+
+/// The counters recorded for each window, as the count within the window
+const COUNTERS: &[&str] = &[
+    "request",
+    "request_ex",
+    "response",
+    "response_ex",
+    "response_hit",
+    "connect",
+    "connect_ex",
+];
+
+/// The response latency percentiles recorded for each window
+const PERCENTILES: &[&str] = &["p25", "p50", "p75", "p90", "p99", "p999", "p9999"];
+
+pub struct Sqlite {
+    connection: Connection,
+    run_id: String,
+    insert: String,
+}
+
+impl Sqlite {
+    /// Open or create the database and record the metadata of this run
+    pub fn new(file: &str, config: &Config) -> Result<Self, rusqlite::Error> {
+        let connection = Connection::open(file)?;
+        let columns: Vec<&str> = COUNTERS.iter().chain(PERCENTILES).copied().collect();
+        connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS runs (
+                run_id TEXT PRIMARY KEY,
+                started INTEGER NOT NULL,
+                protocol TEXT NOT NULL,
+                endpoints TEXT NOT NULL,
+                latency_unit TEXT NOT NULL,
+                config TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS windows (
+                run_id TEXT NOT NULL REFERENCES runs (run_id),
+                window_number INTEGER NOT NULL,
+                warmup INTEGER NOT NULL,
+                elapsed_secs REAL NOT NULL,
+                {}
+            );",
+            columns
+                .iter()
+                .map(|c| format!("{} INTEGER", c))
+                .collect::<Vec<String>>()
+                .join(",\n")
+        ))?;
+
+        let run_id = format!("{:016x}", rand::random::<u64>());
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        let endpoints: Vec<String> = config.endpoints().iter().map(|e| e.to_string()).collect();
+        connection.execute(
+            "INSERT INTO runs (run_id, started, protocol, endpoints, latency_unit, config)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                run_id,
+                started,
+                format!("{:?}", config.general().protocol()),
+                endpoints.join(","),
+                config.general().latency_unit().suffix(),
+                config.to_json(),
+            ],
+        )?;
+
+        let insert = format!(
+            "INSERT INTO windows (run_id, window_number, warmup, elapsed_secs, {}) VALUES ({})",
+            columns.join(", "),
+            vec!["?"; columns.len() + 4].join(", ")
+        );
+
+        Ok(Self {
+            connection,
+            run_id,
+            insert,
+        })
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Insert a row for the window. Counters and percentiles which were not
+    /// recorded are left null.
+    pub fn insert(
+        &self,
+        window: u64,
+        warmup: bool,
+        elapsed_secs: f64,
+        counters: &[(&str, u64)],
+        percentiles: &[(String, u64)],
+    ) {
+        let mut values = vec![
+            Value::Text(self.run_id.clone()),
+            Value::Integer(window as i64),
+            Value::Integer(warmup as i64),
+            Value::Real(elapsed_secs),
+        ];
+        for counter in COUNTERS {
+            values.push(match counters.iter().find(|(name, _)| name == counter) {
+                Some((_, value)) => Value::Integer(*value as i64),
+                None => Value::Null,
+            });
+        }
+        for percentile in PERCENTILES {
+            values.push(
+                match percentiles
+                    .iter()
+                    .find(|(label, _)| label.as_str() == *percentile)
+                {
+                    Some((_, value)) => Value::Integer(*value as i64),
+                    None => Value::Null,
+                },
+            );
+        }
+        if let Err(e) = self
+            .connection
+            .execute(&self.insert, params_from_iter(values))
+        {
+            warn!("failed to insert window into sqlite: {}", e);
+        }
+    }
+}
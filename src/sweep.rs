@@ -0,0 +1,129 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! A request rate which steps through a series of levels, holding each for a
+//! number of windows. The latency at the end of each level is collected into a
+//! summary table, giving a latency versus throughput curve from a single run.
+
+use std::io::{Error, ErrorKind};
+
+pub struct RateSweep {
+    levels: Vec<u64>,
+    windows: usize,
+}
+
+impl RateSweep {
+    /// Parse a `START:STOP:STEP` specification of request rates. The levels
+    /// run from the start towards the stop, which is included if it falls on
+    /// a step, and may either ascend or descend.
+    pub fn parse(spec: &str, windows: usize) -> Result<Self, Error> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("bad sweep: {}, expected START:STOP:STEP", spec),
+            )
+        };
+        let parts: Vec<u64> = spec
+            .split(':')
+            .map(|p| p.trim().parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let (start, stop, step) = match parts[..] {
+            [start, stop, step] if start > 0 && stop > 0 && step > 0 => (start, stop, step),
+            _ => return Err(invalid()),
+        };
+        if windows == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "sweep windows must be positive",
+            ));
+        }
+
+        let mut levels = vec![start];
+        let mut rate = start;
+        loop {
+            rate = if stop >= start {
+                match rate.checked_add(step) {
+                    Some(rate) if rate <= stop => rate,
+                    _ => break,
+                }
+            } else {
+                match rate.checked_sub(step) {
+                    Some(rate) if rate >= stop && rate > 0 => rate,
+                    _ => break,
+                }
+            };
+            levels.push(rate);
+        }
+        Ok(Self { levels, windows })
+    }
+
+    /// The number of windows each level is held for
+    pub fn windows(&self) -> usize {
+        self.windows
+    }
+
+    /// The number of windows needed to run every level
+    pub fn total_windows(&self) -> usize {
+        self.levels.len() * self.windows
+    }
+
+    /// The request rate for the window, counting from zero, or `None` once
+    /// every level has run
+    pub fn rate(&self, window: usize) -> Option<u64> {
+        self.levels.get(window / self.windows).copied()
+    }
+}
+
+/// The measurements for a single level of the sweep
+pub struct SweepLevel {
+    pub rate: u64,
+    pub response_rate: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+}
+
+/// Render the levels as a table, one line per level
+pub fn summary(levels: &[SweepLevel], unit: &str) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:>12} {:>14} {:>10} {:>10} {:>10} {:>10}",
+        "rate",
+        "response/s",
+        format!("p50 ({})", unit),
+        format!("p90 ({})", unit),
+        format!("p99 ({})", unit),
+        format!("p999 ({})", unit),
+    )];
+    for level in levels {
+        lines.push(format!(
+            "{:>12} {:>14.2} {:>10} {:>10} {:>10} {:>10}",
+            level.rate, level.response_rate, level.p50, level.p90, level.p99, level.p999
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let sweep = RateSweep::parse("1000:5000:2000", 2).unwrap();
+        assert_eq!(sweep.levels, vec![1000, 3000, 5000]);
+        assert_eq!(sweep.total_windows(), 6);
+        assert_eq!(sweep.rate(0), Some(1000));
+        assert_eq!(sweep.rate(1), Some(1000));
+        assert_eq!(sweep.rate(2), Some(3000));
+        assert_eq!(sweep.rate(6), None);
+
+        let sweep = RateSweep::parse("5000:1000:3000", 1).unwrap();
+        assert_eq!(sweep.levels, vec![5000, 2000]);
+
+        assert!(RateSweep::parse("1000:5000", 1).is_err());
+        assert!(RateSweep::parse("1000:5000:0", 1).is_err());
+        assert!(RateSweep::parse("1000:5000:1000", 0).is_err());
+    }
+}
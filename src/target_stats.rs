@@ -0,0 +1,45 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Request and response counts for a single target, used to break down the
+//! overall stats when a run drives more than one target. The same counts are
+//! kept for each workload of a keyspace.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct TargetStats {
+    name: String,
+    request: AtomicU64,
+    response: AtomicU64,
+}
+
+impl TargetStats {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            request: AtomicU64::new(0),
+            response: AtomicU64::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn increment_request(&self) {
+        self.request.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_response(&self) {
+        self.response.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the requests and responses since the previous call
+    pub fn take(&self) -> (u64, u64) {
+        (
+            self.request.swap(0, Ordering::Relaxed),
+            self.response.swap(0, Ordering::Relaxed),
+        )
+    }
+}
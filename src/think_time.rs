@@ -0,0 +1,85 @@
+// Copyright 2022 Twitter, Inc.
+// Licensed under the Apache License, Version 2.0
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! An empirical think time distribution, loaded from a file with one think
+//! time in microseconds per line. Each pause is drawn uniformly from the
+//! samples, so the pauses follow the same distribution as the recorded ones.
+
+use rand::Rng;
+use std::io::{Error, ErrorKind};
+
+pub struct ThinkTimes {
+    /// the samples, in nanoseconds
+    samples: Vec<u64>,
+}
+
+impl ThinkTimes {
+    /// Load the samples from a file
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    /// Parse the samples, skipping blank lines, comments, and a header line
+    fn parse(content: &str) -> Result<Self, Error> {
+        let mut samples = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.parse::<f64>() {
+                Ok(us) if us.is_finite() && us >= 0.0 => {
+                    samples.push((us * 1000.0) as u64);
+                }
+                _ if number == 0 => {
+                    // header
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("bad think time on line {}: {}", number + 1, line),
+                    ));
+                }
+            }
+        }
+        if samples.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "think time file has no samples",
+            ));
+        }
+        Ok(Self { samples })
+    }
+
+    /// Draw a think time, in nanoseconds
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> u64 {
+        self.samples[rng.gen_range(0..self.samples.len())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parse() {
+        let times = ThinkTimes::parse("think_time_us\n100\n\n# idle\n2.5\n").unwrap();
+        assert_eq!(times.samples, vec![100_000, 2_500]);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert!(times.samples.contains(&times.sample(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(ThinkTimes::parse("").is_err());
+        assert!(ThinkTimes::parse("100\nslow\n").is_err());
+        assert!(ThinkTimes::parse("100\n-5\n").is_err());
+    }
+}
@@ -2,44 +2,324 @@
 // Licensed under the Apache License, Version 2.0
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use crate::baseline::Reservoir;
+use crate::cluster::SlotMap;
 use crate::codec::*;
+use crate::command_stats::CommandStats;
+use crate::config::RequestContext;
 use crate::config_file::Tls;
+use crate::exemplars::ExemplarHistogram;
 use crate::metrics::*;
-use crate::session::TcpStream;
+use crate::phases::Phases;
+use crate::poisson::PoissonArrivals;
+use crate::report::RunLatency;
+use crate::response_sizes::ResponseSizes;
+use crate::samples::LatencySamples;
+use crate::session::{PipeStream, TcpStream};
+use crate::target_stats::TargetStats;
 use crate::*;
 use boring::x509::X509;
+use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng, SeedableRng};
 use rustcommon_heatmap::AtomicHeatmap;
 use rustcommon_heatmap::AtomicU64;
 use rustcommon_ratelimiter::Ratelimiter;
 use std::io::{BufRead, Write};
 use std::net::SocketAddr;
 
-use crate::config_file::Protocol;
+use crate::config_file::{LatencyUnit, Protocol, ThinkTimeModel, Verb};
 
 use boring::ssl::*;
 use mio::{Events, Poll, Token};
 use slab::Slab;
 
-use std::collections::VecDeque;
+use rand_distr::{Distribution, Exp};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
+/// The id of the next request, unique across all workers
+static NEXT_REQUEST_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Which commands a session may carry when reads and writes are routed to
+/// separate endpoints.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Any,
+    Read,
+    Write,
+}
+
+/// Requests for a single command which were encoded at startup, sent in turn
+struct FrameRing {
+    frames: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl FrameRing {
+    fn next(&mut self) -> &[u8] {
+        let index = self.next;
+        self.next = (index + 1) % self.frames.len();
+        &self.frames[index]
+    }
+}
+
+/// The most times a request is redirected between cluster nodes before it is
+/// abandoned
+const MAX_REDIRECTS: usize = 5;
+
+/// A request which a cluster node redirected to the node serving its key
+struct Redirect {
+    endpoint: SocketAddr,
+    /// whether the slot is migrating, so the request must follow an `ASKING`
+    asking: bool,
+    request: Vec<u8>,
+    command: Option<(usize, usize)>,
+    /// the number of times the request has been redirected
+    attempts: usize,
+}
+
+/// The most commands skipped in a row because their own ratelimit is
+/// exhausted, before waiting for a ratelimit instead
+const MAX_SKIPPED_COMMANDS: usize = 16;
+
+/// The most slow requests which are logged each second
+const SLOW_REQUEST_LOG_LIMIT: usize = 10;
+
+/// The most bytes of each request and response shown for a slow request
+const SLOW_REQUEST_LOG_BYTES: usize = 256;
+
+/// Limits the messages logged each second so that a failing server does not
+/// flood the log. Messages past the limit are counted and the count is logged
+/// once the second is over.
+struct LogLimit {
+    name: &'static str,
+    limit: usize,
+    /// the start of the current one second logging window
+    window: Instant,
+    logged: usize,
+    suppressed: usize,
+}
+
+impl LogLimit {
+    fn new(name: &'static str, limit: usize) -> Self {
+        Self {
+            name,
+            limit,
+            window: Instant::now(),
+            logged: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Start a new window once the current one is over, logging the number of
+    /// messages suppressed during it
+    fn tick(&mut self) {
+        let now = Instant::now();
+        if now - self.window >= std::time::Duration::from_secs(1) {
+            if self.suppressed > 0 {
+                warn!("{}: {} not logged", self.name, self.suppressed);
+            }
+            self.window = now;
+            self.logged = 0;
+            self.suppressed = 0;
+        }
+    }
+
+    /// Returns true if another message may be logged in this window
+    fn allow(&mut self) -> bool {
+        self.tick();
+        if self.logged >= self.limit {
+            self.suppressed += 1;
+            return false;
+        }
+        self.logged += 1;
+        true
+    }
+}
+
+/// Logs the detail of requests whose response took longer than the threshold,
+/// rate-limited so a slow server does not flood the log
+struct SlowRequests {
+    threshold: std::time::Duration,
+    limit: LogLimit,
+}
+
+impl SlowRequests {
+    fn new(threshold: std::time::Duration) -> Self {
+        Self {
+            threshold,
+            limit: LogLimit::new("slow request", SLOW_REQUEST_LOG_LIMIT),
+        }
+    }
+
+    fn log(
+        &mut self,
+        endpoint: Option<&SocketAddr>,
+        latency: std::time::Duration,
+        request: &[u8],
+        response: &[u8],
+    ) {
+        if !self.limit.allow() {
+            return;
+        }
+        let endpoint = endpoint
+            .map(|e| e.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        warn!(
+            "slow request: endpoint: {} latency: {} us request: \"{}\" response: \"{}\"",
+            endpoint,
+            latency.as_micros(),
+            escape(request),
+            escape(response)
+        );
+    }
+}
+
+/// A printable form of the bytes, truncated to a length suitable for logging
+fn escape(bytes: &[u8]) -> String {
+    let truncated = bytes.len() > SLOW_REQUEST_LOG_BYTES;
+    let bytes = &bytes[..bytes.len().min(SLOW_REQUEST_LOG_BYTES)];
+    let mut escaped = bytes.escape_ascii().to_string();
+    if truncated {
+        escaped.push_str("...");
+    }
+    escaped
+}
+
+/// A sequence of commands in progress on a session
+struct SequenceState {
+    /// the keyspace and index of the sequence
+    index: (usize, usize),
+    /// the position of the next command to send
+    next: usize,
+    /// the key shared by the commands
+    key: Vec<u8>,
+    started: Instant,
+}
+
 pub struct Worker {
     codec: Box<dyn Codec>,
+    config: Arc<Config>,
     connect_queue: VecDeque<(SocketAddr, Option<SslSession>)>,
     connect_ratelimit: Option<Arc<Ratelimiter>>,
     poll: Poll,
     ready_queue: VecDeque<Token>,
+    /// ready sessions held back while the ready queue is at its cap, queued
+    /// again once it has room
+    deferred: VecDeque<Token>,
     reconnect_ratelimit: Option<Arc<Ratelimiter>>,
     request_ratelimit: Option<Arc<Ratelimiter>>,
+    /// this worker's share of the request rate, when requests arrive as a
+    /// Poisson process
+    poisson: Option<PoissonArrivals>,
+    command_ratelimits: HashMap<(usize, usize), Arc<Ratelimiter>>,
+    /// the ratelimit of each workload, by keyspace and workload index
+    workload_ratelimits: Vec<((usize, usize), Arc<Ratelimiter>)>,
+    /// the stats of the workload each workload command belongs to
+    workload_stats: HashMap<(usize, usize), Arc<TargetStats>>,
     sessions: Slab<Session>,
     tls: Option<SslConnector>,
+    /// connectors for endpoints with their own TLS settings, where `None` is
+    /// plaintext
+    endpoint_tls: HashMap<SocketAddr, Option<SslConnector>>,
+    /// log the protocol negotiated with ALPN for each connection
+    alpn: bool,
     connect_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    lifetime_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
     request_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
     request_waterfall: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    value_heatmaps: HashMap<usize, Arc<AtomicHeatmap<u64, AtomicU64>>>,
     pipeline: usize,
+    connects_per_tick: usize,
+    max_connections: Option<usize>,
+    deadline: Option<std::time::Duration>,
+    pad_request_to: Option<usize>,
+    count_failures_in_rate: bool,
+    /// this worker's share of the request ratelimit, per second, used to count
+    /// the requests which come due while no connection is ready
+    due_rate: Option<f64>,
+    /// when requests were last accounted for, either by sending one or by
+    /// counting those due as dropped. Unset until the first request is sent,
+    /// so that connecting at startup is not counted as a backlog
+    due_since: Option<Instant>,
+    request_ids: bool,
+    subscribe: Option<Vec<u8>>,
+    /// the no-op request sent to verify each new connection, if enabled
+    verify: Option<Vec<u8>>,
+    /// sessions which are waiting for the reply to the verification request,
+    /// with when it was sent
+    verifying: HashMap<Token, Instant>,
+    /// how long to wait for the reply to the verification request
+    verify_timeout: std::time::Duration,
+    delivery_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    /// how often each TLS session updates its keys, if enabled
+    key_update_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    response_sizes: Option<Arc<ResponseSizes>>,
+    latency_samples: Option<LatencySamples>,
+    /// a sample of response latencies to compare against a baseline run
+    baseline_samples: Option<Reservoir>,
+    latency_histogram: Option<Arc<ExemplarHistogram>>,
+    phases: Option<Arc<Phases>>,
+    rng: SmallRng,
+    roles: HashMap<Token, Role>,
+    pending_write: Option<bool>,
+    next_command: Option<(usize, usize)>,
+    admitted: Vec<(usize, usize)>,
+    /// the sequence whose first commands were admitted for the next request
+    pending_sequence: Option<(usize, usize)>,
+    /// the sequence in progress on each session
+    sequences: HashMap<Token, SequenceState>,
+    sequence_heatmaps: HashMap<(usize, usize), Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    wait_heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    /// the previous command chosen in each keyspace, for markov models
+    previous_commands: Vec<Option<usize>>,
+    iterations: Option<Arc<std::sync::atomic::AtomicU64>>,
+    addrs: HashMap<Token, SocketAddr>,
+    connect_failures: HashMap<SocketAddr, usize>,
+    queue_warn_depth: usize,
+    queue_warned: bool,
+    target_stats: Option<Arc<TargetStats>>,
+    command_stats: Option<Arc<CommandStats>>,
+    /// latency over the whole run for the report, with the index of the
+    /// target this worker drives
+    run_latency: Option<(Arc<RunLatency>, usize)>,
+    latency_unit: LatencyUnit,
+    request_budget: Option<u64>,
+    budget_complete: Option<Arc<AtomicUsize>>,
+    /// the number of responses outstanding across all sessions, so that the
+    /// end of a request budget is found without scanning the sessions
+    outstanding: usize,
+    cluster: Option<SlotMap>,
+    /// the key chosen to route the next command by its cluster slot
+    routed_key: Option<Vec<u8>>,
+    /// the id the routed key is tagged with, when requests are tagged
+    routed_id: Option<u64>,
+    /// redirected requests waiting for a session to the node serving them
+    redirects: VecDeque<Redirect>,
+    /// sessions carrying a redirected request, with the number of times it
+    /// has been redirected
+    redirected: HashMap<Token, usize>,
+    /// sessions waiting for the reply to an `ASKING`
+    asking: HashSet<Token>,
+    /// the endpoints given in redirects, once resolved
+    resolved: HashMap<String, SocketAddr>,
+    frames: HashMap<(usize, usize), FrameRing>,
+    max_incomplete_reads: Option<usize>,
+    thinking: BinaryHeap<Reverse<(u64, usize)>>,
+    epoch: Instant,
+    delayed_connects: VecDeque<(u64, SocketAddr, Option<SslSession>)>,
+    recycle: Option<Arc<AtomicUsize>>,
+    recycle_seen: usize,
+    /// set once the minimum fraction of connections are established, response
+    /// latencies are not recorded until then
+    ready: Option<Arc<AtomicBool>>,
+    slow_requests: Option<SlowRequests>,
+    /// limits the errors logged for responses and connects
+    error_log: LogLimit,
 }
 
 impl Worker {
@@ -51,6 +331,35 @@ impl Worker {
         let mut connect_queue = VecDeque::with_capacity(connections);
         let ready_queue = VecDeque::with_capacity(connections);
         let pipeline = config.connection().pipeline();
+        let connects_per_tick = config.connection().connects_per_tick();
+        // a lone worker has the whole per-client cap, the client gives each
+        // worker its share of the cap with `set_max_connections`
+        let max_connections = config.connection().max_connections_per_client();
+        let latency_unit = config.general().latency_unit();
+        let max_incomplete_reads = config.request().max_incomplete_reads();
+        let deadline = config.request().deadline();
+
+        // subscribed sessions send a single subscribe request once connected
+        let subscribe = if config.general().subscribe().is_empty() {
+            None
+        } else {
+            let mut frame = Vec::new();
+            Redis::subscribe(config.general().subscribe(), &mut frame);
+            Some(frame)
+        };
+
+        // verified sessions send a no-op request once connected
+        let verify = if config.connection().verify_on_connect() {
+            let mut frame = Vec::new();
+            if config.general().protocol() == Protocol::Memcache {
+                Memcache::version(&mut frame);
+            } else {
+                Redis::verify(&mut frame);
+            }
+            Some(frame)
+        } else {
+            None
+        };
 
         // initialize sessions
         for endpoint in config.endpoints() {
@@ -73,33 +382,144 @@ impl Worker {
         } else {
             None
         };
+        let endpoint_tls = config
+            .tls_endpoints()
+            .iter()
+            .map(|(endpoint, tls)| {
+                let connector = tls
+                    .as_ref()
+                    .and_then(|tls| ssl_connector(tls).expect("bad tls config"));
+                (*endpoint, connector)
+            })
+            .collect();
+        let alpn = tls.is_some() && config.tls().map(|t| !t.alpn().is_empty()) == Some(true);
 
         // initialize the codec
-        let codec = match config.general().protocol() {
+        let mut codec = match config.general().protocol() {
             Protocol::Ping => Box::new(Ping::new(config.clone())) as Box<dyn Codec>,
             Protocol::Echo => Box::new(Echo::new(config.clone())) as Box<dyn Codec>,
-            Protocol::Memcache => Box::new(Memcache::new(config.clone())) as Box<dyn Codec>,
+            Protocol::Memcache | Protocol::MemcacheBinary => {
+                Box::new(Memcache::new(config.clone())) as Box<dyn Codec>
+            }
             Protocol::Redis | Protocol::RedisInline | Protocol::RedisResp => {
                 Box::new(Redis::new(config.clone())) as Box<dyn Codec>
             }
             Protocol::ThriftCache => Box::new(ThriftCache::new(config.clone())) as Box<dyn Codec>,
         };
 
+        // optionally, encode requests for each command ahead of time
+        let mut frames = HashMap::new();
+        if let Some(count) = config.request().precompute_requests() {
+            for (k, keyspace) in config.keyspaces().iter().enumerate() {
+                for (c, command) in keyspace.commands().iter().enumerate() {
+                    let ring = (0..count)
+                        .map(|_| codec.frame(keyspace, command, &mut RequestContext::default()))
+                        .collect::<Option<Vec<Vec<u8>>>>()
+                        .map(|frames| FrameRing { frames, next: 0 });
+                    match ring {
+                        Some(ring) => {
+                            frames.insert((k, c), ring);
+                        }
+                        None => fatal!("protocol does not support precomputed requests"),
+                    }
+                }
+            }
+        }
+
         // return the worker
         Ok(Worker {
+            config,
             poll,
             connect_queue,
             connect_ratelimit: None,
             ready_queue,
+            deferred: VecDeque::new(),
             reconnect_ratelimit: None,
             request_ratelimit: None,
+            poisson: match config.request().ratelimit() {
+                Some(rate) if config.request().poisson() => Some(PoissonArrivals::new(
+                    rate as f64 / config.general().threads() as f64,
+                )),
+                _ => None,
+            },
+            command_ratelimits: HashMap::new(),
+            workload_ratelimits: Vec::new(),
+            workload_stats: HashMap::new(),
             sessions,
+            alpn,
             tls,
+            endpoint_tls,
             codec,
             connect_heatmap: None,
+            lifetime_heatmap: None,
             request_heatmap: None,
             request_waterfall: None,
+            value_heatmaps: HashMap::new(),
             pipeline,
+            connects_per_tick,
+            max_connections,
+            deadline,
+            pad_request_to: config.request().pad_request_to(),
+            count_failures_in_rate: config.request().count_failures_in_rate(),
+            due_rate: config
+                .request()
+                .ratelimit()
+                .map(|rate| rate as f64 / config.general().threads() as f64),
+            due_since: None,
+            request_ids: config.request().request_id(),
+            subscribe,
+            verify,
+            verifying: HashMap::new(),
+            verify_timeout: config.connection().verify_timeout(),
+            delivery_heatmap: None,
+            key_update_heatmap: None,
+            response_sizes: None,
+            latency_samples: None,
+            baseline_samples: None,
+            latency_histogram: None,
+            phases: None,
+            rng: SmallRng::from_entropy(),
+            roles: HashMap::new(),
+            pending_write: None,
+            next_command: None,
+            admitted: Vec::with_capacity(pipeline),
+            pending_sequence: None,
+            sequences: HashMap::new(),
+            sequence_heatmaps: HashMap::new(),
+            wait_heatmap: None,
+            previous_commands: Vec::new(),
+            iterations: None,
+            addrs: HashMap::new(),
+            connect_failures: HashMap::new(),
+            queue_warn_depth: connections,
+            queue_warned: false,
+            target_stats: None,
+            command_stats: None,
+            run_latency: None,
+            latency_unit,
+            request_budget: None,
+            budget_complete: None,
+            outstanding: 0,
+            cluster: None,
+            routed_key: None,
+            routed_id: None,
+            redirects: VecDeque::new(),
+            redirected: HashMap::new(),
+            asking: HashSet::new(),
+            resolved: HashMap::new(),
+            frames,
+            max_incomplete_reads,
+            thinking: BinaryHeap::new(),
+            epoch: Instant::now(),
+            delayed_connects: VecDeque::new(),
+            recycle: None,
+            recycle_seen: 0,
+            ready: None,
+            error_log: LogLimit::new("errors", config.debug().log_rate_limit()),
+            slow_requests: config
+                .request()
+                .slow_request_threshold()
+                .map(SlowRequests::new),
         })
     }
 
@@ -120,21 +540,101 @@ impl Worker {
         self.request_ratelimit = ratelimiter;
     }
 
+    /// Provide dedicated ratelimiters for commands, keyed by the index of the
+    /// keyspace and the index of the command within it. Commands without one
+    /// use the request ratelimit.
+    pub fn set_command_ratelimits(
+        &mut self,
+        ratelimiters: HashMap<(usize, usize), Arc<Ratelimiter>>,
+    ) {
+        self.command_ratelimits = ratelimiters;
+    }
+
+    /// Provide the ratelimiter of each workload, by keyspace and workload
+    /// index
+    pub fn set_workload_ratelimits(
+        &mut self,
+        ratelimiters: Vec<((usize, usize), Arc<Ratelimiter>)>,
+    ) {
+        self.workload_ratelimits = ratelimiters;
+    }
+
+    /// Provide the stats of each workload, for each of its commands
+    pub fn set_workload_stats(&mut self, stats: HashMap<(usize, usize), Arc<TargetStats>>) {
+        self.workload_stats = stats;
+    }
+
     /// Provide a heatmap for recording connect latency
     pub fn set_connect_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
         self.connect_heatmap = heatmap;
     }
 
+    /// Provide a heatmap for recording connection lifetime in milliseconds
+    pub fn set_lifetime_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
+        self.lifetime_heatmap = heatmap;
+    }
+
+    /// Provide a heatmap for recording the delivery latency of messages on
+    /// subscribed sessions
+    pub fn set_delivery_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
+        self.delivery_heatmap = heatmap;
+    }
+
+    /// Provide a heatmap for recording the latency of requests sent along with
+    /// a TLS key update
+    pub fn set_key_update_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
+        self.key_update_heatmap = heatmap;
+    }
+
+    /// Record response latency by the size of the response
+    pub fn set_response_sizes(&mut self, sizes: Option<Arc<ResponseSizes>>) {
+        self.response_sizes = sizes;
+    }
+
     /// Provide a heatmap for recording request latency
     pub fn set_request_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
         self.request_heatmap = heatmap;
     }
 
+    /// Provide heatmaps for the latency of each sequence, from sending its
+    /// first command until the response to its last command, by index
+    pub fn set_sequence_heatmaps(
+        &mut self,
+        heatmaps: HashMap<(usize, usize), Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    ) {
+        self.sequence_heatmaps = heatmaps;
+    }
+
+    /// Provide a heatmap for recording the latency of `WAIT` requests
+    pub fn set_wait_heatmap(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
+        self.wait_heatmap = heatmap;
+    }
+
+    /// Provide heatmaps for recording request latency by the length of the
+    /// value sent with the request
+    pub fn set_value_heatmaps(
+        &mut self,
+        heatmaps: HashMap<usize, Arc<AtomicHeatmap<u64, AtomicU64>>>,
+    ) {
+        self.value_heatmaps = heatmaps;
+    }
+
     /// Provide a heatmap for recording request latencies into the waterfall
     pub fn set_request_waterfall(&mut self, heatmap: Option<Arc<AtomicHeatmap<u64, AtomicU64>>>) {
         self.request_waterfall = heatmap;
     }
 
+    /// Provide a file for recording every response latency
+    pub fn set_latency_samples(&mut self, samples: Option<LatencySamples>) {
+        self.latency_samples = samples;
+    }
+
+    /// Provide a reservoir for sampling response latency to compare against a
+    /// baseline run
+    pub fn set_baseline_samples(&mut self, samples: Option<Reservoir>) {
+        self.baseline_samples = samples;
+    }
+
     /// Internal function to connect the session
     fn connect(
         &mut self,
@@ -142,8 +642,62 @@ impl Worker {
         ssl_session: Option<SslSession>,
     ) -> Result<Token, std::io::Error> {
         CONNECT.increment();
-        let stream = TcpStream::connect(addr)?;
-        let mut session = if let Some(tls) = &self.tls {
+        let mut session = match self.config.exec() {
+            Some(command) => {
+                Session::pipe_with_capacity(PipeStream::spawn(command)?, 1024, 512 * 1024)
+            }
+            None => self.tcp_session(addr, ssl_session)?,
+        };
+
+        session.set_key_update_interval(
+            self.config
+                .endpoint_tls(&addr)
+                .and_then(|tls| tls.key_update()),
+        );
+
+        let entry = self.sessions.vacant_entry();
+        let token = Token(entry.key());
+        session.set_token(token);
+        session.set_timestamp(Instant::now());
+        entry.insert(session);
+        self.addrs.insert(token, addr);
+
+        if self.config.routing() {
+            let read = self.config.read_endpoints().contains(&addr);
+            let write = self.config.write_endpoints().contains(&addr);
+            let role = match (read, write) {
+                (true, false) => Role::Read,
+                (false, true) => Role::Write,
+                _ => Role::Any,
+            };
+            self.roles.insert(token, role);
+        }
+
+        Ok(token)
+    }
+
+    /// Open a TCP connection to the address, with TLS if it is configured
+    fn tcp_session(
+        &self,
+        addr: SocketAddr,
+        ssl_session: Option<SslSession>,
+    ) -> Result<Session, std::io::Error> {
+        let mut stream = TcpStream::connect(addr)?;
+        let connection = self.config.connection();
+        if connection.tcp_nodelay() {
+            stream.set_nodelay(true)?;
+        }
+        if connection.tcp_quickack() {
+            stream.set_quickack(true)?;
+        }
+        if connection.tcp_cork() {
+            stream.set_cork(true)?;
+        }
+        let tls = match self.endpoint_tls.get(&addr) {
+            Some(tls) => tls.as_ref(),
+            None => self.tls.as_ref(),
+        };
+        let session = if let Some(tls) = tls {
             if let Ok(mut connect_config) = tls.configure() {
                 if let Some(ssl_session) = ssl_session {
                     unsafe {
@@ -153,11 +707,22 @@ impl Worker {
                     }
                 }
 
-                match connect_config.connect("localhost", stream) {
+                let sni = self
+                    .config
+                    .endpoint_tls(&addr)
+                    .and_then(|tls| tls.sni())
+                    .unwrap_or_else(|| "localhost".to_string());
+                match connect_config.connect(&sni, stream) {
                     Ok(stream) => {
                         if stream.ssl().session_reused() {
                             SESSION_REUSE.increment();
                         }
+                        if self.alpn {
+                            info!(
+                                "alpn: negotiated: {}",
+                                alpn_name(stream.ssl().selected_alpn_protocol())
+                            );
+                        }
                         Session::tls_with_capacity(stream, 1024, 512 * 1024)
                     }
                     Err(HandshakeError::WouldBlock(stream)) => {
@@ -176,29 +741,194 @@ impl Worker {
         } else {
             Session::plain_with_capacity(stream, 1024, 512 * 1024)
         };
-
-        let entry = self.sessions.vacant_entry();
-        let token = Token(entry.key());
-        session.set_token(token);
-        session.set_timestamp(Instant::now());
-        entry.insert(session);
-        Ok(token)
+        Ok(session)
     }
 
     /// Internal function to disconnect the session
     fn disconnect(&mut self, token: Token) -> Result<(), std::io::Error> {
+        self.close(token, true)
+    }
+
+    /// Internal function to handle a failed connect for the session. The
+    /// address is re-queued unless it has been abandoned and dropped.
+    fn connect_failed(&mut self, token: Token) -> Result<(), std::io::Error> {
+        CONNECT_EX.increment();
+        // an error pending on the socket gives the reason
+        match get_session!(self, token).map(|session| session.take_error()) {
+            Ok(Ok(Some(e))) => record_connect_error(&e),
+            _ => {
+                CONNECT_EX_OTHER.increment();
+            }
+        }
+        self.requeue_failed(token)
+    }
+
+    /// Internal function to handle a session which failed the tls handshake
+    fn handshake_failed(&mut self, token: Token) -> Result<(), std::io::Error> {
+        CONNECT_EX.increment();
+        CONNECT_EX_TLS.increment();
+        self.requeue_failed(token)
+    }
+
+    /// Close a session which failed to connect, re-queueing its address
+    /// unless it has been abandoned
+    fn requeue_failed(&mut self, token: Token) -> Result<(), std::io::Error> {
+        let requeue = match self.addrs.get(&token).copied() {
+            Some(addr) => self.record_connect_failure(addr),
+            None => true,
+        };
+        self.close(token, requeue)
+    }
+
+    /// Internal function to close the session, optionally returning its
+    /// address to the connect queue
+    fn close(&mut self, token: Token, requeue: bool) -> Result<(), std::io::Error> {
         OPEN.decrement();
+        self.verifying.remove(&token);
+        self.deferred.retain(|t| *t != token);
+        self.redirected.remove(&token);
+        self.asking.remove(&token);
+        self.sequences.remove(&token);
+        self.roles.remove(&token);
+        self.ready_queue.retain(|t| *t != token);
+        self.thinking.retain(|Reverse((_, key))| *key != token.0);
         let session = get_session_mut!(self, token)?;
         let _ = session.deregister(&self.poll);
         let peer_addr = session.peer_addr();
         let ssl_session = session.ssl_session();
+        if let (Some(heatmap), Some(established)) = (&self.lifetime_heatmap, session.established())
+        {
+            let now = Instant::now();
+            heatmap.increment(now, (now - established).as_nanos() as u64 / 1_000_000, 1);
+        }
         session.close();
-        if let Ok(addr) = peer_addr {
+        self.outstanding -= session.outstanding();
+        // free the slot, so the slab only holds live sessions
+        self.sessions.remove(token.0);
+        let addr = self.addrs.remove(&token).or_else(|| peer_addr.ok());
+        if let (Some(addr), true) = (addr, requeue) {
             self.connect_queue.push_back((addr, ssl_session));
+            self.check_queue_depth();
         }
         Ok(())
     }
 
+    /// Internal function to handle the server closing the session. With a
+    /// reconnect delay, the address only returns to the connect queue once
+    /// the delay has elapsed.
+    fn server_closed(&mut self, token: Token) -> Result<(), std::io::Error> {
+        let delay = match self.config.connection().reconnect_delay() {
+            Some(delay) => delay.as_nanos() as u64,
+            None => return self.disconnect(token),
+        };
+        let session = get_session_mut!(self, token)?;
+        let peer_addr = session.peer_addr();
+        let ssl_session = session.ssl_session();
+        let addr = self.addrs.get(&token).copied().or_else(|| peer_addr.ok());
+        self.close(token, false)?;
+        if let Some(addr) = addr {
+            let wake = self.clock() + delay;
+            self.delayed_connects.push_back((wake, addr, ssl_session));
+        }
+        Ok(())
+    }
+
+    /// Move addresses whose reconnect delay has elapsed to the connect queue
+    fn wake_delayed_connects(&mut self) {
+        let now = self.clock();
+        while let Some((wake, _, _)) = self.delayed_connects.front() {
+            if *wake > now {
+                break;
+            }
+            if let Some((_, addr, ssl_session)) = self.delayed_connects.pop_front() {
+                self.connect_queue.push_back((addr, ssl_session));
+            }
+        }
+    }
+
+    /// Provide the window counter which triggers recycling a fraction of the
+    /// established sessions at the start of each window
+    pub fn set_recycle(&mut self, windows: Arc<AtomicUsize>) {
+        self.recycle_seen = windows.load(Ordering::Relaxed);
+        self.recycle = Some(windows);
+    }
+
+    /// Provide the flag which is set once the minimum fraction of connections
+    /// are established, holding back response latencies until then
+    pub fn set_ready(&mut self, ready: Option<Arc<AtomicBool>>) {
+        self.ready = ready;
+    }
+
+    /// Cap the number of connections this worker holds open
+    pub fn set_max_connections(&mut self, max: Option<usize>) {
+        self.max_connections = max;
+    }
+
+    /// Once a new window starts, close a fraction of the established sessions
+    /// for reconnect. Only idle sessions are closed, so no requests are lost.
+    fn do_recycle(&mut self) {
+        let windows = match self.recycle {
+            Some(ref windows) => windows.load(Ordering::Relaxed),
+            None => return,
+        };
+        if windows == self.recycle_seen {
+            return;
+        }
+        self.recycle_seen = windows;
+
+        let fraction = self
+            .config
+            .connection()
+            .recycle_per_window_fraction()
+            .unwrap_or(0.0);
+        let established = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| !session.is_connecting())
+            .count();
+        let count = (established as f64 * fraction).round() as usize;
+        for _ in 0..count {
+            match self.ready_queue.pop_front() {
+                Some(token) => {
+                    RECYCLED.increment();
+                    let _ = self.disconnect(token);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Track consecutive connect failures for an address. Returns false if the
+    /// address has been abandoned and should not be retried.
+    fn record_connect_failure(&mut self, addr: SocketAddr) -> bool {
+        let retries = match self.config.connection().connect_retries() {
+            Some(retries) => retries,
+            None => {
+                return true;
+            }
+        };
+        let failures = self.connect_failures.entry(addr).or_insert(0);
+        *failures += 1;
+        if *failures < retries {
+            return true;
+        }
+        self.connect_failures.remove(&addr);
+        CONNECTIONS_ABANDONED.increment();
+        if self.config.connection().drop_abandoned() {
+            warn!(
+                "dropping endpoint: {} after {} consecutive connect failures",
+                addr, retries
+            );
+            false
+        } else {
+            warn!(
+                "endpoint: {} had {} consecutive connect failures",
+                addr, retries
+            );
+            true
+        }
+    }
+
     /// Check if the session is connecting
     fn is_connecting(&self, token: Token) -> Result<bool, Error> {
         let session = get_session!(self, token)?;
@@ -224,6 +954,51 @@ impl Worker {
         session.do_handshake()
     }
 
+    /// Record the TCP connect phase on the first event for a connecting
+    /// session. For TLS sessions, this is also when the handshake starts.
+    fn tcp_connected(&mut self, token: Token) {
+        let phases = match self.phases {
+            Some(ref phases) => phases,
+            None => return,
+        };
+        if let Some(session) = self.sessions.get_mut(token.0) {
+            if session.handshake_started().is_none() {
+                let now = Instant::now();
+                phases.tcp_connect((now - session.timestamp()).as_nanos() as u64);
+                session.set_handshake_started(Some(now));
+            }
+        }
+    }
+
+    /// Record the TLS handshake phase once the handshake completes
+    fn handshake_complete(&self, token: Token) {
+        let phases = match self.phases {
+            Some(ref phases) => phases,
+            None => return,
+        };
+        if let Some(started) = self
+            .sessions
+            .get(token.0)
+            .and_then(|session| session.handshake_started())
+        {
+            phases.tls_handshake((Instant::now() - started).as_nanos() as u64);
+        }
+    }
+
+    /// Log the protocol negotiated with ALPN once the handshake completes
+    fn log_alpn(&self, token: Token) {
+        if !self.alpn {
+            return;
+        }
+        if let Some(session) = self.sessions.get(token.0) {
+            info!(
+                "alpn: {:?} negotiated: {}",
+                session,
+                alpn_name(session.alpn_protocol().as_deref())
+            );
+        }
+    }
+
     /// Register the token with the event loop
     fn register(&mut self, token: Token) -> Result<(), Error> {
         let session = get_session_mut!(self, token)?;
@@ -242,116 +1017,1199 @@ impl Worker {
         Ok(session.timestamp())
     }
 
-    /// Generate and send a request over the session
-    fn send_request(&mut self, token: Token, count: usize) -> Result<(), Error> {
-        let session = get_session_mut!(self, token)?;
-        for _ in 0..count {
-            REQUEST.increment();
-            self.codec.encode(session);
+    /// Add the session to the back of the ready queue. Once the queue has
+    /// reached its configured cap, the session is deferred until there is
+    /// room, so that it is not lost.
+    fn enqueue_ready(&mut self, token: Token) {
+        if let Some(max) = self.config.connection().max_ready_queue() {
+            if self.ready_queue.len() >= max {
+                READY_QUEUE_FULL.increment();
+                self.deferred.push_back(token);
+                return;
+            }
         }
-        session.set_outstanding(count);
-        session.set_timestamp(Instant::now());
-        let _ = session.flush();
-        if session.write_pending() > 0 {
-            self.reregister(token)
-        } else {
-            Ok(())
+        self.ready_queue.push_back(token);
+        self.check_queue_depth();
+    }
+
+    /// Move deferred sessions to the ready queue while it is below its cap
+    fn promote_deferred(&mut self) {
+        let max = match self.config.connection().max_ready_queue() {
+            Some(max) => max,
+            None => return,
+        };
+        while self.ready_queue.len() < max {
+            match self.deferred.pop_front() {
+                Some(token) => self.ready_queue.push_back(token),
+                None => break,
+            }
         }
     }
 
-    /// Handle reading from the session
-    fn do_read(&mut self, token: Token) -> Result<(), Error> {
-        let session = get_session_mut!(self, token)?;
+    /// Nanoseconds since the worker was created
+    fn clock(&self) -> u64 {
+        (Instant::now() - self.epoch).as_nanos() as u64
+    }
 
-        match session.fill_buf().map(|b| b.len()) {
-            Ok(0) => {
-                // server hangup
-                Err(Error::new(ErrorKind::Other, "server hangup"))
+    /// Sample the pause before the next request on a session, in nanoseconds,
+    /// if think time is configured
+    fn think_time(&mut self) -> Option<u64> {
+        if let Some(times) = self.config.think_times() {
+            return Some(times.sample(&mut self.rng));
+        }
+        let mean = self.config.request().think_time()?.as_nanos() as u64;
+        let pause = match self.config.request().think_time_model() {
+            ThinkTimeModel::Fixed => mean,
+            ThinkTimeModel::Uniform => self.rng.gen_range(0..=mean * 2),
+            ThinkTimeModel::Exponential => {
+                Exp::new(1.0 / mean as f64).unwrap().sample(&mut self.rng) as u64
             }
-            Ok(_) => {
-                // request parsing
-                while session.outstanding() > 0 {
-                    let response = self.codec.decode(session);
-                    match response {
-                        Ok(()) => {
-                            session.set_outstanding(session.outstanding() - 1);
-                            RESPONSE.increment();
-                            if let Some(ref heatmap) = self.request_heatmap {
-                                let now = Instant::now();
-                                let elapsed = now - session.timestamp();
-                                let us = elapsed.as_nanos() as u64 / 1_000;
-                                heatmap.increment(now, us, 1);
-                                if let Some(ref waterfall) = self.request_waterfall {
-                                    waterfall.increment(now, elapsed.as_nanos() as u64, 1);
-                                }
-                            }
-                        }
-                        Err(e) => match e {
-                            ParseError::Incomplete => {
-                                return Ok(());
-                            }
-                            _ => {
-                                return Err(Error::from(std::io::ErrorKind::InvalidData));
-                            }
-                        },
-                    }
-                }
-                self.ready_queue.push_back(token);
-                Ok(())
+        };
+        Some(pause)
+    }
+
+    /// Return a session to the ready queue once its responses are complete,
+    /// after pausing for the think time if it is configured
+    fn response_complete(&mut self, token: Token) {
+        if self.continue_sequence(token) {
+            return;
+        }
+        match self.think_time() {
+            Some(pause) => {
+                let wake = self.clock() + pause;
+                self.thinking.push(Reverse((wake, token.0)));
             }
-            Err(e) => {
-                match e.kind() {
-                    ErrorKind::WouldBlock => {
-                        // spurious read
-                        let _ = self.reregister(token);
-                        Ok(())
-                    }
-                    ErrorKind::Interrupted => self.do_read(token),
-                    _ => {
-                        trace!("error reading for session: {:?} {:?}", session, e);
-                        Err(e)
-                    }
-                }
+            None => self.enqueue_ready(token),
+        }
+    }
+
+    /// Send the next commands of the sequence in progress on the session, if
+    /// any remain. Once the last response arrives, the latency of the whole
+    /// sequence is recorded. Returns true if more commands were sent.
+    fn continue_sequence(&mut self, token: Token) -> bool {
+        let (index, next) = match self.sequences.get(&token) {
+            Some(state) => (state.index, state.next),
+            None => return false,
+        };
+        let steps = self.config.sequence(index).unwrap_or(&[]);
+        if next < steps.len() {
+            let end = std::cmp::min(next + self.pipeline, steps.len());
+            let batch = steps[next..end].iter().map(|c| (index.0, *c)).collect();
+            if let Some(state) = self.sequences.get_mut(&token) {
+                state.next = end;
+            }
+            // commands admitted for other sessions wait until this is sent
+            let admitted = std::mem::replace(&mut self.admitted, batch);
+            if self.send_request(token).is_err() {
+                let _ = self.disconnect(token);
+            }
+            self.admitted = admitted;
+            return true;
+        }
+        if let Some(state) = self.sequences.remove(&token) {
+            if let Some(heatmap) = self.sequence_heatmaps.get(&index) {
+                let now = Instant::now();
+                let elapsed = (now - state.started).as_nanos() as u64;
+                heatmap.increment(now, self.latency_unit.convert(elapsed), 1);
             }
         }
+        false
     }
 
-    /// Handle writing to the session
-    fn do_write(&mut self, token: Token) -> Result<(), Error> {
-        let session = get_session_mut!(self, token)?;
-        if session.write_pending() > 0 {
-            session.flush()?;
+    /// Move sessions which have finished thinking to the ready queue
+    fn wake_thinking(&mut self) {
+        let now = self.clock();
+        while let Some(Reverse((wake, key))) = self.thinking.peek().copied() {
+            if wake > now {
+                break;
+            }
+            self.thinking.pop();
+            if self.sessions.contains(key) {
+                self.enqueue_ready(Token(key));
+            }
         }
-        Ok(())
     }
 
-    /// Starts the worker event loop. Typically used in a child thread.
-    pub fn run(&mut self) {
-        let mut events = Events::with_capacity(1024);
-        let mut credits = 0;
+    /// Warn once when the ready or connect queue grows beyond the number of
+    /// connections in the pool, which indicates the worker is stuck
+    fn check_queue_depth(&mut self) {
+        let depth = std::cmp::max(self.ready_queue.len(), self.connect_queue.len());
+        if depth > self.queue_warn_depth {
+            if !self.queue_warned {
+                warn!(
+                    "queue depth: {} exceeds pool size: {}, ready: {} connect: {}",
+                    depth,
+                    self.queue_warn_depth,
+                    self.ready_queue.len(),
+                    self.connect_queue.len()
+                );
+                self.queue_warned = true;
+            }
+        } else {
+            self.queue_warned = false;
+        }
+    }
 
-        loop {
+    /// Route requests by the cluster slot of their key. The connection pool is
+    /// replaced with connections to each primary in the slot map.
+    pub fn set_cluster(&mut self, slots: SlotMap) {
+        self.connect_queue.clear();
+        for endpoint in slots.endpoints() {
+            self.connect_endpoint(endpoint);
+        }
+        self.cluster = Some(slots);
+    }
+
+    /// Pin the worker to a single endpoint, replacing its connection pool with
+    /// one to only that endpoint
+    pub fn set_endpoint(&mut self, endpoint: SocketAddr) {
+        self.connect_queue.clear();
+        self.connect_endpoint(endpoint);
+    }
+
+    /// Queue a pool of connections to the endpoint
+    fn connect_endpoint(&mut self, endpoint: SocketAddr) {
+        for _ in 0..self.config.connection().poolsize() {
+            self.connect_queue.push_back((endpoint, None));
+        }
+    }
+
+    /// Update the slot map from a MOVED redirect
+    fn moved(&mut self, slot: u16, endpoint: SocketAddr) {
+        if let Some(ref mut slots) = self.cluster {
+            slots.update(slot, endpoint);
+        }
+    }
+
+    /// Queue a pool of connections to a cluster node, unless there are
+    /// sessions to it already
+    fn connect_node(&mut self, endpoint: SocketAddr) {
+        if self.addrs.values().any(|addr| *addr == endpoint)
+            || self.connect_queue.iter().any(|(addr, _)| *addr == endpoint)
+        {
+            return;
+        }
+        debug!("cluster: connecting to node: {}", endpoint);
+        self.connect_endpoint(endpoint);
+    }
+
+    /// Resolve the endpoint given in a redirect, remembering it so that the
+    /// host is only looked up once
+    fn resolve(&mut self, endpoint: &str) -> Option<SocketAddr> {
+        if let Some(addr) = self.resolved.get(endpoint) {
+            return Some(*addr);
+        }
+        let addr = crate::cluster::resolve(endpoint)?;
+        self.resolved.insert(endpoint.to_string(), addr);
+        Some(addr)
+    }
+
+    /// Queue a request which was redirected by a cluster node to be sent to
+    /// the node serving its key. A MOVED redirect also updates the slot map.
+    fn redirect(
+        &mut self,
+        token: Token,
+        error: RedisError,
+        request: Option<Vec<u8>>,
+        command: Option<(usize, usize)>,
+    ) {
+        let (slot, endpoint, asking) = match error {
+            RedisError::Moved { slot, endpoint } => (slot, endpoint, false),
+            RedisError::Ask { slot, endpoint } => (slot, endpoint, true),
+            _ => return,
+        };
+        let addr = match self.resolve(&endpoint) {
+            Some(addr) => addr,
+            None => {
+                if self.error_log.allow() {
+                    warn!("cluster: failed to resolve: {}", endpoint);
+                }
+                return;
+            }
+        };
+        if !asking {
+            self.moved(slot, addr);
+        }
+        let attempts = self.redirected.get(&token).copied().unwrap_or(0) + 1;
+        let request = match request {
+            Some(request) if attempts <= MAX_REDIRECTS => request,
+            _ => {
+                REQUEST_EX.increment();
+                return;
+            }
+        };
+        self.connect_node(addr);
+        self.redirects.push_back(Redirect {
+            endpoint: addr,
+            asking,
+            request,
+            command,
+            attempts,
+        });
+    }
+
+    /// Send each redirected request on a ready session to the node serving
+    /// it. Requests for nodes without a ready session wait for one.
+    fn send_redirects(&mut self) {
+        for _ in 0..self.redirects.len() {
+            let redirect = match self.redirects.pop_front() {
+                Some(redirect) => redirect,
+                None => return,
+            };
+            let addrs = &self.addrs;
+            let position = self
+                .ready_queue
+                .iter()
+                .position(|token| addrs.get(token) == Some(&redirect.endpoint));
+            match position.and_then(|position| self.ready_queue.remove(position)) {
+                Some(token) => {
+                    if self.send_redirect(token, redirect).is_err() {
+                        let _ = self.disconnect(token);
+                    }
+                }
+                None => self.redirects.push_back(redirect),
+            }
+        }
+    }
+
+    /// Send a redirected request over the session, preceded by an `ASKING`
+    /// if the slot is migrating. Its latency is measured from the retry.
+    fn send_redirect(&mut self, token: Token, redirect: Redirect) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+        REQUEST.increment();
+        REQUEST_REDIRECT.increment();
+        if let Some(ref stats) = self.target_stats {
+            stats.increment_request();
+        }
+        if let Some(index) = redirect.command {
+            if let Some(ref stats) = self.command_stats {
+                stats.increment_request(index);
+            }
+            if let Some(stats) = self.workload_stats.get(&index) {
+                stats.increment_request();
+            }
+            session.push_command(index);
+        }
+        if !self.value_heatmaps.is_empty() {
+            session.push_value_length(None);
+        }
+        if redirect.asking {
+            Redis::asking(session);
+            self.asking.insert(token);
+        }
+        session.write_all(&redirect.request)?;
+        session.push_request(redirect.request);
+        self.redirected.insert(token, redirect.attempts);
+        self.outstanding = self.outstanding + 1 - session.outstanding();
+        session.set_outstanding(1);
+        session.set_timestamp(Instant::now());
+        session.set_first_byte(None);
+        session.set_last_read(Instant::now());
+        let _ = session.flush();
+        if session.write_pending() > 0 {
+            self.reregister(token)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Choose the next command, remembering it in its keyspace so that a
+    /// markov model can choose the one which follows it
+    fn choose_command_index(&mut self, write: Option<bool>) -> (usize, usize) {
+        let index =
+            self.config
+                .choose_command_index_after(&mut self.rng, write, &self.previous_commands);
+        if self.config.keyspaces()[index.0].has_transitions() {
+            if self.previous_commands.len() <= index.0 {
+                self.previous_commands.resize(index.0 + 1, None);
+            }
+            self.previous_commands[index.0] = Some(index.1);
+        }
+        index
+    }
+
+    /// Take a ready session connected to the primary for the key of the next
+    /// command. The key is chosen here and kept until the command is sent, so
+    /// the request is routed by the key it carries. While the primary has
+    /// sessions which are busy, this waits for one of them. If the slot has no
+    /// known primary, or there are no sessions to it, any ready session is
+    /// used and the request follows the redirect.
+    fn next_ready_cluster(&mut self) -> Option<Token> {
+        let index = match self.next_command {
+            Some(index) => index,
+            None => {
+                let index = self.choose_command_index(None);
+                self.next_command = Some(index);
+                index
+            }
+        };
+        if self.routed_key.is_none() {
+            // the key is tagged with the id of the request before routing, so
+            // that the request is routed by the key it carries
+            let mut request = RequestContext::default();
+            if self.request_ids {
+                let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+                request.set_id(Some(id));
+                self.routed_id = Some(id);
+            }
+            let keyspace = &self.config.keyspaces()[index.0];
+            let key = keyspace.generate_key(&mut self.rng, &mut request);
+            self.routed_key = Some(key);
+        }
+        let endpoint = match (&self.cluster, &self.routed_key) {
+            (Some(slots), Some(key)) => slots.endpoint(crate::cluster::slot(key)),
+            _ => None,
+        };
+
+        let position = match endpoint {
+            Some(endpoint) => {
+                let addrs = &self.addrs;
+                match self
+                    .ready_queue
+                    .iter()
+                    .position(|token| addrs.get(token) == Some(&endpoint))
+                {
+                    Some(position) => position,
+                    None if addrs.values().any(|addr| *addr == endpoint) => return None,
+                    None => {
+                        self.connect_node(endpoint);
+                        0
+                    }
+                }
+            }
+            None => 0,
+        };
+        self.ready_queue.remove(position)
+    }
+
+    /// Take the next ready session. When reads and writes are routed to
+    /// separate endpoints, this chooses whether the next request is a read or
+    /// a write and skips over sessions which cannot carry it.
+    fn next_ready(&mut self) -> Option<Token> {
+        if self.cluster.is_some() {
+            return self.next_ready_cluster();
+        }
+
+        if !self.config.routing() {
+            return self.ready_queue.pop_front();
+        }
+
+        let write = match self.pending_write {
+            Some(write) => write,
+            None => {
+                let (_, command) = self.config.choose_command(&mut self.rng, None);
+                let write = command.verb().is_write();
+                self.pending_write = Some(write);
+                write
+            }
+        };
+
+        let roles = &self.roles;
+        let position = self
+            .ready_queue
+            .iter()
+            .position(|token| match roles.get(token) {
+                Some(Role::Read) => !write,
+                Some(Role::Write) => write,
+                Some(Role::Any) | None => true,
+            })?;
+        self.ready_queue.remove(position)
+    }
+
+    /// Generate and send a request over the session
+    fn send_request(&mut self, token: Token) -> Result<(), Error> {
+        // the commands of a sequence share one key
+        let sequence_key = match self.pending_sequence.take() {
+            Some(index) => {
+                let key = match self.routed_key.take() {
+                    Some(key) => key,
+                    None => self.config.keyspaces()[index.0]
+                        .generate_key(&mut self.rng, &mut RequestContext::default()),
+                };
+                self.sequences.insert(
+                    token,
+                    SequenceState {
+                        index,
+                        next: self.admitted.len(),
+                        key: key.clone(),
+                        started: Instant::now(),
+                    },
+                );
+                Some(key)
+            }
+            None => self.sequences.get(&token).map(|state| state.key.clone()),
+        };
+        self.send_commands(token, sequence_key)
+    }
+
+    /// Encode the admitted commands and send them over the session. Each
+    /// command of a sequence uses the key of the sequence as its first key.
+    fn send_commands(&mut self, token: Token, sequence_key: Option<Vec<u8>>) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+        self.pending_write = None;
+        if session.update_keys_if_due() {
+            TLS_KEY_UPDATE.increment();
+        }
+        let count = self.admitted.len();
+        // the first command carries the key it was routed by, and the id
+        // the key is tagged with
+        let mut routed_key = self.routed_key.take();
+        let mut routed_id = self.routed_id.take();
+        for index in self.admitted.drain(..) {
+            REQUEST.increment();
+            if let Some(ref stats) = self.target_stats {
+                stats.increment_request();
+            }
+            if let Some(ref stats) = self.command_stats {
+                stats.increment_request(index);
+            }
+            if let Some(stats) = self.workload_stats.get(&index) {
+                stats.increment_request();
+            }
+            if self.command_stats.is_some()
+                || self.wait_heatmap.is_some()
+                || !self.workload_stats.is_empty()
+                || self.run_latency.is_some()
+            {
+                session.push_command(index);
+            }
+            let (keyspace, command) = self.config.command(index);
+            let start = session.write_pending();
+            if let Some(ring) = self.frames.get_mut(&index) {
+                self.codec
+                    .send_frame(session, keyspace, command, ring.next());
+                if !self.value_heatmaps.is_empty() {
+                    session.push_value_length(None);
+                }
+            } else {
+                let id = if self.request_ids {
+                    let id = routed_id
+                        .take()
+                        .unwrap_or_else(|| NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed));
+                    session.push_request_id(id);
+                    Some(id)
+                } else {
+                    None
+                };
+                let key = routed_key.take();
+                let mut request = match sequence_key.clone().or(key) {
+                    Some(key) => RequestContext::with_key(key),
+                    None => RequestContext::default(),
+                };
+                request.set_fill(keyspace.fills(command));
+                request.set_id(id);
+                self.codec.encode_with_deadline(
+                    session,
+                    keyspace,
+                    command,
+                    &mut request,
+                    self.deadline,
+                );
+                if !self.value_heatmaps.is_empty() {
+                    session.push_value_length(request.value_length());
+                }
+            }
+            if let Some(target) = self.pad_request_to {
+                let length = session.write_pending() - start;
+                if length < target {
+                    self.codec.pad(session, target - length);
+                } else if length > target {
+                    REQUEST_UNPADDED.increment();
+                }
+            }
+            // requests are kept to log them if they are slow, or to retry
+            // them on another node if they are redirected
+            if self.slow_requests.is_some() || self.cluster.is_some() {
+                let request = session.write_buffer()[start..].to_vec();
+                session.push_request(request);
+            }
+        }
+        self.outstanding = self.outstanding + count - session.outstanding();
+        session.set_outstanding(count);
+        session.set_timestamp(Instant::now());
+        session.set_first_byte(None);
+        if let Some(connected_at) = session.take_connected_at() {
+            if let Some(ref phases) = self.phases {
+                phases.connect_to_write((Instant::now() - connected_at).as_nanos() as u64);
+            }
+        }
+        session.set_last_read(Instant::now());
+        let _ = session.flush();
+        if session.write_pending() > 0 {
+            self.reregister(token)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Handle reading from the session
+    fn do_read(&mut self, token: Token) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+        let buffered = session.buffer().len();
+
+        match session.fill_buf().map(|b| b.len()) {
+            Ok(0) => {
+                // server hangup
+                if session.outstanding() > 0 {
+                    SERVER_CLOSED_ACTIVE.increment();
+                } else {
+                    SERVER_CLOSED_IDLE.increment();
+                }
+                Err(Error::new(ErrorKind::UnexpectedEof, "server hangup"))
+            }
+            Ok(len) => {
+                if len > buffered {
+                    session.reset_incomplete_reads();
+                } else {
+                    check_stall(session, self.max_incomplete_reads)?;
+                }
+                let now = Instant::now();
+                session.set_last_read(now);
+                if session.outstanding() > 0 && session.first_byte().is_none() {
+                    session.set_first_byte(Some(now));
+                    if let Some(ref phases) = self.phases {
+                        phases.write_to_first_byte((now - session.timestamp()).as_nanos() as u64);
+                    }
+                }
+
+                // request parsing
+                let mut redirects = Vec::new();
+                let mut complete = true;
+                while session.outstanding() > 0 {
+                    // the reply to an `ASKING` precedes the redirected request
+                    if self.asking.contains(&token) {
+                        match Redis::decode_asking(session.buffer()) {
+                            Ok(consumed) => {
+                                session.consume(consumed);
+                                self.asking.remove(&token);
+                            }
+                            Err(ParseError::Incomplete) => {
+                                complete = false;
+                                break;
+                            }
+                            Err(_) => return Err(Error::from(ErrorKind::InvalidData)),
+                        }
+                        continue;
+                    }
+                    let decode_start = self.phases.as_ref().map(|_| Instant::now());
+                    let buffered = session.buffer().len();
+                    // keep the logged prefix of the response only when the
+                    // request is already slow, as decoding consumes it
+                    let slow_response = match self.slow_requests {
+                        Some(ref slow) if Instant::now() - session.timestamp() > slow.threshold => {
+                            let mut prefix = [0; SLOW_REQUEST_LOG_BYTES + 1];
+                            let len = buffered.min(prefix.len());
+                            prefix[..len].copy_from_slice(&session.buffer()[..len]);
+                            Some(prefix)
+                        }
+                        _ => None,
+                    };
+                    let response = self.codec.decode(session);
+                    match response {
+                        Ok(decoded) => {
+                            session.set_outstanding(session.outstanding() - 1);
+                            self.outstanding -= 1;
+                            session.pop_request_id();
+                            RESPONSE.increment();
+                            RESPONSE_BYTE.add((buffered - session.buffer().len()) as _);
+                            if let Some(ref stats) = self.target_stats {
+                                stats.increment_response();
+                            }
+                            let command = if self.command_stats.is_some()
+                                || self.wait_heatmap.is_some()
+                                || !self.workload_stats.is_empty()
+                                || self.run_latency.is_some()
+                            {
+                                session.pop_command()
+                            } else {
+                                None
+                            };
+                            if let Some(stats) = command.and_then(|i| self.workload_stats.get(&i)) {
+                                stats.increment_response();
+                            }
+                            let now = Instant::now();
+                            let elapsed = now - session.timestamp();
+                            let hit = decoded.hit;
+                            if let (Some(stats), Some(index)) = (&self.command_stats, command) {
+                                let value = self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                stats.increment_response(index, hit, now, value);
+                            }
+                            if let Some(ref phases) = self.phases {
+                                if let Some(start) = decode_start {
+                                    phases.decode((now - start).as_nanos() as u64);
+                                }
+                                if let Some(first_byte) = session.first_byte() {
+                                    phases.first_byte_to_complete(
+                                        (now - first_byte).as_nanos() as u64
+                                    );
+                                }
+                            }
+                            if let (Some(heatmap), Some(index)) = (&self.wait_heatmap, command) {
+                                if self.config.command(index).1.verb() == Verb::Wait {
+                                    let value =
+                                        self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                    heatmap.increment(now, value, 1);
+                                }
+                            }
+                            if session.take_key_update_pending() {
+                                if let Some(ref heatmap) = self.key_update_heatmap {
+                                    let value =
+                                        self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                    heatmap.increment(now, value, 1);
+                                }
+                            }
+                            let ready = self
+                                .ready
+                                .as_ref()
+                                .map_or(true, |ready| ready.load(Ordering::Relaxed));
+                            if let (Some((latency, target)), true) = (&self.run_latency, ready) {
+                                let value = self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                latency.record_response(*target, command, now, value);
+                            }
+                            if let (Some(heatmap), true) = (&self.request_heatmap, ready) {
+                                let value = self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                heatmap.increment(now, value, 1);
+                                if let Some(ref waterfall) = self.request_waterfall {
+                                    waterfall.increment(now, elapsed.as_nanos() as u64, 1);
+                                }
+                            }
+                            if !self.value_heatmaps.is_empty() {
+                                let heatmap = session
+                                    .pop_value_length()
+                                    .and_then(|length| self.value_heatmaps.get(&length));
+                                if let Some(heatmap) = heatmap {
+                                    let value =
+                                        self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                    heatmap.increment(now, value, 1);
+                                }
+                            }
+                            if let Some(ref sizes) = self.response_sizes {
+                                let bytes = buffered - session.buffer().len();
+                                sizes.record(bytes, elapsed.as_nanos() as u64);
+                            }
+                            if let Some(ref mut samples) = self.latency_samples {
+                                samples.record(elapsed.as_nanos() as u64);
+                            }
+                            if let Some(ref mut samples) = self.baseline_samples {
+                                samples.record(elapsed.as_nanos() as u64);
+                            }
+                            if let Some(ref histogram) = self.latency_histogram {
+                                // its bucket bounds are fixed in microseconds,
+                                // whatever the latency unit
+                                histogram.record(elapsed.as_nanos() as u64 / 1_000);
+                            }
+                            let request = session.pop_request();
+                            if let Some(ref mut slow) = self.slow_requests {
+                                let request = request.unwrap_or_default();
+                                if let Some(prefix) = slow_response {
+                                    let len = (buffered - session.buffer().len()).min(prefix.len());
+                                    slow.log(
+                                        self.addrs.get(&token),
+                                        elapsed,
+                                        &request,
+                                        &prefix[..len],
+                                    );
+                                }
+                            }
+                        }
+                        Err(e) => match e {
+                            ParseError::Incomplete => {
+                                complete = false;
+                                break;
+                            }
+                            ParseError::Redis(error) => {
+                                // the error reply completes the request
+                                session.set_outstanding(session.outstanding() - 1);
+                                self.outstanding -= 1;
+                                if !self.value_heatmaps.is_empty() {
+                                    session.pop_value_length();
+                                }
+                                if let Some(id) = session.pop_request_id() {
+                                    if self.error_log.allow() {
+                                        warn!("error response: request id: {:x}: {:?}", id, error);
+                                    }
+                                }
+                                let command = if self.command_stats.is_some()
+                                    || self.wait_heatmap.is_some()
+                                    || !self.workload_stats.is_empty()
+                                    || self.run_latency.is_some()
+                                {
+                                    session.pop_command()
+                                } else {
+                                    None
+                                };
+                                if let (Some(stats), Some(index)) = (&self.command_stats, command) {
+                                    stats.increment_error(index);
+                                }
+                                if let Some(stats) =
+                                    command.and_then(|i| self.workload_stats.get(&i))
+                                {
+                                    stats.increment_response();
+                                }
+                                let request = session.pop_request();
+                                RESPONSE.increment();
+                                if let RedisError::Moved { .. } | RedisError::Ask { .. } = error {
+                                    redirects.push((error, request, command));
+                                }
+                            }
+                            _ => {
+                                if let Some(id) = session.pop_request_id() {
+                                    if self.error_log.allow() {
+                                        warn!("invalid response: request id: {:x}", id);
+                                    }
+                                }
+                                return Err(Error::from(std::io::ErrorKind::InvalidData));
+                            }
+                        },
+                    }
+                }
+                for (error, request, command) in redirects {
+                    self.redirect(token, error, request, command);
+                }
+                if complete {
+                    self.redirected.remove(&token);
+                    self.response_complete(token);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                match e.kind() {
+                    ErrorKind::WouldBlock => {
+                        // spurious read
+                        check_stall(session, self.max_incomplete_reads)?;
+                        let _ = self.reregister(token);
+                        Ok(())
+                    }
+                    ErrorKind::Interrupted => self.do_read(token),
+                    _ => {
+                        trace!("error reading for session: {:?} {:?}", session, e);
+                        Err(e)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the subscribe request on a newly connected session. The session
+    /// does not send any other requests.
+    fn send_subscribe(&mut self, token: Token) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+        if let Some(ref frame) = self.subscribe {
+            session.write_all(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Send the verification request on a newly connected session
+    fn send_verify(&mut self, token: Token) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+        if let Some(ref frame) = self.verify {
+            session.write_all(frame)?;
+            self.verifying.insert(token, Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Read the reply to the verification request. Once a valid reply
+    /// arrives, the session is ready for requests.
+    fn do_read_verify(&mut self, token: Token) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+
+        match session.fill_buf().map(|b| b.len()) {
+            Ok(0) => Err(Error::new(ErrorKind::UnexpectedEof, "server hangup")),
+            Ok(_) => {
+                let reply = if self.config.general().protocol() == Protocol::Memcache {
+                    Memcache::decode_version(session.buffer())
+                } else {
+                    Redis::decode_verify(session.buffer())
+                };
+                match reply {
+                    Ok(consumed) => {
+                        session.consume(consumed);
+                        self.verifying.remove(&token);
+                        self.enqueue_ready(token);
+                        Ok(())
+                    }
+                    Err(ParseError::Incomplete) => Ok(()),
+                    Err(_) => Err(Error::from(ErrorKind::InvalidData)),
+                }
+            }
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock => {
+                    // spurious read
+                    let _ = self.reregister(token);
+                    Ok(())
+                }
+                ErrorKind::Interrupted => self.do_read_verify(token),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Read push messages from a subscribed session, counting each published
+    /// message. When the payload starts with the unix time it was published
+    /// at, in nanoseconds, its delivery latency is recorded.
+    fn do_read_messages(&mut self, token: Token) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+
+        match session.fill_buf().map(|b| b.len()) {
+            Ok(0) => {
+                SERVER_CLOSED_IDLE.increment();
+                Err(Error::new(ErrorKind::UnexpectedEof, "server hangup"))
+            }
+            Ok(_) => loop {
+                let (payload, consumed) = match Redis::decode_push(session.buffer()) {
+                    Ok(push) => push,
+                    Err(ParseError::Incomplete) => return Ok(()),
+                    Err(_) => return Err(Error::from(ErrorKind::InvalidData)),
+                };
+                if let Some(payload) = payload {
+                    SUBSCRIBE_MESSAGE.increment();
+                    if let (Some(heatmap), Some(published)) =
+                        (&self.delivery_heatmap, published_at(payload))
+                    {
+                        let now = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .map(|d| d.as_nanos() as u64)
+                            .unwrap_or(0);
+                        let latency = self.latency_unit.convert(now.saturating_sub(published));
+                        heatmap.increment(Instant::now(), latency, 1);
+                    }
+                }
+                session.consume(consumed);
+            },
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock => {
+                    // spurious read
+                    let _ = self.reregister(token);
+                    Ok(())
+                }
+                ErrorKind::Interrupted => self.do_read_messages(token),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Handle writing to the session
+    fn do_write(&mut self, token: Token) -> Result<(), Error> {
+        let session = get_session_mut!(self, token)?;
+        if session.write_pending() > 0 {
+            session.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Choose commands for the next request until the pipeline is full or the
+    /// ratelimit for the next command is exhausted. Returns true when the
+    /// pipeline is full.
+    fn admit_commands(&mut self) -> bool {
+        let mut skipped = 0;
+        while self.admitted.len() < self.pipeline {
+            if self.request_budget == Some(0) {
+                // send any commands admitted before the budget ran out
+                return !self.admitted.is_empty();
+            }
+            if self.next_command.is_none() {
+                if let Some(index) = self.choose_workload_command() {
+                    self.admit(index);
+                    continue;
+                }
+            }
+            let index = match self.next_command {
+                Some(index) => index,
+                None => {
+                    let index = self.choose_command_index(self.pending_write);
+                    self.next_command = Some(index);
+                    index
+                }
+            };
+            // a sequence is sent on a session of its own
+            let sequence = self.config.sequence(index);
+            if sequence.is_some() && !self.admitted.is_empty() {
+                return true;
+            }
+            let admitted = match (self.command_ratelimits.get(&index), &mut self.poisson) {
+                (Some(r), _) => r.try_wait().is_ok(),
+                (None, Some(arrivals)) => arrivals
+                    .try_wait(std::time::Instant::now(), &mut self.rng)
+                    .is_ok(),
+                (None, None) => match self.request_ratelimit {
+                    Some(ref r) => r.try_wait().is_ok(),
+                    None => true,
+                },
+            };
+            if !admitted {
+                // a command whose own ratelimit is exhausted is skipped, so
+                // that it does not hold back the other commands
+                if self.command_ratelimits.contains_key(&index) && skipped < MAX_SKIPPED_COMMANDS {
+                    skipped += 1;
+                    self.next_command = None;
+                    continue;
+                }
+                return false;
+            }
+            self.next_command = None;
+            if let Some(steps) = sequence {
+                let count = std::cmp::min(steps.len(), self.pipeline);
+                self.admitted
+                    .extend(steps[..count].iter().map(|c| (index.0, *c)));
+                self.pending_sequence = Some(index);
+                if let Some(ref mut budget) = self.request_budget {
+                    *budget -= 1;
+                }
+                return true;
+            }
+            self.admit(index);
+        }
+        true
+    }
+
+    /// Add the command to the next request
+    fn admit(&mut self, index: (usize, usize)) {
+        self.admitted.push(index);
+        // each write is followed by a wait for the replicas, if enabled
+        let keyspace = &self.config.keyspaces()[index.0];
+        if let Some(wait) = keyspace.wait_command() {
+            if keyspace.commands()[index.1].verb().is_write() {
+                self.admitted.push((index.0, wait));
+            }
+        }
+        if let Some(ref mut budget) = self.request_budget {
+            *budget -= 1;
+        }
+    }
+
+    /// Choose a command from the first workload whose ratelimit allows another
+    /// request, if any
+    fn choose_workload_command(&mut self) -> Option<(usize, usize)> {
+        for ((k, w), ratelimit) in &self.workload_ratelimits {
+            if ratelimit.try_wait().is_ok() {
+                let workload = &self.config.keyspaces()[*k].workloads()[*w];
+                return Some((*k, workload.choose_command(&mut self.rng)));
+            }
+        }
+        None
+    }
+
+    /// Count the requests which came due at this worker's share of the
+    /// request ratelimit while no connection was ready to send them. Only a
+    /// backlog after the first request is counted, and tokens are not taken
+    /// from the shared ratelimiter so other workers may still send them.
+    fn drop_due_requests(&mut self, now: Instant) {
+        if !self.ready_queue.is_empty() || self.request_budget.is_some() {
+            return;
+        }
+        let since = match self.due_since {
+            Some(since) => since,
+            None => return,
+        };
+        if let Some(ref mut arrivals) = self.poisson {
+            // the arrivals are this worker's own, so those due are consumed.
+            // They are scheduled on the system clock.
+            let now = std::time::Instant::now();
+            while arrivals.try_wait(now, &mut self.rng).is_ok() {
+                REQUEST_DROPPED.increment();
+            }
+        } else if let Some(rate) = self.due_rate {
+            let due = (now.saturating_duration_since(since).as_secs_f64() * rate).floor();
+            if due >= 1.0 {
+                REQUEST_DROPPED.add(due as _);
+                self.due_since = Some(since + std::time::Duration::from_secs_f64(due / rate));
+            }
+        }
+    }
+
+    /// Limit this worker to sending the given number of requests. Once all of
+    /// them have completed, the shared counter is incremented.
+    pub fn set_request_budget(&mut self, budget: u64, complete: Arc<AtomicUsize>) {
+        self.request_budget = Some(budget);
+        self.budget_complete = Some(complete);
+    }
+
+    /// Signal completion once the request budget is spent and there are no
+    /// outstanding responses
+    fn check_budget(&mut self) {
+        if self.request_budget != Some(0) || !self.admitted.is_empty() || self.outstanding > 0 {
+            return;
+        }
+        if let Some(complete) = self.budget_complete.take() {
+            complete.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Provide a histogram which keeps exemplars of response latency
+    pub fn set_latency_histogram(&mut self, histogram: Option<Arc<ExemplarHistogram>>) {
+        self.latency_histogram = histogram;
+    }
+
+    /// Provide heatmaps for recording the latency of each phase of a request
+    pub fn set_phases(&mut self, phases: Option<Arc<Phases>>) {
+        self.phases = phases;
+    }
+
+    /// Provide the stats for the target this worker drives, when there is more
+    /// than one target
+    pub fn set_target_stats(&mut self, stats: Option<Arc<TargetStats>>) {
+        self.target_stats = stats;
+    }
+
+    /// Provide the counts and latency kept for each command of the target
+    pub fn set_command_stats(&mut self, stats: Option<Arc<CommandStats>>) {
+        self.command_stats = stats;
+    }
+
+    /// Record latency over the whole run for the report, along with the index
+    /// of the target this worker drives
+    pub fn set_run_latency(&mut self, latency: Option<(Arc<RunLatency>, usize)>) {
+        self.run_latency = latency;
+    }
+
+    /// Provide a counter for the event loop iterations of this worker
+    pub fn set_iterations(&mut self, iterations: Option<Arc<std::sync::atomic::AtomicU64>>) {
+        self.iterations = iterations;
+    }
+
+    /// Close any sessions which have outstanding responses but have not
+    /// received any bytes within the read idle timeout
+    fn do_read_timeouts(&mut self, timeout: std::time::Duration) {
+        let now = Instant::now();
+        let timeout = timeout.as_nanos() as u64;
+        let expired: Vec<Token> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                session.outstanding() > 0
+                    && (now - session.last_read()).as_nanos() as u64 >= timeout
+            })
+            .map(|(key, _)| Token(key))
+            .collect();
+        for token in expired {
+            READ_IDLE_TIMEOUT.increment();
+            let _ = self.disconnect(token);
+        }
+    }
+
+    /// Fail the connects of any sessions which have not answered the
+    /// verification request within the verify timeout
+    fn do_verify_timeouts(&mut self, now: Instant) {
+        let expired: Vec<Token> = self
+            .verifying
+            .iter()
+            .filter(|(_, sent)| now.saturating_duration_since(**sent) >= self.verify_timeout)
+            .map(|(token, _)| *token)
+            .collect();
+        for token in expired {
+            CONNECT_TIMEOUT.increment();
+            let _ = self.connect_failed(token);
+        }
+    }
+
+    /// Decide whether to inject a failure for the endpoint when chaos mode is
+    /// configured for it
+    fn chaos(&mut self, addr: SocketAddr) -> bool {
+        match self.config.chaos(&addr) {
+            Some(probability) if self.rng.gen_bool(probability) => {
+                CHAOS.increment();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Simulate a response timeout by abandoning the session, if chaos mode
+    /// injects a failure for its endpoint. Outstanding responses are counted
+    /// as errors, as they would be had the server timed out. Returns true if
+    /// the session was abandoned.
+    fn inject_timeout(&mut self, token: Token) -> bool {
+        let addr = match self.addrs.get(&token).copied() {
+            Some(addr) => addr,
+            None => return false,
+        };
+        if !self.chaos(addr) {
+            return false;
+        }
+        debug!("chaos: abandoning session for endpoint: {}", addr);
+        if get_session!(self, token).map_or(false, |session| session.outstanding() > 0) {
+            RESPONSE_EX.increment();
+            READ_IDLE_TIMEOUT.increment();
+        }
+        let _ = self.disconnect(token);
+        true
+    }
+
+    /// Attempt to establish up to `connects_per_tick` connections from the
+    /// connect queue, stopping early if the connect ratelimit is exhausted.
+    fn do_connects(&mut self) {
+        for _ in 0..self.connects_per_tick {
+            // stop opening connections once the cap is reached, the rest stay
+            // queued until a session closes
+            if let Some(max) = self.max_connections {
+                if self.addrs.len() >= max {
+                    return;
+                }
+            }
             if let Some((addr, ssl_session)) = self.connect_queue.pop_front() {
                 let connect = if let Some(r) = &self.connect_ratelimit {
                     r.try_wait().is_ok()
                 } else {
                     true
                 };
-                if connect {
+                if connect && self.chaos(addr) {
+                    // simulate a failed connect attempt
+                    CONNECT.increment();
+                    CONNECT_EX.increment();
+                    CONNECT_EX_OTHER.increment();
+                    if self.record_connect_failure(addr) {
+                        self.connect_queue.push_back((addr, ssl_session));
+                    }
+                } else if connect {
                     match self.connect(addr, ssl_session) {
                         Ok(token) => {
                             self.register(token).unwrap();
                         }
                         Err(e) => {
-                            println!("connect error: {:?} {}", addr, e);
+                            if self.error_log.allow() {
+                                warn!("connect error: {:?} {}", addr, e);
+                            }
+                            CONNECT_EX.increment();
+                            record_connect_error(&e);
+                            if self.record_connect_failure(addr) {
+                                self.connect_queue.push_back((addr, None));
+                            }
                         }
                     }
                 } else {
                     self.connect_queue.push_front((addr, ssl_session));
+                    return;
                 }
+            } else {
+                return;
             }
+        }
+    }
+
+    /// Starts the worker event loop. Typically used in a child thread.
+    pub fn run(&mut self) {
+        let mut events = Events::with_capacity(1024);
+        let read_idle_timeout = self.config.request().read_idle_timeout();
+        let mut last_sweep = Instant::now();
 
-            if let Some(token) = self.ready_queue.pop_front() {
+        loop {
+            if let Some(ref iterations) = self.iterations {
+                iterations.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+
+            self.do_connects();
+            self.wake_thinking();
+            self.wake_delayed_connects();
+            self.error_log.tick();
+            self.do_recycle();
+            self.promote_deferred();
+            if !self.redirects.is_empty() {
+                self.send_redirects();
+            }
+
+            if let Some(token) = self.next_ready() {
                 let reconnect = if let Some(r) = &self.reconnect_ratelimit {
                     r.try_wait().is_ok()
                 } else {
@@ -359,55 +2217,60 @@ impl Worker {
                 };
                 if reconnect {
                     let _ = self.disconnect(token);
-                } else {
-                    if let Some(r) = &self.request_ratelimit {
-                        while r.try_wait().is_ok() {
-                            credits += 1;
-                            if credits == self.pipeline {
-                                break;
-                            }
-                        }
-                    } else {
-                        credits = self.pipeline;
-                    };
-                    if credits == self.pipeline {
-                        credits = 0;
-                        if self.send_request(token, self.pipeline).is_ok() {
-                            // yay, we sent a request
-                        } else if self.disconnect(token).is_ok() {
-                            REQUEST_EX.increment();
-                        } else {
-                            panic!("this shouldn't happen");
+                } else if self.admit_commands() {
+                    if self.send_request(token).is_ok() {
+                        // yay, we sent a request
+                        if self.count_failures_in_rate {
+                            self.due_since = Some(Instant::now());
                         }
+                    } else if self.disconnect(token).is_ok() {
+                        REQUEST_EX.increment();
                     } else {
-                        self.ready_queue.push_front(token)
+                        panic!("this shouldn't happen");
                     }
+                } else {
+                    self.ready_queue.push_front(token)
                 }
+            } else if self.count_failures_in_rate {
+                self.drop_due_requests(Instant::now());
             }
 
+            // wake in time for the next session to finish thinking
+            let timeout = match self.thinking.peek() {
+                Some(Reverse((wake, _))) => wake.saturating_sub(self.clock()).min(10_000_000),
+                None => 10_000_000,
+            };
             let _ = self
                 .poll
-                .poll(&mut events, Some(std::time::Duration::from_millis(10)));
+                .poll(&mut events, Some(std::time::Duration::from_nanos(timeout)));
 
             for event in &events {
                 let token = event.token();
 
+                // the session may have been closed while handling an earlier
+                // event
+                if !self.sessions.contains(token.0) {
+                    continue;
+                }
+
                 // handle error events first
                 if event.is_error() {
+                    // increment_counter!(&Stat::WorkerEventError);
                     if self.is_connecting(token).unwrap() {
-                        CONNECT_EX.increment();
+                        let _ = self.connect_failed(token);
+                    } else {
+                        let _ = self.disconnect(token);
                     }
-                    // increment_counter!(&Stat::WorkerEventError);
-                    let _ = self.disconnect(token);
                     continue;
                 }
 
                 // handle handshaking
                 if let Ok(true) = self.is_handshaking(token) {
+                    self.tcp_connected(token);
                     if let Err(e) = self.handshake(token) {
                         if e.kind() != ErrorKind::WouldBlock {
-                            CONNECT_EX.increment();
-                            let _ = self.disconnect(token);
+                            let _ = self.handshake_failed(token);
+                            continue;
                         }
                     }
                     match self.is_handshaking(token) {
@@ -417,37 +2280,83 @@ impl Worker {
                         }
                         Ok(false) => {
                             // finished handshaking
+                            self.handshake_complete(token);
+                            self.log_alpn(token);
                         }
                         Err(_) => {
-                            CONNECT_EX.increment();
-                            let _ = self.disconnect(token);
+                            let _ = self.connect_failed(token);
                             continue;
                         }
                     }
                 }
 
-                if event.is_readable() && self.do_read(token).is_err() {
-                    let _ = self.disconnect(token);
+                if event.is_readable()
+                    && !self.is_connecting(token).unwrap_or(true)
+                    && self.inject_timeout(token)
+                {
                     continue;
                 }
 
+                if event.is_readable() && self.verifying.contains_key(&token) {
+                    if self.do_read_verify(token).is_err() {
+                        let _ = self.connect_failed(token);
+                        continue;
+                    }
+                } else if event.is_readable() {
+                    let result = if self.subscribe.is_some() {
+                        self.do_read_messages(token)
+                    } else {
+                        self.do_read(token)
+                    };
+                    if let Err(e) = result {
+                        if e.kind() == ErrorKind::UnexpectedEof {
+                            let _ = self.server_closed(token);
+                        } else {
+                            let _ = self.disconnect(token);
+                        }
+                        continue;
+                    }
+                }
+
                 if event.is_writable() {
                     trace!("got writable for token: {:?}", token);
                     let connecting = self.is_connecting(token).unwrap();
                     let handshaking = self.is_handshaking(token).unwrap();
                     if connecting && !handshaking {
+                        self.tcp_connected(token);
                         self.connected(token).unwrap();
                         OPEN.increment();
                         SESSION.increment();
+                        if let Some(addr) = self.addrs.get(&token) {
+                            self.connect_failures.remove(addr);
+                        }
                         if let Ok(prev) = self.timestamp(token) {
                             if let Some(ref heatmap) = self.connect_heatmap {
                                 let now = Instant::now();
                                 let elapsed = now - prev;
-                                let us = elapsed.as_nanos() as u64 / 1_000;
-                                heatmap.increment(now, us, 1);
+                                let value = self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                heatmap.increment(now, value, 1);
                             }
+                            if let Some((ref latency, _)) = self.run_latency {
+                                let now = Instant::now();
+                                let elapsed = now - prev;
+                                let value = self.latency_unit.convert(elapsed.as_nanos() as u64);
+                                latency.record_connect(now, value);
+                            }
+                        }
+                        if self.subscribe.is_some() {
+                            if self.send_subscribe(token).is_err() {
+                                let _ = self.disconnect(token);
+                                continue;
+                            }
+                        } else if self.verify.is_some() {
+                            if self.send_verify(token).is_err() {
+                                let _ = self.connect_failed(token);
+                                continue;
+                            }
+                        } else {
+                            self.enqueue_ready(token);
                         }
-                        self.ready_queue.push_back(token);
                     } else if connecting {
                         OPEN.increment();
                     }
@@ -459,10 +2368,66 @@ impl Worker {
 
                 let _ = self.reregister(token);
             }
+
+            if self.budget_complete.is_some() {
+                self.check_budget();
+            }
+
+            let now = Instant::now();
+            if (now - last_sweep).as_nanos() as u64 >= 1_000_000 {
+                last_sweep = now;
+                if let Some(timeout) = read_idle_timeout {
+                    self.do_read_timeouts(timeout);
+                }
+                if !self.verifying.is_empty() {
+                    self.do_verify_timeouts(now);
+                }
+            }
         }
     }
 }
 
+/// The publish time of a message, when its payload starts with the unix time
+/// in nanoseconds
+fn published_at(payload: &[u8]) -> Option<u64> {
+    let digits = payload.iter().take_while(|b| b.is_ascii_digit()).count();
+    std::str::from_utf8(&payload[..digits]).ok()?.parse().ok()
+}
+
+/// A printable name for the protocol negotiated with ALPN, if any
+fn alpn_name(protocol: Option<&[u8]>) -> String {
+    match protocol {
+        Some(protocol) => String::from_utf8_lossy(protocol).into_owned(),
+        None => "none".to_string(),
+    }
+}
+
+/// Count a failed connect by the reason the socket gave for it
+fn record_connect_error(e: &Error) {
+    match e.raw_os_error() {
+        Some(libc::ECONNREFUSED) => CONNECT_EX_REFUSED.increment(),
+        Some(libc::ETIMEDOUT) => CONNECT_EX_TIMEDOUT.increment(),
+        Some(libc::EADDRNOTAVAIL) => CONNECT_EX_ADDRNOTAVAIL.increment(),
+        Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH) => CONNECT_EX_UNREACHABLE.increment(),
+        Some(libc::ECONNRESET) => CONNECT_EX_RESET.increment(),
+        Some(libc::EMFILE) | Some(libc::ENFILE) => CONNECT_EX_NOFILE.increment(),
+        _ => CONNECT_EX_OTHER.increment(),
+    };
+}
+
+/// Count a read which added no bytes while responses are outstanding, failing
+/// once there are too many in a row without progress
+fn check_stall(session: &mut Session, max: Option<usize>) -> Result<(), Error> {
+    if let Some(max) = max {
+        if session.outstanding() > 0 && session.incomplete_read() >= max {
+            RESPONSE_EX.increment();
+            RESPONSE_STALL.increment();
+            return Err(Error::new(ErrorKind::Other, "too many incomplete reads"));
+        }
+    }
+    Ok(())
+}
+
 pub fn ssl_connector(config: &Tls) -> Result<Option<SslConnector>, std::io::Error> {
     let mut builder = SslConnector::builder(SslMethod::tls_client())?;
     if !config.verify() {
@@ -545,5 +2510,503 @@ pub fn ssl_connector(config: &Tls) -> Result<Option<SslConnector>, std::io::Erro
         builder.set_session_cache_size(size);
     }
 
+    if !config.alpn().is_empty() {
+        // the wire format is a length-prefixed list of protocols
+        let mut protocols = Vec::new();
+        for protocol in config.alpn() {
+            protocols.push(protocol.len() as u8);
+            protocols.extend_from_slice(protocol.as_bytes());
+        }
+        builder
+            .set_alpn_protos(&protocols)
+            .map_err(|_| Error::new(ErrorKind::Other, "bad alpn protocols"))?;
+    }
+
     Ok(Some(builder.build()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLUSTER: &str = r#"
+        [general]
+        protocol = "redis_resp"
+
+        [target]
+        endpoints = ["127.0.0.1:7000"]
+        cluster = true
+
+        [[keyspace]]
+        commands = [{ verb = "get" }]
+        length = 8
+        cardinality = 1000
+        key_type = "u32"
+        "#;
+
+    fn worker(config: &str) -> Worker {
+        Worker::new(Arc::new(Config::parse(config))).unwrap()
+    }
+
+    /// Add a ready session which appears to be connected to the endpoint
+    fn session(worker: &mut Worker, endpoint: SocketAddr) -> Token {
+        let stream = PipeStream::spawn("cat").unwrap();
+        let mut session = Session::pipe_with_capacity(stream, 1024, 512 * 1024);
+        let entry = worker.sessions.vacant_entry();
+        let token = Token(entry.key());
+        session.set_token(token);
+        entry.insert(session);
+        worker.addrs.insert(token, endpoint);
+        worker.ready_queue.push_back(token);
+        token
+    }
+
+    /// A worker with a session to each of two nodes, which split the slots
+    fn cluster() -> (Worker, SocketAddr, SocketAddr) {
+        let (a, b) = (
+            "127.0.0.1:7000".parse().unwrap(),
+            "127.0.0.1:7001".parse().unwrap(),
+        );
+        let mut slots = SlotMap::empty();
+        for slot in 0..crate::cluster::SLOTS as u16 {
+            slots.update(slot, if slot < 8192 { a } else { b });
+        }
+        let mut worker = worker(CLUSTER);
+        worker.set_cluster(slots);
+        worker.connect_queue.clear();
+        session(&mut worker, a);
+        session(&mut worker, b);
+        (worker, a, b)
+    }
+
+    #[test]
+    fn command_ratelimits() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "memcache"
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [[keyspace]]
+            commands = [{ verb = "get", ratelimit = 1 }, { verb = "set", weight = 1000 }]
+            length = 8
+            values = [{ length = 8 }]
+            "#,
+        );
+        let ratelimit = Arc::new(Ratelimiter::new(1, 1, 1));
+        while ratelimit.try_wait().is_ok() {}
+        let mut ratelimits = HashMap::new();
+        ratelimits.insert((0, 0), ratelimit);
+        worker.set_command_ratelimits(ratelimits);
+
+        // an exhausted command ratelimit does not hold back other commands
+        for _ in 0..10 {
+            worker.next_command = Some((0, 0));
+            assert!(worker.admit_commands());
+            assert_eq!(worker.admitted.drain(..).collect::<Vec<_>>(), vec![(0, 1)]);
+        }
+    }
+
+    #[test]
+    fn read_write_routing() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "redis"
+
+            [target]
+            read_endpoints = ["127.0.0.1:6380"]
+            write_endpoints = ["127.0.0.1:6379"]
+
+            [[keyspace]]
+            commands = [{ verb = "get" }, { verb = "set" }]
+            "#,
+        );
+        worker.connect_queue.clear();
+        let read = session(&mut worker, "127.0.0.1:6380".parse().unwrap());
+        let write = session(&mut worker, "127.0.0.1:6379".parse().unwrap());
+        worker.roles.insert(read, Role::Read);
+        worker.roles.insert(write, Role::Write);
+
+        // a write skips over the read session ahead of it in the queue
+        worker.pending_write = Some(true);
+        assert_eq!(worker.next_ready(), Some(write));
+        assert_eq!(worker.next_ready(), None);
+
+        worker.ready_queue.push_back(write);
+        worker.pending_write = Some(false);
+        assert_eq!(worker.next_ready(), Some(read));
+        assert_eq!(worker.next_ready(), None);
+
+        // the role of a request is chosen once and kept until it is sent
+        worker.ready_queue.push_back(read);
+        worker.pending_write = None;
+        let token = worker.next_ready().unwrap();
+        assert_eq!(worker.pending_write, Some(token == write));
+    }
+
+    #[test]
+    fn chaos_timeout() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "memcache"
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [[chaos]]
+            endpoint = "127.0.0.1:11211"
+            probability = 1.0
+
+            [[keyspace]]
+            commands = [{ verb = "get" }]
+            length = 8
+            "#,
+        );
+        worker.connect_queue.clear();
+        let endpoint = "127.0.0.1:11211".parse().unwrap();
+        let idle = session(&mut worker, endpoint);
+        let busy = session(&mut worker, endpoint);
+        worker.sessions[busy.0].set_outstanding(1);
+        worker.outstanding = 1;
+
+        // an abandoned session with a response outstanding counts as a timeout
+        let timeouts = READ_IDLE_TIMEOUT.value();
+        assert!(worker.inject_timeout(busy));
+        assert!(READ_IDLE_TIMEOUT.value() > timeouts);
+        assert_eq!(worker.outstanding, 0);
+
+        assert!(worker.inject_timeout(idle));
+        assert!(worker.sessions.is_empty());
+    }
+
+    #[test]
+    fn recycle() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "memcache"
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [connection]
+            recycle_per_window_fraction = 0.5
+
+            [[keyspace]]
+            commands = [{ verb = "get" }]
+            length = 8
+            "#,
+        );
+        worker.connect_queue.clear();
+        let windows = Arc::new(AtomicUsize::new(0));
+        worker.set_recycle(windows.clone());
+        let endpoint = "127.0.0.1:11211".parse().unwrap();
+        let tokens: Vec<Token> = (0..4).map(|_| session(&mut worker, endpoint)).collect();
+
+        // closed sessions leave the slab and are not counted
+        worker.disconnect(tokens[0]).unwrap();
+        worker.disconnect(tokens[1]).unwrap();
+        assert_eq!(worker.sessions.len(), 2);
+        assert_eq!(worker.ready_queue.len(), 2);
+
+        // half of the two live sessions are recycled
+        windows.fetch_add(1, Ordering::Relaxed);
+        worker.do_recycle();
+        assert_eq!(worker.sessions.len(), 1);
+        assert_eq!(worker.ready_queue.len(), 1);
+    }
+
+    #[test]
+    fn request_budget() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "echo"
+
+            [target]
+            endpoints = ["127.0.0.1:12321"]
+
+            [[keyspace]]
+            commands = [{ verb = "echo" }]
+            length = 16
+            "#,
+        );
+        worker.connect_queue.clear();
+        let complete = Arc::new(AtomicUsize::new(0));
+        worker.set_request_budget(2, complete.clone());
+        let endpoint = "127.0.0.1:12321".parse().unwrap();
+        let tokens: Vec<Token> = (0..2).map(|_| session(&mut worker, endpoint)).collect();
+
+        // the budget is spent on a request over each session
+        for token in &tokens {
+            assert!(worker.admit_commands());
+            worker.send_request(*token).unwrap();
+        }
+        assert_eq!(worker.request_budget, Some(0));
+        assert_eq!(worker.outstanding, 2);
+        worker.check_budget();
+        assert_eq!(complete.load(Ordering::Relaxed), 0);
+
+        // a closed session no longer has a response outstanding
+        worker.disconnect(tokens[1]).unwrap();
+        assert_eq!(worker.outstanding, 1);
+        worker.check_budget();
+        assert_eq!(complete.load(Ordering::Relaxed), 0);
+
+        // completion is signalled once the last response is read
+        let session = &mut worker.sessions[tokens[0].0];
+        while session.write_pending() > 0 {
+            session.flush().unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        worker.do_read(tokens[0]).unwrap();
+        assert_eq!(worker.outstanding, 0);
+        worker.check_budget();
+        assert_eq!(complete.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn incomplete_reads() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "memcache"
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [request]
+            max_incomplete_reads = 2
+
+            [[keyspace]]
+            commands = [{ verb = "get" }]
+            length = 8
+            "#,
+        );
+        worker.connect_queue.clear();
+        let token = session(&mut worker, "127.0.0.1:11211".parse().unwrap());
+        let echo = |worker: &mut Worker, bytes: &[u8]| {
+            let session = &mut worker.sessions[token.0];
+            session.write_all(bytes).unwrap();
+            session.flush().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        };
+        worker.sessions[token.0].set_outstanding(1);
+        worker.outstanding = 1;
+
+        // reads which trickle in more of the response are progress
+        for _ in 0..4 {
+            echo(&mut worker, b"VA");
+            worker.do_read(token).unwrap();
+        }
+
+        // reads which add no bytes are counted until the session is closed
+        worker.do_read(token).unwrap();
+        echo(&mut worker, b"LUE");
+        worker.do_read(token).unwrap();
+        worker.do_read(token).unwrap();
+        assert!(worker.do_read(token).is_err());
+    }
+
+    #[test]
+    fn verify_timeout() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "memcache"
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [connection]
+            verify_on_connect = true
+            verify_timeout_ms = 10
+
+            [[keyspace]]
+            commands = [{ verb = "get" }]
+            length = 8
+            "#,
+        );
+        worker.connect_queue.clear();
+        let endpoint = "127.0.0.1:11211".parse().unwrap();
+        let token = session(&mut worker, endpoint);
+        worker.ready_queue.clear();
+        worker.send_verify(token).unwrap();
+
+        // an unanswered verification fails the connect once it times out
+        let sent = worker.verifying[&token];
+        worker.do_verify_timeouts(sent + std::time::Duration::from_millis(5));
+        assert!(worker.verifying.contains_key(&token));
+        worker.do_verify_timeouts(sent + std::time::Duration::from_millis(10));
+        assert!(worker.verifying.is_empty());
+        assert!(worker
+            .connect_queue
+            .iter()
+            .any(|(addr, _)| *addr == endpoint));
+    }
+
+    #[test]
+    fn drop_due_requests() {
+        let mut worker = worker(
+            r#"
+            [general]
+            protocol = "memcache"
+            threads = 1
+
+            [target]
+            endpoints = ["127.0.0.1:11211"]
+
+            [request]
+            ratelimit = 1000
+            count_failures_in_rate = true
+
+            [[keyspace]]
+            commands = [{ verb = "get" }]
+            length = 8
+            "#,
+        );
+        let ratelimit = Arc::new(Ratelimiter::new(1, 1, 1000));
+        worker.set_request_ratelimit(Some(ratelimit.clone()));
+        let start = Instant::now();
+
+        // nothing is counted before the first request is sent
+        worker.drop_due_requests(start + std::time::Duration::from_secs(1));
+        assert_eq!(worker.due_since, None);
+
+        // once sending, the requests due during a backlog are counted without
+        // taking tokens from the shared ratelimiter
+        worker.due_since = Some(start);
+        let dropped = REQUEST_DROPPED.value();
+        worker.drop_due_requests(start + std::time::Duration::from_millis(10));
+        assert_eq!(REQUEST_DROPPED.value() - dropped, 10);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(ratelimit.try_wait().is_ok());
+
+        // with a session ready there is no backlog
+        let token = session(&mut worker, "127.0.0.1:11211".parse().unwrap());
+        worker.drop_due_requests(start + std::time::Duration::from_millis(20));
+        assert_eq!(REQUEST_DROPPED.value() - dropped, 10);
+        worker.ready_queue.retain(|t| *t != token);
+        worker.drop_due_requests(start + std::time::Duration::from_millis(20));
+        assert_eq!(REQUEST_DROPPED.value() - dropped, 20);
+    }
+
+    #[test]
+    fn slot_routing() {
+        let (mut worker, a, b) = cluster();
+        for _ in 0..100 {
+            let token = worker.next_ready_cluster().unwrap();
+            let key = worker.routed_key.take().unwrap();
+            let owner = if crate::cluster::slot(&key) < 8192 {
+                a
+            } else {
+                b
+            };
+            assert_eq!(worker.addrs[&token], owner);
+            worker.next_command = None;
+            worker.ready_queue.push_back(token);
+        }
+
+        // while the owner of the slot is busy, the key waits for it
+        let key = b"00000000".to_vec();
+        let owner = if crate::cluster::slot(&key) < 8192 {
+            a
+        } else {
+            b
+        };
+        let busy = worker
+            .ready_queue
+            .iter()
+            .position(|token| worker.addrs[token] == owner)
+            .unwrap();
+        let busy = worker.ready_queue.remove(busy).unwrap();
+        worker.routed_key = Some(key.clone());
+        assert_eq!(worker.next_ready_cluster(), None);
+
+        // without any session to the owner, another node carries the request
+        // and the owner is connected
+        worker.addrs.remove(&busy);
+        assert!(worker.next_ready_cluster().is_some());
+        assert!(worker.connect_queue.iter().any(|(addr, _)| *addr == owner));
+        assert_eq!(worker.routed_key, Some(key));
+    }
+
+    #[test]
+    fn tagged_routing() {
+        let (mut worker, a, b) = cluster();
+        worker.request_ids = true;
+
+        // the key is routed as it is sent, with the id of the request
+        let token = worker.next_ready_cluster().unwrap();
+        let id = worker.routed_id.unwrap();
+        let key = worker.routed_key.clone().unwrap();
+        assert!(key.starts_with(format!("{:x}:", id).as_bytes()));
+        let owner = if crate::cluster::slot(&key) < 8192 {
+            a
+        } else {
+            b
+        };
+        assert_eq!(worker.addrs[&token], owner);
+
+        worker.admitted.push((0, 0));
+        worker.send_commands(token, None).unwrap();
+        let session = &mut worker.sessions[token.0];
+        assert_eq!(session.pop_request_id(), Some(id));
+        let request = session.pop_request().unwrap();
+        assert!(request.windows(key.len()).any(|window| window == key));
+    }
+
+    #[test]
+    fn redirects() {
+        let (mut worker, a, b) = cluster();
+        let token = worker.ready_queue.pop_front().unwrap();
+        let request = b"*2\r\n$3\r\nget\r\n$8\r\n00000000\r\n".to_vec();
+
+        // a MOVED redirect updates the slot map and retries on the new owner
+        let moved = RedisError::Moved {
+            slot: 1,
+            endpoint: b.to_string(),
+        };
+        worker.redirect(token, moved, Some(request.clone()), Some((0, 0)));
+        assert_eq!(worker.cluster.as_ref().unwrap().endpoint(1), Some(b));
+        worker.send_redirects();
+        assert!(worker.redirects.is_empty());
+        let retry = worker
+            .addrs
+            .iter()
+            .find(|(_, addr)| **addr == b)
+            .map(|(token, _)| *token)
+            .unwrap();
+        assert!(!worker.ready_queue.contains(&retry));
+        assert_eq!(worker.redirected.get(&retry), Some(&1));
+        let session = &mut worker.sessions[retry.0];
+        assert_eq!(session.outstanding(), 1);
+        assert_eq!(session.pop_command(), Some((0, 0)));
+        assert_eq!(session.pop_request(), Some(request.clone()));
+
+        // an ASK redirect is sent after an ASKING, without updating the map
+        let ask = RedisError::Ask {
+            slot: 2,
+            endpoint: a.to_string(),
+        };
+        worker.redirect(retry, ask, Some(request.clone()), None);
+        worker.ready_queue.push_back(token);
+        worker.send_redirects();
+        assert_eq!(worker.cluster.as_ref().unwrap().endpoint(2), Some(a));
+        assert!(worker.asking.contains(&token));
+        assert_eq!(worker.redirected.get(&token), Some(&2));
+
+        // a request which keeps being redirected is abandoned
+        worker.redirected.insert(token, MAX_REDIRECTS);
+        let moved = RedisError::Moved {
+            slot: 1,
+            endpoint: a.to_string(),
+        };
+        worker.redirect(token, moved, Some(request), None);
+        assert!(worker.redirects.is_empty());
+    }
+}